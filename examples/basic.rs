@@ -1,4 +1,3 @@
-use brewdiff;
 use std::fmt::Write;
 use std::path::Path;
 
@@ -54,7 +53,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Show the diff
     println!("\n🔄 Differences (current vs intended):");
     let mut writer = StdoutWriter;
-    let lines = brewdiff::write_homebrew_diffln(
+    let report = brewdiff::write_homebrew_diffln(
         &mut writer,
         // Current system as "old"
         Path::new("/run/current-system"),
@@ -62,7 +61,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         current_profile,
     )?;
 
-    if lines == 3 {
+    if !report.had_changes {
         println!("  Your Homebrew installation matches the nix-darwin configuration!");
     }
 
@@ -0,0 +1,4 @@
+//! Builds libbrewdiff_ffi from the `ffi` feature of brewdiff, the library itself stays an rlib
+//! See `brewdiff::ffi` for the exported functions
+
+pub use brewdiff::ffi::{brewdiff_compute_json, brewdiff_string_free};
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    brewdiff::fuzzing::brew_list_versions(data);
+    brewdiff::fuzzing::brew_outdated(data);
+});
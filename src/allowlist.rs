@@ -0,0 +1,403 @@
+//! Acknowledging expected drift, so known differences don't fail checks until they expire
+//!
+//! Read from `[[acknowledged]]` tables of the config file:
+//!
+//! ```toml
+//! [[acknowledged]]
+//! name = "docker"
+//! direction = "removed"
+//! expires = 2026-12-31
+//! ```
+
+use crate::config;
+use crate::date::is_date;
+use crate::diff::{HomebrewDiffData, ItemKey};
+use crate::error::{Error, Result};
+use crate::events::Category;
+use crate::filter::NameFilter;
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use toml::Spanned;
+
+/// Which way an entry drifts from the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DriftDirection {
+    /// Declared but not installed
+    Added,
+    /// Installed but not declared, whether activation uninstalls it or not
+    Removed,
+    /// Installed with a newer version available
+    Upgraded,
+}
+
+impl fmt::Display for DriftDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DriftDirection::Added => "added",
+            DriftDirection::Removed => "removed",
+            DriftDirection::Upgraded => "upgraded",
+        })
+    }
+}
+
+impl FromStr for DriftDirection {
+    type Err = Error;
+
+    fn from_str(direction: &str) -> Result<Self> {
+        match direction.trim().to_lowercase().as_str() {
+            "added" | "add" => Ok(DriftDirection::Added),
+            "removed" | "remove" | "unmanaged" => Ok(DriftDirection::Removed),
+            "upgraded" | "upgrade" => Ok(DriftDirection::Upgraded),
+            _ => Err(Error::InvalidConfig(format!(
+                "unknown direction {}, expected added, removed or upgraded",
+                direction.trim()
+            ))),
+        }
+    }
+}
+
+/// An expected drift of entries matching a name, which may be a glob like `font-*`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Acknowledgement {
+    pub name: String,
+    pub direction: DriftDirection,
+    /// Last day the acknowledgement holds, as `YYYY-MM-DD`, None to never expire
+    pub expires: Option<String>,
+}
+
+impl Acknowledgement {
+    /// Whether the expiry date lies before today, a `YYYY-MM-DD` date
+    pub fn is_expired(&self, today: &str) -> bool {
+        self.expires
+            .as_deref()
+            .is_some_and(|expires| expires < today)
+    }
+
+    fn matches(&self, name: &str, direction: DriftDirection) -> bool {
+        self.direction == direction && NameFilter::new([self.name.as_str()]).matches(name)
+    }
+}
+
+/// The `[[acknowledged]]` tables of a config file
+#[derive(Debug, Default, Deserialize)]
+struct AllowlistFile {
+    #[serde(default)]
+    acknowledged: Vec<Spanned<AcknowledgedTable>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AcknowledgedTable {
+    name: Option<String>,
+    direction: Option<String>,
+    expires: Option<Expiry>,
+}
+
+/// A bare TOML date or a `"YYYY-MM-DD"` string
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Expiry {
+    Date(toml::value::Datetime),
+    Text(String),
+}
+
+/// Acknowledged drifts, in the order of the config file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Allowlist {
+    pub entries: Vec<Acknowledgement>,
+}
+
+impl Allowlist {
+    /// Read the allowlist from a config file, a missing file acknowledges nothing
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(&config::read(path)?)
+    }
+
+    /// Parse `[[acknowledged]]` tables with `name`, `direction` and an optional `expires`
+    pub fn parse(content: &str) -> Result<Self> {
+        let file: AllowlistFile = config::parse(content)?;
+        let entries = file
+            .acknowledged
+            .into_iter()
+            .map(|table| {
+                let line = config::line_of(content, table.span().start);
+                let invalid =
+                    |reason: String| Error::InvalidConfig(format!("line {}: {}", line, reason));
+                let table = table.into_inner();
+
+                let name = table
+                    .name
+                    .filter(|name| !name.is_empty())
+                    .ok_or_else(|| invalid("acknowledgement without a name".to_string()))?;
+                let direction = table
+                    .direction
+                    .ok_or_else(|| invalid(format!("no direction for {}", name)))?
+                    .parse()
+                    .map_err(|e| match e {
+                        Error::InvalidConfig(reason) => invalid(reason),
+                        e => e,
+                    })?;
+                let expires = table
+                    .expires
+                    .map(|expires| {
+                        let date = match expires {
+                            Expiry::Date(date) => date.to_string(),
+                            Expiry::Text(date) => date,
+                        };
+                        if is_date(&date) {
+                            Ok(date)
+                        } else {
+                            Err(invalid(format!("expiry {} isn't a YYYY-MM-DD date", date)))
+                        }
+                    })
+                    .transpose()?;
+
+                Ok(Acknowledgement {
+                    name,
+                    direction,
+                    expires,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { entries })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The acknowledgement for a drifting entry, preferring ones that haven't expired
+    pub fn find(
+        &self,
+        name: &str,
+        direction: DriftDirection,
+        today: &str,
+    ) -> Option<&Acknowledgement> {
+        let mut matching = self
+            .entries
+            .iter()
+            .filter(|entry| entry.matches(name, direction));
+        let first = matching.next()?;
+        if first.is_expired(today) {
+            matching
+                .find(|entry| !entry.is_expired(today))
+                .or(Some(first))
+        } else {
+            Some(first)
+        }
+    }
+}
+
+/// A drifting entry the allowlist matched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcknowledgedDrift {
+    pub name: String,
+    pub category: Category,
+    pub direction: DriftDirection,
+    /// From the matching acknowledgement
+    pub expires: Option<String>,
+}
+
+impl fmt::Display for AcknowledgedDrift {
+    /// e.g. `docker (removed)` or `wget (added until 2026-12-31)`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}", self.name, self.direction)?;
+        if let Some(expires) = &self.expires {
+            write!(f, " until {}", expires)?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// Move acknowledged entries of one list out of the diff, expired ones stay and are recorded
+fn take_acknowledged<T: ItemKey>(
+    items: &mut Vec<T>,
+    category: Category,
+    direction: DriftDirection,
+    allowlist: &Allowlist,
+    today: &str,
+    acknowledged: &mut Vec<AcknowledgedDrift>,
+    expired: &mut Vec<AcknowledgedDrift>,
+) {
+    items.retain(|item| {
+        let Some(acknowledgement) = allowlist.find(item.key(), direction, today) else {
+            return true;
+        };
+        let drift = AcknowledgedDrift {
+            name: item.key().to_string(),
+            category,
+            direction,
+            expires: acknowledgement.expires.clone(),
+        };
+        if acknowledgement.is_expired(today) {
+            expired.push(drift);
+            true
+        } else {
+            acknowledged.push(drift);
+            false
+        }
+    });
+}
+
+impl HomebrewDiffData {
+    /// A copy of the diff with entries the allowlist acknowledges until at least `today`
+    /// moved to `acknowledged`, so they no longer count as changes
+    pub fn acknowledged(&self, allowlist: &Allowlist, today: &str) -> Self {
+        let mut diff = self.clone();
+        if allowlist.is_empty() {
+            return diff;
+        }
+
+        for (category, packages) in [
+            (Category::Taps, &mut diff.taps),
+            (Category::Formulae, &mut diff.brews),
+            (Category::Casks, &mut diff.casks),
+            (Category::AppStore, &mut diff.mas_apps),
+        ] {
+            take_acknowledged(
+                &mut packages.added,
+                category,
+                DriftDirection::Added,
+                allowlist,
+                today,
+                &mut diff.acknowledged,
+                &mut diff.expired_acknowledgements,
+            );
+            take_acknowledged(
+                &mut packages.removed,
+                category,
+                DriftDirection::Removed,
+                allowlist,
+                today,
+                &mut diff.acknowledged,
+                &mut diff.expired_acknowledgements,
+            );
+            take_acknowledged(
+                &mut packages.upgraded,
+                category,
+                DriftDirection::Upgraded,
+                allowlist,
+                today,
+                &mut diff.acknowledged,
+                &mut diff.expired_acknowledgements,
+            );
+        }
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::{ChangeEffect, Removal};
+
+    #[test]
+    fn test_parse_allowlist() {
+        let allowlist = Allowlist::parse(
+            r#"
+[annotations]
+docker = "kept manual"
+
+[[acknowledged]]
+name = "docker"
+direction = "removed"
+expires = 2026-12-31  # colima migration
+
+[[acknowledged]]
+name = 'font-*'
+direction = "added"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            allowlist.entries,
+            vec![
+                Acknowledgement {
+                    name: "docker".to_string(),
+                    direction: DriftDirection::Removed,
+                    expires: Some("2026-12-31".to_string()),
+                },
+                Acknowledgement {
+                    name: "font-*".to_string(),
+                    direction: DriftDirection::Added,
+                    expires: None,
+                },
+            ]
+        );
+
+        let error = Allowlist::parse("[[acknowledged]]\nname = \"docker\"\nexpires = \"soon\"\n")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid config: line 1: no direction for docker"
+        );
+        assert!(Allowlist::parse(
+            "[[acknowledged]]\nname = \"x\"\ndirection = \"added\"\nexpires = \"soon\"\n"
+        )
+        .is_err());
+        assert!(Allowlist::parse(
+            "[[acknowledged]]\nname = \"x\"\ndirection = \"added\"\nexpires = \"2026-13-01\"\n"
+        )
+        .is_err());
+
+        // Keys may be quoted like any TOML key
+        let quoted =
+            Allowlist::parse("[[acknowledged]]\n\"name\" = \"wget\"\n'direction' = \"added\"\n")
+                .unwrap();
+        assert_eq!(quoted.entries[0].name, "wget");
+    }
+
+    #[test]
+    fn test_acknowledged_diff() {
+        let mut original = HomebrewDiffData::default();
+        original.casks.added = vec!["font-fira-code".to_string(), "firefox".to_string()];
+        original.casks.removed = vec![Removal::new("docker", ChangeEffect::WillBeRemoved)];
+        original.brews.removed = vec![Removal::new("wget", ChangeEffect::WillBeRemoved)];
+        let allowlist = Allowlist {
+            entries: vec![
+                Acknowledgement {
+                    name: "font-*".to_string(),
+                    direction: DriftDirection::Added,
+                    expires: None,
+                },
+                Acknowledgement {
+                    name: "docker".to_string(),
+                    direction: DriftDirection::Removed,
+                    expires: Some("2026-10-16".to_string()),
+                },
+                Acknowledgement {
+                    name: "wget".to_string(),
+                    direction: DriftDirection::Added,
+                    expires: None,
+                },
+            ],
+        };
+
+        let diff = original.acknowledged(&allowlist, "2026-10-16");
+        assert_eq!(diff.casks.added, vec!["firefox".to_string()]);
+        assert!(diff.casks.removed.is_empty());
+        assert_eq!(diff.brews.removed.len(), 1);
+        assert_eq!(
+            diff.acknowledged
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "font-fira-code (added)",
+                "docker (removed until 2026-10-16)"
+            ]
+        );
+
+        let expired = original.acknowledged(&allowlist, "2026-10-17");
+        assert_eq!(expired.casks.removed.len(), 1);
+        assert_eq!(expired.acknowledged.len(), 1);
+        assert_eq!(expired.expired_acknowledgements[0].name, "docker");
+        assert_eq!(
+            expired.concerns(None)[0].message,
+            "docker was acknowledged until 2026-10-16, it counts as drift again"
+        );
+    }
+}
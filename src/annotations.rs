@@ -0,0 +1,155 @@
+//! Notes users attach to package names, so known and intended drift explains itself in the report
+//!
+//! Read from the `[annotations]` table of the config file, other tables and keys configure
+//! other settings:
+//!
+//! ```toml
+//! [annotations]
+//! docker = "kept manual until colima migration"
+//! "hashicorp/tap/terraform" = "pinned to 1.5 for the infra repo"
+//! ```
+
+use crate::config;
+use crate::error::{Error, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The `[annotations]` table of a config file
+#[derive(Debug, Default, Deserialize)]
+struct AnnotationsFile {
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+/// Notes by package name, matched case-insensitively
+/// Formulae and casks match with or without their tap on either side, App Store apps without their id
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Annotations {
+    notes: HashMap<String, String>,
+}
+
+impl Annotations {
+    /// `~/.config/brewdiff/config.toml`, None without a home directory
+    pub fn default_path() -> Option<PathBuf> {
+        config::default_path()
+    }
+
+    /// Read annotations from a config file, a missing file has none
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(&config::read(path)?)
+    }
+
+    /// Parse the `name = "note"` entries of the `[annotations]` table, the rest of the config
+    /// is left to the settings it configures
+    pub fn parse(content: &str) -> Result<Self> {
+        let file: AnnotationsFile = config::parse(content)?;
+        if file.annotations.contains_key("") {
+            return Err(Error::InvalidConfig("missing package name".to_string()));
+        }
+        let notes = file
+            .annotations
+            .into_iter()
+            .map(|(name, note)| (name.to_lowercase(), note))
+            .collect();
+        Ok(Self { notes })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    /// The note for an entry, by its full name first
+    /// A tap-qualified note applies to an unqualified name unless several taps' notes would
+    pub fn note(&self, name: &str) -> Option<&str> {
+        if self.notes.is_empty() {
+            return None;
+        }
+        let full = name.to_lowercase();
+        let short = short_name(&full);
+        let app = full
+            .strip_suffix(')')
+            .and_then(|name| name.rsplit_once(" ("))
+            .map(|(app, _)| app);
+
+        let note = [Some(full.as_str()), Some(short), app]
+            .into_iter()
+            .flatten()
+            .find_map(|key| self.notes.get(key));
+        if let Some(note) = note {
+            return Some(note);
+        }
+        if full.contains('/') {
+            return None;
+        }
+        let mut qualified = self
+            .notes
+            .iter()
+            .filter(|(key, _)| key.contains('/') && short_name(key) == short);
+        match (qualified.next(), qualified.next()) {
+            (Some((_, note)), None) => Some(note),
+            _ => None,
+        }
+    }
+}
+
+/// `terraform` of `hashicorp/tap/terraform`
+fn short_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_annotations() {
+        let annotations = Annotations::parse(
+            r#"
+# Known drift
+[annotations]
+docker = "kept manual until colima migration"  # since March
+"hashicorp/tap/terraform" = 'pinned to 1.5'
+Xcode = "installed by \"xcodes\""
+
+[display]
+verbose = true
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            annotations.note("docker"),
+            Some("kept manual until colima migration")
+        );
+        assert_eq!(
+            annotations.note("hashicorp/tap/terraform"),
+            Some("pinned to 1.5")
+        );
+        assert_eq!(annotations.note("terraform"), Some("pinned to 1.5"));
+        assert_eq!(annotations.note("other/tap/terraform"), None);
+        assert_eq!(
+            annotations.note("Xcode (497799835)"),
+            Some("installed by \"xcodes\"")
+        );
+        assert_eq!(annotations.note("verbose"), None);
+    }
+
+    #[test]
+    fn test_short_names_and_errors() {
+        let annotations = Annotations::parse("[annotations]\ndocker = \"manual\"\n").unwrap();
+        assert_eq!(annotations.note("homebrew/cask/Docker"), Some("manual"));
+
+        // Top-level settings aren't annotations
+        let annotations = Annotations::parse("verbose = true\n[annotations]\n").unwrap();
+        assert!(annotations.is_empty());
+
+        let error = Annotations::parse("[annotations]\ndocker = 1\n").unwrap_err();
+        assert!(error.to_string().starts_with("Invalid config: line 2: "));
+        let error = Annotations::parse("[annotations]\ndocker = manual\n").unwrap_err();
+        assert!(error.to_string().starts_with("Invalid config: line 2: "));
+        assert!(Annotations::load(Path::new("/nonexistent/config.toml"))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,214 @@
+//! Append-only log of the commands run to reconcile Homebrew, for tracing changes on shared machines
+
+use crate::error::Result;
+use crate::redact::Redactor;
+use crate::runner::CommandRunner;
+use serde_json::json;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Most bytes of stdout and stderr kept per record, from the end where errors usually are
+const EXCERPT_LEN: usize = 4096;
+
+/// A JSON lines file with one record per command
+/// Commands and their output are redacted before they're written, the user is kept
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+    redactor: Redactor,
+}
+
+/// What ran, who ran it and how it went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub user: String,
+    pub command: String,
+    /// Exit code, None if the command didn't start or was killed by a signal
+    pub status: Option<i32>,
+    pub stdout: String,
+    /// Also holds the error if the command didn't start
+    pub stderr: String,
+}
+
+impl AuditLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            redactor: Redactor::from_env(),
+        }
+    }
+
+    /// Redact with the given redactor instead of the one for the current user and machine
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = redactor;
+        self
+    }
+
+    /// `~/Library/Logs/brewdiff/audit.jsonl`, None without a home directory
+    pub fn default_path() -> Option<PathBuf> {
+        let home = std::env::var_os("HOME")?;
+        Some(Path::new(&home).join("Library/Logs/brewdiff/audit.jsonl"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a record as a single line, creating the file and its directory if needed
+    pub fn append(&self, record: &AuditRecord) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let line = json!({
+            "timestamp": record.timestamp,
+            "user": record.user,
+            "command": self.redactor.redact(&record.command),
+            "status": record.status,
+            "stdout": self.redactor.redact(&record.stdout),
+            "stderr": self.redactor.redact(&record.stderr),
+        });
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        // One write per line, so records of concurrent runs don't interleave
+        file.write_all(format!("{}\n", line).as_bytes())?;
+        Ok(())
+    }
+}
+
+impl AuditRecord {
+    /// Record the result of running `program` with `args` as the current user
+    pub fn new(program: &str, args: &[&str], result: &io::Result<Output>) -> Self {
+        let command = std::iter::once(program)
+            .chain(args.iter().copied())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let (status, stdout, stderr) = match result {
+            Ok(output) => (
+                output.status.code(),
+                excerpt(&output.stdout),
+                excerpt(&output.stderr),
+            ),
+            Err(e) => (None, String::new(), e.to_string()),
+        };
+
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+            user: std::env::var("USER").unwrap_or_else(|_| "unknown".to_string()),
+            command,
+            status,
+            stdout,
+            stderr,
+        }
+    }
+}
+
+/// The end of a command's output, lossily decoded
+fn excerpt(output: &[u8]) -> String {
+    let start = output.len().saturating_sub(EXCERPT_LEN);
+    String::from_utf8_lossy(&output[start..]).into_owned()
+}
+
+/// Runs commands through another runner and records each of them in the audit log
+/// Use it for commands that change the installation, e.g. running a delta Brewfile
+pub struct AuditingRunner<'a> {
+    inner: &'a dyn CommandRunner,
+    log: AuditLog,
+}
+
+impl<'a> AuditingRunner<'a> {
+    pub fn new(inner: &'a dyn CommandRunner, log: AuditLog) -> Self {
+        Self { inner, log }
+    }
+}
+
+impl CommandRunner for AuditingRunner<'_> {
+    fn output(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+        let result = self.inner.output(program, args);
+        // The command already ran, so a failing log must not hide its result
+        let logged = self.log.append(&AuditRecord::new(program, args, &result));
+        #[cfg(feature = "tracing")]
+        if let Err(e) = &logged {
+            crate::trace::event!(error = %e, path = %self.log.path.display(), "failed to write audit log");
+        }
+        logged.ok();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::FixtureRunner;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_auditing_runner_appends_records() {
+        let temp_dir = TempDir::new().unwrap();
+        let log = AuditLog::new(temp_dir.path().join("logs/audit.jsonl"));
+        let fixture = FixtureRunner::load("sonoma-arm64");
+        let runner = AuditingRunner::new(&fixture, log.clone());
+
+        runner.output("brew", &["tap"]).unwrap();
+        runner.output("brew", &["untap", "acme/tools"]).unwrap();
+
+        let content = fs::read_to_string(log.path()).unwrap();
+        let records: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["command"], "brew tap");
+        assert_eq!(records[0]["status"], 0);
+        assert_eq!(records[0]["stdout"], "hashicorp/tap\nhomebrew/bundle\n");
+        assert_eq!(records[1]["command"], "brew untap acme/tools");
+        assert_eq!(records[1]["status"], 127);
+        assert!(records[1]["timestamp"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_append_redacts_commands_and_output() {
+        let temp_dir = TempDir::new().unwrap();
+        let log = AuditLog::new(temp_dir.path().join("audit.jsonl"))
+            .with_redactor(Redactor::new(Some("/Users/alice"), &[]));
+        let result = Err(io::Error::other("/Users/alice/Brewfile: not found"));
+
+        log.append(&AuditRecord::new(
+            "brew",
+            &["bundle", "--file=/Users/alice/Brewfile"],
+            &result,
+        ))
+        .unwrap();
+
+        let content = fs::read_to_string(log.path()).unwrap();
+        let record: serde_json::Value = serde_json::from_str(content.trim()).unwrap();
+        assert_eq!(record["command"], "brew bundle --file=~/Brewfile");
+        assert_eq!(record["stderr"], "~/Brewfile: not found");
+    }
+
+    #[test]
+    fn test_record_of_command_that_failed_to_start() {
+        let result = Err(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+        let record = AuditRecord::new("brew", &["bundle"], &result);
+
+        assert_eq!(record.command, "brew bundle");
+        assert_eq!(record.status, None);
+        assert_eq!(record.stderr, "no such file");
+    }
+
+    #[test]
+    fn test_excerpt_keeps_the_end() {
+        let output = vec![b'a'; EXCERPT_LEN + 10];
+        assert_eq!(excerpt(&output).len(), EXCERPT_LEN);
+        assert_eq!(excerpt(b"done\n"), "done\n");
+    }
+}
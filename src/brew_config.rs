@@ -0,0 +1,202 @@
+//! The Homebrew installation detection runs against, from its prefix or `brew config`
+
+use crate::compat::BrewCompat;
+use crate::runner::CommandRunner;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Details of the Homebrew installation, fields are None when they couldn't be determined
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BrewConfig {
+    /// Release, e.g. `4.3.5`
+    pub version: Option<String>,
+    /// e.g. `/opt/homebrew`, where the Cellar and Caskroom are scanned
+    pub prefix: Option<PathBuf>,
+    /// Whether formulae and casks come from the JSON API instead of local homebrew/core
+    /// and homebrew/cask clones
+    pub uses_api: Option<bool>,
+    /// e.g. `arm64` or `x86_64`
+    pub arch: Option<String>,
+    /// e.g. `14.5`
+    pub macos: Option<String>,
+}
+
+impl BrewConfig {
+    /// What the prefix tells without spawning brew, the release stays unknown
+    /// `/opt/homebrew` is only used by Apple Silicon installations, `/usr/local` on macOS by Intel ones
+    pub fn from_prefix(prefix: &Path) -> Self {
+        let arch = match prefix.to_str() {
+            Some("/opt/homebrew") => Some("arm64"),
+            Some("/usr/local") if cfg!(target_os = "macos") => Some("x86_64"),
+            _ => None,
+        };
+        Self {
+            prefix: Some(prefix.to_path_buf()),
+            arch: arch.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    /// Fill in the release from `brew --version`, which unlike `brew config` doesn't start Ruby
+    /// Only detection that depends on the release, like `brew outdated`, needs it
+    pub(crate) fn detect_version(&mut self, runner: &dyn CommandRunner, brew: &str) {
+        self.version = runner
+            .output(brew, &["--version"])
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| BrewCompat::parse_version(&String::from_utf8_lossy(&output.stdout)));
+    }
+
+    /// Run `brew config`, everything stays unknown if it fails
+    /// It takes seconds, detection uses `from_prefix` and `detect_version` instead
+    pub fn detect(runner: &dyn CommandRunner, brew: &str) -> Self {
+        runner
+            .output(brew, &["config"])
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| Self::parse(&String::from_utf8_lossy(&output.stdout)))
+            .unwrap_or_default()
+    }
+
+    /// Parse `brew config` output, `KEY: value` lines
+    pub(crate) fn parse(output: &str) -> Self {
+        let fields: Vec<(&str, &str)> = output
+            .lines()
+            .filter_map(|line| line.split_once(": "))
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .collect();
+        let field = |key: &str| {
+            fields
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, value)| *value)
+        };
+
+        // Local clones report their git HEAD, the API its JSON download time
+        let uses_api = if field("HOMEBREW_NO_INSTALL_FROM_API").is_some() {
+            Some(false)
+        } else if field("Core tap JSON").is_some() {
+            Some(true)
+        } else if field("Core tap HEAD").is_some() || field("Core tap origin").is_some() {
+            Some(false)
+        } else {
+            None
+        };
+        // e.g. `macOS: 14.5-arm64`
+        let (macos, arch) = match field("macOS").map(|value| value.rsplit_once('-')) {
+            Some(Some((macos, arch))) => (Some(macos.to_string()), Some(arch.to_string())),
+            Some(None) => (field("macOS").map(str::to_string), None),
+            None => (None, None),
+        };
+
+        Self {
+            version: field("HOMEBREW_VERSION")
+                .and_then(|version| version.split('-').next())
+                .map(str::to_string),
+            prefix: field("HOMEBREW_PREFIX").map(PathBuf::from),
+            uses_api,
+            arch,
+            macos,
+        }
+    }
+
+    /// Whether two configs describe the same Homebrew installation, unknown fields match anything
+    pub fn same_installation(&self, other: &Self) -> bool {
+        fn agree<T: PartialEq>(a: &Option<T>, b: &Option<T>) -> bool {
+            a.is_none() || b.is_none() || a == b
+        }
+        agree(&self.prefix, &other.prefix) && agree(&self.arch, &other.arch)
+    }
+
+    /// Which brew features detection can rely on
+    pub fn compat(&self) -> BrewCompat {
+        BrewCompat {
+            version: self.version.clone(),
+        }
+    }
+}
+
+impl fmt::Display for BrewConfig {
+    /// e.g. `Homebrew 4.3.5 at /opt/homebrew, arm64, macOS 14.5, formulae from the API`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Homebrew {}",
+            self.version.as_deref().unwrap_or("unknown")
+        )?;
+        if let Some(prefix) = &self.prefix {
+            write!(f, " at {}", prefix.display())?;
+        }
+        if let Some(arch) = &self.arch {
+            write!(f, ", {}", arch)?;
+        }
+        if let Some(macos) = &self.macos {
+            write!(f, ", macOS {}", macos)?;
+        }
+        match self.uses_api {
+            Some(true) => write!(f, ", formulae from the API"),
+            Some(false) => write!(f, ", formulae from local taps"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::FixtureRunner;
+
+    #[test]
+    fn test_parse_brew_config() {
+        let config = BrewConfig::parse(
+            "HOMEBREW_VERSION: 4.3.5-31-g8a8e8b1\n\
+             ORIGIN: https://github.com/Homebrew/brew\n\
+             Core tap JSON: 05 Jun 18:27 UTC\n\
+             Core cask tap JSON: 05 Jun 18:27 UTC\n\
+             HOMEBREW_PREFIX: /opt/homebrew\n\
+             CPU: deca-core 64-bit arm_firestorm_icestorm\n\
+             macOS: 14.5-arm64\n\
+             Rosetta 2: false\n",
+        );
+
+        assert_eq!(config.version.as_deref(), Some("4.3.5"));
+        assert_eq!(config.prefix, Some(PathBuf::from("/opt/homebrew")));
+        assert_eq!(config.uses_api, Some(true));
+        assert_eq!(config.arch.as_deref(), Some("arm64"));
+        assert_eq!(
+            config.to_string(),
+            "Homebrew 4.3.5 at /opt/homebrew, arm64, macOS 14.5, formulae from the API"
+        );
+    }
+
+    #[test]
+    fn test_parse_brew_config_local_taps() {
+        let config = BrewConfig::parse(
+            "HOMEBREW_VERSION: 3.2.17\n\
+             Core tap origin: https://github.com/Homebrew/homebrew-core\n\
+             Core tap HEAD: 0b5b\n\
+             HOMEBREW_PREFIX: /usr/local\n\
+             macOS: 11.6-x86_64\n",
+        );
+
+        assert_eq!(config.uses_api, Some(false));
+        assert_eq!(config.arch.as_deref(), Some("x86_64"));
+        assert_eq!(config.compat().version.as_deref(), Some("3.2.17"));
+        assert_eq!(BrewConfig::parse(""), BrewConfig::default());
+    }
+
+    #[test]
+    fn test_from_prefix() {
+        let mut config = BrewConfig::from_prefix(Path::new("/opt/homebrew"));
+        assert_eq!(config.arch.as_deref(), Some("arm64"));
+        assert_eq!(config.version, None);
+
+        config.detect_version(&FixtureRunner::load("sonoma-arm64"), "brew");
+        assert_eq!(config.version.as_deref(), Some("4.3.5"));
+        assert_eq!(config.uses_api, None);
+        assert_eq!(
+            BrewConfig::from_prefix(Path::new("/home/linuxbrew/.linuxbrew")).arch,
+            None
+        );
+    }
+}
@@ -0,0 +1,241 @@
+//! Redacted debug bundles, so parsing bugs can be reproduced without the reporter's machine
+
+use crate::diff::HomebrewDiffData;
+use crate::display::{self, strip_ansi_codes, DisplayOptions};
+use crate::doctor::{self, Finding};
+use crate::error::Result;
+use crate::intent::HomebrewIntent;
+use crate::redact::Redactor;
+use crate::runner::{CommandRunner, SystemRunner};
+use crate::state::HomebrewState;
+use serde_json::json;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+/// brew invocations whose output is captured: those detection runs, including its fallbacks
+/// for an unreadable Cellar or Caskroom, and `brew config` for context
+/// Detection lists versions only for the packages in the diff, the bundle lists them all
+const BREW_COMMANDS: &[&[&str]] = &[
+    &["--version"],
+    &["config"],
+    &["leaves"],
+    &["list", "--cask"],
+    &["list", "--versions"],
+    &["list", "--cask", "--versions"],
+    &["tap"],
+    &["outdated", "--json=v2", "--greedy-auto-updates"],
+];
+
+/// Everything needed to reproduce a diff, redacted so it can be attached to public issues
+/// Files are named by their path in the archive, e.g. `commands.txt` or `diff.txt`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugBundle {
+    pub files: Vec<(String, String)>,
+}
+
+impl DebugBundle {
+    /// Content of the file at `path` in the archive
+    pub fn file(&self, path: &str) -> Option<&str> {
+        self.files
+            .iter()
+            .find(|(name, _)| name == path)
+            .map(|(_, content)| content.as_str())
+    }
+
+    /// Write the files as an uncompressed tar archive, all under a `brewdiff-debug/` directory
+    pub fn write_archive<W: io::Write>(&self, writer: W) -> Result<()> {
+        let mut archive = tar::Builder::new(writer);
+        for (path, content) in &self.files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive.append_data(
+                &mut header,
+                format!("brewdiff-debug/{}", path),
+                content.as_bytes(),
+            )?;
+        }
+        archive.into_inner()?.flush()?;
+        Ok(())
+    }
+}
+
+/// Collect a debug bundle for the given profile
+pub fn collect_debug_bundle(profile: &Path) -> DebugBundle {
+    collect_debug_bundle_with(&SystemRunner::default(), profile)
+}
+
+/// Collect a debug bundle, running detection and the captured commands through the runner
+pub fn collect_debug_bundle_with(runner: &dyn CommandRunner, profile: &Path) -> DebugBundle {
+    assemble(
+        runner,
+        profile,
+        HomebrewState::detect_with(runner),
+        &doctor::run_diagnostics_with(runner, profile),
+    )
+}
+
+/// Write a debug bundle for the given profile to `output` as a tar archive
+pub fn write_debug_bundle(profile: &Path, output: &Path) -> Result<()> {
+    collect_debug_bundle(profile).write_archive(File::create(output)?)
+}
+
+/// Build the bundle from the detected state and diagnostics
+/// Captured output goes to `commands.txt` in the format of tests/fixtures, so it can be
+/// replayed, exit codes and stderr go to `manifest.json`
+fn assemble(
+    runner: &dyn CommandRunner,
+    profile: &Path,
+    state: Result<HomebrewState>,
+    findings: &[Finding],
+) -> DebugBundle {
+    let brew = HomebrewState::get_brew_command();
+    let commands: Vec<(&str, &[&str])> = BREW_COMMANDS
+        .iter()
+        .map(|args| (brew, *args))
+        .chain([("mas", &["list"][..])])
+        .collect();
+
+    let mut transcript = String::new();
+    let mut statuses = Vec::new();
+    for (program, args) in commands {
+        // Recorded by basename like the fixtures, detection calls brew by its full path
+        let name = Path::new(program)
+            .file_name()
+            .map_or(program.into(), |name| name.to_string_lossy());
+        let command = format!("{} {}", name, args.join(" "));
+        match runner.output(program, args) {
+            Ok(output) => {
+                transcript.push_str(&format!("$ {}\n", command));
+                transcript.push_str(&String::from_utf8_lossy(&output.stdout));
+                statuses.push(json!({
+                    "command": command,
+                    "status": output.status.code(),
+                    "stderr": String::from_utf8_lossy(&output.stderr),
+                }));
+            }
+            Err(e) => statuses.push(json!({ "command": command, "error": e.to_string() })),
+        }
+    }
+
+    let brewfile = HomebrewIntent::find_brewfile_path(profile);
+    let intent = HomebrewIntent::extract(profile);
+    let diff = match (&state, &intent) {
+        (Ok(state), Ok(intent)) => {
+            let diff_data = HomebrewDiffData::compute(state, intent);
+            let mut rendered = String::new();
+            display::write_diff_with_options(&mut rendered, &diff_data, &DisplayOptions::default())
+                .map(|_| strip_ansi_codes(&rendered))
+                .unwrap_or_else(|e| e.to_string())
+        }
+        (Err(e), _) | (_, Err(e)) => e.to_string(),
+    };
+    let mut diagnostics = String::new();
+    let _ = doctor::write_diagnostics(&mut diagnostics, findings);
+
+    let manifest = json!({
+        "brewdiff_version": env!("CARGO_PKG_VERSION"),
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "profile": profile.display().to_string(),
+        "brewfile": brewfile.as_ref().map(|path| path.display().to_string()).map_err(|e| e.to_string()),
+        "commands": statuses,
+    });
+    let debug = |result: Result<String>| result.unwrap_or_else(|e| e.to_string());
+
+    let mut files = vec![
+        (
+            "manifest.json".to_string(),
+            serde_json::to_string_pretty(&manifest).unwrap_or_default(),
+        ),
+        ("commands.txt".to_string(), transcript),
+    ];
+    if let Ok(content) = brewfile.and_then(|path| Ok(fs::read_to_string(path)?)) {
+        files.push(("Brewfile".to_string(), content));
+    }
+    files.extend([
+        (
+            "intent.txt".to_string(),
+            debug(intent.map(|intent| format!("{:#?}\n", intent))),
+        ),
+        (
+            "state.txt".to_string(),
+            debug(state.map(|state| format!("{:#?}\n", state))),
+        ),
+        ("diff.txt".to_string(), diff),
+        (
+            "diagnostics.txt".to_string(),
+            strip_ansi_codes(&diagnostics),
+        ),
+    ]);
+
+    let redactor = Redactor::from_env();
+    DebugBundle {
+        files: files
+            .into_iter()
+            .map(|(path, content)| (path, redactor.redact(&content)))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::FixtureRunner;
+
+    #[test]
+    fn test_assemble_debug_bundle() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let brewfile = temp_dir.path().join("Brewfile");
+        fs::write(&brewfile, "brew \"wget\"\nbrew \"htop\"\n").unwrap();
+        let runner = FixtureRunner::load("sonoma-arm64");
+
+        let bundle = assemble(&runner, &brewfile, runner.detect(), &[]);
+
+        // The captured output replays like the fixture it came from
+        let replay = FixtureRunner::parse(bundle.file("commands.txt").unwrap());
+        let version = replay.output("brew", &["--version"]).unwrap();
+        assert_eq!(version.stdout, b"Homebrew 4.3.5-31-g8a8e8b1\n");
+        assert_eq!(
+            bundle.file("Brewfile"),
+            Some("brew \"wget\"\nbrew \"htop\"\n")
+        );
+        assert!(bundle.file("diff.txt").unwrap().contains("[A] htop"));
+        let manifest: serde_json::Value =
+            serde_json::from_str(bundle.file("manifest.json").unwrap()).unwrap();
+        assert_eq!(manifest["brewdiff_version"], env!("CARGO_PKG_VERSION"));
+        // Unrecorded commands exit like a missing program
+        assert_eq!(manifest["commands"][4]["command"], "brew list --versions");
+        assert_eq!(manifest["commands"][4]["status"], 127);
+
+        let mut archive = Vec::new();
+        bundle.write_archive(&mut archive).unwrap();
+        let paths: Vec<String> = tar::Archive::new(archive.as_slice())
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert_eq!(paths[0], "brewdiff-debug/manifest.json");
+        assert!(paths.contains(&"brewdiff-debug/Brewfile".to_string()));
+    }
+
+    #[test]
+    fn test_assemble_without_profile() {
+        let runner = FixtureRunner::load("sonoma-arm64");
+
+        let bundle = assemble(
+            &runner,
+            Path::new("/nonexistent/profile"),
+            runner.detect(),
+            &[],
+        );
+
+        assert_eq!(bundle.file("Brewfile"), None);
+        assert!(bundle
+            .file("intent.txt")
+            .unwrap()
+            .contains("/nonexistent/profile"));
+    }
+}
@@ -0,0 +1,110 @@
+use crate::error::Result;
+use crate::intent::HomebrewIntent;
+use crate::runner::CommandRunner;
+use crate::state::HomebrewState;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Memoized Homebrew state so repeated calls within one process share a detection pass
+#[derive(Debug, Default)]
+pub struct StateCache {
+    state: Mutex<Option<Arc<HomebrewState>>>,
+}
+
+static GLOBAL: StateCache = StateCache::new();
+
+impl StateCache {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(None),
+        }
+    }
+
+    /// The process-wide cache used by the convenience functions
+    pub fn global() -> &'static Self {
+        &GLOBAL
+    }
+
+    /// Cached state, detecting it first if there is none yet
+    pub fn get(&self) -> Result<Arc<HomebrewState>> {
+        self.get_or_detect(HomebrewState::detect)
+    }
+
+    /// Cached state with versions for the installed packages the intent's lock file pins,
+    /// since the diff compares them and detection through brew leaves them empty
+    pub fn get_for(
+        &self,
+        nix_intent: &HomebrewIntent,
+        runner: &dyn CommandRunner,
+    ) -> Result<Arc<HomebrewState>> {
+        let mut state = self.get()?;
+        if state.lacks_locked_versions(&nix_intent.locked) {
+            Arc::make_mut(&mut state).load_locked_versions(runner, &nix_intent.locked)?;
+        }
+        Ok(state)
+    }
+
+    /// Cached state, falling back to `detect` if there is none yet
+    /// Concurrent callers wait for the first detection instead of running their own
+    pub fn get_or_detect(
+        &self,
+        detect: impl FnOnce() -> Result<HomebrewState>,
+    ) -> Result<Arc<HomebrewState>> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(state) = state.as_ref() {
+            return Ok(Arc::clone(state));
+        }
+
+        let detected = Arc::new(detect()?);
+        *state = Some(Arc::clone(&detected));
+        Ok(detected)
+    }
+
+    /// Drop the cached state, e.g. after installing or removing packages
+    pub fn invalidate(&self) {
+        *self.state.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    /// Check if a state is cached
+    pub fn is_cached(&self) -> bool {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_detects_once_until_invalidated() {
+        let cache = StateCache::new();
+        let detections = Cell::new(0);
+        let detect = || {
+            detections.set(detections.get() + 1);
+            Ok(HomebrewState::default())
+        };
+
+        let first = cache.get_or_detect(detect).unwrap();
+        let second = cache.get_or_detect(detect).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(detections.get(), 1);
+
+        cache.invalidate();
+        assert!(!cache.is_cached());
+        cache.get_or_detect(detect).unwrap();
+        assert_eq!(detections.get(), 2);
+    }
+
+    #[test]
+    fn test_failed_detection_is_not_cached() {
+        let cache = StateCache::new();
+
+        let result = cache.get_or_detect(|| Err(crate::Error::HomebrewNotFound));
+
+        assert!(result.is_err());
+        assert!(!cache.is_cached());
+    }
+}
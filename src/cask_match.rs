@@ -0,0 +1,298 @@
+//! Matching declared casks to installed ones by the app bundles they install, so a renamed
+//! cask isn't shown as an unrelated add/remove pair, e.g. `cask "visual-studio-code"` and
+//! an installed `vscode` both installing `com.microsoft.VSCode`
+
+use crate::diff::HomebrewDiffData;
+use crate::error::{Error, Result};
+use crate::runner::CommandRunner;
+use crate::state::HomebrewState;
+use crate::trace::{event, span};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+
+/// A declared cask that is installed under another token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaskMatch {
+    pub declared: String,
+    pub installed: String,
+    /// What both have in common, a bundle identifier like `org.mozilla.firefox`
+    /// or, for casks without one in their metadata, an app bundle like `Firefox.app`
+    pub bundle: String,
+    /// Cleanup uninstalls the installed cask before installing the declared one,
+    /// so both stay in the diff rather than being matched away
+    pub reinstalled: bool,
+}
+
+/// Bundle identifiers and app bundles each cask in `brew info --json=v2 --cask` output declares
+/// Identifiers come from the apps `uninstall` and `zap` quit, apps from `app` artifacts
+pub(crate) fn parse_cask_bundles(json: &[u8]) -> Result<HashMap<String, BTreeSet<String>>> {
+    let info: Value = serde_json::from_slice(json)
+        .map_err(|e| Error::CommandFailed(format!("brew info returned invalid JSON: {}", e)))?;
+    let casks = info["casks"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    let strings = |value: &Value| -> Vec<String> {
+        match value {
+            Value::String(s) => vec![s.clone()],
+            Value::Array(items) => items
+                .iter()
+                .filter_map(|item| Some(item.as_str()?.to_string()))
+                .collect(),
+            _ => Vec::new(),
+        }
+    };
+    Ok(casks
+        .iter()
+        .filter_map(|cask| {
+            let token = cask["token"].as_str()?.to_string();
+            let mut bundles = BTreeSet::new();
+            for artifact in cask["artifacts"].as_array()? {
+                bundles.extend(strings(&artifact["app"]));
+                for stanza in ["uninstall", "zap"] {
+                    for directive in artifact[stanza].as_array().into_iter().flatten() {
+                        bundles.extend(strings(&directive["quit"]));
+                    }
+                }
+            }
+            Some((token, bundles))
+        })
+        .collect())
+}
+
+/// The bundle identifier in an Info.plist converted to JSON
+fn bundle_id_from_info_plist(json: &[u8]) -> Option<String> {
+    let info: Value = serde_json::from_slice(json).ok()?;
+    Some(info.get("CFBundleIdentifier")?.as_str()?.trim().to_string()).filter(|id| !id.is_empty())
+}
+
+/// Expand a leading `~` of an appdir like `~/Applications`
+fn expand_home(dir: &str) -> PathBuf {
+    match (dir.strip_prefix('~'), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest.trim_start_matches('/')),
+        _ => PathBuf::from(dir),
+    }
+}
+
+/// Bundle identifiers and app bundles of an installed cask, read from its apps' Info.plist
+/// Receipts without an appdir were installed to wherever brew defaulted to at the time,
+/// so both app directories are tried rather than assuming one
+fn installed_bundles(
+    runner: &dyn CommandRunner,
+    current_state: &HomebrewState,
+    cask: &str,
+) -> BTreeSet<String> {
+    let Some(receipt) = current_state.cask_receipts.get(cask) else {
+        return BTreeSet::new();
+    };
+    let appdirs: Vec<PathBuf> = match &receipt.appdir {
+        Some(appdir) => vec![expand_home(appdir)],
+        None => vec![
+            PathBuf::from("/Applications"),
+            expand_home("~/Applications"),
+        ],
+    };
+
+    let mut bundles = BTreeSet::new();
+    for app in &receipt.apps {
+        let id = appdirs.iter().find_map(|appdir| {
+            let info_plist = appdir.join(app).join("Contents/Info.plist");
+            let output = runner
+                .output(
+                    "plutil",
+                    &["-convert", "json", "-o", "-", &info_plist.to_string_lossy()],
+                )
+                .ok()?;
+            if !output.status.success() {
+                return None;
+            }
+            bundle_id_from_info_plist(&output.stdout)
+        });
+        bundles.extend(id);
+        bundles.insert(app.clone());
+    }
+    bundles
+}
+
+/// Pair declared and installed casks sharing a bundle, preferring identifiers over app names
+/// Matches aren't reinstalled yet, see `match_casks_by_bundle`
+fn find_matches(
+    declared: &HashMap<String, BTreeSet<String>>,
+    installed: &HashMap<String, BTreeSet<String>>,
+) -> Vec<CaskMatch> {
+    let mut declared: Vec<(&String, &BTreeSet<String>)> = declared.iter().collect();
+    declared.sort();
+    let mut taken = BTreeSet::new();
+    let mut matches = Vec::new();
+    for (token, bundles) in declared {
+        let found = installed
+            .iter()
+            .filter(|(name, _)| !taken.contains(*name))
+            .filter_map(|(name, installed)| {
+                let shared: Vec<&String> = bundles.intersection(installed).collect();
+                // Identifiers are more specific than app names, which may be shared by forks
+                let bundle = shared
+                    .iter()
+                    .find(|bundle| !bundle.ends_with(".app"))
+                    .or(shared.first())?;
+                Some((name, bundle.to_string()))
+            })
+            .min();
+        if let Some((name, bundle)) = found {
+            taken.insert(name.clone());
+            matches.push(CaskMatch {
+                declared: token.clone(),
+                installed: name.clone(),
+                bundle,
+                reinstalled: false,
+            });
+        }
+    }
+    matches
+}
+
+impl HomebrewDiffData {
+    /// Match added casks to removed ones that install the same app, by bundle identifier
+    /// from cask metadata and installed apps' Info.plist
+    /// Matches activation leaves installed move out of the diff, those cleanup removes stay
+    /// in it, since the installed cask really is uninstalled before the declared one installs
+    /// Needs the state for cask receipts, casks without receipts aren't matched
+    pub fn match_casks_by_bundle(
+        &mut self,
+        runner: &dyn CommandRunner,
+        current_state: &HomebrewState,
+    ) -> Result<()> {
+        let _span = span!("match_casks_by_bundle");
+        if self.casks.added.is_empty() || self.casks.removed.is_empty() {
+            return Ok(());
+        }
+
+        let mut args = vec!["info", "--json=v2", "--cask"];
+        args.extend(self.casks.added.iter().map(String::as_str));
+        let output = runner
+            .output(HomebrewState::get_brew_command(), &args)
+            .map_err(|e| Error::CommandFailed(format!("brew info failed: {}", e)))?;
+        // brew info fails entirely if any token is unknown, like a cask that was renamed away
+        if !output.status.success() {
+            return Ok(());
+        }
+        let declared = parse_cask_bundles(&output.stdout)?;
+        let installed: HashMap<String, BTreeSet<String>> = self
+            .casks
+            .removed
+            .iter()
+            .map(|removal| {
+                let bundles = installed_bundles(runner, current_state, &removal.name);
+                (removal.name.clone(), bundles)
+            })
+            .collect();
+
+        let mut matches = find_matches(&declared, &installed);
+        for matched in &mut matches {
+            matched.reinstalled = self.casks.removed.iter().any(|removal| {
+                removal.name == matched.installed && removal.effect.is_destructive()
+            });
+        }
+        let kept = |name: &str, side: fn(&CaskMatch) -> &str| {
+            !matches.iter().any(|m| !m.reinstalled && side(m) == name)
+        };
+        self.casks.added.retain(|name| kept(name, |m| &m.declared));
+        self.casks
+            .removed
+            .retain(|removal| kept(&removal.name, |m| &m.installed));
+        self.casks.matching += matches.iter().filter(|m| !m.reinstalled).count();
+        event!(matches = matches.len(), "matched casks by bundle");
+        self.cask_matches = matches;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::{ChangeEffect, Removal};
+    use crate::fixtures::FixtureRunner;
+    use crate::state::CaskReceipt;
+
+    fn state() -> HomebrewState {
+        let mut state = HomebrewState::default();
+        for (token, app) in [("vscode", "Visual Studio Code.app"), ("slack", "Slack.app")] {
+            state.cask_receipts.insert(
+                token.to_string(),
+                CaskReceipt {
+                    appdir: Some("/Applications".to_string()),
+                    apps: vec![app.to_string()],
+                    ..Default::default()
+                },
+            );
+        }
+        state
+    }
+
+    fn diff(effect: ChangeEffect) -> HomebrewDiffData {
+        let mut diff = HomebrewDiffData::default();
+        diff.casks.added = vec!["firefox".to_string(), "visual-studio-code".to_string()];
+        diff.casks.removed = vec![
+            Removal::new("slack", effect),
+            Removal::new("vscode", effect),
+        ];
+        diff
+    }
+
+    #[test]
+    fn test_parse_cask_bundles() {
+        let bundles = parse_cask_bundles(
+            br#"{"casks": [{"token": "firefox", "artifacts": [
+                {"app": ["Firefox.app"]},
+                {"uninstall": [{"quit": ["org.mozilla.firefox", "org.mozilla.crashreporter"]}]}
+            ]}]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            bundles["firefox"],
+            BTreeSet::from([
+                "Firefox.app".to_string(),
+                "org.mozilla.crashreporter".to_string(),
+                "org.mozilla.firefox".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_match_casks_by_bundle() {
+        let runner = FixtureRunner::load("cask-bundles");
+        let mut diff = diff(ChangeEffect::LeftInstalled);
+
+        diff.match_casks_by_bundle(&runner, &state()).unwrap();
+
+        assert_eq!(
+            diff.cask_matches,
+            vec![CaskMatch {
+                declared: "visual-studio-code".to_string(),
+                installed: "vscode".to_string(),
+                bundle: "com.microsoft.VSCode".to_string(),
+                reinstalled: false,
+            }]
+        );
+        assert_eq!(diff.casks.added, vec!["firefox"]);
+        assert_eq!(diff.casks.removed.len(), 1);
+        assert_eq!(diff.casks.matching, 1);
+    }
+
+    #[test]
+    fn test_cleanup_keeps_matched_removals() {
+        let runner = FixtureRunner::load("cask-bundles");
+        let mut diff = diff(ChangeEffect::WillBeRemoved);
+
+        diff.match_casks_by_bundle(&runner, &state()).unwrap();
+
+        assert_eq!(diff.cask_matches.len(), 1);
+        assert!(diff.cask_matches[0].reinstalled);
+        assert_eq!(diff.casks.added.len(), 2);
+        assert_eq!(diff.casks.removed.len(), 2);
+        assert_eq!(diff.casks.matching, 0);
+    }
+}
@@ -0,0 +1,130 @@
+//! Which Homebrew features detection can rely on, based on the installed release
+//! Older releases get compatible code paths instead of misparsed output
+
+use crate::severity::{Concern, Severity};
+use crate::version;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A brew feature that only exists from some release on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BrewFeature {
+    /// `brew outdated --json=v2`, without it upgrades aren't detected
+    OutdatedJsonV2,
+    /// `brew outdated --greedy-auto-updates`, without it self-updating casks never show as outdated
+    GreedyAutoUpdates,
+}
+
+impl BrewFeature {
+    pub const ALL: [BrewFeature; 2] = [BrewFeature::OutdatedJsonV2, BrewFeature::GreedyAutoUpdates];
+
+    /// The first Homebrew release with the feature
+    pub fn since(self) -> &'static str {
+        match self {
+            BrewFeature::OutdatedJsonV2 => "2.6.0",
+            BrewFeature::GreedyAutoUpdates => "3.3.0",
+        }
+    }
+}
+
+impl fmt::Display for BrewFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BrewFeature::OutdatedJsonV2 => "brew outdated --json=v2",
+            BrewFeature::GreedyAutoUpdates => "brew outdated --greedy-auto-updates",
+        })
+    }
+}
+
+/// The installed Homebrew release, None if it couldn't be determined
+/// Unknown releases are assumed to support everything, like a current Homebrew
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BrewCompat {
+    pub version: Option<String>,
+}
+
+impl BrewCompat {
+    /// The release from `brew --version` output, e.g. `4.3.5` from `Homebrew 4.3.5-31-g8a8e8b1`
+    pub(crate) fn parse_version(output: &str) -> Option<String> {
+        let version = output.lines().next()?.strip_prefix("Homebrew ")?.trim();
+        let release = version.split(['-', ' ']).next()?;
+        release
+            .starts_with(|c: char| c.is_ascii_digit())
+            .then(|| release.to_string())
+    }
+
+    pub fn supports(&self, feature: BrewFeature) -> bool {
+        self.version
+            .as_deref()
+            .is_none_or(|installed| version::compare(installed, feature.since()) != Ordering::Less)
+    }
+
+    pub fn unsupported(&self) -> Vec<BrewFeature> {
+        BrewFeature::ALL
+            .into_iter()
+            .filter(|feature| !self.supports(*feature))
+            .collect()
+    }
+
+    /// One warning per unsupported feature, e.g. for `DisplayOptions::concerns`
+    pub fn concerns(&self) -> Vec<Concern> {
+        let installed = self.version.as_deref().unwrap_or_default();
+        self.unsupported()
+            .into_iter()
+            .map(|feature| {
+                Concern::new(
+                    Severity::Warning,
+                    format!(
+                        "brew {} unsupported for {}, it needs {} or newer",
+                        installed,
+                        feature,
+                        feature.since()
+                    ),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            BrewCompat::parse_version("Homebrew 4.3.5-31-g8a8e8b1\n"),
+            Some("4.3.5".to_string())
+        );
+        assert_eq!(
+            BrewCompat::parse_version(
+                "Homebrew 2.5.1\nHomebrew/homebrew-core (git revision 0b5b; last commit 2020-09-20)\n"
+            ),
+            Some("2.5.1".to_string())
+        );
+        assert_eq!(
+            BrewCompat::parse_version("Homebrew >=4.0.0 (shallow or no git repository)"),
+            None
+        );
+        assert_eq!(BrewCompat::parse_version("command not found"), None);
+    }
+
+    #[test]
+    fn test_supports() {
+        let old = BrewCompat {
+            version: Some("3.2.17".to_string()),
+        };
+        assert!(old.supports(BrewFeature::OutdatedJsonV2));
+        assert_eq!(old.unsupported(), vec![BrewFeature::GreedyAutoUpdates]);
+        assert_eq!(
+            old.concerns()[0].message,
+            "brew 3.2.17 unsupported for brew outdated --greedy-auto-updates, it needs 3.3.0 or newer"
+        );
+
+        assert!(BrewCompat::default().unsupported().is_empty());
+        let current = BrewCompat {
+            version: Some("4.3.5".to_string()),
+        };
+        assert!(current.unsupported().is_empty());
+    }
+}
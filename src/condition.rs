@@ -0,0 +1,359 @@
+//! Host and user conditionals in Brewfiles, e.g. `cask "slack" if Socket.gethostname == "work-mac"`
+//! Only simple comparisons are understood, anything else counts as true like before
+
+use crate::runner::CommandRunner;
+
+/// The machine Brewfile conditionals are evaluated against
+/// Without hostnames or a user, conditions on them can't be evaluated and count as true
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BrewfileContext {
+    /// Every name the machine goes by, e.g. `work-mac.local`, `work-mac` and its LocalHostName
+    pub hostnames: Vec<String>,
+    pub user: Option<String>,
+}
+
+impl BrewfileContext {
+    /// Hostnames from `hostname` and `scutil`, the user from `$USER`
+    pub fn detect(runner: &dyn CommandRunner) -> Self {
+        let mut hostnames = Vec::new();
+        let queries: [(&str, &[&str]); 3] = [
+            ("hostname", &[]),
+            ("scutil", &["--get", "LocalHostName"]),
+            ("scutil", &["--get", "ComputerName"]),
+        ];
+        for (program, args) in queries {
+            let Ok(output) = runner.output(program, args) else {
+                continue;
+            };
+            let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if !output.status.success() || name.is_empty() {
+                continue;
+            }
+            // Socket.gethostname may or may not include the domain
+            if let Some((short, _)) = name.split_once('.') {
+                hostnames.push(short.to_string());
+            }
+            hostnames.push(name);
+        }
+        hostnames.sort_unstable();
+        hostnames.dedup();
+
+        Self {
+            hostnames,
+            user: std::env::var("USER").ok().filter(|user| !user.is_empty()),
+        }
+    }
+
+    fn is_host(&self, name: &str) -> Option<bool> {
+        if self.hostnames.is_empty() {
+            return None;
+        }
+        Some(
+            self.hostnames
+                .iter()
+                .any(|host| host.eq_ignore_ascii_case(name)),
+        )
+    }
+
+    fn is_user(&self, name: &str) -> Option<bool> {
+        Some(self.user.as_deref()? == name)
+    }
+}
+
+/// What a conditional compares against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Subject {
+    Host,
+    User,
+}
+
+impl Subject {
+    /// Recognize Ruby expressions for the hostname or user, e.g. `` `hostname`.chomp ``
+    fn parse(expr: &str) -> Option<Self> {
+        const HOST: [&str; 4] = ["hostname", "HOSTNAME", "LocalHostName", "ComputerName"];
+        const USER: [&str; 4] = ["\"USER\"", "'USER'", "whoami", "getlogin"];
+        if HOST.iter().any(|marker| expr.contains(marker)) {
+            Some(Subject::Host)
+        } else if USER.iter().any(|marker| expr.contains(marker)) || expr.contains("LOGNAME") {
+            Some(Subject::User)
+        } else {
+            None
+        }
+    }
+
+    fn matches(self, value: &str, context: &BrewfileContext) -> Option<bool> {
+        match self {
+            Subject::Host => context.is_host(value),
+            Subject::User => context.is_user(value),
+        }
+    }
+}
+
+/// Evaluate a condition, None if it isn't understood or the context lacks what it checks
+pub(crate) fn evaluate(condition: &str, context: &BrewfileContext) -> Option<bool> {
+    let condition = condition.trim();
+    let condition = condition.strip_suffix(" then").unwrap_or(condition).trim();
+    if let Some(inner) = strip_parens(condition) {
+        return evaluate(inner, context);
+    }
+
+    if let Some((left, right)) = split_top_level(condition, "||") {
+        return match (evaluate(left, context), evaluate(right, context)) {
+            (Some(true), _) | (_, Some(true)) => Some(true),
+            (Some(false), Some(false)) => Some(false),
+            _ => None,
+        };
+    }
+    if let Some((left, right)) = split_top_level(condition, "&&") {
+        return match (evaluate(left, context), evaluate(right, context)) {
+            (Some(false), _) | (_, Some(false)) => Some(false),
+            (Some(true), Some(true)) => Some(true),
+            _ => None,
+        };
+    }
+
+    for (operator, negate) in [("==", false), ("!=", true)] {
+        if let Some((left, right)) = split_top_level(condition, operator) {
+            let (subject, value) = match (string_literal(left), string_literal(right)) {
+                (None, Some(value)) => (Subject::parse(left)?, value),
+                (Some(value), None) => (Subject::parse(right)?, value),
+                _ => return None,
+            };
+            return subject.matches(value, context).map(|m| m != negate);
+        }
+    }
+
+    // ["work-mac", "studio"].include?(hostname) or %w[work-mac studio].include?(hostname)
+    let (list, argument) = condition.split_once(".include?")?;
+    let subject = Subject::parse(argument)?;
+    let mut any_unknown = false;
+    for value in list_literal(list)? {
+        match subject.matches(&value, context) {
+            Some(true) => return Some(true),
+            Some(false) => {}
+            None => any_unknown = true,
+        }
+    }
+    (!any_unknown).then_some(false)
+}
+
+/// The inside of a condition wrapped in a single pair of parentheses, e.g. not `(a) || (b)`
+fn strip_parens(condition: &str) -> Option<&str> {
+    let inner = condition.strip_prefix('(')?.strip_suffix(')')?;
+    let mut depth = 0;
+    for c in inner.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return None,
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    Some(inner)
+}
+
+/// Split at the first occurrence of the operator outside of quotes
+fn split_top_level<'a>(condition: &'a str, operator: &str) -> Option<(&'a str, &'a str)> {
+    let mut quote = None;
+    for (idx, c) in condition.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, _) if condition[idx..].starts_with(operator) => {
+                return Some((&condition[..idx], &condition[idx + operator.len()..]));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn string_literal(expr: &str) -> Option<&str> {
+    let expr = expr.trim();
+    expr.strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .or_else(|| {
+            expr.strip_prefix('\'')
+                .and_then(|rest| rest.strip_suffix('\''))
+        })
+}
+
+fn list_literal(expr: &str) -> Option<Vec<String>> {
+    let expr = expr.trim();
+    if let Some(words) = expr
+        .strip_prefix("%w[")
+        .or_else(|| expr.strip_prefix("%w("))
+    {
+        let words = words.strip_suffix([']', ')'])?;
+        return Some(words.split_whitespace().map(str::to_string).collect());
+    }
+    let items = expr.strip_prefix('[')?.strip_suffix(']')?;
+    items
+        .split(',')
+        .map(|item| string_literal(item).map(str::to_string))
+        .collect()
+}
+
+/// Split a trailing `if` or `unless` modifier off a line, e.g. `brew "x" if cond`
+/// Returns the entry and the condition, which is negated for `unless`
+pub(crate) fn split_modifier(line: &str) -> (&str, Option<(&str, bool)>) {
+    for (keyword, negate) in [(" if ", false), (" unless ", true)] {
+        if let Some((entry, condition)) = split_top_level(line, keyword) {
+            return (entry.trim_end(), Some((condition, negate)));
+        }
+    }
+    (line, None)
+}
+
+/// Tracks which lines are active in nested `if`/`unless`/`elsif`/`else` blocks
+#[derive(Debug, Default)]
+pub(crate) struct Blocks {
+    frames: Vec<Frame>,
+}
+
+#[derive(Debug)]
+struct Frame {
+    active: bool,
+    /// Whether a branch was taken, None if a condition couldn't be evaluated
+    taken: Option<bool>,
+}
+
+impl Blocks {
+    /// Whether entries at the current position apply to this machine
+    pub(crate) fn active(&self) -> bool {
+        self.frames.iter().all(|frame| frame.active)
+    }
+
+    /// How many blocks the current position is nested in
+    pub(crate) fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Consume a control flow line, returns false for anything else
+    pub(crate) fn control(&mut self, line: &str, context: &BrewfileContext) -> bool {
+        let keyword = line.split_whitespace().next().unwrap_or_default();
+        let condition = line[keyword.len()..].trim();
+        match keyword {
+            "if" | "unless" => {
+                let value = evaluate(condition, context).map(|v| v != (keyword == "unless"));
+                self.frames.push(Frame {
+                    active: value.unwrap_or(true),
+                    taken: value,
+                });
+            }
+            "elsif" => {
+                if let Some(frame) = self.frames.last_mut() {
+                    match frame.taken {
+                        Some(true) => frame.active = false,
+                        Some(false) => {
+                            let value = evaluate(condition, context);
+                            frame.active = value.unwrap_or(true);
+                            frame.taken = value;
+                        }
+                        None => frame.active = true,
+                    }
+                }
+            }
+            "else" => {
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.active = frame.taken.is_none_or(|taken| !taken);
+                }
+            }
+            "end" => {
+                self.frames.pop();
+            }
+            // Other blocks have an `end` too, they don't affect which entries apply
+            "case" | "begin" | "def" | "while" | "until" | "class" | "module" => {
+                self.frames.push(Frame {
+                    active: true,
+                    taken: None,
+                });
+            }
+            _ if line.ends_with(" do") || line.contains(" do |") => {
+                self.frames.push(Frame {
+                    active: true,
+                    taken: None,
+                });
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> BrewfileContext {
+        BrewfileContext {
+            hostnames: vec!["work-mac".to_string(), "work-mac.local".to_string()],
+            user: Some("kilian".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let context = context();
+
+        assert_eq!(
+            evaluate("`hostname`.chomp == \"work-mac\"", &context),
+            Some(true)
+        );
+        assert_eq!(
+            evaluate("Socket.gethostname == 'WORK-MAC.local'", &context),
+            Some(true)
+        );
+        assert_eq!(
+            evaluate("ENV[\"USER\"] != \"kilian\"", &context),
+            Some(false)
+        );
+        assert_eq!(
+            evaluate("%w[studio work-mac].include?(Socket.gethostname)", &context),
+            Some(true)
+        );
+        assert_eq!(
+            evaluate("[\"studio\"].include?(ENV.fetch(\"USER\"))", &context),
+            Some(false)
+        );
+        assert_eq!(
+            evaluate(
+                "(ENV['USER'] == 'kilian') && `hostname`.chomp == 'studio'",
+                &context
+            ),
+            Some(false)
+        );
+        assert_eq!(
+            evaluate(
+                "(ENV['USER'] == 'x') || (ENV['USER'] == 'kilian')",
+                &context
+            ),
+            Some(true)
+        );
+        assert_eq!(evaluate("File.exist?(\"/opt/x\")", &context), None);
+        assert_eq!(
+            evaluate("ENV[\"USER\"] == \"kilian\"", &BrewfileContext::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_split_modifier() {
+        assert_eq!(
+            split_modifier("brew \"x\", link: false if ENV[\"USER\"] == \"a b\""),
+            (
+                "brew \"x\", link: false",
+                Some(("ENV[\"USER\"] == \"a b\"", false))
+            )
+        );
+        assert_eq!(
+            split_modifier("cask \"if this\""),
+            ("cask \"if this\"", None)
+        );
+        assert_eq!(
+            split_modifier("cask \"x\" unless cond"),
+            ("cask \"x\"", Some(("cond", true)))
+        );
+    }
+}
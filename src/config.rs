@@ -0,0 +1,48 @@
+//! The TOML config file annotations and acknowledgements are read from, each setting only
+//! deserializes the tables it owns
+
+use crate::error::{Error, Result};
+use serde::de::DeserializeOwned;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// `~/.config/brewdiff/config.toml`, None without a home directory
+pub fn default_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(Path::new(&home).join(".config/brewdiff/config.toml"))
+}
+
+/// The contents of a config file, a missing file is empty so every setting keeps its default
+pub(crate) fn read(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Deserialize a setting from config file contents, errors name the line they're on
+pub(crate) fn parse<T: DeserializeOwned>(content: &str) -> Result<T> {
+    toml::from_str(content).map_err(|e| {
+        let line = e.span().map_or(1, |span| line_of(content, span.start));
+        Error::InvalidConfig(format!("line {}: {}", line, e.message()))
+    })
+}
+
+/// The 1-based line a byte offset into the contents is on
+pub(crate) fn line_of(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_errors_name_the_line() {
+        let error = parse::<HashMap<String, String>>("a = \"x\"\nb = 1\n").unwrap_err();
+        assert!(error.to_string().starts_with("Invalid config: line 2: "));
+        assert_eq!(line_of("a\nb\n", 2), 2);
+    }
+}
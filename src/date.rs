@@ -0,0 +1,88 @@
+//! Calendar dates as `YYYY-MM-DD`, without a date library
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Today's date in the local timezone as `YYYY-MM-DD`, e.g. to compare expiry dates against
+pub fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64);
+    date_from_days((secs + utc_offset(secs)).div_euclid(86_400))
+}
+
+/// Seconds the local timezone is ahead of UTC at a time, 0 where it can't be determined
+fn utc_offset(secs: i64) -> i64 {
+    #[cfg(unix)]
+    {
+        let time = secs as libc::time_t;
+        // SAFETY: tm is plain data, localtime_r only writes through the pointers during the call
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        if !unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+            return tm.tm_gmtoff as i64;
+        }
+    }
+    0
+}
+
+/// The civil date a number of days after the Unix epoch, see
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub(crate) fn date_from_days(days: i64) -> String {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Whether a value is an existing `YYYY-MM-DD` date, so `2026-13-01` or `2026-02-30` aren't
+pub(crate) fn is_date(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    let shaped = bytes.len() == 10
+        && bytes.iter().enumerate().all(|(i, b)| match i {
+            4 | 7 => *b == b'-',
+            _ => b.is_ascii_digit(),
+        });
+    if !shaped {
+        return false;
+    }
+    let number = |range: std::ops::Range<usize>| value[range].parse::<u32>().unwrap_or_default();
+    let (year, month, day) = (number(0..4), number(5..7), number(8..10));
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return false,
+    };
+    (1..=days).contains(&day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date_from_days() {
+        assert_eq!(date_from_days(0), "1970-01-01");
+        assert_eq!(date_from_days(19_782), "2024-02-29");
+        assert_eq!(date_from_days(20_742), "2026-10-16");
+    }
+
+    #[test]
+    fn test_is_date() {
+        assert!(is_date("2026-12-31"));
+        assert!(is_date("2024-02-29"));
+        assert!(!is_date("2026-13-01"));
+        assert!(!is_date("2026-00-10"));
+        assert!(!is_date("2025-02-29"));
+        assert!(!is_date("2026-04-31"));
+        assert!(!is_date("soon"));
+        assert!(is_date(&today()));
+    }
+}
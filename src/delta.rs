@@ -0,0 +1,147 @@
+//! Brewfile containing only what the diff adds, for applying it without a full activation
+
+use crate::diff::HomebrewDiffData;
+use crate::error::Result;
+use crate::intent::HomebrewIntent;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Write a Brewfile with the added taps, formulae, casks and App Store apps
+/// Options are taken from the intent, so `brew bundle --file=<delta>` installs them as activation would
+/// Returns the number of entries written, the Brewfile only has a header comment if there are none
+pub fn write_additions_brewfile<W: Write>(
+    writer: &mut W,
+    diff_data: &HomebrewDiffData,
+    nix_intent: &HomebrewIntent,
+) -> Result<usize> {
+    writeln!(
+        writer,
+        "# Additions only, apply with `brew bundle --file=<this file>`"
+    )?;
+    let mut entries = 0;
+
+    for tap in &diff_data.taps.added {
+        match nix_intent.tap_remotes.get(tap) {
+            Some(remote) => writeln!(writer, "tap \"{}\", \"{}\"", tap, remote)?,
+            None => writeln!(writer, "tap \"{}\"", tap)?,
+        }
+        entries += 1;
+    }
+
+    // Global cask arguments apply to the added casks just like during activation
+    if !diff_data.casks.added.is_empty() && !nix_intent.cask_args.is_empty() {
+        writeln!(writer, "cask_args {}", option_list(&nix_intent.cask_args))?;
+    }
+
+    for brew in &diff_data.brews.added {
+        write!(writer, "brew \"{}\"", brew)?;
+        if let Some(options) = nix_intent.brew_options.get(brew) {
+            if !options.args.is_empty() {
+                let args: Vec<String> = options
+                    .args
+                    .iter()
+                    .map(|arg| format!("\"{}\"", arg))
+                    .collect();
+                write!(writer, ", args: [{}]", args.join(", "))?;
+            }
+            if let Some(link) = options.link {
+                write!(writer, ", link: {}", link)?;
+            }
+        }
+        writeln!(writer)?;
+        entries += 1;
+    }
+
+    for cask in &diff_data.casks.added {
+        write!(writer, "cask \"{}\"", cask)?;
+        if let Some(options) = nix_intent.cask_options.get(cask) {
+            if !options.args.is_empty() {
+                write!(writer, ", args: {{ {} }}", option_list(&options.args))?;
+            }
+            if options.greedy {
+                write!(writer, ", greedy: true")?;
+            }
+        }
+        writeln!(writer)?;
+        entries += 1;
+    }
+
+    for app in &diff_data.mas_apps.added {
+        // Entries are stored as "App Name (id)"
+        if let Some((name, id)) = app.strip_suffix(')').and_then(|app| app.rsplit_once(" (")) {
+            writeln!(writer, "mas \"{}\", id: {}", name, id)?;
+            entries += 1;
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Format raw option values back into a `key: value` list
+fn option_list(options: &BTreeMap<String, String>) -> String {
+    options
+        .iter()
+        .map(|(key, value)| format!("{}: {}", key, value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::HomebrewState;
+
+    #[test]
+    fn test_additions_brewfile_round_trips() {
+        let brewfile = r#"
+tap "hashicorp/tap"
+tap "acme/tools", "https://git.acme.dev/tools.git"
+cask_args appdir: "~/Applications"
+brew "git"
+brew "ffmpeg", args: ["with-fdk-aac"], link: false
+cask "firefox", args: { appdir: "/Applications" }, greedy: true
+cask "slack"
+mas "Xcode", id: 497799835
+"#;
+        let intent = HomebrewIntent::from_brewfile(brewfile);
+        let state = HomebrewState::from_bundle_dump(
+            "tap \"hashicorp/tap\"\nbrew \"git\"\ncask \"slack\"\nbrew \"wget\"\n",
+        );
+        let diff = HomebrewDiffData::compute(&state, &intent);
+
+        let mut output = String::new();
+        let entries = write_additions_brewfile(&mut output, &diff, &intent).unwrap();
+
+        assert_eq!(entries, 4);
+        assert!(output.contains("tap \"acme/tools\", \"https://git.acme.dev/tools.git\"\n"));
+        assert!(output.contains("cask_args appdir: \"~/Applications\"\n"));
+        assert!(output.contains("brew \"ffmpeg\", args: [\"with-fdk-aac\"], link: false\n"));
+        assert!(output
+            .contains("cask \"firefox\", args: { appdir: \"/Applications\" }, greedy: true\n"));
+        assert!(output.contains("mas \"Xcode\", id: 497799835\n"));
+        assert!(!output.contains("brew \"git\""));
+        assert!(!output.contains("wget"));
+
+        // Parsing the delta yields exactly the additions, with their options
+        let delta = HomebrewIntent::from_brewfile(&output);
+        assert_eq!(delta.brews, ["ffmpeg".to_string()].into());
+        assert_eq!(delta.brew_options, intent.brew_options);
+        assert_eq!(delta.casks, ["firefox".to_string()].into());
+        assert_eq!(delta.cask_options, intent.cask_options);
+        assert_eq!(delta.mas_apps, intent.mas_apps);
+    }
+
+    #[test]
+    fn test_additions_brewfile_without_additions() {
+        let mut output = String::new();
+        let entries = write_additions_brewfile(
+            &mut output,
+            &HomebrewDiffData::default(),
+            &HomebrewIntent::default(),
+        )
+        .unwrap();
+
+        assert_eq!(entries, 0);
+        assert_eq!(output.lines().count(), 1);
+    }
+}
@@ -1,6 +1,22 @@
-use crate::intent::HomebrewIntent;
-use crate::state::HomebrewState;
+use crate::allowlist::AcknowledgedDrift;
+use crate::cask_match::CaskMatch;
+use crate::duplicates::{self, Duplicate};
+use crate::error::{Error, Result};
+use crate::intent::{CleanupMode, HomebrewIntent, IntentFlags};
+use crate::metadata::{self, FormulaMetadata, MetadataOptions, TapInfo};
+use crate::overlap::NixOverlap;
+use crate::runner::CommandRunner;
+use crate::security::AffectedFormula;
+use crate::severity::Severity;
+use crate::state::{DetectionSource, FormulaReceipt, HomebrewState};
+use crate::trace::{event, span};
+use crate::version::{self, Bump};
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::Arc;
+use unicode_normalization::UnicodeNormalization;
 
 #[derive(Debug, Clone, Default)]
 pub struct HomebrewDiffData {
@@ -8,115 +24,1279 @@ pub struct HomebrewDiffData {
     pub casks: PackageDiff,
     pub taps: SetDiff,
     pub mas_apps: SetDiff,
+    /// Installed formulae whose link state differs from their declared `link:` option
+    pub link_mismatches: Vec<LinkMismatch>,
+    /// Installed formulae built with different options than their declared `args:`
+    pub option_mismatches: Vec<OptionMismatch>,
+    /// Installed casks whose app lives in a different directory than the declared `appdir`
+    pub appdir_mismatches: Vec<AppdirMismatch>,
+    /// Installed taps cloned from a different URL than declared, only populated by `load_tap_info`
+    pub tap_remote_mismatches: Vec<RemoteMismatch>,
+    /// Pinned formulae in the diff: outdated ones activation doesn't upgrade and
+    /// removed ones someone meant to keep
+    pub pinned_brews: HashSet<String>,
+    /// App Store apps whose id matches but whose name differs between intent and state
+    pub mas_name_mismatches: Vec<NameMismatch>,
+    /// Declared casks installed under another token, only populated by `match_casks_by_bundle`
+    pub cask_matches: Vec<CaskMatch>,
+    /// Undeclared formulae that a declared version of the same formula replaces
+    pub version_switches: Vec<VersionSwitch>,
+    /// Installed or declared entries of different categories that look like the same app
+    pub duplicates: Vec<Duplicate>,
+    /// Taps declared formulae and casks come from that are neither declared nor installed
+    pub missing_taps: Vec<MissingTap>,
+    /// Installed taps that no installed or declared package comes from
+    pub unused_taps: Vec<UnusedTap>,
+    /// Entries moved out of the diff by an allowlist, only populated by `acknowledged`
+    pub acknowledged: Vec<AcknowledgedDrift>,
+    /// Entries an allowlist acknowledged until a day that has passed, so they count again
+    pub expired_acknowledgements: Vec<AcknowledgedDrift>,
+    /// Formulae the nix profile provides as well, only populated by `load_nix_overlaps`
+    pub nix_overlaps: Vec<NixOverlap>,
+    /// Added formulae by the nixpkgs package of the same name,
+    /// only populated by `load_nixpkgs_equivalents`
+    pub nixpkgs_equivalents: HashMap<String, String>,
+    /// Added and outdated formulae with known vulnerabilities, only populated by `load_advisories`
+    pub security: Vec<AffectedFormula>,
+    /// How activation invokes `brew bundle`, e.g. whether it cleans up or upgrades
+    pub flags: IntentFlags,
+    /// Versions a `Brewfile.lock.json` resolved added formulae and casks to
+    pub locked_versions: HashMap<String, String>,
+    /// Install receipts of installed formulae in the diff
+    pub formula_receipts: HashMap<String, FormulaReceipt>,
+    /// Metadata for formulae in the diff, only populated by `load_metadata`
+    pub formula_metadata: HashMap<String, FormulaMetadata>,
+    /// Dependencies each added formula would install along with it,
+    /// only populated by `load_dependency_counts`
+    pub dependency_counts: HashMap<String, usize>,
+    /// Installed taps as reported by brew, only populated by `load_tap_info`
+    pub tap_info: HashMap<String, TapInfo>,
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct PackageDiff {
-    pub added: Vec<String>,
-    pub removed: Vec<String>,
+/// A computed diff along with the state and intent it was computed from
+/// Shared so callers can inspect them after rendering without cloning or detecting again
+#[derive(Debug, Clone)]
+pub struct SharedDiff {
+    pub diff: HomebrewDiffData,
+    pub state: Arc<HomebrewState>,
+    pub intent: Arc<HomebrewIntent>,
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct SetDiff {
-    pub added: Vec<String>,
-    pub removed: Vec<String>,
+impl SharedDiff {
+    pub fn compute(state: Arc<HomebrewState>, intent: Arc<HomebrewIntent>) -> Self {
+        Self {
+            diff: HomebrewDiffData::compute(&state, &intent),
+            state,
+            intent,
+        }
+    }
+}
+
+/// Added, removed and upgraded entries of one category
+/// Generic over the added item type so new categories and richer items share the diff logic
+#[derive(Debug, Clone)]
+pub struct Diff<T: ItemKey = String> {
+    pub added: Vec<T>,
+    pub removed: Vec<Removal>,
+    /// Always empty for categories without versions, e.g. taps
+    pub upgraded: Vec<VersionChange>,
+    /// Installed entries that are declared, not narrowed by name filters
+    pub matching: usize,
+}
+
+/// Formulae and casks, which have versions and can be upgraded
+pub type PackageDiff = Diff<String>;
+
+/// Taps and App Store apps, which are only added or removed
+pub type SetDiff = Diff<String>;
+
+/// An item that is matched between state and intent by its key, e.g. its name
+pub trait ItemKey {
+    fn key(&self) -> &str;
+}
+
+impl ItemKey for String {
+    fn key(&self) -> &str {
+        self
+    }
+}
+
+impl ItemKey for Removal {
+    fn key(&self) -> &str {
+        &self.name
+    }
+}
+
+impl ItemKey for VersionChange {
+    fn key(&self) -> &str {
+        &self.name
+    }
+}
+
+/// An installed formula that is linked while declared with `link: false`, or vice versa
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkMismatch {
+    pub name: String,
+    pub declared_linked: bool,
+    pub linked: bool,
+}
+
+/// An installed formula whose build options differ from its declared `args:`
+/// Options are normalized to their `--with-foo` form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionMismatch {
+    pub name: String,
+    pub declared: Vec<String>,
+    pub installed: Vec<String>,
+}
+
+/// An installed cask whose app directory differs from its declared `appdir`
+/// brew bundle doesn't move installed apps, so this persists until reinstalled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppdirMismatch {
+    pub name: String,
+    pub declared: String,
+    pub installed: String,
+}
+
+/// An installed tap whose remote differs from the URL declared in the Brewfile
+/// brew bundle doesn't re-tap existing taps, so packages keep coming from `remote`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteMismatch {
+    pub name: String,
+    pub declared: String,
+    /// None for taps installed from the API without a git clone
+    pub remote: Option<String>,
+}
+
+/// A tap that declared packages need, e.g. `hashicorp/tap` for `hashicorp/tap/terraform`,
+/// that isn't declared or installed, so installing them fails during activation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingTap {
+    pub name: String,
+    /// Fully qualified names of the declared packages from the tap
+    pub packages: Vec<String>,
+}
+
+/// Taps that are never unused: the official package taps are always available, the others
+/// provide brew commands rather than packages, e.g. `brew bundle` itself
+const ALWAYS_USED_TAPS: [&str; 7] = [
+    "homebrew/core",
+    "homebrew/cask",
+    "homebrew/bundle",
+    "homebrew/services",
+    "homebrew/command-not-found",
+    "homebrew/autoupdate",
+    "homebrew/aliases",
+];
+
+/// An installed tap that provides no installed or declared package, so it can be removed
+/// Unlike undeclared taps, these may be declared, e.g. a tap kept long after its last package
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnusedTap {
+    pub name: String,
+    pub declared: bool,
+}
+
+/// An App Store app that is installed under a different name than declared
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameMismatch {
+    pub id: String,
+    pub declared: String,
+    pub installed: String,
+}
+
+/// An undeclared formula superseded by a declared version of it, e.g. `python@3.11`
+/// installed while `python@3.12` is declared, activation removes one and installs the other
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionSwitch {
+    /// The formula without version, e.g. `python`
+    pub family: String,
+    pub from: String,
+    /// The newest declared version, which may already be installed
+    pub to: String,
+}
+
+/// The formula a versioned formula is a version of, e.g. `python` for `python@3.12`
+/// Names whose `@` isn't followed by a version, and unversioned names, are their own family
+pub fn version_family(name: &str) -> &str {
+    match name.rsplit_once('@') {
+        Some((family, version))
+            if !family.is_empty() && version.starts_with(|c: char| c.is_ascii_digit()) =>
+        {
+            family
+        }
+        _ => name,
+    }
+}
+
+/// An installed entry that isn't declared in the intent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Removal {
+    pub name: String,
+    pub effect: ChangeEffect,
+    /// Installed version, None for taps and App Store apps
+    pub version: Option<String>,
+    /// How much attention the removal needs, e.g. a warning for pinned formulae
+    pub severity: Severity,
+}
+
+impl Removal {
+    pub fn new(name: impl Into<String>, effect: ChangeEffect) -> Self {
+        Self {
+            name: name.into(),
+            effect,
+            version: None,
+            severity: effect.severity(),
+        }
+    }
+
+    /// Attach the installed version
+    pub fn with_version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+}
+
+/// Removals that activation actually performs
+pub(crate) fn destructive(removed: &[Removal]) -> impl Iterator<Item = &Removal> {
+    removed
+        .iter()
+        .filter(|removal| removal.effect.is_destructive())
+}
+
+/// What activation actually does with an undeclared entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeEffect {
+    /// Uninstalled by `brew bundle --cleanup`
+    WillBeRemoved,
+    /// Uninstalled along with its app data by `brew bundle --cleanup --zap`
+    WillBeZapped,
+    /// Not managed by nix-darwin, activation leaves it installed
+    LeftInstalled,
+}
+
+impl ChangeEffect {
+    /// Derive the effect of the cleanup mode on an undeclared entry
+    /// Only casks can be zapped, everything else is uninstalled as usual
+    pub fn from_cleanup(cleanup: CleanupMode, zappable: bool) -> Self {
+        match cleanup {
+            CleanupMode::None => ChangeEffect::LeftInstalled,
+            CleanupMode::Zap if zappable => ChangeEffect::WillBeZapped,
+            CleanupMode::Uninstall | CleanupMode::Zap => ChangeEffect::WillBeRemoved,
+        }
+    }
+
+    /// Check if activation uninstalls the entry
+    pub fn is_destructive(&self) -> bool {
+        !matches!(self, ChangeEffect::LeftInstalled)
+    }
+
+    /// Zapping deletes app data that can't be reinstalled, plain removals can be undone
+    pub fn severity(self) -> Severity {
+        match self {
+            ChangeEffect::WillBeZapped => Severity::Warning,
+            ChangeEffect::WillBeRemoved | ChangeEffect::LeftInstalled => Severity::Info,
+        }
+    }
+}
+
+/// An installed, intended package with a newer version available
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionChange {
+    pub name: String,
+    pub installed: String,
+    pub available: String,
+    /// False when activation runs with `--no-upgrade` and leaves the package as is
+    pub applied: bool,
+    /// Cask updates itself, brew's version is likely stale and only greedy casks are upgraded
+    pub auto_updates: bool,
+}
+
+impl VersionChange {
+    /// How big of a jump the upgrade is, None if the versions are equivalent
+    pub fn bump(&self) -> Option<Bump> {
+        version::classify(&self.installed, &self.available)
+    }
+}
+
+impl<T: ItemKey> Default for Diff<T> {
+    fn default() -> Self {
+        Self {
+            added: Vec::new(),
+            removed: Vec::new(),
+            upgraded: Vec::new(),
+            matching: 0,
+        }
+    }
+}
+
+impl<T: ItemKey> Diff<T> {
+    /// Upgrades that activation will actually perform
+    pub fn upgrades(&self) -> impl Iterator<Item = &VersionChange> {
+        self.upgraded.iter().filter(|change| change.applied)
+    }
+
+    /// Check if nothing was added, removed or upgraded
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.upgraded.is_empty()
+    }
+
+    /// Sort all entries naturally by key for consistent output
+    pub fn sort(&mut self) {
+        self.added.sort_by(|a, b| natural_cmp(a.key(), b.key()));
+        self.removed.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+        self.upgraded.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+    }
+}
+
+impl<T: ItemKey + Clone> Diff<T> {
+    /// Diff intended items against the keys of installed items, without upgrades
+    pub fn between<'a>(
+        installed: impl IntoIterator<Item = &'a str>,
+        intended: impl IntoIterator<Item = &'a T>,
+        removal_effect: ChangeEffect,
+    ) -> Self
+    where
+        T: 'a,
+    {
+        let installed: HashSet<&str> = installed.into_iter().collect();
+        let intended: Vec<&T> = intended.into_iter().collect();
+        let wanted: HashSet<&str> = intended.iter().map(|item| item.key()).collect();
+        let matching = installed.iter().filter(|key| wanted.contains(*key)).count();
+
+        let mut diff = Self {
+            added: intended
+                .into_iter()
+                .filter(|item| !installed.contains(item.key()))
+                .cloned()
+                .collect(),
+            removed: installed
+                .into_iter()
+                .filter(|key| !wanted.contains(key))
+                .map(|key| Removal::new(key, removal_effect))
+                .collect(),
+            upgraded: Vec::new(),
+            matching,
+        };
+        diff.sort();
+        diff
+    }
 }
 
 impl HomebrewDiffData {
     pub fn compute(current_state: &HomebrewState, nix_intent: &HomebrewIntent) -> Self {
+        let _span = span!("compute_diff");
+        let cleanup = nix_intent.flags.cleanup;
+
+        Self {
+            brews: Self::compute_package_diff(
+                &current_state.installed_brews,
+                &Self::with_locked_upgrades(
+                    &current_state.installed_brews,
+                    &current_state.outdated_brews,
+                    &nix_intent.locked.brews,
+                ),
+                &nix_intent.brews,
+                !nix_intent.flags.no_upgrade,
+                ChangeEffect::from_cleanup(cleanup, false),
+            ),
+            casks: Self::compute_package_diff(
+                &current_state.installed_casks,
+                &Self::with_locked_upgrades(
+                    &current_state.installed_casks,
+                    &current_state.outdated_casks,
+                    &nix_intent.locked.casks,
+                ),
+                &nix_intent.casks,
+                !nix_intent.flags.no_upgrade,
+                ChangeEffect::from_cleanup(cleanup, true),
+            ),
+            taps: Self::compute_set_diff(
+                &current_state.installed_taps,
+                &nix_intent.taps,
+                ChangeEffect::from_cleanup(cleanup, false),
+            ),
+            ..Self::compute_details(current_state, nix_intent)
+        }
+        .finish(current_state, nix_intent)
+    }
+
+    /// Like `compute`, but consumes the inputs and moves names into the result instead of cloning them
+    /// Use this on hot paths that discard the state and intent afterwards
+    pub fn compute_owned(mut current_state: HomebrewState, mut nix_intent: HomebrewIntent) -> Self {
+        let _span = span!("compute_diff_owned");
+        let cleanup = nix_intent.flags.cleanup;
+        let upgrade = !nix_intent.flags.no_upgrade;
+        // The details compare installed and declared names, so they go before the names are moved
+        let details = Self::compute_details(&current_state, &nix_intent);
+        let locked_brews = Self::locked_upgrades(
+            &current_state.installed_brews,
+            &current_state.outdated_brews,
+            &nix_intent.locked.brews,
+        );
+        current_state.outdated_brews.extend(locked_brews);
+        let locked_casks = Self::locked_upgrades(
+            &current_state.installed_casks,
+            &current_state.outdated_casks,
+            &nix_intent.locked.casks,
+        );
+        current_state.outdated_casks.extend(locked_casks);
+
         Self {
-            brews: Self::compute_package_diff(&current_state.installed_brews, &nix_intent.brews),
-            casks: Self::compute_package_diff(&current_state.installed_casks, &nix_intent.casks),
-            taps: Self::compute_set_diff(&current_state.installed_taps, &nix_intent.taps),
-            // Note: nix-darwin only installs missing MAS apps, it doesn't uninstall extras
-            // So we only show additions, not removals
-            mas_apps: Self::compute_mas_additions_only(
-                &current_state.installed_mas_apps,
-                &nix_intent.mas_apps,
+            brews: Self::compute_package_diff_owned(
+                mem::take(&mut current_state.installed_brews),
+                mem::take(&mut current_state.outdated_brews),
+                mem::take(&mut nix_intent.brews),
+                upgrade,
+                ChangeEffect::from_cleanup(cleanup, false),
+            ),
+            casks: Self::compute_package_diff_owned(
+                mem::take(&mut current_state.installed_casks),
+                mem::take(&mut current_state.outdated_casks),
+                mem::take(&mut nix_intent.casks),
+                upgrade,
+                ChangeEffect::from_cleanup(cleanup, true),
+            ),
+            taps: Self::compute_set_diff_owned(
+                mem::take(&mut current_state.installed_taps),
+                mem::take(&mut nix_intent.taps),
+                ChangeEffect::from_cleanup(cleanup, false),
             ),
+            ..details
         }
+        .finish(&current_state, &nix_intent)
+    }
+
+    /// Everything but the package and tap diffs, which `compute` and `compute_owned` build
+    /// from borrowed and owned names respectively
+    fn compute_details(current_state: &HomebrewState, nix_intent: &HomebrewIntent) -> Self {
+        let (mas_apps, mas_name_mismatches) =
+            Self::compute_mas_diff(&current_state.installed_mas_apps, &nix_intent.mas_apps);
+
+        Self {
+            link_mismatches: Self::compute_link_mismatches(current_state, nix_intent),
+            option_mismatches: Self::compute_option_mismatches(current_state, nix_intent),
+            appdir_mismatches: Self::compute_appdir_mismatches(current_state, nix_intent),
+            version_switches: Self::compute_version_switches(current_state, nix_intent),
+            duplicates: duplicates::find_duplicates(current_state, nix_intent),
+            missing_taps: Self::compute_missing_taps(current_state, nix_intent),
+            unused_taps: Self::compute_unused_taps(current_state, nix_intent),
+            mas_apps,
+            mas_name_mismatches,
+            flags: nix_intent.flags.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Annotate the computed diff and keep receipts for installed formulae that show up in it
+    fn finish(mut self, current_state: &HomebrewState, nix_intent: &HomebrewIntent) -> Self {
+        self.annotate(current_state, nix_intent);
+
+        let in_diff = self.diffed_formulae();
+        self.formula_receipts = current_state
+            .formula_receipts
+            .iter()
+            .filter(|(name, _)| in_diff.contains(name.as_str()))
+            .map(|(name, receipt)| (name.clone(), receipt.clone()))
+            .collect();
+
+        event!(changes = self.total_changes(), "computed diff");
+        self
+    }
+
+    /// Fill in details from the state that depend on the computed package and tap diffs
+    fn annotate(&mut self, current_state: &HomebrewState, nix_intent: &HomebrewIntent) {
+        // brew bundle neither installs nor upgrades skipped entries, they're still declared
+        let skip = &nix_intent.flags.skip;
+        if !skip.is_empty() {
+            self.brews.added.retain(|name| !skip.brews.contains(name));
+            self.casks.added.retain(|name| !skip.casks.contains(name));
+            self.taps.added.retain(|name| !skip.taps.contains(name));
+            self.mas_apps.added.retain(|app| !skip.skips_mas_app(app));
+            for change in &mut self.brews.upgraded {
+                change.applied &= !skip.brews.contains(&change.name);
+            }
+            for change in &mut self.casks.upgraded {
+                change.applied &= !skip.casks.contains(&change.name);
+            }
+        }
+
+        self.locked_versions = self
+            .brews
+            .added
+            .iter()
+            .filter_map(|name| Some((name, nix_intent.locked.brews.get(name)?)))
+            .chain(
+                self.casks
+                    .added
+                    .iter()
+                    .filter_map(|name| Some((name, nix_intent.locked.casks.get(name)?))),
+            )
+            .map(|(name, version)| (name.clone(), version.clone()))
+            .collect();
+
+        // brew upgrade refuses pinned formulae, they stay at their installed version
+        for change in &mut self.brews.upgraded {
+            if current_state.pinned_brews.contains(&change.name) {
+                change.applied = false;
+                self.pinned_brews.insert(change.name.clone());
+            }
+        }
+
+        // brew upgrade skips self-updating casks unless they're greedy
+        for change in &mut self.casks.upgraded {
+            if let Some(receipt) = current_state.cask_receipts.get(&change.name) {
+                change.auto_updates = receipt.auto_updates;
+                change.applied &= !receipt.auto_updates || nix_intent.is_greedy(&change.name);
+            }
+        }
+
+        // Pinning says the installed version matters, uninstalling it is likely unintended
+        for removal in &mut self.brews.removed {
+            if current_state.pinned_brews.contains(&removal.name) {
+                self.pinned_brews.insert(removal.name.clone());
+                if removal.effect.is_destructive() {
+                    removal.severity = removal.severity.max(Severity::Warning);
+                }
+            }
+        }
+    }
+
+    /// Installed formulae that show up in the diff
+    fn diffed_formulae(&self) -> HashSet<&str> {
+        self.brews
+            .removed
+            .iter()
+            .map(|r| r.name.as_str())
+            .chain(self.brews.upgraded.iter().map(|c| c.name.as_str()))
+            .collect()
     }
 
     fn compute_package_diff(
         installed: &HashMap<String, String>, // name -> version
+        outdated: &HashMap<String, String>,  // name -> newest version
         intended: &HashSet<String>,          // just names
+        upgrade: bool,
+        removal_effect: ChangeEffect,
     ) -> PackageDiff {
-        let mut added = Vec::new();
-        let mut removed = Vec::new();
+        let mut diff = Diff::between(
+            installed.keys().map(String::as_str),
+            intended,
+            removal_effect,
+        );
+        for removal in &mut diff.removed {
+            removal.version = installed
+                .get(&removal.name)
+                .filter(|version| !version::is_unversioned(version))
+                .cloned();
+        }
 
-        // Find packages to add
-        for pkg in intended {
-            if !installed.contains_key(pkg) {
-                added.push(pkg.clone());
+        // Find intended packages that activation could upgrade
+        // brew --greedy lists every `version :latest` cask as outdated, they have no version to change
+        for (pkg, available) in outdated {
+            if version::is_unversioned(available) {
+                continue;
+            }
+            if let (true, Some(version)) = (intended.contains(pkg), installed.get(pkg)) {
+                diff.upgraded.push(VersionChange {
+                    name: pkg.clone(),
+                    installed: version.clone(),
+                    available: available.clone(),
+                    applied: upgrade,
+                    auto_updates: false,
+                });
             }
         }
 
-        // Find packages to remove
-        for pkg in installed.keys() {
-            if !intended.contains(pkg) {
-                removed.push(pkg.clone());
+        diff.sort();
+        diff
+    }
+
+    /// Installed packages a lock file resolved to a newer version that brew outdated doesn't
+    /// report, e.g. because this machine's brew hasn't fetched the newer formulae yet
+    /// Compared against the newest installed version, packages without one are skipped
+    fn locked_upgrades(
+        installed: &HashMap<String, String>,
+        outdated: &HashMap<String, String>,
+        locked: &HashMap<String, String>,
+    ) -> Vec<(String, String)> {
+        locked
+            .iter()
+            .filter(|(name, version)| {
+                !outdated.contains_key(*name)
+                    && installed
+                        .get(*name)
+                        .and_then(|installed| version::newest(installed))
+                        .is_some_and(|installed| {
+                            version::compare(version, installed) == Ordering::Greater
+                        })
+            })
+            .map(|(name, version)| (name.clone(), version.clone()))
+            .collect()
+    }
+
+    /// Outdated packages along with the `locked_upgrades`, only copied if there are any
+    fn with_locked_upgrades<'a>(
+        installed: &HashMap<String, String>,
+        outdated: &'a HashMap<String, String>,
+        locked: &HashMap<String, String>,
+    ) -> Cow<'a, HashMap<String, String>> {
+        let upgrades = Self::locked_upgrades(installed, outdated, locked);
+        if upgrades.is_empty() {
+            return Cow::Borrowed(outdated);
+        }
+        let mut outdated = outdated.clone();
+        outdated.extend(upgrades);
+        Cow::Owned(outdated)
+    }
+
+    fn compute_set_diff(
+        current: &HashSet<String>,
+        intended: &HashSet<String>,
+        removal_effect: ChangeEffect,
+    ) -> SetDiff {
+        Diff::between(current.iter().map(String::as_str), intended, removal_effect)
+    }
+
+    /// Owned counterpart of `compute_package_diff` that moves names instead of cloning them
+    fn compute_package_diff_owned(
+        installed: HashMap<String, String>,
+        mut outdated: HashMap<String, String>,
+        intended: HashSet<String>,
+        upgrade: bool,
+        removal_effect: ChangeEffect,
+    ) -> PackageDiff {
+        let (kept, added): (HashSet<String>, HashSet<String>) = intended
+            .into_iter()
+            .partition(|pkg| installed.contains_key(pkg));
+        let mut diff = PackageDiff {
+            added: added.into_iter().collect(),
+            matching: kept.len(),
+            ..Default::default()
+        };
+
+        for (pkg, version) in installed {
+            if !kept.contains(&pkg) {
+                let mut removal = Removal::new(pkg, removal_effect);
+                if !version::is_unversioned(&version) {
+                    removal.version = Some(version);
+                }
+                diff.removed.push(removal);
+            } else if let Some(available) = outdated
+                .remove(&pkg)
+                .filter(|available| !version::is_unversioned(available))
+            {
+                diff.upgraded.push(VersionChange {
+                    name: pkg,
+                    installed: version,
+                    available,
+                    applied: upgrade,
+                    auto_updates: false,
+                });
             }
         }
 
-        // Sort for consistent output
-        added.sort();
-        removed.sort();
+        diff.sort();
+        diff
+    }
+
+    /// Owned counterpart of `compute_set_diff`
+    fn compute_set_diff_owned(
+        current: HashSet<String>,
+        intended: HashSet<String>,
+        removal_effect: ChangeEffect,
+    ) -> SetDiff {
+        let (kept, removed): (HashSet<String>, HashSet<String>) = current
+            .into_iter()
+            .partition(|name| intended.contains(name));
+        let mut diff = SetDiff {
+            added: intended
+                .into_iter()
+                .filter(|name| !kept.contains(name))
+                .collect(),
+            removed: removed
+                .into_iter()
+                .map(|name| Removal::new(name, removal_effect))
+                .collect(),
+            upgraded: Vec::new(),
+            matching: kept.len(),
+        };
+        diff.sort();
+        diff
+    }
+
+    fn compute_link_mismatches(
+        current_state: &HomebrewState,
+        nix_intent: &HomebrewIntent,
+    ) -> Vec<LinkMismatch> {
+        let Some(linked) = &current_state.linked_brews else {
+            return Vec::new();
+        };
+
+        let mut mismatches: Vec<LinkMismatch> = nix_intent
+            .brew_options
+            .iter()
+            .filter(|(name, _)| current_state.installed_brews.contains_key(*name))
+            .filter_map(|(name, options)| {
+                let declared_linked = options.link?;
+                let is_linked = linked.contains(name);
+                (declared_linked != is_linked).then(|| LinkMismatch {
+                    name: name.clone(),
+                    declared_linked,
+                    linked: is_linked,
+                })
+            })
+            .collect();
 
-        PackageDiff { added, removed }
+        mismatches.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+        mismatches
     }
 
-    fn compute_set_diff(current: &HashSet<String>, intended: &HashSet<String>) -> SetDiff {
-        let mut added: Vec<String> = intended.difference(current).cloned().collect();
-        let mut removed: Vec<String> = current.difference(intended).cloned().collect();
+    fn compute_option_mismatches(
+        current_state: &HomebrewState,
+        nix_intent: &HomebrewIntent,
+    ) -> Vec<OptionMismatch> {
+        // Only formula options end up in the receipt, install flags like HEAD don't
+        let normalize = |options: &[String]| {
+            let mut normalized: Vec<String> = options
+                .iter()
+                .map(|option| format!("--{}", option.trim_start_matches('-')))
+                .filter(|option| option.starts_with("--with-") || option.starts_with("--without-"))
+                .collect();
+            normalized.sort();
+            normalized.dedup();
+            normalized
+        };
 
-        added.sort();
-        removed.sort();
+        let mut mismatches: Vec<OptionMismatch> = nix_intent
+            .brew_options
+            .iter()
+            .filter(|(_, options)| !options.args.is_empty())
+            .filter_map(|(name, options)| {
+                let receipt = current_state.formula_receipts.get(name)?;
+                let declared = normalize(&options.args);
+                let installed = normalize(&receipt.used_options);
+                (declared != installed).then(|| OptionMismatch {
+                    name: name.clone(),
+                    declared,
+                    installed,
+                })
+            })
+            .collect();
 
-        SetDiff { added, removed }
+        mismatches.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+        mismatches
     }
 
-    /// Compute only additions for MAS apps since nix-darwin doesn't uninstall them
-    fn compute_mas_additions_only(
+    pub(crate) fn compute_missing_taps(
+        current_state: &HomebrewState,
+        nix_intent: &HomebrewIntent,
+    ) -> Vec<MissingTap> {
+        // Tap names are case-insensitive, the official ones are always available
+        let available: HashSet<String> = nix_intent
+            .taps
+            .iter()
+            .chain(&current_state.installed_taps)
+            .map(|tap| tap.to_lowercase())
+            .chain(["homebrew/core".to_string(), "homebrew/cask".to_string()])
+            .collect();
+        let skip = &nix_intent.flags.skip;
+        let packages = nix_intent
+            .brews
+            .iter()
+            .filter(|name| !skip.brews.contains(*name))
+            .chain(
+                nix_intent
+                    .casks
+                    .iter()
+                    .filter(|name| !skip.casks.contains(*name)),
+            );
+
+        let mut missing: HashMap<String, Vec<String>> = HashMap::new();
+        for package in packages {
+            if let Some(tap) = metadata::tap_of(package) {
+                if !available.contains(&tap.to_lowercase()) {
+                    missing
+                        .entry(tap.to_string())
+                        .or_default()
+                        .push(package.clone());
+                }
+            }
+        }
+
+        let mut missing: Vec<MissingTap> = missing
+            .into_iter()
+            .map(|(name, mut packages)| {
+                packages.sort_by(|a, b| natural_cmp(a, b));
+                MissingTap { name, packages }
+            })
+            .collect();
+        missing.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+        missing
+    }
+
+    /// Pair undeclared formulae with the newest declared formula of their family, so
+    /// `python@3.11` -> `python@3.12` reads as a switch rather than an unrelated removal
+    fn compute_version_switches(
+        current_state: &HomebrewState,
+        nix_intent: &HomebrewIntent,
+    ) -> Vec<VersionSwitch> {
+        let mut declared: HashMap<&str, &str> = HashMap::new();
+        for name in &nix_intent.brews {
+            let newest = declared.entry(version_family(name)).or_insert(name);
+            if natural_cmp(name, newest) == Ordering::Greater {
+                *newest = name;
+            }
+        }
+
+        let mut switches: Vec<VersionSwitch> = current_state
+            .installed_brews
+            .keys()
+            .filter(|name| !nix_intent.brews.contains(*name))
+            .filter_map(|name| {
+                let family = version_family(name);
+                declared.get(family).map(|to| VersionSwitch {
+                    family: family.to_string(),
+                    from: name.clone(),
+                    to: to.to_string(),
+                })
+            })
+            .collect();
+        switches.sort_by(|a, b| natural_cmp(&a.from, &b.from));
+        switches
+    }
+
+    /// Only computed when the Cellar was scanned, since other sources don't record
+    /// which tap a formula came from and every third-party tap would look unused
+    fn compute_unused_taps(
+        current_state: &HomebrewState,
+        nix_intent: &HomebrewIntent,
+    ) -> Vec<UnusedTap> {
+        // Without receipts, packages whose names don't include their tap can't be attributed
+        if current_state.sources.formulae != DetectionSource::Cellar
+            || current_state.sources.casks != DetectionSource::Caskroom
+        {
+            return Vec::new();
+        }
+
+        let qualified = current_state
+            .installed_brews
+            .keys()
+            .chain(current_state.installed_casks.keys())
+            .chain(&nix_intent.brews)
+            .chain(&nix_intent.casks)
+            .filter_map(|name| metadata::tap_of(name));
+        // Kegs of dependencies count too, untapping would orphan them
+        let received = current_state.keg_taps.values().map(String::as_str).chain(
+            current_state
+                .cask_receipts
+                .values()
+                .filter_map(|receipt| receipt.tap.as_deref()),
+        );
+        let used: HashSet<String> = qualified.chain(received).map(str::to_lowercase).collect();
+        let declared: HashSet<String> = nix_intent.taps.iter().map(|t| t.to_lowercase()).collect();
+
+        let mut unused: Vec<UnusedTap> = current_state
+            .installed_taps
+            .iter()
+            .filter(|tap| !ALWAYS_USED_TAPS.contains(&tap.to_lowercase().as_str()))
+            .filter(|tap| !used.contains(&tap.to_lowercase()))
+            .map(|tap| UnusedTap {
+                name: tap.clone(),
+                declared: declared.contains(&tap.to_lowercase()),
+            })
+            .collect();
+        unused.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+        unused
+    }
+
+    /// Casks without a declared appdir go wherever brew defaults to, which `HOMEBREW_CASK_OPTS`
+    /// may change, so there's nothing to compare them against
+    fn compute_appdir_mismatches(
+        current_state: &HomebrewState,
+        nix_intent: &HomebrewIntent,
+    ) -> Vec<AppdirMismatch> {
+        let mut mismatches: Vec<AppdirMismatch> = nix_intent
+            .casks
+            .iter()
+            .filter_map(|name| {
+                let installed = current_state.cask_receipts.get(name)?.appdir.as_deref()?;
+                let declared = nix_intent.intended_appdir(name)?;
+                (normalize_appdir(declared) != normalize_appdir(installed)).then(|| {
+                    AppdirMismatch {
+                        name: name.clone(),
+                        declared: declared.to_string(),
+                        installed: installed.to_string(),
+                    }
+                })
+            })
+            .collect();
+
+        mismatches.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+        mismatches
+    }
+
+    /// Compute the MAS diff, matching apps by id so renamed apps aren't an add/remove pair
+    fn compute_mas_diff(
         current: &HashSet<String>,
         intended: &HashSet<String>,
-    ) -> SetDiff {
-        let mut added: Vec<String> = intended.difference(current).cloned().collect();
-        added.sort();
+    ) -> (SetDiff, Vec<NameMismatch>) {
+        // Note: nix-darwin only installs missing MAS apps, it doesn't uninstall extras
+        // So removals are purely informational, regardless of cleanup mode
+        let normalize = |apps: &HashSet<String>| -> HashSet<String> {
+            apps.iter().map(|app| normalize_mas_name(app)).collect()
+        };
+        let mut diff = Self::compute_set_diff(
+            &normalize(current),
+            &normalize(intended),
+            ChangeEffect::LeftInstalled,
+        );
+        let mut mismatches = Vec::new();
+
+        diff.added.retain(|declared| {
+            let Some(id) = mas_id(declared) else {
+                return true;
+            };
+            let Some(idx) = diff
+                .removed
+                .iter()
+                .position(|r| mas_id(&r.name) == Some(id))
+            else {
+                return true;
+            };
+
+            let installed = diff.removed.remove(idx).name;
+            diff.matching += 1;
+            mismatches.push(NameMismatch {
+                id: id.to_string(),
+                declared: declared.clone(),
+                installed,
+            });
+            false
+        });
+
+        (diff, mismatches)
+    }
+
+    /// Look up metadata (e.g. keg-only status) for every formula in the diff, from what
+    /// Homebrew has locally, asking `brew info` through the runner for the rest
+    pub fn load_metadata(&mut self, runner: &dyn CommandRunner) -> Result<()> {
+        self.load_metadata_with(runner, &MetadataOptions::default())
+    }
+
+    /// Like `load_metadata`, e.g. also fetching formulae of untapped taps from GitHub
+    pub fn load_metadata_with(
+        &mut self,
+        runner: &dyn CommandRunner,
+        options: &MetadataOptions,
+    ) -> Result<()> {
+        let names: Vec<&str> = self
+            .brews
+            .added
+            .iter()
+            .map(String::as_str)
+            .chain(self.brews.removed.iter().map(|r| r.name.as_str()))
+            .chain(self.brews.upgraded.iter().map(|c| c.name.as_str()))
+            .collect();
+
+        if !names.is_empty() {
+            self.formula_metadata = metadata::formula_metadata(runner, &names, options)?;
+        }
+        Ok(())
+    }
+
+    /// Look up installed versions of removed and upgraded packages detected without one
+    /// Detection through brew only gathers names, most installed packages never show up in the diff
+    pub fn load_versions(&mut self, runner: &dyn CommandRunner) -> Result<()> {
+        Self::load_package_versions(&mut self.brews, runner, false)?;
+        Self::load_package_versions(&mut self.casks, runner, true)
+    }
+
+    fn load_package_versions(
+        diff: &mut PackageDiff,
+        runner: &dyn CommandRunner,
+        cask: bool,
+    ) -> Result<()> {
+        let mut names: Vec<String> = diff
+            .removed
+            .iter()
+            .filter(|r| r.version.is_none())
+            .map(|r| r.name.clone())
+            .chain(
+                diff.upgraded
+                    .iter()
+                    .filter(|c| c.installed.is_empty())
+                    .map(|c| c.name.clone()),
+            )
+            .collect();
+        if names.is_empty() {
+            return Ok(());
+        }
+        names.sort();
+
+        let versions = HomebrewState::get_list_versions(runner, &names, cask)?;
+        for removal in &mut diff.removed {
+            if removal.version.is_none() {
+                removal.version = versions
+                    .get(&removal.name)
+                    .filter(|version| !version::is_unversioned(version))
+                    .cloned();
+            }
+        }
+        for change in &mut diff.upgraded {
+            if let (true, Some(version)) = (change.installed.is_empty(), versions.get(&change.name))
+            {
+                change.installed = version.clone();
+            }
+        }
+        Ok(())
+    }
 
-        SetDiff {
-            added,
-            removed: Vec::new(), // nix-darwin doesn't uninstall MAS apps
+    /// Count the dependencies of added formulae that aren't installed yet, via `brew deps`
+    /// Counts stay empty if brew doesn't know one of the formulae
+    pub fn load_dependency_counts(
+        &mut self,
+        runner: &dyn CommandRunner,
+        current_state: &HomebrewState,
+    ) -> Result<()> {
+        let _span = span!("load_dependency_counts", count = self.brews.added.len());
+        if self.brews.added.is_empty() {
+            return Ok(());
         }
+
+        let mut args = vec!["deps", "--formula", "--for-each"];
+        args.extend(self.brews.added.iter().map(String::as_str));
+        let output = runner
+            .output(HomebrewState::get_brew_command(), &args)
+            .map_err(|e| Error::CommandFailed(format!("brew deps failed: {}", e)))?;
+        if !output.status.success() {
+            return Ok(());
+        }
+
+        let deps = parse_deps_output(&String::from_utf8_lossy(&output.stdout));
+        let short = |name: &str| name.rsplit('/').next().unwrap_or(name).to_string();
+        self.dependency_counts = self
+            .brews
+            .added
+            .iter()
+            .filter_map(|name| {
+                let deps = deps.get(name).or_else(|| deps.get(&short(name)))?;
+                let missing = deps
+                    .iter()
+                    .filter(|dep| !current_state.installed_brews.contains_key(&short(dep)))
+                    .count();
+                Some((name.clone(), missing))
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// The tap an added formula will be installed from
+    /// Unqualified names come from homebrew/core unless metadata says otherwise
+    pub fn formula_tap<'a>(&'a self, name: &'a str) -> &'a str {
+        metadata::tap_of(name)
+            .or_else(|| self.formula_metadata.get(name)?.tap.as_deref())
+            .unwrap_or("homebrew/core")
+    }
+
+    /// The tap an added cask will be installed from
+    pub fn cask_tap<'a>(&self, name: &'a str) -> &'a str {
+        metadata::tap_of(name).unwrap_or("homebrew/cask")
+    }
+
+    /// Look up installed taps and compare their remotes against the declared URLs
+    pub fn load_tap_info(
+        &mut self,
+        runner: &dyn CommandRunner,
+        nix_intent: &HomebrewIntent,
+    ) -> Result<()> {
+        if nix_intent.tap_remotes.is_empty() {
+            return Ok(());
+        }
+
+        self.tap_info = metadata::tap_info(runner)?;
+        self.tap_remote_mismatches =
+            Self::compute_tap_remote_mismatches(&self.tap_info, &nix_intent.tap_remotes);
+        Ok(())
+    }
+
+    fn compute_tap_remote_mismatches(
+        tap_info: &HashMap<String, TapInfo>,
+        declared: &HashMap<String, String>,
+    ) -> Vec<RemoteMismatch> {
+        // Equivalent URLs differ in case, trailing slashes or a .git suffix
+        let normalize = |url: &str| {
+            let url = url.trim().trim_end_matches('/');
+            url.strip_suffix(".git").unwrap_or(url).to_lowercase()
+        };
+
+        let mut mismatches: Vec<RemoteMismatch> = declared
+            .iter()
+            .filter_map(|(name, declared)| {
+                let info = tap_info.get(name)?;
+                let matches = info
+                    .remote
+                    .as_deref()
+                    .is_some_and(|remote| normalize(remote) == normalize(declared));
+                (!matches).then(|| RemoteMismatch {
+                    name: name.clone(),
+                    declared: declared.clone(),
+                    remote: info.remote.clone(),
+                })
+            })
+            .collect();
+
+        mismatches.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+        mismatches
     }
 
     /// Check if there are any changes
     pub fn has_changes(&self) -> bool {
-        !self.brews.added.is_empty()
+        !self.link_mismatches.is_empty()
+            || !self.option_mismatches.is_empty()
+            || !self.appdir_mismatches.is_empty()
+            || !self.tap_remote_mismatches.is_empty()
+            || self.brews.upgrades().next().is_some()
+            || self.casks.upgrades().next().is_some()
+            || !self.brews.added.is_empty()
             || !self.brews.removed.is_empty()
             || !self.casks.added.is_empty()
             || !self.casks.removed.is_empty()
             || !self.taps.added.is_empty()
             || !self.taps.removed.is_empty()
             || !self.mas_apps.added.is_empty()
-        // Note: mas_apps.removed is always empty since nix-darwin doesn't uninstall MAS apps
+        // Note: mas_apps.removed is informational since nix-darwin doesn't uninstall MAS apps
+    }
+
+    /// Formulae, casks and App Store apps activation uninstalls, taps aren't packages
+    /// Both the removal warning and `SafetyPolicy::max_removals` compare against this
+    pub fn destructive_removals(&self) -> usize {
+        [
+            &self.brews.removed,
+            &self.casks.removed,
+            &self.mas_apps.removed,
+        ]
+        .into_iter()
+        .map(|removed| destructive(removed).count())
+        .sum()
     }
 
     /// Get total count of changes
     pub fn total_changes(&self) -> usize {
-        self.brews.added.len()
+        self.link_mismatches.len()
+            + self.option_mismatches.len()
+            + self.appdir_mismatches.len()
+            + self.tap_remote_mismatches.len()
+            + self.brews.upgrades().count()
+            + self.casks.upgrades().count()
+            + self.brews.added.len()
             + self.brews.removed.len()
             + self.casks.added.len()
             + self.casks.removed.len()
             + self.taps.added.len()
             + self.taps.removed.len()
             + self.mas_apps.added.len()
-        // Note: mas_apps.removed is always empty since nix-darwin doesn't uninstall MAS apps
+        // Note: mas_apps.removed is informational since nix-darwin doesn't uninstall MAS apps
     }
 }
 
+/// Parse `brew deps --for-each` output, `name: dep dep` lines
+pub(crate) fn parse_deps_output(output: &str) -> HashMap<String, Vec<String>> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, deps)| {
+            (
+                name.trim().to_string(),
+                deps.split_whitespace().map(str::to_string).collect(),
+            )
+        })
+        .collect()
+}
+
+/// Case-insensitive ordering where digit runs compare numerically, e.g. `app2` < `App10`
+/// Falls back to byte order so equal-looking names still sort deterministically
+pub(crate) fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().flat_map(char::to_lowercase).peekable();
+    let mut b_chars = b.chars().flat_map(char::to_lowercase).peekable();
+
+    loop {
+        let (a_char, b_char) = match (a_chars.peek(), b_chars.peek()) {
+            (Some(&a_char), Some(&b_char)) => (a_char, b_char),
+            (a_char, b_char) => return a_char.is_some().cmp(&b_char.is_some()).then(a.cmp(b)),
+        };
+
+        let ordering = if a_char.is_ascii_digit() && b_char.is_ascii_digit() {
+            let take_number = |chars: &mut std::iter::Peekable<_>| {
+                let mut digits = String::new();
+                while let Some(c) = chars.next_if(|c: &char| c.is_ascii_digit()) {
+                    digits.push(c);
+                }
+                digits
+            };
+            let a_number = take_number(&mut a_chars);
+            let b_number = take_number(&mut b_chars);
+            let a_number = a_number.trim_start_matches('0');
+            let b_number = b_number.trim_start_matches('0');
+            a_number
+                .len()
+                .cmp(&b_number.len())
+                .then_with(|| a_number.cmp(b_number))
+        } else {
+            a_chars.next();
+            b_chars.next();
+            a_char.cmp(&b_char)
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Expand `~` and drop trailing slashes so equivalent app directories compare equal
+fn normalize_appdir(appdir: &str) -> String {
+    let expanded = match (appdir.strip_prefix('~'), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}{}", home, rest),
+        _ => appdir.to_string(),
+    };
+    let trimmed = expanded.trim_end_matches('/');
+    if trimmed.is_empty() {
+        "/".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// Normalize an App Store app name so equal-looking names compare equal
+/// Applies NFC, straightens curly quotes and collapses (non-breaking) whitespace
+pub(crate) fn normalize_mas_name(name: &str) -> String {
+    let straightened: String = name
+        .nfc()
+        .map(|c| match c {
+            '\u{2018}' | '\u{2019}' => '\'',
+            '\u{201C}' | '\u{201D}' => '"',
+            c => c,
+        })
+        .collect();
+    straightened
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Extract the numeric id from an "App Name (id)" entry
+pub(crate) fn mas_id(entry: &str) -> Option<&str> {
+    let id = entry.strip_suffix(')')?.rsplit_once('(')?.1;
+    (!id.is_empty() && id.chars().all(|c| c.is_ascii_digit())).then_some(id)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::intent::{BrewOptions, CaskOptions};
+    use crate::state::CaskReceipt;
 
     #[test]
     fn test_compute_package_diff_additions() {
@@ -127,7 +1307,13 @@ mod tests {
         intended.insert("wget".to_string());
         intended.insert("curl".to_string());
 
-        let diff = HomebrewDiffData::compute_package_diff(&installed, &intended);
+        let diff = HomebrewDiffData::compute_package_diff(
+            &installed,
+            &HashMap::new(),
+            &intended,
+            false,
+            ChangeEffect::WillBeRemoved,
+        );
 
         assert_eq!(diff.added, vec!["curl"]);
         assert!(diff.removed.is_empty());
@@ -142,10 +1328,379 @@ mod tests {
         let mut intended = HashSet::new();
         intended.insert("wget".to_string());
 
-        let diff = HomebrewDiffData::compute_package_diff(&installed, &intended);
+        let diff = HomebrewDiffData::compute_package_diff(
+            &installed,
+            &HashMap::new(),
+            &intended,
+            false,
+            ChangeEffect::WillBeRemoved,
+        );
 
         assert!(diff.added.is_empty());
-        assert_eq!(diff.removed, vec!["curl"]);
+        assert_eq!(
+            diff.removed,
+            vec![Removal::new("curl", ChangeEffect::WillBeRemoved).with_version("8.4.0")]
+        );
+    }
+
+    #[test]
+    fn test_compute_package_diff_upgrades() {
+        let mut installed = HashMap::new();
+        installed.insert("wget".to_string(), "1.21.3".to_string());
+        installed.insert("curl".to_string(), "8.4.0".to_string());
+
+        let mut outdated = HashMap::new();
+        outdated.insert("wget".to_string(), "1.24.5".to_string());
+        outdated.insert("curl".to_string(), "8.5.0".to_string());
+
+        let mut intended = HashSet::new();
+        intended.insert("wget".to_string());
+
+        let diff = HomebrewDiffData::compute_package_diff(
+            &installed,
+            &outdated,
+            &intended,
+            true,
+            ChangeEffect::WillBeRemoved,
+        );
+
+        // curl isn't intended, so activation won't upgrade it
+        assert_eq!(
+            diff.upgraded,
+            vec![VersionChange {
+                name: "wget".to_string(),
+                installed: "1.21.3".to_string(),
+                available: "1.24.5".to_string(),
+                applied: true,
+                auto_updates: false,
+            }]
+        );
+
+        let no_upgrade = HomebrewDiffData::compute_package_diff(
+            &installed,
+            &outdated,
+            &intended,
+            false,
+            ChangeEffect::WillBeRemoved,
+        );
+        assert_eq!(no_upgrade.upgraded.len(), 1);
+        assert_eq!(no_upgrade.upgrades().count(), 0);
+    }
+
+    #[test]
+    fn test_latest_casks_are_version_agnostic() {
+        let installed = HashMap::from([
+            ("docker".to_string(), "latest".to_string()),
+            ("slack".to_string(), "latest".to_string()),
+            ("firefox".to_string(), "121.0".to_string()),
+        ]);
+        let outdated = HashMap::from([
+            ("docker".to_string(), "latest".to_string()),
+            ("firefox".to_string(), "latest".to_string()),
+        ]);
+        let intended = HashSet::from(["docker".to_string(), "firefox".to_string()]);
+
+        let diff = HomebrewDiffData::compute_package_diff(
+            &installed,
+            &outdated,
+            &intended,
+            true,
+            ChangeEffect::WillBeRemoved,
+        );
+        assert!(diff.upgraded.is_empty());
+        assert_eq!(diff.removed[0].name, "slack");
+        assert_eq!(diff.removed[0].version, None);
+
+        let owned = HomebrewDiffData::compute_package_diff_owned(
+            installed,
+            outdated,
+            intended,
+            true,
+            ChangeEffect::WillBeRemoved,
+        );
+        assert!(owned.upgraded.is_empty());
+        assert_eq!(owned.removed[0].version, None);
+    }
+
+    #[test]
+    fn test_pinned_formulae_not_upgraded() {
+        let mut state = HomebrewState::default();
+        for formula in ["wget", "node"] {
+            state
+                .installed_brews
+                .insert(formula.to_string(), "1.0".to_string());
+            state
+                .outdated_brews
+                .insert(formula.to_string(), "2.0".to_string());
+        }
+        state.pinned_brews.insert("node".to_string());
+        let intent = HomebrewIntent::from_brewfile("brew \"wget\"\nbrew \"node\"\n");
+
+        let diff = HomebrewDiffData::compute(&state, &intent);
+
+        let upgrades: Vec<&str> = diff.brews.upgrades().map(|c| c.name.as_str()).collect();
+        assert_eq!(upgrades, vec!["wget"]);
+        assert_eq!(diff.pinned_brews, HashSet::from(["node".to_string()]));
+        assert_eq!(diff.total_changes(), 1);
+
+        // Cleanup uninstalls pinned formulae too, which deserves a warning
+        state.pinned_brews.insert("wget".to_string());
+        let mut intent = HomebrewIntent::from_brewfile("brew \"node\"\n");
+        intent.flags.cleanup = CleanupMode::Uninstall;
+        let diff = HomebrewDiffData::compute(&state, &intent);
+        assert_eq!(diff.brews.removed[0].name, "wget");
+        assert_eq!(diff.brews.removed[0].severity, Severity::Warning);
+        assert!(diff.pinned_brews.contains("wget"));
+    }
+
+    #[test]
+    fn test_locked_upgrades() {
+        let installed = HashMap::from([
+            ("wget".to_string(), "1.21.4".to_string()),
+            // Versions weren't looked up
+            ("jq".to_string(), String::new()),
+            // Two kegs, the newer one is what the lock resolved to
+            ("node".to_string(), "21.7.3 22.2.0".to_string()),
+        ]);
+        let locked = HashMap::from([
+            ("wget".to_string(), "1.24.5".to_string()),
+            ("jq".to_string(), "1.7.1".to_string()),
+            ("node".to_string(), "22.2.0".to_string()),
+        ]);
+
+        let upgrades = HomebrewDiffData::locked_upgrades(&installed, &HashMap::new(), &locked);
+
+        assert_eq!(upgrades, vec![("wget".to_string(), "1.24.5".to_string())]);
+    }
+
+    #[test]
+    fn test_auto_updating_casks_not_upgraded() {
+        let mut state = HomebrewState::default();
+        for cask in ["google-chrome", "iterm2"] {
+            state
+                .installed_casks
+                .insert(cask.to_string(), "1.0".to_string());
+            state
+                .outdated_casks
+                .insert(cask.to_string(), "2.0".to_string());
+        }
+        state.cask_receipts.insert(
+            "google-chrome".to_string(),
+            CaskReceipt {
+                auto_updates: true,
+                ..Default::default()
+            },
+        );
+
+        let mut intent = HomebrewIntent::default();
+        intent.casks.insert("google-chrome".to_string());
+        intent.casks.insert("iterm2".to_string());
+
+        let diff = HomebrewDiffData::compute(&state, &intent);
+
+        let upgrades: Vec<&str> = diff.casks.upgrades().map(|c| c.name.as_str()).collect();
+        assert_eq!(upgrades, vec!["iterm2"]);
+        assert!(diff.casks.upgraded[0].auto_updates);
+        assert_eq!(diff.total_changes(), 1);
+
+        intent.cask_options.insert(
+            "google-chrome".to_string(),
+            CaskOptions {
+                greedy: true,
+                ..Default::default()
+            },
+        );
+        let greedy = HomebrewDiffData::compute(&state, &intent);
+        assert_eq!(greedy.casks.upgrades().count(), 2);
+    }
+
+    #[test]
+    fn test_package_taps() {
+        let mut diff = HomebrewDiffData::default();
+        diff.formula_metadata.insert(
+            "gh-dash".to_string(),
+            FormulaMetadata {
+                name: "gh-dash".to_string(),
+                tap: Some("dlvhdr/formulae".to_string()),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(diff.formula_tap("wget"), "homebrew/core");
+        assert_eq!(diff.formula_tap("hashicorp/tap/terraform"), "hashicorp/tap");
+        assert_eq!(diff.formula_tap("gh-dash"), "dlvhdr/formulae");
+        assert_eq!(diff.cask_tap("firefox"), "homebrew/cask");
+        assert_eq!(
+            diff.cask_tap("homebrew/cask-fonts/font-fira-code"),
+            "homebrew/cask-fonts"
+        );
+    }
+
+    #[test]
+    fn test_missing_taps() {
+        let mut state = HomebrewState::default();
+        state.installed_taps.insert("acme/tools".to_string());
+        let mut intent = HomebrewIntent::default();
+        intent.taps.insert("Homebrew/Cask-Fonts".to_string());
+        intent.brews.extend([
+            "wget".to_string(),
+            "hashicorp/tap/terraform".to_string(),
+            "hashicorp/tap/vault".to_string(),
+            "acme/tools/widget".to_string(),
+            "homebrew/core/jq".to_string(),
+        ]);
+        intent.casks.extend([
+            "homebrew/cask-fonts/font-fira-code".to_string(),
+            "homebrew/cask/firefox".to_string(),
+        ]);
+
+        let expected = vec![MissingTap {
+            name: "hashicorp/tap".to_string(),
+            packages: vec![
+                "hashicorp/tap/terraform".to_string(),
+                "hashicorp/tap/vault".to_string(),
+            ],
+        }];
+        assert_eq!(
+            HomebrewDiffData::compute(&state, &intent).missing_taps,
+            expected
+        );
+        assert_eq!(
+            HomebrewDiffData::compute_owned(state, intent).missing_taps,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_parse_deps_output() {
+        let deps =
+            parse_deps_output("ffmpeg: aom dav1d lame openssl@3 x264\nhashicorp/tap/terraform:\n");
+
+        assert_eq!(deps["ffmpeg"].len(), 5);
+        assert_eq!(deps["ffmpeg"][3], "openssl@3");
+        assert!(deps["hashicorp/tap/terraform"].is_empty());
+    }
+
+    #[test]
+    fn test_unused_taps() {
+        let mut state = HomebrewState::default();
+        for tap in [
+            "homebrew/core",
+            "hashicorp/tap",
+            "acme/tools",
+            "old/stale",
+            "kept/declared",
+            "acme/libs",
+        ] {
+            state.installed_taps.insert(tap.to_string());
+        }
+        state
+            .installed_brews
+            .insert("terraform".to_string(), "1.8.0".to_string());
+        state
+            .keg_taps
+            .insert("terraform".to_string(), "hashicorp/tap".to_string());
+        // Only installed as a dependency, so it isn't among the installed brews
+        state
+            .keg_taps
+            .insert("libwidget".to_string(), "acme/libs".to_string());
+        let mut intent = HomebrewIntent::default();
+        intent.taps.insert("kept/declared".to_string());
+        intent.brews.insert("acme/tools/widget".to_string());
+
+        assert!(HomebrewDiffData::compute(&state, &intent)
+            .unused_taps
+            .is_empty());
+
+        state.sources.formulae = DetectionSource::Cellar;
+        assert!(HomebrewDiffData::compute(&state, &intent)
+            .unused_taps
+            .is_empty());
+
+        state.sources.casks = DetectionSource::Caskroom;
+        state.installed_taps.insert("homebrew/bundle".to_string());
+        let diff = HomebrewDiffData::compute_owned(state, intent);
+        assert_eq!(
+            diff.unused_taps,
+            vec![
+                UnusedTap {
+                    name: "kept/declared".to_string(),
+                    declared: true,
+                },
+                UnusedTap {
+                    name: "old/stale".to_string(),
+                    declared: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tap_remote_mismatches() {
+        let tap_info: HashMap<String, TapInfo> = [
+            (
+                "acme/tools",
+                Some("https://GitHub.com/acme/homebrew-tools/"),
+            ),
+            (
+                "acme/fork",
+                Some("https://github.com/someone-else/homebrew-fork"),
+            ),
+            ("acme/api", None),
+        ]
+        .into_iter()
+        .map(|(name, remote)| {
+            let info = TapInfo {
+                name: name.to_string(),
+                remote: remote.map(String::from),
+            };
+            (name.to_string(), info)
+        })
+        .collect();
+        let declared: HashMap<String, String> = [
+            ("acme/tools", "https://github.com/acme/homebrew-tools.git"),
+            ("acme/fork", "https://github.com/acme/homebrew-fork"),
+            ("acme/missing", "https://github.com/acme/homebrew-missing"),
+        ]
+        .into_iter()
+        .map(|(name, url)| (name.to_string(), url.to_string()))
+        .collect();
+
+        let mismatches = HomebrewDiffData::compute_tap_remote_mismatches(&tap_info, &declared);
+
+        assert_eq!(
+            mismatches,
+            vec![RemoteMismatch {
+                name: "acme/fork".to_string(),
+                declared: "https://github.com/acme/homebrew-fork".to_string(),
+                remote: Some("https://github.com/someone-else/homebrew-fork".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut names = vec![
+            "Zoom",
+            "alacritty",
+            "python@3.12",
+            "python@3.9",
+            "Éclair",
+            "zed",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            vec![
+                "alacritty",
+                "python@3.9",
+                "python@3.12",
+                "zed",
+                "Zoom",
+                "Éclair"
+            ]
+        );
+        assert_eq!(natural_cmp("Git", "git"), Ordering::Less);
     }
 
     #[test]
@@ -157,12 +1712,384 @@ mod tests {
         intended.insert("homebrew/core".to_string());
         intended.insert("homebrew/cask".to_string());
 
-        let diff = HomebrewDiffData::compute_set_diff(&current, &intended);
+        let diff =
+            HomebrewDiffData::compute_set_diff(&current, &intended, ChangeEffect::WillBeRemoved);
 
         assert_eq!(diff.added, vec!["homebrew/cask"]);
         assert!(diff.removed.is_empty());
     }
 
+    #[test]
+    fn test_removal_effects_follow_cleanup() {
+        let mut state = HomebrewState::default();
+        state
+            .installed_brews
+            .insert("wget".to_string(), "1.21.3".to_string());
+        state
+            .installed_casks
+            .insert("slack".to_string(), "4.36.140".to_string());
+
+        let mut intent = HomebrewIntent::default();
+        let diff = HomebrewDiffData::compute(&state, &intent);
+        assert_eq!(diff.brews.removed[0].effect, ChangeEffect::LeftInstalled);
+        assert_eq!(diff.casks.removed[0].effect, ChangeEffect::LeftInstalled);
+
+        intent.flags.cleanup = CleanupMode::Zap;
+        let diff = HomebrewDiffData::compute(&state, &intent);
+        assert_eq!(diff.brews.removed[0].effect, ChangeEffect::WillBeRemoved);
+        assert_eq!(diff.casks.removed[0].effect, ChangeEffect::WillBeZapped);
+    }
+
+    #[test]
+    fn test_compute_keeps_receipts_for_diffed_formulae() {
+        let mut state = HomebrewState::default();
+        for name in ["ffmpeg", "wget"] {
+            state
+                .installed_brews
+                .insert(name.to_string(), "1.0".to_string());
+            state.formula_receipts.insert(
+                name.to_string(),
+                FormulaReceipt {
+                    installed_on_request: true,
+                    poured_from_bottle: name == "wget",
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut intent = HomebrewIntent::default();
+        intent.brews.insert("wget".to_string());
+
+        let diff = HomebrewDiffData::compute(&state, &intent);
+
+        assert_eq!(diff.formula_receipts.len(), 1);
+        assert!(diff.formula_receipts["ffmpeg"].built_from_source());
+    }
+
+    #[test]
+    fn test_diff_between_custom_items() {
+        #[derive(Debug, Clone, PartialEq)]
+        struct Extension {
+            id: String,
+            version: Option<String>,
+        }
+        impl ItemKey for Extension {
+            fn key(&self) -> &str {
+                &self.id
+            }
+        }
+
+        let intended = [
+            Extension {
+                id: "rust-lang.rust-analyzer".to_string(),
+                version: None,
+            },
+            Extension {
+                id: "golang.go".to_string(),
+                version: Some("0.40".to_string()),
+            },
+        ];
+        let diff = Diff::between(
+            ["golang.go", "ms-python.python"],
+            &intended,
+            ChangeEffect::LeftInstalled,
+        );
+
+        assert_eq!(diff.added, vec![intended[0].clone()]);
+        assert_eq!(
+            diff.removed,
+            vec![Removal::new(
+                "ms-python.python",
+                ChangeEffect::LeftInstalled
+            )]
+        );
+        assert!(diff.upgraded.is_empty());
+    }
+
+    #[test]
+    fn test_skipped_entries_are_neither_added_nor_upgraded() {
+        let mut state = HomebrewState::default();
+        state
+            .installed_brews
+            .insert("node".to_string(), "21.7.3".to_string());
+        state
+            .outdated_brews
+            .insert("node".to_string(), "22.2.0".to_string());
+
+        let mut intent = HomebrewIntent::from_brewfile(
+            "brew \"node\"\nbrew \"wget\"\ncask \"firefox\"\nmas \"Xcode\", id: 497799835\n",
+        );
+        intent.flags = IntentFlags::from_bundle_invocation(
+            "env HOMEBREW_BUNDLE_BREW_SKIP='node wget' HOMEBREW_BUNDLE_MAS_SKIP=Xcode \
+             brew bundle --file='/nix/store/xxx-Brewfile' --cleanup",
+        );
+
+        let diff = HomebrewDiffData::compute(&state, &intent);
+
+        assert!(diff.brews.added.is_empty());
+        assert!(diff.brews.removed.is_empty());
+        assert!(!diff.brews.upgraded[0].applied);
+        assert_eq!(diff.casks.added, vec!["firefox".to_string()]);
+        assert!(diff.mas_apps.added.is_empty());
+    }
+
+    #[test]
+    fn test_compute_owned_matches_compute() {
+        let mut state = HomebrewState::default();
+        for (name, version) in [("git", "2.0"), ("wget", "1.0"), ("ffmpeg", "6.0")] {
+            state
+                .installed_brews
+                .insert(name.to_string(), version.to_string());
+            state.formula_receipts.insert(
+                name.to_string(),
+                FormulaReceipt {
+                    installed_on_request: true,
+                    ..Default::default()
+                },
+            );
+        }
+        state
+            .outdated_brews
+            .insert("wget".to_string(), "1.1".to_string());
+        state
+            .installed_casks
+            .insert("zoom".to_string(), "5".to_string());
+        state.installed_taps.insert("old/tap".to_string());
+
+        let mut intent = HomebrewIntent::default();
+        for name in ["wget", "ffmpeg", "jq"] {
+            intent.brews.insert(name.to_string());
+        }
+        intent.casks.insert("firefox".to_string());
+        intent.taps.insert("new/tap".to_string());
+        intent.flags.cleanup = CleanupMode::Zap;
+
+        let borrowed = HomebrewDiffData::compute(&state, &intent);
+        let owned = HomebrewDiffData::compute_owned(state, intent);
+
+        assert_eq!(owned.brews.added, borrowed.brews.added);
+        assert_eq!(owned.brews.removed, borrowed.brews.removed);
+        assert_eq!(owned.brews.upgraded, borrowed.brews.upgraded);
+        assert_eq!(owned.casks.added, borrowed.casks.added);
+        assert_eq!(owned.casks.removed, borrowed.casks.removed);
+        assert_eq!(owned.taps.added, borrowed.taps.added);
+        assert_eq!(owned.taps.removed, borrowed.taps.removed);
+        assert_eq!(owned.pinned_brews, borrowed.pinned_brews);
+        assert_eq!(owned.flags, borrowed.flags);
+        assert_eq!(
+            owned.formula_receipts.keys().collect::<HashSet<_>>(),
+            borrowed.formula_receipts.keys().collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn test_shared_diff_keeps_inputs() {
+        let mut state = HomebrewState::default();
+        state
+            .installed_brews
+            .insert("git".to_string(), "2.0".to_string());
+        let mut intent = HomebrewIntent::default();
+        intent.brews.insert("wget".to_string());
+
+        let state = Arc::new(state);
+        let shared = SharedDiff::compute(Arc::clone(&state), Arc::new(intent));
+
+        assert_eq!(shared.diff.brews.added, vec!["wget"]);
+        assert!(Arc::ptr_eq(&shared.state, &state));
+        assert!(shared.intent.brews.contains("wget"));
+    }
+
+    #[test]
+    fn test_link_mismatches() {
+        let mut state = HomebrewState::default();
+        for name in ["python@3.11", "openssl@3", "wget"] {
+            state
+                .installed_brews
+                .insert(name.to_string(), "1.0".to_string());
+        }
+        state.linked_brews = Some(HashSet::from([
+            "python@3.11".to_string(),
+            "wget".to_string(),
+        ]));
+
+        let mut intent = HomebrewIntent::default();
+        for (name, link) in [("python@3.11", false), ("openssl@3", true), ("wget", true)] {
+            intent.brews.insert(name.to_string());
+            intent.brew_options.insert(
+                name.to_string(),
+                BrewOptions {
+                    link: Some(link),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let diff = HomebrewDiffData::compute(&state, &intent);
+
+        assert_eq!(
+            diff.link_mismatches,
+            vec![
+                LinkMismatch {
+                    name: "openssl@3".to_string(),
+                    declared_linked: true,
+                    linked: false,
+                },
+                LinkMismatch {
+                    name: "python@3.11".to_string(),
+                    declared_linked: false,
+                    linked: true,
+                },
+            ]
+        );
+        assert!(diff.has_changes());
+
+        // Without link data there's nothing to compare against
+        state.linked_brews = None;
+        assert!(HomebrewDiffData::compute(&state, &intent)
+            .link_mismatches
+            .is_empty());
+    }
+
+    #[test]
+    fn test_option_mismatches() {
+        let mut state = HomebrewState::default();
+        for (name, used_options) in [("ffmpeg", vec![]), ("wget", vec!["--with-libressl"])] {
+            state
+                .installed_brews
+                .insert(name.to_string(), "1.0".to_string());
+            state.formula_receipts.insert(
+                name.to_string(),
+                FormulaReceipt {
+                    installed_on_request: true,
+                    used_options: used_options.into_iter().map(String::from).collect(),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut intent = HomebrewIntent::default();
+        for (name, args) in [
+            ("ffmpeg", vec!["with-fdk-aac", "HEAD"]),
+            ("wget", vec!["with-libressl"]),
+        ] {
+            intent.brews.insert(name.to_string());
+            intent.brew_options.insert(
+                name.to_string(),
+                BrewOptions {
+                    args: args.into_iter().map(String::from).collect(),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let diff = HomebrewDiffData::compute(&state, &intent);
+
+        assert_eq!(
+            diff.option_mismatches,
+            vec![OptionMismatch {
+                name: "ffmpeg".to_string(),
+                declared: vec!["--with-fdk-aac".to_string()],
+                installed: vec![],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_version_switches() {
+        assert_eq!(version_family("python@3.12"), "python");
+        assert_eq!(version_family("openssl@3"), "openssl");
+        assert_eq!(version_family("python"), "python");
+        assert_eq!(
+            version_family("firefox@developer-edition"),
+            "firefox@developer-edition"
+        );
+
+        let mut state = HomebrewState::default();
+        for name in ["python@3.11", "python@3.12", "node@18", "wget"] {
+            state
+                .installed_brews
+                .insert(name.to_string(), "1.0".to_string());
+        }
+        let mut intent = HomebrewIntent::default();
+        for name in ["python@3.9", "python@3.12", "node", "curl"] {
+            intent.brews.insert(name.to_string());
+        }
+
+        let diff = HomebrewDiffData::compute(&state, &intent);
+
+        assert_eq!(
+            diff.version_switches,
+            vec![
+                VersionSwitch {
+                    family: "node".to_string(),
+                    from: "node@18".to_string(),
+                    to: "node".to_string(),
+                },
+                VersionSwitch {
+                    family: "python".to_string(),
+                    from: "python@3.11".to_string(),
+                    to: "python@3.12".to_string(),
+                },
+            ]
+        );
+        let owned = HomebrewDiffData::compute_owned(state, intent);
+        assert_eq!(owned.version_switches, diff.version_switches);
+    }
+
+    #[test]
+    fn test_appdir_mismatches() {
+        let home = std::env::var("HOME").unwrap_or_default();
+        let mut state = HomebrewState::default();
+        for (name, appdir) in [
+            ("firefox", "/Applications"),
+            ("iterm2", "/Applications/"),
+            ("docker", "~/Applications"),
+        ] {
+            state
+                .installed_casks
+                .insert(name.to_string(), "1.0".to_string());
+            state.cask_receipts.insert(
+                name.to_string(),
+                CaskReceipt {
+                    appdir: Some(appdir.replace('~', &home)),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut intent = HomebrewIntent::default();
+        for name in ["firefox", "iterm2", "docker"] {
+            intent.casks.insert(name.to_string());
+        }
+        intent
+            .cask_args
+            .insert("appdir".to_string(), "\"~/Applications\"".to_string());
+        intent.cask_options.insert(
+            "iterm2".to_string(),
+            CaskOptions {
+                args: [("appdir".to_string(), "\"/Applications\"".to_string())].into(),
+                ..Default::default()
+            },
+        );
+
+        let diff = HomebrewDiffData::compute(&state, &intent);
+
+        assert_eq!(
+            diff.appdir_mismatches,
+            vec![AppdirMismatch {
+                name: "firefox".to_string(),
+                declared: "~/Applications".to_string(),
+                installed: "/Applications".to_string(),
+            }]
+        );
+        assert!(diff.has_changes());
+
+        // Without a declared appdir wherever brew put it is fine
+        intent.cask_args.clear();
+        let diff = HomebrewDiffData::compute(&state, &intent);
+        assert!(diff.appdir_mismatches.is_empty());
+    }
+
     #[test]
     fn test_has_changes() {
         let state = HomebrewState::default();
@@ -177,8 +2104,8 @@ mod tests {
     }
 
     #[test]
-    fn test_mas_additions_only() {
-        // Test that MAS apps only show additions, never removals
+    fn test_mas_removals_left_installed() {
+        // Test that extra MAS apps are never reported as being uninstalled
         let mut current = HashSet::new();
         current.insert("Existing App (123)".to_string());
         current.insert("To Be Removed (456)".to_string());
@@ -187,11 +2114,88 @@ mod tests {
         intended.insert("Existing App (123)".to_string());
         intended.insert("New App (789)".to_string());
 
-        let diff = HomebrewDiffData::compute_mas_additions_only(&current, &intended);
+        let state = HomebrewState {
+            installed_mas_apps: current,
+            ..Default::default()
+        };
+        let mut intent = HomebrewIntent {
+            mas_apps: intended,
+            ..Default::default()
+        };
+        intent.flags.cleanup = CleanupMode::Zap;
+
+        let diff = HomebrewDiffData::compute(&state, &intent);
 
         // Should only show the new app as addition
-        assert_eq!(diff.added, vec!["New App (789)"]);
-        // Should NOT show "To Be Removed" in removals since nix-darwin doesn't uninstall MAS apps
-        assert!(diff.removed.is_empty());
+        assert_eq!(diff.mas_apps.added, vec!["New App (789)"]);
+        // nix-darwin doesn't uninstall MAS apps, even with cleanup enabled
+        assert_eq!(
+            diff.mas_apps.removed,
+            vec![Removal::new(
+                "To Be Removed (456)",
+                ChangeEffect::LeftInstalled
+            )]
+        );
+        // Informational entries don't count as changes
+        assert_eq!(diff.total_changes(), 1);
+    }
+
+    #[test]
+    fn test_mas_name_mismatch_matched_by_id() {
+        let mut current = HashSet::new();
+        current.insert("Xcode-beta (497799835)".to_string());
+        current.insert("Extra (111)".to_string());
+
+        let mut intended = HashSet::new();
+        intended.insert("Xcode (497799835)".to_string());
+        intended.insert("Keynote (409183694)".to_string());
+
+        let (diff, mismatches) = HomebrewDiffData::compute_mas_diff(&current, &intended);
+
+        assert_eq!(diff.added, vec!["Keynote (409183694)"]);
+        assert_eq!(
+            diff.removed,
+            vec![Removal::new("Extra (111)", ChangeEffect::LeftInstalled)]
+        );
+        assert_eq!(
+            mismatches,
+            vec![NameMismatch {
+                id: "497799835".to_string(),
+                declared: "Xcode (497799835)".to_string(),
+                installed: "Xcode-beta (497799835)".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_mas_names_normalized() {
+        let mut state = HomebrewState::default();
+        // Decomposed é, curly apostrophe and a non-breaking space
+        state
+            .installed_mas_apps
+            .insert("Pixelmator Pro Caf\u{65}\u{301} (1)".to_string());
+        state
+            .installed_mas_apps
+            .insert("Mac\u{2019}s\u{a0}Toolbox  (2)".to_string());
+
+        let mut intent = HomebrewIntent::default();
+        intent
+            .mas_apps
+            .insert("Pixelmator Pro Caf\u{e9} (1)".to_string());
+        intent.mas_apps.insert("Mac's Toolbox (2)".to_string());
+
+        let diff = HomebrewDiffData::compute(&state, &intent);
+
+        assert!(diff.mas_apps.added.is_empty());
+        assert!(diff.mas_apps.removed.is_empty());
+        assert!(diff.mas_name_mismatches.is_empty());
+    }
+
+    #[test]
+    fn test_mas_id() {
+        assert_eq!(mas_id("Xcode (497799835)"), Some("497799835"));
+        assert_eq!(mas_id("App (with parens) (123)"), Some("123"));
+        assert_eq!(mas_id("No Id"), None);
+        assert_eq!(mas_id("Bad (abc)"), None);
     }
 }
@@ -0,0 +1,104 @@
+//! Counterparts of dix's entry points, so tools already integrating dix need little glue
+
+use crate::diff::HomebrewDiffData;
+use crate::display;
+use crate::error::{Error, Result};
+use std::fmt::{self, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+
+/// Like dix's `spawn_size_diff`, takes both profiles even though only the new one matters
+/// Homebrew state isn't part of the old profile, it's detected from the running system
+pub fn spawn_homebrew_diff(
+    _path_old: PathBuf,
+    path_new: PathBuf,
+) -> JoinHandle<Result<HomebrewDiffData>> {
+    crate::spawn_homebrew_diff(path_new)
+}
+
+/// Like dix's `write_size_diffln`, writes the result of `spawn_homebrew_diff`
+/// Returns number of lines written
+pub fn write_homebrew_diffln(writer: &mut impl Write, diff: &HomebrewDiffData) -> Result<usize> {
+    Ok(display::write_diff(writer, diff)?.lines)
+}
+
+/// Like dix's `write_paths_diffln`, including the `<<<`/`>>>` header
+/// Returns number of lines written
+pub fn write_paths_diffln(
+    writer: &mut impl Write,
+    path_old: &Path,
+    path_new: &Path,
+) -> Result<usize> {
+    Ok(crate::write_homebrew_diffln(writer, path_old, path_new)?.lines)
+}
+
+/// Write dix's system diff followed by the Homebrew diff, returns number of lines written
+/// Pass dix's `write_paths_diffln` as `write_system`; Homebrew is detected in the background meanwhile
+pub fn write_system_diffln<W, E>(
+    writer: &mut W,
+    path_old: &Path,
+    path_new: &Path,
+    write_system: impl FnOnce(&mut W, &Path, &Path) -> std::result::Result<usize, E>,
+) -> Result<usize>
+where
+    W: Write,
+    E: fmt::Display,
+{
+    let homebrew = spawn_homebrew_diff(path_old.to_path_buf(), path_new.to_path_buf());
+    let mut lines_written =
+        write_system(writer, path_old, path_new).map_err(|e| Error::SystemDiff(e.to_string()))?;
+
+    let diff = homebrew
+        .join()
+        .unwrap_or_else(|payload| panic::resume_unwind(payload))?;
+    lines_written += write_homebrew_section(writer, &diff, lines_written)?;
+    Ok(lines_written)
+}
+
+/// Write the Homebrew diff after other output, separated by a blank line
+fn write_homebrew_section<W: Write>(
+    writer: &mut W,
+    diff: &HomebrewDiffData,
+    lines_before: usize,
+) -> Result<usize> {
+    let mut section = String::new();
+    let lines = display::write_diff(&mut section, diff)?.lines;
+    if lines == 0 {
+        return Ok(0);
+    }
+
+    let separator = usize::from(lines_before > 0);
+    if separator > 0 {
+        writeln!(writer)?;
+    }
+    writer.write_str(&section)?;
+    Ok(separator + lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_homebrew_section_follows_system_diff() {
+        let mut diff = HomebrewDiffData::default();
+        diff.brews.added = vec!["wget".to_string()];
+
+        let mut output = "<<< old\n>>> new\n\nCHANGED\n".to_string();
+        let lines = write_homebrew_section(&mut output, &diff, 4).unwrap();
+
+        // blank + ADDED + Formulae + wget
+        assert_eq!(lines, 4);
+        assert!(output.contains("CHANGED\n\nADDED (1)\n"));
+    }
+
+    #[test]
+    fn test_empty_homebrew_section() {
+        let mut output = String::new();
+        let lines = write_homebrew_section(&mut output, &HomebrewDiffData::default(), 4).unwrap();
+
+        assert_eq!(lines, 0);
+        assert!(output.is_empty());
+    }
+}
@@ -0,0 +1,415 @@
+use crate::compat::BrewCompat;
+use crate::error::Result;
+use crate::intent::HomebrewIntent;
+use crate::runner::{CommandRunner, SystemRunner};
+use crate::state::{tap_repository, HomebrewState};
+use owo_colors::OwoColorize;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Outcome of a single diagnostic check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    Warning,
+    Failed,
+}
+
+/// A single diagnostic finding with an optional hint on how to fix it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub check: &'static str,
+    pub status: CheckStatus,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl Finding {
+    fn ok(check: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            check,
+            status: CheckStatus::Ok,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn warning(check: &'static str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            check,
+            status: CheckStatus::Warning,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn failed(check: &'static str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            check,
+            status: CheckStatus::Failed,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Run all environment diagnostics against the given nix-darwin profile
+pub fn run_diagnostics(profile: &Path) -> Vec<Finding> {
+    run_diagnostics_with(&SystemRunner::default(), profile)
+}
+
+/// Run all environment diagnostics, running brew and mas through the given runner
+pub fn run_diagnostics_with(runner: &dyn CommandRunner, profile: &Path) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    findings.extend(check_brew(runner));
+    findings.push(check_mas(runner));
+    findings.extend(check_profile(profile));
+    if HomebrewState::homebrew_installed() {
+        findings.extend(check_taps(runner));
+        // Ownership is only known on Unix, brew doesn't run anywhere else anyway
+        #[cfg(unix)]
+        findings.extend(check_permissions());
+    }
+
+    findings
+}
+
+/// Check if there are any failed checks
+pub fn has_failures(findings: &[Finding]) -> bool {
+    findings.iter().any(|f| f.status == CheckStatus::Failed)
+}
+
+/// Write diagnostic findings, returns number of lines written
+pub fn write_diagnostics<W: Write>(writer: &mut W, findings: &[Finding]) -> Result<usize> {
+    let mut lines_written = 0;
+
+    for finding in findings {
+        let marker = match finding.status {
+            CheckStatus::Ok => "OK".green().bold().to_string(),
+            CheckStatus::Warning => "WARN".yellow().bold().to_string(),
+            CheckStatus::Failed => "FAIL".red().bold().to_string(),
+        };
+        writeln!(
+            writer,
+            "[{}] {}: {}",
+            marker, finding.check, finding.message
+        )?;
+        lines_written += 1;
+
+        if let Some(hint) = &finding.hint {
+            writeln!(writer, "      {}", hint)?;
+            lines_written += 1;
+        }
+    }
+
+    Ok(lines_written)
+}
+
+fn check_brew(runner: &dyn CommandRunner) -> Vec<Finding> {
+    if !HomebrewState::homebrew_installed() {
+        return vec![Finding::failed(
+            "brew",
+            "Homebrew not found at /opt/homebrew or /usr/local",
+            "Install Homebrew from https://brew.sh or enable nix-darwin's homebrew module",
+        )];
+    }
+
+    let brew = HomebrewState::get_brew_command();
+    match runner.output(brew, &["--version"]) {
+        Ok(output) if output.status.success() => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let version = stdout.lines().next().unwrap_or("unknown version");
+            let mut findings = vec![Finding::ok("brew", format!("{} ({})", version, brew))];
+            let compat = BrewCompat {
+                version: BrewCompat::parse_version(&stdout),
+            };
+            findings.extend(compat.concerns().into_iter().map(|concern| {
+                Finding::warning(
+                    "brew",
+                    concern.message,
+                    "Run `brew update` to upgrade Homebrew",
+                )
+            }));
+            findings.extend(check_outdated(runner, &compat));
+            findings
+        }
+        Ok(_) | Err(_) => vec![Finding::failed(
+            "brew",
+            format!("{} exists but could not be executed", brew),
+            "Run `brew doctor` to check your Homebrew installation",
+        )],
+    }
+}
+
+fn check_mas(runner: &dyn CommandRunner) -> Finding {
+    let found = runner
+        .output("which", &["mas"])
+        .map(|output| output.status.success())
+        .unwrap_or(false);
+
+    if found {
+        Finding::ok("mas", "mas CLI found")
+    } else {
+        Finding::warning(
+            "mas",
+            "mas CLI not found, App Store apps are detected via Spotlight",
+            "Add `mas` to homebrew.brews if you declare homebrew.masApps",
+        )
+    }
+}
+
+fn check_profile(profile: &Path) -> Vec<Finding> {
+    if !profile.exists() {
+        return vec![Finding::failed(
+            "profile",
+            format!("{} does not exist", profile.display()),
+            "Pass the path of a built nix-darwin system profile",
+        )];
+    }
+
+    let mut findings = vec![Finding::ok(
+        "profile",
+        format!("{} exists", profile.display()),
+    )];
+
+    let activate_path = profile.join("activate");
+    if let Err(e) = fs::read_to_string(&activate_path) {
+        findings.push(Finding::failed(
+            "activation script",
+            format!("{} is not readable: {}", activate_path.display(), e),
+            "Make sure the profile was built by nix-darwin",
+        ));
+        return findings;
+    }
+    findings.push(Finding::ok(
+        "activation script",
+        format!("{} is readable", activate_path.display()),
+    ));
+
+    let brewfile_path = match HomebrewIntent::find_brewfile_path(profile) {
+        Ok(path) => path,
+        Err(_) => {
+            findings.push(Finding::warning(
+                "Brewfile",
+                "No brew bundle invocation in the activation script",
+                "Enable `homebrew.enable` in your nix-darwin configuration",
+            ));
+            return findings;
+        }
+    };
+
+    match HomebrewIntent::parse_brewfile(&brewfile_path) {
+        Ok(intent) => findings.push(Finding::ok(
+            "Brewfile",
+            format!(
+                "{} declares {} formulae, {} casks, {} taps, {} App Store apps",
+                brewfile_path.display(),
+                intent.brews.len(),
+                intent.casks.len(),
+                intent.taps.len(),
+                intent.mas_apps.len()
+            ),
+        )),
+        Err(e) => findings.push(Finding::failed(
+            "Brewfile",
+            format!("{} could not be read: {}", brewfile_path.display(), e),
+            "Rebuild the profile so the referenced Brewfile exists in the store",
+        )),
+    }
+
+    findings
+}
+
+fn check_taps(runner: &dyn CommandRunner) -> Vec<Finding> {
+    let brew = HomebrewState::get_brew_command();
+    let output = match runner.output(brew, &["tap"]) {
+        Ok(output) if output.status.success() => output,
+        _ => {
+            return vec![Finding::failed(
+                "taps",
+                "`brew tap` failed",
+                "Run `brew doctor` to check your Homebrew installation",
+            )]
+        }
+    };
+
+    let prefix = HomebrewState::get_brew_prefix();
+    let content = String::from_utf8_lossy(&output.stdout);
+    let broken: Vec<&str> = content
+        .lines()
+        .filter(|tap| !tap_repository(Path::new(prefix), tap).is_dir())
+        .collect();
+
+    if broken.is_empty() {
+        vec![Finding::ok("taps", "All taps have a repository")]
+    } else {
+        vec![Finding::warning(
+            "taps",
+            format!("Taps without a repository: {}", broken.join(", ")),
+            "Re-tap them with `brew tap --repair`",
+        )]
+    }
+}
+
+#[cfg(unix)]
+fn check_permissions() -> Vec<Finding> {
+    let prefix = HomebrewState::get_brew_prefix();
+    // SAFETY: geteuid has no preconditions and can't fail
+    let uid = unsafe { libc::geteuid() };
+    let mut findings = Vec::new();
+
+    for dir in ["Cellar", "Caskroom"] {
+        findings.extend(check_owner(&Path::new(prefix).join(dir), uid));
+    }
+
+    if findings.is_empty() {
+        findings.push(Finding::ok(
+            "permissions",
+            format!("{} is writable", prefix),
+        ));
+    }
+
+    findings
+}
+
+/// Detection skips upgrades when `brew outdated` reports something it can't parse
+fn check_outdated(runner: &dyn CommandRunner, compat: &BrewCompat) -> Option<Finding> {
+    let output = HomebrewState::run_outdated(runner, compat).ok()??;
+    let e = HomebrewState::parse_outdated_output(&output).err()?;
+    Some(Finding::warning(
+        "brew",
+        format!("Upgrades are not shown: {}", e),
+        "Run `brew outdated --json=v2` to see what brew reports",
+    ))
+}
+
+/// brew refuses to write to directories another user owns, even if their mode allows it
+#[cfg(unix)]
+fn check_owner(path: &Path, uid: u32) -> Option<Finding> {
+    use std::os::unix::fs::MetadataExt;
+
+    let problem = match fs::metadata(path) {
+        Ok(metadata) if metadata.uid() != uid => {
+            format!("{} is owned by another user", path.display())
+        }
+        Ok(metadata) if metadata.permissions().readonly() => {
+            format!("{} is read-only", path.display())
+        }
+        Ok(_) => return None,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => format!("{} is not accessible: {}", path.display(), e),
+    };
+    Some(Finding::warning(
+        "permissions",
+        problem,
+        format!(
+            "Fix ownership with `sudo chown -R $(whoami) {}`",
+            path.display()
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{strip_ansi_codes, FixtureRunner};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_check_mas() {
+        let finding = check_mas(&FixtureRunner::load("sonoma-arm64"));
+        assert_eq!(finding.status, CheckStatus::Ok);
+
+        // Unrecorded commands exit like a missing program
+        let finding = check_mas(&FixtureRunner::parse(""));
+        assert_eq!(finding.status, CheckStatus::Warning);
+    }
+
+    #[test]
+    fn test_check_outdated() {
+        let compat = BrewCompat {
+            version: Some("4.3.5".to_string()),
+        };
+        assert_eq!(
+            check_outdated(&FixtureRunner::load("sonoma-arm64"), &compat),
+            None
+        );
+
+        let runner = FixtureRunner::parse(
+            "$ brew outdated --json=v2 --greedy-auto-updates\nError: No available formula\n",
+        );
+        let finding = check_outdated(&runner, &compat).unwrap();
+        assert_eq!(finding.status, CheckStatus::Warning);
+        assert!(finding.message.contains("invalid JSON"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_owner() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let uid = fs::metadata(temp_dir.path()).unwrap().uid();
+
+        assert_eq!(check_owner(temp_dir.path(), uid), None);
+        assert_eq!(check_owner(&temp_dir.path().join("Caskroom"), uid), None);
+        let finding = check_owner(temp_dir.path(), uid + 1).unwrap();
+        assert_eq!(finding.status, CheckStatus::Warning);
+        assert!(finding.message.ends_with("is owned by another user"));
+    }
+
+    #[test]
+    fn test_check_profile_missing() {
+        let findings = check_profile(Path::new("/nonexistent/profile"));
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].status, CheckStatus::Failed);
+        assert!(has_failures(&findings));
+    }
+
+    #[test]
+    fn test_check_profile_with_brewfile() {
+        let temp_dir = TempDir::new().unwrap();
+        let brewfile_path = temp_dir.path().join("Brewfile");
+        fs::write(
+            temp_dir.path().join("activate"),
+            format!(
+                "brew bundle --file='{}' --no-upgrade\n",
+                brewfile_path.display()
+            ),
+        )
+        .unwrap();
+        fs::write(&brewfile_path, "brew \"git\"\ncask \"firefox\"\n").unwrap();
+
+        let findings = check_profile(temp_dir.path());
+        assert!(!has_failures(&findings));
+        let brewfile = findings.iter().find(|f| f.check == "Brewfile").unwrap();
+        assert!(brewfile.message.contains("1 formulae, 1 casks"));
+    }
+
+    #[test]
+    fn test_check_profile_without_homebrew() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("activate"), "#!/bin/sh\n").unwrap();
+
+        let findings = check_profile(temp_dir.path());
+        let brewfile = findings.iter().find(|f| f.check == "Brewfile").unwrap();
+        assert_eq!(brewfile.status, CheckStatus::Warning);
+    }
+
+    #[test]
+    fn test_write_diagnostics() {
+        let findings = vec![
+            Finding::ok("brew", "Homebrew 4.4.0"),
+            Finding::failed("profile", "missing", "build it"),
+        ];
+        let mut output = String::new();
+        let lines = write_diagnostics(&mut output, &findings).unwrap();
+
+        assert_eq!(lines, 3);
+        let clean = strip_ansi_codes(&output);
+        assert!(clean.contains("[OK] brew: Homebrew 4.4.0"));
+        assert!(clean.contains("[FAIL] profile: missing"));
+    }
+}
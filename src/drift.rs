@@ -0,0 +1,115 @@
+//! A single number for how far the machine drifted from its configuration, e.g. for dashboards
+
+use crate::diff::{Diff, HomebrewDiffData, ItemKey};
+use crate::events::Category;
+
+/// Entries of one category that are in sync with the intent or not
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CategoryDrift {
+    pub category: Category,
+    /// Installed and declared
+    pub matching: usize,
+    /// Declared but not installed
+    pub missing: usize,
+    /// Installed but not declared
+    pub undeclared: usize,
+}
+
+impl CategoryDrift {
+    fn new<T: ItemKey>(category: Category, diff: &Diff<T>) -> Self {
+        Self {
+            category,
+            matching: diff.matching,
+            missing: diff.added.len(),
+            undeclared: diff.removed.len(),
+        }
+    }
+
+    /// Everything installed or declared
+    pub fn total(&self) -> usize {
+        self.matching + self.missing + self.undeclared
+    }
+
+    pub fn percent_in_sync(&self) -> f64 {
+        percent(self.matching, self.total())
+    }
+}
+
+/// How much of what's installed or declared matches, per category and overall
+#[derive(Debug, Clone, PartialEq)]
+pub struct DriftScore {
+    pub categories: Vec<CategoryDrift>,
+}
+
+impl DriftScore {
+    pub fn matching(&self) -> usize {
+        self.categories.iter().map(|c| c.matching).sum()
+    }
+
+    pub fn total(&self) -> usize {
+        self.categories.iter().map(CategoryDrift::total).sum()
+    }
+
+    /// Share of installed and declared entries that match, 100 when there are none
+    pub fn percent_in_sync(&self) -> f64 {
+        percent(self.matching(), self.total())
+    }
+}
+
+fn percent(matching: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 100.0;
+    }
+    matching as f64 * 100.0 / total as f64
+}
+
+impl HomebrewDiffData {
+    /// How closely installed packages match the intent, counting unmanaged App Store apps too
+    /// Version upgrades and option mismatches don't count as drift
+    pub fn drift(&self) -> DriftScore {
+        DriftScore {
+            categories: vec![
+                CategoryDrift::new(Category::Taps, &self.taps),
+                CategoryDrift::new(Category::Formulae, &self.brews),
+                CategoryDrift::new(Category::Casks, &self.casks),
+                CategoryDrift::new(Category::AppStore, &self.mas_apps),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intent::HomebrewIntent;
+    use crate::state::HomebrewState;
+
+    #[test]
+    fn test_drift_score() {
+        let mut state = HomebrewState::default();
+        for brew in ["git", "wget", "jq"] {
+            state
+                .installed_brews
+                .insert(brew.to_string(), String::new());
+        }
+        state
+            .installed_casks
+            .insert("slack".to_string(), String::new());
+        let mut intent = HomebrewIntent::default();
+        intent.brews.extend(["git".to_string(), "wget".to_string()]);
+        intent.casks.insert("firefox".to_string());
+
+        let drift = HomebrewDiffData::compute(&state, &intent).drift();
+
+        let formulae = drift.categories[1];
+        assert_eq!(
+            (formulae.matching, formulae.missing, formulae.undeclared),
+            (2, 0, 1)
+        );
+        assert_eq!(drift.categories[2].percent_in_sync(), 0.0);
+        assert_eq!(drift.matching(), 2);
+        assert_eq!(drift.total(), 5);
+        assert_eq!(drift.percent_in_sync(), 40.0);
+        assert_eq!(HomebrewDiffData::default().drift().percent_in_sync(), 100.0);
+    }
+}
@@ -0,0 +1,163 @@
+//! The same application installed or declared in more than one category, e.g. `brew "neovim"`
+//! next to `cask "neovim"`, or a cask next to the App Store version of the app
+//! Entries are matched by normalized name, and installed casks also by the names of the app
+//! bundles their receipts list, which catches casks whose token differs from the app
+
+use crate::diff::version_family;
+use crate::events::Category;
+use crate::explain::label;
+use crate::intent::HomebrewIntent;
+use crate::state::HomebrewState;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Entries of different categories that likely are the same application
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Duplicate {
+    /// Sorted by category, then name
+    pub entries: Vec<(Category, String)>,
+}
+
+impl Duplicate {
+    /// Check if any entry matches
+    pub fn any(&self, mut f: impl FnMut(&str) -> bool) -> bool {
+        self.entries.iter().any(|(_, name)| f(name))
+    }
+}
+
+impl std::fmt::Display for Duplicate {
+    /// e.g. "formula neovim and cask neovim"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let entries: Vec<String> = self
+            .entries
+            .iter()
+            .map(|(category, name)| format!("{} {}", label(*category), name))
+            .collect();
+        match entries.split_last() {
+            Some((last, rest)) if !rest.is_empty() => {
+                write!(f, "{} and {}", rest.join(", "), last)
+            }
+            _ => f.write_str(&entries.concat()),
+        }
+    }
+}
+
+/// Lowercase letters and digits only, so `Visual Studio Code` matches `visual-studio-code`
+fn match_key(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// The name of an entry without tap, version or App Store id
+fn bare_name(category: Category, entry: &str) -> &str {
+    let name = match category {
+        Category::AppStore => entry.rsplit_once(" (").map_or(entry, |(name, _)| name),
+        _ => entry.rsplit('/').next().unwrap_or(entry),
+    };
+    match category {
+        Category::Formulae => version_family(name),
+        _ => name,
+    }
+}
+
+/// Installed and declared formulae, casks and App Store apps that share a name across
+/// categories, casks also match by the app bundles they installed
+pub fn find_duplicates(
+    current_state: &HomebrewState,
+    nix_intent: &HomebrewIntent,
+) -> Vec<Duplicate> {
+    let formulae = current_state
+        .installed_brews
+        .keys()
+        .chain(&nix_intent.brews)
+        .map(|name| (Category::Formulae, name));
+    let casks = current_state
+        .installed_casks
+        .keys()
+        .chain(&nix_intent.casks)
+        .map(|name| (Category::Casks, name));
+    let apps = current_state
+        .installed_mas_apps
+        .iter()
+        .chain(&nix_intent.mas_apps)
+        .map(|name| (Category::AppStore, name));
+
+    let mut by_key: BTreeMap<String, BTreeSet<(Category, &str)>> = BTreeMap::new();
+    for (category, name) in formulae.chain(casks).chain(apps) {
+        let mut keys = vec![match_key(bare_name(category, name))];
+        let receipt = match category {
+            Category::Casks => current_state.cask_receipts.get(name),
+            _ => None,
+        };
+        if let Some(receipt) = receipt {
+            keys.extend(
+                receipt
+                    .apps
+                    .iter()
+                    .map(|app| match_key(app.trim_end_matches(".app"))),
+            );
+        }
+        for key in keys.into_iter().filter(|key| !key.is_empty()) {
+            by_key.entry(key).or_default().insert((category, name));
+        }
+    }
+
+    // A cask matching by both its name and its app would otherwise show up twice
+    let groups: BTreeSet<BTreeSet<(Category, &str)>> = by_key
+        .into_values()
+        .filter(|entries| {
+            let categories: BTreeSet<Category> = entries.iter().map(|(c, _)| *c).collect();
+            categories.len() > 1
+        })
+        .collect();
+    groups
+        .into_iter()
+        .map(|entries| Duplicate {
+            entries: entries
+                .into_iter()
+                .map(|(category, name)| (category, name.to_string()))
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::CaskReceipt;
+
+    #[test]
+    fn test_find_duplicates() {
+        let mut state = HomebrewState::from_bundle_dump(
+            "brew \"neovim\"\nbrew \"wget\"\ncask \"visual-studio-code\"\nmas \"Xcode\", id: 497799835\n",
+        );
+        state
+            .installed_casks
+            .insert("1password".to_string(), "8.10".to_string());
+        state.cask_receipts.insert(
+            "1password".to_string(),
+            CaskReceipt {
+                apps: vec!["1Password 7.app".to_string()],
+                ..Default::default()
+            },
+        );
+        let intent = HomebrewIntent::from_brewfile(
+            "brew \"python@3.12\"\ncask \"neovim\"\ncask \"python\"\nmas \"1Password 7\", id: 1333542190\nmas \"Visual Studio Code\", id: 1\n",
+        );
+
+        let duplicates = find_duplicates(&state, &intent);
+
+        let rendered: Vec<String> = duplicates.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            rendered,
+            vec![
+                "formula neovim and cask neovim",
+                "formula python@3.12 and cask python",
+                "cask 1password and App Store app 1Password 7 (1333542190)",
+                "cask visual-studio-code and App Store app Visual Studio Code (1)",
+            ]
+        );
+        assert!(duplicates[0].any(|name| name == "neovim"));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::safety::SafetyViolation;
 use std::fmt;
 use std::io;
 use thiserror::Error;
@@ -13,6 +14,9 @@ pub enum Error {
     #[error("Brewfile not found in activation script")]
     BrewfileNotFound,
 
+    #[error("Brewfile at {0} is outside the Nix store and the profile")]
+    UntrustedBrewfile(String),
+
     #[error("Failed to parse Brewfile: {0}")]
     ParseError(String),
 
@@ -28,8 +32,30 @@ pub enum Error {
     #[error("UTF-8 conversion error: {0}")]
     Utf8(#[from] std::string::FromUtf8Error),
 
+    #[cfg(feature = "watch")]
+    #[error("Watch error: {0}")]
+    Watch(#[from] notify::Error),
+
+    #[error("System diff failed: {0}")]
+    SystemDiff(String),
+
     #[error("Command execution failed: {0}")]
     CommandFailed(String),
+
+    #[error("Another reconciliation is in progress, lock held at {0}")]
+    ReconcileInProgress(String),
+
+    #[error("Git hook already exists at {0}, remove it or call brewdiff from it")]
+    HookExists(String),
+
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
+    #[error("Unknown category {0}, expected taps, formulae, casks or mas")]
+    UnknownCategory(String),
+
+    #[error("Refusing to activate without --force: {}", SafetyViolation::join(.0))]
+    Unsafe(Vec<SafetyViolation>),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,196 @@
+use crate::diff::{HomebrewDiffData, Removal, VersionChange};
+use crate::error::{Error, Result};
+use std::str::FromStr;
+
+/// A package category of the diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Category {
+    Taps,
+    Formulae,
+    Casks,
+    AppStore,
+}
+
+impl Category {
+    pub const ALL: [Category; 4] = [
+        Category::Taps,
+        Category::Formulae,
+        Category::Casks,
+        Category::AppStore,
+    ];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl FromStr for Category {
+    type Err = Error;
+
+    /// Parse a category name as given to `--only` and `--skip`
+    fn from_str(name: &str) -> Result<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "taps" | "tap" => Ok(Category::Taps),
+            "formulae" | "formula" | "brews" | "brew" => Ok(Category::Formulae),
+            "casks" | "cask" => Ok(Category::Casks),
+            "mas" | "appstore" | "app-store" => Ok(Category::AppStore),
+            _ => Err(Error::UnknownCategory(name.trim().to_string())),
+        }
+    }
+}
+
+/// A selection of categories, e.g. from `--only casks` or `--skip mas,taps`
+/// Defaults to all of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CategorySet(u8);
+
+impl Default for CategorySet {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl CategorySet {
+    pub fn all() -> Self {
+        Self::only(Category::ALL)
+    }
+
+    pub fn none() -> Self {
+        Self(0)
+    }
+
+    /// Only the given categories
+    pub fn only(categories: impl IntoIterator<Item = Category>) -> Self {
+        Self(categories.into_iter().fold(0, |bits, c| bits | c.bit()))
+    }
+
+    /// This selection without the categories of the other, e.g. for `--skip`
+    pub fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// Categories selected in both
+    pub fn intersect(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    pub fn contains(self, category: Category) -> bool {
+        self.0 & category.bit() != 0
+    }
+
+    pub fn is_all(self) -> bool {
+        self == Self::all()
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Category> {
+        Category::ALL.into_iter().filter(move |c| self.contains(*c))
+    }
+}
+
+impl FromStr for CategorySet {
+    type Err = Error;
+
+    /// Parse a comma separated list like `mas,taps`
+    fn from_str(list: &str) -> Result<Self> {
+        let categories = list
+            .split(',')
+            .filter(|name| !name.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<Category>>>()?;
+        Ok(Self::only(categories))
+    }
+}
+
+/// A typed step through the diff, for embedders doing their own rendering
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffEvent<'a> {
+    /// Start of a category with at least one item
+    SectionStart(Category),
+    /// Declared but not installed
+    ItemAdded(&'a str),
+    /// Installed but not declared, check the effect for whether activation removes it
+    ItemRemoved(&'a Removal),
+    /// Newer version available, check `applied` for whether activation upgrades it
+    ItemUpgraded(&'a VersionChange),
+    /// End of the category started last
+    SectionEnd(Category),
+}
+
+impl HomebrewDiffData {
+    /// Walk the diff as events, one section per non-empty category
+    /// Taps come first, App Store apps last, items keep the diff's sort order
+    pub fn events(&self) -> impl Iterator<Item = DiffEvent<'_>> {
+        let categories = [
+            (Category::Taps, &self.taps),
+            (Category::Formulae, &self.brews),
+            (Category::Casks, &self.casks),
+            (Category::AppStore, &self.mas_apps),
+        ];
+
+        let mut events = Vec::new();
+        for (category, diff) in categories {
+            if diff.is_empty() {
+                continue;
+            }
+
+            events.push(DiffEvent::SectionStart(category));
+            events.extend(diff.added.iter().map(|name| DiffEvent::ItemAdded(name)));
+            events.extend(diff.removed.iter().map(DiffEvent::ItemRemoved));
+            events.extend(diff.upgraded.iter().map(DiffEvent::ItemUpgraded));
+            events.push(DiffEvent::SectionEnd(category));
+        }
+        events.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::ChangeEffect;
+
+    #[test]
+    fn test_category_set() {
+        let only: CategorySet = "casks".parse().unwrap();
+        assert_eq!(only.iter().collect::<Vec<_>>(), vec![Category::Casks]);
+
+        let skipped = CategorySet::all().without("mas, taps".parse::<CategorySet>().unwrap());
+        assert!(skipped.contains(Category::Formulae));
+        assert!(skipped.contains(Category::Casks));
+        assert!(!skipped.contains(Category::AppStore));
+        assert!(!skipped.contains(Category::Taps));
+        assert_eq!(skipped.intersect(only), only);
+
+        assert!(matches!(
+            "kegs".parse::<CategorySet>(),
+            Err(Error::UnknownCategory(name)) if name == "kegs"
+        ));
+    }
+
+    #[test]
+    fn test_events() {
+        let mut diff = HomebrewDiffData::default();
+        diff.brews.added = vec!["wget".to_string()];
+        diff.brews.removed = vec![Removal::new("git", ChangeEffect::WillBeRemoved)];
+        diff.mas_apps.added = vec!["Xcode (497799835)".to_string()];
+
+        let events: Vec<DiffEvent> = diff.events().collect();
+
+        assert_eq!(
+            events,
+            vec![
+                DiffEvent::SectionStart(Category::Formulae),
+                DiffEvent::ItemAdded("wget"),
+                DiffEvent::ItemRemoved(&diff.brews.removed[0]),
+                DiffEvent::SectionEnd(Category::Formulae),
+                DiffEvent::SectionStart(Category::AppStore),
+                DiffEvent::ItemAdded("Xcode (497799835)"),
+                DiffEvent::SectionEnd(Category::AppStore),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_events_without_changes() {
+        assert_eq!(HomebrewDiffData::default().events().count(), 0);
+    }
+}
@@ -0,0 +1,461 @@
+//! Why an entry shows up in the diff, from its Brewfile line to how it was detected
+
+use crate::diff::{self, HomebrewDiffData, Removal, VersionChange};
+use crate::error::Result;
+use crate::events::Category;
+use crate::intent::HomebrewIntent;
+use crate::metadata;
+use crate::state::{DetectionSource, HomebrewState};
+use crate::version;
+use owo_colors::OwoColorize;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+/// Everything known about the entries matching a name
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Explanation {
+    pub query: String,
+    /// The Brewfile the declarations were found in, None when it wasn't read from a profile
+    pub brewfile: Option<PathBuf>,
+    pub declarations: Vec<Declaration>,
+    pub installations: Vec<Installation>,
+    pub changes: Vec<Change>,
+    /// How the name was matched to entries with other names, e.g. tap-qualified formulae
+    pub resolution: Vec<String>,
+}
+
+/// A Brewfile line declaring a matching entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Declaration {
+    pub category: Category,
+    /// 1-based
+    pub line: usize,
+    pub text: String,
+}
+
+/// A matching installed entry and where detection found it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Installation {
+    pub category: Category,
+    pub name: String,
+    /// None for taps, App Store apps and versions that weren't looked up
+    pub version: Option<String>,
+    pub source: DetectionSource,
+}
+
+/// A matching entry of the diff
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added(Category, String),
+    Removed(Category, Removal),
+    Upgraded(Category, VersionChange),
+}
+
+impl Explanation {
+    /// Whether anything matched the name
+    pub fn is_empty(&self) -> bool {
+        self.declarations.is_empty() && self.installations.is_empty() && self.changes.is_empty()
+    }
+}
+
+/// Explain the entries matching `query`, given the Brewfile the intent was parsed from
+/// The query can be a short or tap-qualified name, a tap, or an App Store app's name or id
+pub fn explain(
+    query: &str,
+    brewfile: &str,
+    current_state: &HomebrewState,
+    diff_data: &HomebrewDiffData,
+) -> Explanation {
+    let query = query.trim();
+    let mut explanation = Explanation {
+        query: query.to_string(),
+        ..Default::default()
+    };
+    let resolution = &mut explanation.resolution;
+
+    // Parse line by line to know where each entry is declared
+    for (index, line) in brewfile.lines().enumerate() {
+        let parsed = HomebrewIntent::from_brewfile(line);
+        let entries = [
+            (Category::Taps, &parsed.taps),
+            (Category::Formulae, &parsed.brews),
+            (Category::Casks, &parsed.casks),
+            (Category::AppStore, &parsed.mas_apps),
+        ];
+        for (category, names) in entries {
+            if names
+                .iter()
+                .any(|name| matches(query, name, category, resolution))
+            {
+                explanation.declarations.push(Declaration {
+                    category,
+                    line: index + 1,
+                    text: line.trim().to_string(),
+                });
+            }
+        }
+    }
+
+    let sources = current_state.sources;
+    let installed = [
+        (
+            Category::Taps,
+            sources.taps,
+            unversioned(&current_state.installed_taps),
+        ),
+        (
+            Category::Formulae,
+            sources.formulae,
+            versioned(&current_state.installed_brews),
+        ),
+        (
+            Category::Casks,
+            sources.casks,
+            versioned(&current_state.installed_casks),
+        ),
+        (
+            Category::AppStore,
+            sources.mas_apps,
+            unversioned(&current_state.installed_mas_apps),
+        ),
+    ];
+    for (category, source, entries) in installed {
+        for (name, version) in entries {
+            if matches(query, name, category, resolution) {
+                explanation.installations.push(Installation {
+                    category,
+                    name: name.to_string(),
+                    version: version.map(str::to_string),
+                    source,
+                });
+            }
+        }
+    }
+    explanation
+        .installations
+        .sort_by(|a, b| diff::natural_cmp(&a.name, &b.name));
+
+    let diffs = [
+        (Category::Taps, &diff_data.taps),
+        (Category::Formulae, &diff_data.brews),
+        (Category::Casks, &diff_data.casks),
+        (Category::AppStore, &diff_data.mas_apps),
+    ];
+    for (category, diff) in diffs {
+        for name in &diff.added {
+            if matches(query, name, category, resolution) {
+                explanation
+                    .changes
+                    .push(Change::Added(category, name.clone()));
+            }
+        }
+        for removal in &diff.removed {
+            if matches(query, &removal.name, category, resolution) {
+                explanation
+                    .changes
+                    .push(Change::Removed(category, removal.clone()));
+            }
+        }
+        for change in &diff.upgraded {
+            if matches(query, &change.name, category, resolution) {
+                explanation
+                    .changes
+                    .push(Change::Upgraded(category, change.clone()));
+            }
+        }
+    }
+
+    // Renamed App Store apps are neither added nor removed, only matched by id
+    for mismatch in &diff_data.mas_name_mismatches {
+        let declared = matches(query, &mismatch.declared, Category::AppStore, resolution);
+        let installed = matches(query, &mismatch.installed, Category::AppStore, resolution);
+        if declared || installed {
+            push_step(
+                resolution,
+                format!(
+                    "{} is installed as {}, matched by App Store id {}",
+                    mismatch.declared, mismatch.installed, mismatch.id
+                ),
+            );
+        }
+    }
+
+    explanation
+}
+
+/// Whether `entry` is what `query` refers to, recording how if the names differ
+fn matches(query: &str, entry: &str, category: Category, resolution: &mut Vec<String>) -> bool {
+    if entry == query {
+        return true;
+    }
+
+    let step = match category {
+        Category::Formulae | Category::Casks => {
+            if let Some(tap) =
+                metadata::tap_of(entry).filter(|tap| entry[tap.len() + 1..] == *query)
+            {
+                format!("{} resolves to {} from {}", query, entry, tap)
+            } else if metadata::tap_of(query).is_some_and(|tap| query[tap.len() + 1..] == *entry) {
+                format!("{} is known to brew as {}", query, entry)
+            } else {
+                return false;
+            }
+        }
+        Category::Taps if entry.eq_ignore_ascii_case(query) => {
+            format!("tap names are case-insensitive, {} is {}", query, entry)
+        }
+        Category::Taps => return false,
+        Category::AppStore => {
+            let id = diff::mas_id(entry);
+            if id.is_some() && (id == Some(query) || id == diff::mas_id(query)) {
+                format!("App Store id {} is {}", id.unwrap_or_default(), entry)
+            } else if mas_name(entry).is_some_and(|name| {
+                diff::normalize_mas_name(name) == diff::normalize_mas_name(query)
+            }) {
+                format!("{} is the App Store app {}", query, entry)
+            } else {
+                return false;
+            }
+        }
+    };
+
+    push_step(resolution, step);
+    true
+}
+
+fn push_step(resolution: &mut Vec<String>, step: String) {
+    if !resolution.contains(&step) {
+        resolution.push(step);
+    }
+}
+
+/// The name part of an "App Name (id)" entry
+fn mas_name(entry: &str) -> Option<&str> {
+    Some(entry.rsplit_once(" (")?.0)
+}
+
+fn versioned(entries: &HashMap<String, String>) -> Vec<(&str, Option<&str>)> {
+    entries
+        .iter()
+        .map(|(name, version)| {
+            (
+                name.as_str(),
+                Some(version.as_str()).filter(|version| !version::is_unversioned(version)),
+            )
+        })
+        .collect()
+}
+
+fn unversioned(entries: &HashSet<String>) -> Vec<(&str, Option<&str>)> {
+    entries.iter().map(|name| (name.as_str(), None)).collect()
+}
+
+pub(crate) fn label(category: Category) -> &'static str {
+    match category {
+        Category::Taps => "tap",
+        Category::Formulae => "formula",
+        Category::Casks => "cask",
+        Category::AppStore => "App Store app",
+    }
+}
+
+/// Write an explanation, returns number of lines written
+pub fn write_explanation<W: Write>(writer: &mut W, explanation: &Explanation) -> Result<usize> {
+    let mut lines_written = 0;
+
+    if explanation.is_empty() {
+        writeln!(
+            writer,
+            "{} isn't declared, installed or in the diff",
+            explanation.query.bold()
+        )?;
+        return Ok(1);
+    }
+    writeln!(writer, "{}", explanation.query.bold())?;
+    lines_written += 1;
+
+    let brewfile = explanation
+        .brewfile
+        .as_ref()
+        .map_or_else(|| "Brewfile".to_string(), |path| path.display().to_string());
+    if explanation.declarations.is_empty() {
+        writeln!(writer, "  Declared: not in {}", brewfile)?;
+        lines_written += 1;
+    }
+    for declaration in &explanation.declarations {
+        writeln!(
+            writer,
+            "  Declared: {}:{} {}",
+            brewfile,
+            declaration.line,
+            declaration.text.dimmed()
+        )?;
+        lines_written += 1;
+    }
+
+    if explanation.installations.is_empty() {
+        writeln!(writer, "  Installed: no")?;
+        lines_written += 1;
+    }
+    for installation in &explanation.installations {
+        write!(
+            writer,
+            "  Installed: {} {}",
+            label(installation.category),
+            installation.name
+        )?;
+        if let Some(version) = &installation.version {
+            write!(writer, " {}", version)?;
+        }
+        writeln!(writer, ", found by {}", installation.source)?;
+        lines_written += 1;
+    }
+
+    if explanation.changes.is_empty() {
+        writeln!(writer, "  Diff: unchanged")?;
+        lines_written += 1;
+    }
+    for change in &explanation.changes {
+        match change {
+            Change::Added(category, name) => writeln!(
+                writer,
+                "  Diff: {} {} {}",
+                "added".green(),
+                label(*category),
+                name
+            )?,
+            Change::Removed(category, removal) => {
+                let effect = if removal.effect.is_destructive() {
+                    "removed by activation"
+                } else {
+                    "left installed by activation"
+                };
+                writeln!(
+                    writer,
+                    "  Diff: {} {} {}, {}",
+                    "undeclared".red(),
+                    label(*category),
+                    removal.name,
+                    effect
+                )?
+            }
+            Change::Upgraded(category, change) => writeln!(
+                writer,
+                "  Diff: {} {} {} {} -> {}{}",
+                "upgraded".yellow(),
+                label(*category),
+                change.name,
+                change.installed,
+                change.available,
+                if change.applied { "" } else { ", not applied" }
+            )?,
+        }
+        lines_written += 1;
+    }
+
+    for step in &explanation.resolution {
+        writeln!(writer, "  Resolved: {}", step)?;
+        lines_written += 1;
+    }
+
+    Ok(lines_written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::strip_ansi_codes;
+
+    const BREWFILE: &str = r#"tap "hashicorp/tap"
+brew "hashicorp/tap/terraform"
+brew "wget"
+cask "firefox"
+mas "Xcode", id: 497799835
+"#;
+
+    fn explain_in(query: &str, dump: &str) -> Explanation {
+        let state = HomebrewState::from_bundle_dump(dump);
+        let intent = HomebrewIntent::from_brewfile(BREWFILE);
+        let diff = HomebrewDiffData::compute(&state, &intent);
+        explain(query, BREWFILE, &state, &diff)
+    }
+
+    #[test]
+    fn test_explain_tap_qualified_formula() {
+        let explanation = explain_in("terraform", "brew \"wget\"\n");
+
+        assert_eq!(
+            explanation.declarations,
+            vec![Declaration {
+                category: Category::Formulae,
+                line: 2,
+                text: "brew \"hashicorp/tap/terraform\"".to_string(),
+            }]
+        );
+        assert!(explanation.installations.is_empty());
+        assert_eq!(
+            explanation.changes,
+            vec![Change::Added(
+                Category::Formulae,
+                "hashicorp/tap/terraform".to_string()
+            )]
+        );
+        assert_eq!(
+            explanation.resolution,
+            vec!["terraform resolves to hashicorp/tap/terraform from hashicorp/tap"]
+        );
+    }
+
+    #[test]
+    fn test_explain_undeclared_entry() {
+        let explanation = explain_in("htop", "brew \"wget\"\nbrew \"htop\"\n");
+
+        assert!(explanation.declarations.is_empty());
+        assert_eq!(
+            explanation.installations,
+            vec![Installation {
+                category: Category::Formulae,
+                name: "htop".to_string(),
+                version: None,
+                source: DetectionSource::BundleDump,
+            }]
+        );
+        assert!(matches!(
+            &explanation.changes[..],
+            [Change::Removed(Category::Formulae, removal)] if removal.name == "htop"
+        ));
+        assert!(explanation.resolution.is_empty());
+    }
+
+    #[test]
+    fn test_explain_renamed_app_store_app() {
+        let explanation = explain_in("497799835", "mas \"Xcode-beta\", id: 497799835\n");
+
+        assert_eq!(explanation.declarations.len(), 1);
+        assert_eq!(explanation.installations[0].name, "Xcode-beta (497799835)");
+        assert!(explanation.changes.is_empty());
+        assert!(explanation
+            .resolution
+            .contains(&"Xcode (497799835) is installed as Xcode-beta (497799835), matched by App Store id 497799835".to_string()));
+    }
+
+    #[test]
+    fn test_write_explanation() {
+        let mut explanation = explain_in("firefox", "cask \"firefox\"\n");
+        explanation.brewfile = Some(PathBuf::from("/nix/store/abc-Brewfile"));
+
+        let mut output = String::new();
+        let lines = write_explanation(&mut output, &explanation).unwrap();
+        let output = strip_ansi_codes(&output);
+
+        assert_eq!(lines, 4);
+        assert!(output.contains("Declared: /nix/store/abc-Brewfile:4 cask \"firefox\"\n"));
+        assert!(output.contains("Installed: cask firefox, found by brew bundle dump\n"));
+        assert!(output.contains("Diff: unchanged\n"));
+
+        let mut output = String::new();
+        write_explanation(&mut output, &explain_in("nope", "")).unwrap();
+        assert_eq!(output.lines().count(), 1);
+    }
+}
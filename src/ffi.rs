@@ -0,0 +1,151 @@
+//! Minimal C ABI for embedding the diff in Swift or Objective-C apps
+
+use crate::diff::{ChangeEffect, Diff, HomebrewDiffData, ItemKey};
+use crate::error::Result;
+use crate::intent::HomebrewIntent;
+use crate::runner::SystemRunner;
+use crate::state::HomebrewState;
+use serde_json::{json, Value};
+use std::ffi::{c_char, CStr, CString};
+use std::panic;
+use std::path::Path;
+
+/// Compute the diff for a built profile and return it as a JSON string
+/// Failures are returned as `{"error": "..."}`, NULL only if `profile_path` is NULL
+///
+/// # Safety
+///
+/// `profile_path` must be NULL or a valid NUL-terminated string. The returned string
+/// must be released with `brewdiff_string_free`.
+#[no_mangle]
+pub unsafe extern "C" fn brewdiff_compute_json(profile_path: *const c_char) -> *mut c_char {
+    if profile_path.is_null() {
+        return std::ptr::null_mut();
+    }
+    let profile = CStr::from_ptr(profile_path).to_string_lossy().into_owned();
+
+    let value = panic::catch_unwind(|| compute(Path::new(&profile)))
+        .unwrap_or_else(|_| Err(crate::Error::CommandFailed("brewdiff panicked".to_string())))
+        .unwrap_or_else(|e| json!({ "error": e.to_string() }));
+
+    // serde_json escapes control characters, so the output never contains a NUL
+    CString::new(value.to_string())
+        .map(CString::into_raw)
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// Release a string returned by `brewdiff_compute_json`
+///
+/// # Safety
+///
+/// `s` must be NULL or a pointer returned by `brewdiff_compute_json` that wasn't freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn brewdiff_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+fn compute(profile: &Path) -> Result<Value> {
+    // A broken profile fails fast, without waiting for detection
+    let nix_intent = HomebrewIntent::extract(profile)?;
+    let runner = SystemRunner::default();
+    let mut current_state = HomebrewState::detect_with(&runner)?;
+    current_state.load_locked_versions(&runner, &nix_intent.locked)?;
+    let mut diff = HomebrewDiffData::compute_owned(current_state, nix_intent);
+    diff.load_versions(&runner)?;
+    Ok(diff_to_json(&diff))
+}
+
+/// JSON shape handed over the C ABI, kept flat so it's easy to decode with Codable
+fn diff_to_json(diff: &HomebrewDiffData) -> Value {
+    json!({
+        "has_changes": diff.has_changes(),
+        "total_changes": diff.total_changes(),
+        "taps": category_to_json(&diff.taps),
+        "formulae": category_to_json(&diff.brews),
+        "casks": category_to_json(&diff.casks),
+        "mas_apps": category_to_json(&diff.mas_apps),
+    })
+}
+
+fn category_to_json<T: ItemKey>(diff: &Diff<T>) -> Value {
+    let removed: Vec<Value> = diff
+        .removed
+        .iter()
+        .map(|removal| {
+            json!({
+                "name": removal.name,
+                "version": removal.version,
+                "effect": effect_name(removal.effect),
+                "severity": removal.severity.to_string(),
+            })
+        })
+        .collect();
+    let upgraded: Vec<Value> = diff
+        .upgraded
+        .iter()
+        .map(|change| {
+            json!({
+                "name": change.name,
+                "installed": change.installed,
+                "available": change.available,
+                "applied": change.applied,
+            })
+        })
+        .collect();
+
+    json!({
+        "added": diff.added.iter().map(ItemKey::key).collect::<Vec<_>>(),
+        "removed": removed,
+        "upgraded": upgraded,
+    })
+}
+
+fn effect_name(effect: ChangeEffect) -> &'static str {
+    match effect {
+        ChangeEffect::WillBeRemoved => "remove",
+        ChangeEffect::WillBeZapped => "zap",
+        ChangeEffect::LeftInstalled => "keep",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::Removal;
+
+    #[test]
+    fn test_diff_to_json() {
+        let mut diff = HomebrewDiffData::default();
+        diff.brews.added = vec!["wget".to_string()];
+        diff.casks.removed =
+            vec![Removal::new("slack", ChangeEffect::WillBeZapped).with_version("4.41")];
+
+        let value = diff_to_json(&diff);
+
+        assert_eq!(value["has_changes"], true);
+        assert_eq!(value["formulae"]["added"], json!(["wget"]));
+        assert_eq!(
+            value["casks"]["removed"],
+            json!([{ "name": "slack", "version": "4.41", "effect": "zap", "severity": "warning" }])
+        );
+        assert_eq!(value["taps"]["upgraded"], json!([]));
+    }
+
+    #[test]
+    fn test_compute_json_round_trip() {
+        let profile = CString::new("/nonexistent/profile").unwrap();
+
+        unsafe {
+            let json = brewdiff_compute_json(profile.as_ptr());
+            assert!(!json.is_null());
+            let value: Value =
+                serde_json::from_str(CStr::from_ptr(json).to_str().unwrap()).unwrap();
+            assert!(value["error"].is_string());
+            brewdiff_string_free(json);
+
+            assert!(brewdiff_compute_json(std::ptr::null()).is_null());
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Narrowing the diff down to names matching glob patterns, e.g. `python*`, or to categories
+
+use crate::diff::{Diff, HomebrewDiffData, ItemKey};
+use crate::events::{Category, CategorySet};
+
+/// Glob patterns an entry's name has to match one of
+/// `*` matches any run of characters and `?` a single one, case-insensitively
+/// Formulae and casks also match by the name without their tap, App Store apps without their id
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NameFilter {
+    patterns: Vec<String>,
+}
+
+impl NameFilter {
+    pub fn new<I, S>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            patterns: patterns
+                .into_iter()
+                .map(|pattern| pattern.into().to_lowercase())
+                .filter(|pattern| !pattern.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Whether there are no patterns, so everything matches
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Whether the name matches any pattern, always true without patterns
+    pub fn matches(&self, name: &str) -> bool {
+        if self.patterns.is_empty() {
+            return true;
+        }
+        let name = name.to_lowercase();
+        let short = name.rsplit('/').next().unwrap_or(&name);
+        let app = name
+            .strip_suffix(')')
+            .and_then(|name| name.rsplit_once(" ("))
+            .map(|(app, _)| app);
+
+        self.patterns.iter().any(|pattern| {
+            glob_match(pattern, &name)
+                || glob_match(pattern, short)
+                || app.is_some_and(|app| glob_match(pattern, app))
+        })
+    }
+}
+
+impl<T: ItemKey> Diff<T> {
+    fn retain_names(&mut self, filter: &NameFilter) {
+        self.added.retain(|item| filter.matches(item.key()));
+        self.removed.retain(|removal| filter.matches(removal.key()));
+        self.upgraded.retain(|change| filter.matches(change.key()));
+    }
+}
+
+impl HomebrewDiffData {
+    /// A copy of the diff with only the entries whose names match the filter
+    /// Applies to every category and mismatch, so sections and stats agree
+    pub fn filtered(&self, filter: &NameFilter) -> Self {
+        let mut diff = self.clone();
+        diff.brews.retain_names(filter);
+        diff.casks.retain_names(filter);
+        diff.taps.retain_names(filter);
+        diff.mas_apps.retain_names(filter);
+        diff.link_mismatches
+            .retain(|mismatch| filter.matches(&mismatch.name));
+        diff.option_mismatches
+            .retain(|mismatch| filter.matches(&mismatch.name));
+        diff.appdir_mismatches
+            .retain(|mismatch| filter.matches(&mismatch.name));
+        diff.tap_remote_mismatches
+            .retain(|mismatch| filter.matches(&mismatch.name));
+        diff.mas_name_mismatches.retain(|mismatch| {
+            filter.matches(&mismatch.declared) || filter.matches(&mismatch.installed)
+        });
+        diff.cask_matches.retain(|matched| {
+            filter.matches(&matched.declared) || filter.matches(&matched.installed)
+        });
+        diff.version_switches
+            .retain(|switch| filter.matches(&switch.from) || filter.matches(&switch.to));
+        diff.duplicates
+            .retain(|duplicate| duplicate.any(|name| filter.matches(name)));
+        diff.pinned_brews.retain(|name| filter.matches(name));
+        diff.locked_versions.retain(|name, _| filter.matches(name));
+        diff.unused_taps.retain(|tap| filter.matches(&tap.name));
+        diff.missing_taps.retain(|tap| {
+            filter.matches(&tap.name) || tap.packages.iter().any(|name| filter.matches(name))
+        });
+        diff.acknowledged
+            .retain(|drift| filter.matches(&drift.name));
+        diff.expired_acknowledgements
+            .retain(|drift| filter.matches(&drift.name));
+        diff.security
+            .retain(|formula| filter.matches(&formula.name));
+        diff.nix_overlaps
+            .retain(|overlap| filter.matches(&overlap.formula));
+        diff.nixpkgs_equivalents
+            .retain(|name, _| filter.matches(name));
+        diff
+    }
+
+    /// A copy of the diff with only the selected categories, along with their mismatches
+    pub fn restricted(&self, categories: CategorySet) -> Self {
+        let mut diff = self.clone();
+        if !categories.contains(Category::Formulae) {
+            diff.brews = Diff::default();
+            diff.link_mismatches.clear();
+            diff.option_mismatches.clear();
+            diff.security.clear();
+            diff.nix_overlaps.clear();
+            diff.nixpkgs_equivalents.clear();
+            diff.version_switches.clear();
+            diff.pinned_brews.clear();
+        }
+        if !categories.contains(Category::Casks) {
+            diff.casks = Diff::default();
+            diff.appdir_mismatches.clear();
+            diff.cask_matches.clear();
+        }
+        if !categories.contains(Category::Taps) {
+            diff.taps = Diff::default();
+            diff.tap_remote_mismatches.clear();
+            diff.missing_taps.clear();
+            diff.unused_taps.clear();
+        }
+        if !categories.contains(Category::AppStore) {
+            diff.mas_apps = Diff::default();
+            diff.mas_name_mismatches.clear();
+        }
+        diff.acknowledged
+            .retain(|drift| categories.contains(drift.category));
+        diff.expired_acknowledgements
+            .retain(|drift| categories.contains(drift.category));
+        diff.duplicates.retain(|duplicate| {
+            duplicate
+                .entries
+                .iter()
+                .any(|(category, _)| categories.contains(*category))
+        });
+        diff
+    }
+}
+
+/// Match a whole name against a glob, backtracking to the last `*` on a mismatch
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more character and retry
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::{ChangeEffect, Removal};
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("python*", "python@3.12"));
+        assert!(glob_match("*sql*", "postgresql@16"));
+        assert!(glob_match("node??", "node22"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("python*", "cpython"));
+        assert!(!glob_match("node?", "node"));
+    }
+
+    #[test]
+    fn test_name_filter() {
+        let filter = NameFilter::new(["Python*", "xcode"]);
+
+        assert!(filter.matches("python@3.12"));
+        assert!(filter.matches("homebrew/core/python-tk@3.12"));
+        assert!(filter.matches("Xcode (497799835)"));
+        assert!(!filter.matches("node"));
+        assert!(NameFilter::default().matches("node"));
+    }
+
+    #[test]
+    fn test_filtered_diff() {
+        let mut diff = HomebrewDiffData::default();
+        diff.brews.added = vec!["python@3.12".to_string(), "node".to_string()];
+        diff.casks.removed = vec![Removal::new("pycharm", ChangeEffect::WillBeRemoved)];
+        diff.taps.added = vec!["hashicorp/tap".to_string()];
+
+        let filtered = diff.filtered(&NameFilter::new(["py*"]));
+
+        assert_eq!(filtered.brews.added, vec!["python@3.12".to_string()]);
+        assert_eq!(filtered.casks.removed.len(), 1);
+        assert!(filtered.taps.added.is_empty());
+        assert_eq!(filtered.total_changes(), 2);
+    }
+
+    #[test]
+    fn test_restricted_diff() {
+        let mut diff = HomebrewDiffData::default();
+        diff.brews.added = vec!["wget".to_string()];
+        diff.casks.added = vec!["firefox".to_string()];
+        diff.taps.removed = vec![Removal::new("old/tap", ChangeEffect::WillBeRemoved)];
+
+        let restricted = diff.restricted(CategorySet::only([Category::Casks]));
+
+        assert_eq!(restricted.casks.added, vec!["firefox".to_string()]);
+        assert!(restricted.brews.is_empty());
+        assert!(restricted.taps.is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+//! Replays `brew`/`mas` output recorded on real machines, see tests/fixtures
+//! Lets detection, diffing and display be tested end to end without Homebrew
+
+use crate::brew_config::BrewConfig;
+use crate::error::Result;
+use crate::runner::CommandRunner;
+use crate::state::{DetectionSource, DetectionSources, HomebrewState};
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{ExitStatus, Output};
+use std::sync::Mutex;
+
+/// A `CommandRunner` answering with recorded output
+/// Commands that weren't recorded exit with 127, like a missing program
+#[derive(Default)]
+pub(crate) struct FixtureRunner {
+    outputs: HashMap<String, String>,
+    calls: Mutex<Vec<String>>,
+}
+
+impl FixtureRunner {
+    /// Load `tests/fixtures/<name>.txt`
+    pub(crate) fn load(name: &str) -> Self {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(format!("{}.txt", name));
+        let transcript = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("can't read fixture {}: {}", path.display(), e));
+        Self::parse(&transcript)
+    }
+
+    /// Parse a transcript of `$ command` lines, each followed by its stdout
+    pub(crate) fn parse(transcript: &str) -> Self {
+        let mut outputs = HashMap::new();
+        let mut current: Option<(String, String)> = None;
+
+        for line in transcript.lines() {
+            if let Some(command) = line.strip_prefix("$ ") {
+                outputs.extend(current.take());
+                current = Some((command.trim().to_string(), String::new()));
+            } else if let Some((_, stdout)) = &mut current {
+                stdout.push_str(line);
+                stdout.push('\n');
+            }
+        }
+        outputs.extend(current);
+
+        Self {
+            outputs,
+            ..Default::default()
+        }
+    }
+
+    /// Also answer `command` with `stdout`, e.g. for commands naming the test's temporary paths
+    pub(crate) fn with(mut self, command: impl Into<String>, stdout: impl Into<String>) -> Self {
+        self.outputs.insert(command.into(), stdout.into());
+        self
+    }
+
+    /// Commands run so far, recorded or not, in the order they were run
+    pub(crate) fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Detect the state from recorded output only
+    /// Skips the platform check and the Cellar/Caskroom scans, which read the host's filesystem
+    pub(crate) fn detect(&self) -> Result<HomebrewState> {
+        let brew_config = BrewConfig::detect(self, "brew");
+        let (outdated_brews, outdated_casks) =
+            HomebrewState::get_outdated(self, &brew_config.compat())?;
+        Ok(HomebrewState {
+            brew_config,
+            installed_brews: HomebrewState::get_installed_formulae_from_brew(self)?,
+            installed_casks: HomebrewState::get_installed_casks_from_brew(self)?,
+            installed_taps: HomebrewState::get_taps(self)?,
+            installed_mas_apps: HomebrewState::parse_mas_list_output(
+                self.outputs.get("mas list").map_or("", String::as_str),
+            ),
+            outdated_brews,
+            outdated_casks,
+            sources: DetectionSources {
+                formulae: DetectionSource::BrewLeaves,
+                casks: DetectionSource::BrewListCask,
+                taps: DetectionSource::BrewTap,
+                mas_apps: DetectionSource::MasList,
+            },
+            ..Default::default()
+        })
+    }
+}
+
+pub(crate) use crate::display::strip_ansi_codes;
+
+impl CommandRunner for FixtureRunner {
+    fn output(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+        // Recorded with plain `brew`, detection calls it by its full path
+        let program = Path::new(program)
+            .file_name()
+            .map_or(program.into(), |name| name.to_string_lossy());
+        let command = std::iter::once(program.as_ref())
+            .chain(args.iter().copied())
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.calls.lock().unwrap().push(command.clone());
+
+        let (code, stdout) = match self.outputs.get(&command) {
+            Some(stdout) => (0, stdout.clone().into_bytes()),
+            None => (127, Vec::new()),
+        };
+        Ok(Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout,
+            stderr: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::{ChangeEffect, HomebrewDiffData};
+    use crate::display::{self, DisplayOptions};
+    use crate::intent::HomebrewIntent;
+    use crate::lockfile::LockedVersions;
+
+    const BREWFILE: &str = r#"
+tap "hashicorp/tap"
+brew "jq"
+brew "node"
+brew "ripgrep"
+brew "wget"
+brew "htop"
+cask "firefox"
+mas "Xcode", id: 497799835
+"#;
+
+    #[test]
+    fn test_replays_recorded_output() {
+        let runner = FixtureRunner::load("sonoma-arm64");
+
+        let output = runner.output("/opt/homebrew/bin/brew", &["tap"]).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"hashicorp/tap\nhomebrew/bundle\n");
+
+        let missing = runner.output("brew", &["doctor"]).unwrap();
+        assert!(!missing.status.success());
+    }
+
+    #[test]
+    fn test_detect_from_fixture() {
+        let state = FixtureRunner::load("sonoma-arm64").detect().unwrap();
+
+        // Versions are only looked up for packages in the diff
+        assert_eq!(state.installed_brews.len(), 4);
+        assert_eq!(state.installed_brews["wget"], "");
+        assert_eq!(state.installed_casks.len(), 2);
+        assert_eq!(state.outdated_brews["node"], "22.2.0");
+        assert_eq!(state.outdated_casks["firefox"], "126.0");
+        assert!(state.installed_taps.contains("homebrew/bundle"));
+        assert!(state.installed_mas_apps.contains("Keynote (409183694)"));
+        assert_eq!(state.brew_compat().version.as_deref(), Some("4.3.5"));
+        assert_eq!(
+            state.brew_config.prefix.as_deref(),
+            Some(Path::new("/opt/homebrew"))
+        );
+        assert_eq!(state.brew_config.uses_api, Some(true));
+        assert_eq!(
+            state.meta().to_string(),
+            "recorded output of Homebrew 4.3.5 at /opt/homebrew (arm64)"
+        );
+    }
+
+    #[test]
+    fn test_load_locked_versions_from_fixture() {
+        let runner = FixtureRunner::load("sonoma-arm64");
+        let mut state = runner.detect().unwrap();
+        let mut locked = LockedVersions::default();
+        locked
+            .brews
+            .insert("node".to_string(), "22.2.0".to_string());
+        assert!(state.lacks_locked_versions(&locked));
+
+        state.load_locked_versions(&runner, &locked).unwrap();
+
+        assert_eq!(state.installed_brews["node"], "21.7.3");
+        assert_eq!(state.installed_brews["wget"], "");
+        assert!(!state.lacks_locked_versions(&locked));
+    }
+
+    #[test]
+    fn test_detect_diff_display_from_fixture() {
+        let runner = FixtureRunner::load("sonoma-arm64");
+        let state = runner.detect().unwrap();
+        let intent = HomebrewIntent::from_brewfile(BREWFILE);
+
+        let mut diff = HomebrewDiffData::compute(&state, &intent);
+        diff.load_versions(&runner).unwrap();
+        assert_eq!(diff.brews.added, vec!["htop".to_string()]);
+        assert_eq!(diff.casks.removed.len(), 1);
+        assert_eq!(diff.casks.removed[0].name, "slack");
+        assert_eq!(diff.casks.removed[0].effect, ChangeEffect::LeftInstalled);
+        assert_eq!(diff.casks.removed[0].version.as_deref(), Some("4.38.125"));
+        assert_eq!(diff.brews.upgraded[0].installed, "21.7.3");
+        assert_eq!(diff.casks.upgraded[0].installed, "125.0.3");
+
+        let options = DisplayOptions::default();
+        let rendered = strip_ansi_codes(&crate::render_homebrew_diff(&diff, &options).unwrap());
+        assert!(rendered.contains("[A] htop"));
+        assert!(rendered.contains("node"));
+        assert!(rendered.contains("slack 4.38.125"));
+        assert!(rendered.contains("Keynote (409183694)"));
+        assert!(!rendered.contains("wget"));
+
+        let mut output = String::new();
+        let report = display::write_diff_with_options(&mut output, &diff, &options).unwrap();
+        assert!(report.had_changes);
+        assert_eq!(report.lines, output.lines().count());
+    }
+}
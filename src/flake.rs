@@ -0,0 +1,287 @@
+//! Intent straight from nix-darwin's `homebrew` options, without building the profile or parsing
+//! a Brewfile, either by evaluating a flake with `nix eval` or from exported JSON
+
+use crate::error::{Error, Result};
+use crate::intent::{BrewOptions, CaskOptions, CleanupMode, HomebrewIntent, IntentFlags};
+use crate::runner::{CommandRunner, SystemRunner};
+use crate::trace::span;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// Only the options describing packages, the rest of `config.homebrew` may not serialize
+const SELECT: &str = "h: { inherit (h) taps brews casks masApps caskArgs onActivation; }";
+
+/// The parts of nix-darwin's `homebrew` options we need
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct HomebrewConfig {
+    taps: Vec<Tap>,
+    brews: Vec<Brew>,
+    casks: Vec<Cask>,
+    mas_apps: BTreeMap<String, u64>,
+    cask_args: BTreeMap<String, Value>,
+    on_activation: OnActivation,
+}
+
+#[derive(Debug, Deserialize)]
+struct Tap {
+    name: String,
+    clone_target: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Brew {
+    name: String,
+    args: Option<Vec<String>>,
+    /// `true`, `false` or `"overwrite"`
+    link: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Cask {
+    name: String,
+    args: Option<BTreeMap<String, Value>>,
+    greedy: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct OnActivation {
+    cleanup: String,
+    auto_update: bool,
+    upgrade: bool,
+    extra_flags: Vec<String>,
+}
+
+impl HomebrewIntent {
+    /// Evaluate `darwinConfigurations.<host>.config.homebrew` of a flake, e.g. `.` or `github:user/config`
+    /// Previews a configuration without building it, the host defaults to the machine's
+    /// LocalHostName like darwin-rebuild
+    pub fn from_flake(flake: &str, host: Option<&str>) -> Result<Self> {
+        Self::from_flake_with(&SystemRunner::default(), flake, host)
+    }
+
+    /// Like `from_flake`, running nix through the given runner
+    pub fn from_flake_with(
+        runner: &dyn CommandRunner,
+        flake: &str,
+        host: Option<&str>,
+    ) -> Result<Self> {
+        let host = match host {
+            Some(host) => host.to_string(),
+            None => local_host_name(runner)?,
+        };
+        let _span = span!("intent_from_flake", flake = flake, host = %host);
+        let installable = format!(
+            "{}#darwinConfigurations.\"{}\".config.homebrew",
+            flake, host
+        );
+        let output = runner
+            .output(
+                "nix",
+                &[
+                    "--extra-experimental-features",
+                    "nix-command flakes",
+                    "eval",
+                    "--json",
+                    &installable,
+                    "--apply",
+                    SELECT,
+                ],
+            )
+            .map_err(|e| Error::CommandFailed(format!("nix eval failed: {}", e)))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(Error::CommandFailed(format!(
+                "nix eval {} failed: {}",
+                installable,
+                stderr.trim().lines().last().unwrap_or_default()
+            )));
+        }
+
+        Self::from_darwin_options_json(output.stdout.as_slice())
+    }
+
+    /// Read nix-darwin's `homebrew` options exported as JSON, see `from_darwin_options_json`
+    pub fn from_darwin_options_file(path: &Path) -> Result<Self> {
+        Self::from_darwin_options_json(BufReader::new(File::open(path)?))
+    }
+
+    /// Parse nix-darwin's `homebrew` options as JSON, e.g. from
+    /// `nix eval --json .#darwinConfigurations.work-mac.config.homebrew`
+    /// Accepts the options themselves or an object with them under `homebrew`,
+    /// flags mirror what activation would pass to `brew bundle`
+    pub fn from_darwin_options_json<R: Read>(reader: R) -> Result<Self> {
+        let invalid = |e: serde_json::Error| {
+            Error::ParseError(format!("Invalid homebrew configuration: {}", e))
+        };
+        let mut value: Value = serde_json::from_reader(reader).map_err(invalid)?;
+        if let Some(homebrew) = value.get_mut("homebrew").filter(|h| h.is_object()) {
+            value = homebrew.take();
+        }
+        let config: HomebrewConfig = serde_json::from_value(value).map_err(invalid)?;
+        let mut intent = Self::default();
+
+        for tap in config.taps {
+            if let Some(remote) = tap.clone_target {
+                intent.tap_remotes.insert(tap.name.clone(), remote);
+            }
+            intent.taps.insert(tap.name);
+        }
+        for brew in config.brews {
+            let options = BrewOptions {
+                link: brew.link.and_then(|link| link.as_bool()),
+                args: brew.args.unwrap_or_default(),
+            };
+            if options != BrewOptions::default() {
+                intent.brew_options.insert(brew.name.clone(), options);
+            }
+            intent.brews.insert(brew.name);
+        }
+        for cask in config.casks {
+            let options = CaskOptions {
+                args: cask.args.map(arguments).unwrap_or_default(),
+                greedy: cask.greedy.unwrap_or_default(),
+            };
+            if options != CaskOptions::default() {
+                intent.cask_options.insert(cask.name.clone(), options);
+            }
+            intent.casks.insert(cask.name);
+        }
+        intent.mas_apps = config
+            .mas_apps
+            .into_iter()
+            .map(|(name, id)| format!("{} ({})", name, id))
+            .collect();
+        intent.cask_args = arguments(config.cask_args);
+
+        let activation = config.on_activation;
+        let has_flag = |flag: &str| activation.extra_flags.iter().any(|f| f == flag);
+        let mut env = BTreeMap::new();
+        if !activation.auto_update {
+            env.insert("HOMEBREW_NO_AUTO_UPDATE".to_string(), "1".to_string());
+        }
+        intent.flags = IntentFlags {
+            no_upgrade: !activation.upgrade,
+            cleanup: match activation.cleanup.as_str() {
+                "zap" => CleanupMode::Zap,
+                "uninstall" => CleanupMode::Uninstall,
+                _ => CleanupMode::None,
+            },
+            force: has_flag("--force"),
+            greedy: has_flag("--greedy"),
+            env,
+            ..Default::default()
+        };
+
+        Ok(intent)
+    }
+}
+
+/// Cask arguments that are set, nix-darwin declares every known one as nullable
+fn arguments(args: BTreeMap<String, Value>) -> BTreeMap<String, String> {
+    args.into_iter()
+        .filter_map(|(key, value)| match value {
+            Value::String(value) => Some((key, value)),
+            Value::Bool(_) | Value::Number(_) => Some((key, value.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+fn local_host_name(runner: &dyn CommandRunner) -> Result<String> {
+    let output = runner
+        .output("scutil", &["--get", "LocalHostName"])
+        .map_err(|e| Error::CommandFailed(format!("scutil failed: {}", e)))?;
+    let name = String::from_utf8(output.stdout)?.trim().to_string();
+    if !output.status.success() || name.is_empty() {
+        return Err(Error::CommandFailed(
+            "Couldn't determine the LocalHostName, pass a host".to_string(),
+        ));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_darwin_options_json() {
+        let json = br#"{
+            "taps": [
+                {"name": "homebrew/core", "clone_target": null, "force_auto_update": null},
+                {"name": "acme/tools", "clone_target": "https://git.example.com/acme/tools", "force_auto_update": null}
+            ],
+            "brews": [
+                {"name": "git", "args": null, "link": null, "start_service": null},
+                {"name": "ffmpeg", "args": ["with-fdk-aac"], "link": false, "start_service": null}
+            ],
+            "casks": [
+                {"name": "firefox", "args": null, "greedy": null},
+                {"name": "zoom", "args": {"appdir": "~/Applications", "require_sha": null, "no_quarantine": true}, "greedy": true}
+            ],
+            "masApps": {"Xcode": 497799835},
+            "caskArgs": {"appdir": "/Applications", "fontdir": null},
+            "onActivation": {"autoUpdate": false, "cleanup": "zap", "upgrade": true, "extraFlags": ["--force"]}
+        }"#;
+
+        let intent = HomebrewIntent::from_darwin_options_json(&json[..]).unwrap();
+
+        assert_eq!(intent.taps.len(), 2);
+        assert_eq!(
+            intent.tap_remotes["acme/tools"],
+            "https://git.example.com/acme/tools"
+        );
+        assert_eq!(intent.brews.len(), 2);
+        assert_eq!(
+            intent.brew_options["ffmpeg"],
+            BrewOptions {
+                link: Some(false),
+                args: vec!["with-fdk-aac".to_string()],
+            }
+        );
+        assert!(!intent.brew_options.contains_key("git"));
+        assert_eq!(intent.cask_options["zoom"].args.len(), 2);
+        assert!(intent.cask_options["zoom"].greedy);
+        assert_eq!(intent.intended_appdir("zoom"), Some("~/Applications"));
+        assert_eq!(intent.intended_appdir("firefox"), Some("/Applications"));
+        assert!(intent.mas_apps.contains("Xcode (497799835)"));
+
+        assert!(!intent.flags.no_upgrade);
+        assert!(intent.flags.force);
+        assert_eq!(intent.flags.cleanup, CleanupMode::Zap);
+        assert_eq!(intent.flags.env["HOMEBREW_NO_AUTO_UPDATE"], "1");
+    }
+
+    #[test]
+    fn test_from_darwin_options_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("options.json");
+        std::fs::write(
+            &path,
+            r#"{"homebrew": {"enable": true, "brewfile": "brew \"wget\"\n", "brews": [{"name": "wget"}]}}"#,
+        )
+        .unwrap();
+        let intent = HomebrewIntent::from_darwin_options_file(&path).unwrap();
+        assert_eq!(intent.brews, ["wget".to_string()].into());
+    }
+
+    #[test]
+    fn test_from_darwin_options_json_defaults() {
+        let intent = HomebrewIntent::from_darwin_options_json(&b"{}"[..]).unwrap();
+        assert!(!intent.has_packages());
+        assert!(intent.flags.no_upgrade);
+        assert_eq!(intent.flags.cleanup, CleanupMode::None);
+
+        assert!(matches!(
+            HomebrewIntent::from_darwin_options_json(&b"not json"[..]),
+            Err(Error::ParseError(_))
+        ));
+    }
+}
@@ -0,0 +1,32 @@
+//! Entry points for the fuzz targets in `fuzz/`, not a stable API
+
+use crate::intent::{CleanupMode, HomebrewIntent, IntentFlags};
+use crate::state::HomebrewState;
+
+/// Parse Brewfile contents
+pub fn brewfile(content: &str) {
+    let _ = HomebrewIntent::from_brewfile(content);
+}
+
+/// Find and interpret the `brew bundle` invocation of an activation script
+pub fn activation_script(content: &str) {
+    if let Ok((_, invocation)) = HomebrewIntent::bundle_invocation_from_script(content) {
+        let _ = IntentFlags::from_bundle_invocation(&invocation);
+        let _ = CleanupMode::from_bundle_invocation(&invocation);
+    }
+}
+
+/// Parse `brew list --versions` output
+pub fn brew_list_versions(output: &[u8]) {
+    let _ = HomebrewState::parse_list_versions_output(output);
+}
+
+/// Parse `brew outdated --json=v2` output
+pub fn brew_outdated(output: &[u8]) {
+    let _ = HomebrewState::parse_outdated_output(output);
+}
+
+/// Parse `mas list` output
+pub fn mas_list(content: &str) {
+    let _ = HomebrewState::parse_mas_list_output(content);
+}
@@ -0,0 +1,238 @@
+//! Homebrew intent across past system generations, for auditing how the managed packages evolved
+
+use crate::diff::natural_cmp;
+use crate::error::{Error, Result};
+use crate::events::Category;
+use crate::explain::label;
+use crate::intent::HomebrewIntent;
+use owo_colors::OwoColorize;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where nix-darwin keeps its system generations
+pub const PROFILES_DIR: &str = "/nix/var/nix/profiles";
+
+/// A system generation, e.g. `/nix/var/nix/profiles/system-42-link`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Generation {
+    pub number: u64,
+    pub path: PathBuf,
+}
+
+impl Generation {
+    /// The `system-<n>-link` generations in a profiles directory, oldest first
+    pub fn list(dir: &Path) -> Result<Vec<Self>> {
+        let mut generations: Vec<Self> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let number = name
+                    .to_str()?
+                    .strip_prefix("system-")?
+                    .strip_suffix("-link")?
+                    .parse()
+                    .ok()?;
+                Some(Self {
+                    number,
+                    path: entry.path(),
+                })
+            })
+            .collect();
+        generations.sort_by_key(|generation| generation.number);
+        Ok(generations)
+    }
+
+    /// What the generation declared, empty if it didn't enable Homebrew
+    pub fn intent(&self) -> Result<HomebrewIntent> {
+        match HomebrewIntent::extract(&self.path) {
+            Err(Error::BrewfileNotFound) => Ok(HomebrewIntent::default()),
+            result => result,
+        }
+    }
+}
+
+/// Whether a generation started or stopped declaring a package
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+}
+
+/// A declaration change between a generation and the one before it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimelineEntry {
+    pub generation: u64,
+    pub category: Category,
+    pub name: String,
+    pub kind: ChangeKind,
+}
+
+/// Changes between consecutive generations, oldest first
+/// The first generation is the baseline, its packages aren't reported as added
+pub fn timeline(intents: &[(u64, HomebrewIntent)]) -> Vec<TimelineEntry> {
+    let mut entries = Vec::new();
+
+    for pair in intents.windows(2) {
+        let ((_, before), (generation, after)) = (&pair[0], &pair[1]);
+        let categories = [
+            (Category::Taps, &before.taps, &after.taps),
+            (Category::Formulae, &before.brews, &after.brews),
+            (Category::Casks, &before.casks, &after.casks),
+            (Category::AppStore, &before.mas_apps, &after.mas_apps),
+        ];
+        for (category, before, after) in categories {
+            let changes = [
+                (ChangeKind::Added, sorted_difference(after, before)),
+                (ChangeKind::Removed, sorted_difference(before, after)),
+            ];
+            for (kind, names) in changes {
+                entries.extend(names.into_iter().map(|name| TimelineEntry {
+                    generation: *generation,
+                    category,
+                    name: name.clone(),
+                    kind,
+                }));
+            }
+        }
+    }
+
+    entries
+}
+
+fn sorted_difference<'a>(a: &'a HashSet<String>, b: &'a HashSet<String>) -> Vec<&'a String> {
+    let mut names: Vec<&String> = a.difference(b).collect();
+    names.sort_by(|a, b| natural_cmp(a, b));
+    names
+}
+
+/// Extract the intent of every generation in a profiles directory, oldest first
+/// Generations whose profile can't be read, e.g. after garbage collection, are skipped
+pub fn intents(dir: &Path) -> Result<Vec<(u64, HomebrewIntent)>> {
+    Ok(Generation::list(dir)?
+        .into_iter()
+        .filter_map(|generation| {
+            let intent = generation.intent();
+            #[cfg(feature = "tracing")]
+            if let Err(e) = &intent {
+                crate::trace::event!(generation = generation.number, error = %e, "skipping generation");
+            }
+            intent.ok().map(|intent| (generation.number, intent))
+        })
+        .collect())
+}
+
+/// Write one line per package in the order they first changed, returns number of lines written
+/// e.g. `terraform (formula): added in gen 40, removed in gen 45`
+pub fn write_timeline<W: Write>(writer: &mut W, entries: &[TimelineEntry]) -> Result<usize> {
+    if entries.is_empty() {
+        writeln!(writer, "No Homebrew changes across generations")?;
+        return Ok(1);
+    }
+
+    let mut order = Vec::new();
+    let mut histories: HashMap<(Category, &str), Vec<&TimelineEntry>> = HashMap::new();
+    for entry in entries {
+        let key = (entry.category, entry.name.as_str());
+        let history = histories.entry(key).or_default();
+        if history.is_empty() {
+            order.push(key);
+        }
+        history.push(entry);
+    }
+
+    for key in &order {
+        let (category, name) = key;
+        let changes: Vec<String> = histories[key]
+            .iter()
+            .map(|entry| match entry.kind {
+                ChangeKind::Added => format!("{} in gen {}", "added".green(), entry.generation),
+                ChangeKind::Removed => format!("{} in gen {}", "removed".red(), entry.generation),
+            })
+            .collect();
+        writeln!(
+            writer,
+            "{} ({}): {}",
+            name.bold(),
+            label(*category),
+            changes.join(", ")
+        )?;
+    }
+
+    Ok(order.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::strip_ansi_codes;
+    use tempfile::TempDir;
+
+    fn intent(brews: &[&str], casks: &[&str]) -> HomebrewIntent {
+        HomebrewIntent {
+            brews: brews.iter().map(|name| name.to_string()).collect(),
+            casks: casks.iter().map(|name| name.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_list_generations() {
+        let dir = TempDir::new().unwrap();
+        for name in [
+            "system-45-link",
+            "system-9-link",
+            "system",
+            "per-user",
+            "system-x-link",
+        ] {
+            fs::create_dir(dir.path().join(name)).unwrap();
+        }
+
+        let generations = Generation::list(dir.path()).unwrap();
+
+        let numbers: Vec<u64> = generations.iter().map(|g| g.number).collect();
+        assert_eq!(numbers, vec![9, 45]);
+        assert_eq!(generations[1].path, dir.path().join("system-45-link"));
+    }
+
+    #[test]
+    fn test_timeline() {
+        let intents = vec![
+            (38, intent(&["git"], &["firefox"])),
+            (40, intent(&["git", "terraform"], &["firefox"])),
+            (45, intent(&["git"], &[])),
+            (47, intent(&["git", "terraform"], &[])),
+        ];
+
+        let entries = timeline(&intents);
+
+        assert_eq!(entries.len(), 4);
+        assert_eq!(
+            entries[1],
+            TimelineEntry {
+                generation: 45,
+                category: Category::Formulae,
+                name: "terraform".to_string(),
+                kind: ChangeKind::Removed,
+            }
+        );
+
+        let mut output = String::new();
+        let lines = write_timeline(&mut output, &entries).unwrap();
+        assert_eq!(lines, 2);
+        assert_eq!(
+            strip_ansi_codes(&output),
+            "terraform (formula): added in gen 40, removed in gen 45, added in gen 47\n\
+             firefox (cask): removed in gen 45\n"
+        );
+
+        let entries = timeline(&[
+            (1, intent(&[], &[])),
+            (2, intent(&[], &["Zoom", "alacritty"])),
+        ]);
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["alacritty", "Zoom"]);
+    }
+}
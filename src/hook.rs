@@ -0,0 +1,211 @@
+//! Git hook for nix-darwin config repositories, warning about Homebrew drift before a commit or push
+
+use crate::diff::HomebrewDiffData;
+use crate::display::{self, DiffReport};
+use crate::error::{Error, Result};
+use crate::runner::CommandRunner;
+use crate::shell::quote;
+use owo_colors::OwoColorize;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// First line after the shebang, marks hooks that can be replaced on reinstall
+const MARKER: &str = "# Installed by brewdiff";
+
+/// When the hook runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HookKind {
+    PreCommit,
+    #[default]
+    PrePush,
+}
+
+impl HookKind {
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            HookKind::PreCommit => "pre-commit",
+            HookKind::PrePush => "pre-push",
+        }
+    }
+}
+
+/// How the hook finds the Brewfile and what it runs to diff it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookOptions {
+    pub kind: HookKind,
+    /// Name under `darwinConfigurations`, None for the machine's LocalHostName like darwin-rebuild
+    pub configuration: Option<String>,
+    /// Run with the evaluated Brewfile's path, should call `run_git_hook`
+    pub command: String,
+}
+
+impl Default for HookOptions {
+    fn default() -> Self {
+        Self {
+            kind: HookKind::default(),
+            configuration: None,
+            command: "brewdiff hook run".to_string(),
+        }
+    }
+}
+
+/// Install the hook into the repository's hooks directory, returns the hook's path
+/// Refuses to replace hooks that weren't installed by brewdiff
+pub fn install(repo: &Path, options: &HookOptions, runner: &dyn CommandRunner) -> Result<PathBuf> {
+    let path = hooks_dir(repo, runner)?.join(options.kind.file_name());
+    if let Ok(existing) = fs::read_to_string(&path) {
+        if !existing.contains(MARKER) {
+            return Err(Error::HookExists(path.display().to_string()));
+        }
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, script(options))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(path)
+}
+
+/// The hooks directory, respecting worktrees and `core.hooksPath`
+fn hooks_dir(repo: &Path, runner: &dyn CommandRunner) -> Result<PathBuf> {
+    let repo_arg = repo.to_string_lossy();
+    let output = runner
+        .output(
+            "git",
+            &["-C", &repo_arg, "rev-parse", "--git-path", "hooks"],
+        )
+        .map_err(|e| Error::CommandFailed(format!("git rev-parse failed: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(Error::CommandFailed(format!(
+            "{} is not a git repository",
+            repo.display()
+        )));
+    }
+
+    // Relative to the repository unless hooks live elsewhere
+    let hooks = PathBuf::from(String::from_utf8(output.stdout)?.trim());
+    Ok(repo.join(hooks))
+}
+
+/// The hook script, evaluates the Brewfile without building and never blocks git
+fn script(options: &HookOptions) -> String {
+    let action = match options.kind {
+        HookKind::PreCommit => "committing",
+        HookKind::PrePush => "pushing",
+    };
+    let host = match &options.configuration {
+        Some(configuration) => quote(configuration),
+        None => "${BREWDIFF_HOST:-$(scutil --get LocalHostName 2>/dev/null)}".to_string(),
+    };
+
+    format!(
+        r#"#!/bin/sh
+{MARKER}, warns about Homebrew drift before {action}
+# Evaluates the config's Brewfile without building anything and never blocks git
+repo=$(git rev-parse --show-toplevel) || exit 0
+host={host}
+brewfile=$(mktemp) || exit 0
+trap 'rm -f "$brewfile"' EXIT
+if ! nix eval --raw "$repo#darwinConfigurations.\"$host\".config.homebrew.brewfile" >"$brewfile" 2>/dev/null; then
+    echo "brewdiff: couldn't evaluate the Brewfile of $host, skipping" >&2
+    exit 0
+fi
+{command} "$brewfile" >&2 || true
+exit 0
+"#,
+        command = options.command,
+    )
+}
+
+/// Write the drift between the config and this machine, nothing if there is none
+/// The evaluated Brewfile lacks activation's cleanup flags, so undeclared packages are unmanaged
+pub fn write_drift<W: Write>(writer: &mut W, diff_data: &HomebrewDiffData) -> Result<DiffReport> {
+    if !diff_data.has_changes() {
+        return Ok(DiffReport::default());
+    }
+
+    writeln!(
+        writer,
+        "{} Homebrew on this machine differs from the config, activate it after pulling\n",
+        "brewdiff:".yellow().bold()
+    )?;
+    let mut report = display::write_diff(writer, diff_data)?;
+    report.lines += 2;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::SystemRunner;
+    use std::process::Command;
+    use tempfile::TempDir;
+
+    fn git_repo() -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        let status = Command::new("git")
+            .args(["init", "--quiet"])
+            .current_dir(temp_dir.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        temp_dir
+    }
+
+    #[test]
+    fn test_install_hook() {
+        let repo = git_repo();
+        let options = HookOptions {
+            configuration: Some("work-mac".to_string()),
+            ..Default::default()
+        };
+
+        let path = install(repo.path(), &options, &SystemRunner::default()).unwrap();
+
+        assert_eq!(path, repo.path().join(".git/hooks/pre-push"));
+        let script = fs::read_to_string(&path).unwrap();
+        assert!(script.starts_with("#!/bin/sh\n# Installed by brewdiff"));
+        assert!(script.contains("host='work-mac'\n"));
+        assert!(script.contains("brewdiff hook run \"$brewfile\""));
+
+        // Reinstalling replaces our own hook
+        assert!(install(repo.path(), &options, &SystemRunner::default()).is_ok());
+    }
+
+    #[test]
+    fn test_install_keeps_foreign_hook() {
+        let repo = git_repo();
+        let path = repo.path().join(".git/hooks/pre-commit");
+        fs::write(&path, "#!/bin/sh\nmake lint\n").unwrap();
+        let options = HookOptions {
+            kind: HookKind::PreCommit,
+            ..Default::default()
+        };
+
+        let error = install(repo.path(), &options, &SystemRunner::default()).unwrap_err();
+
+        assert!(matches!(error, Error::HookExists(_)));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "#!/bin/sh\nmake lint\n");
+    }
+
+    #[test]
+    fn test_write_drift() {
+        let mut output = String::new();
+        let report = write_drift(&mut output, &HomebrewDiffData::default()).unwrap();
+        assert!(output.is_empty());
+        assert!(!report.had_changes);
+
+        let mut diff = HomebrewDiffData::default();
+        diff.casks.added = vec!["firefox".to_string()];
+        write_drift(&mut output, &diff).unwrap();
+        assert!(output.contains("differs from the config"));
+        assert!(output.contains("firefox"));
+    }
+}
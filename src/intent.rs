@@ -1,8 +1,12 @@
+use crate::condition::{self, BrewfileContext};
 use crate::error::{Error, Result};
+use crate::lockfile::LockedVersions;
+use crate::runner::{CommandRunner, SystemRunner};
+use crate::trace::{event, span};
 use regex::Regex;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 /// What nix-darwin wants to be installed
 #[derive(Debug, Clone, Default, PartialEq)]
@@ -11,12 +15,229 @@ pub struct HomebrewIntent {
     pub casks: HashSet<String>,
     pub taps: HashSet<String>,
     pub mas_apps: HashSet<String>, // Store as "name (id)" for display
+    pub flags: IntentFlags,
+    pub brew_options: HashMap<String, BrewOptions>, // only for brews declaring options
+    pub cask_args: BTreeMap<String, String>,        // global `cask_args`, e.g. appdir
+    pub cask_options: HashMap<String, CaskOptions>, // only for casks declaring options
+    pub tap_remotes: HashMap<String, String>,       // only for taps declaring a clone URL
+    pub locked: LockedVersions,                     // from an adjacent Brewfile.lock.json
 }
 
+/// Per-cask options from a Brewfile line, e.g. `cask "foo", args: { appdir: "~/Applications" }`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CaskOptions {
+    /// Install arguments overriding the global `cask_args`
+    pub args: BTreeMap<String, String>,
+    /// `greedy: true`: upgraded even if it updates itself
+    pub greedy: bool,
+}
+
+/// Per-formula options from a Brewfile line, e.g. `brew "foo", link: false`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BrewOptions {
+    /// Whether the formula should be linked into the prefix, None if not declared
+    pub link: Option<bool>,
+    /// Install options, e.g. `args: ["with-fdk-aac"]`
+    pub args: Vec<String>,
+}
+
+/// How activation invokes `brew bundle`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IntentFlags {
+    /// `--no-upgrade`: outdated packages are left alone
+    pub no_upgrade: bool,
+    /// `--cleanup` / `--zap`
+    pub cleanup: CleanupMode,
+    /// `--force`: overwrite existing files and force-uninstall during cleanup
+    pub force: bool,
+    /// `--greedy` or `HOMEBREW_UPGRADE_GREEDY`: self-updating casks are upgraded too
+    pub greedy: bool,
+    /// Environment assignments preceding the invocation, e.g. `HOMEBREW_NO_AUTO_UPDATE=1`
+    pub env: BTreeMap<String, String>,
+    /// Entries `brew bundle` skips because of `HOMEBREW_BUNDLE_*_SKIP` in `env`
+    pub skip: SkipLists,
+}
+
+/// Declared entries `brew bundle` neither installs nor upgrades, but keeps on cleanup
+/// From the space separated `HOMEBREW_BUNDLE_{BREW,CASK,TAP,MAS}_SKIP` lists
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SkipLists {
+    pub brews: HashSet<String>,
+    pub casks: HashSet<String>,
+    pub taps: HashSet<String>,
+    /// App Store ids or names
+    pub mas_apps: HashSet<String>,
+}
+
+impl SkipLists {
+    fn from_env(env: &BTreeMap<String, String>) -> Self {
+        let list = |key: &str| -> HashSet<String> {
+            env.get(key)
+                .map(|value| value.split_whitespace().map(str::to_string).collect())
+                .unwrap_or_default()
+        };
+
+        Self {
+            brews: list("HOMEBREW_BUNDLE_BREW_SKIP"),
+            casks: list("HOMEBREW_BUNDLE_CASK_SKIP"),
+            taps: list("HOMEBREW_BUNDLE_TAP_SKIP"),
+            mas_apps: list("HOMEBREW_BUNDLE_MAS_SKIP"),
+        }
+    }
+
+    /// Whether an "App Name (id)" entry is skipped, by id or name like brew bundle
+    pub fn skips_mas_app(&self, entry: &str) -> bool {
+        let (name, id) = entry
+            .strip_suffix(')')
+            .and_then(|entry| entry.rsplit_once(" ("))
+            .unwrap_or((entry, ""));
+        self.mas_apps.contains(id) || self.mas_apps.contains(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.brews.is_empty()
+            && self.casks.is_empty()
+            && self.taps.is_empty()
+            && self.mas_apps.is_empty()
+    }
+}
+
+impl IntentFlags {
+    /// Parse flags and env assignments from a `brew bundle` invocation line
+    /// Activation runs brew through sudo, which resets the caller's environment,
+    /// so only assignments in the activation script apply
+    /// `HOMEBREW_BUNDLE_FILE` is ignored since nix-darwin always passes `--file`
+    pub fn from_bundle_invocation(invocation: &str) -> Self {
+        let (prefix, command) = invocation
+            .split_once("brew bundle")
+            .unwrap_or(("", invocation));
+        let args: Vec<&str> = command.split_whitespace().collect();
+
+        let env: BTreeMap<String, String> = shell_words(prefix)
+            .into_iter()
+            .filter_map(|token| {
+                let (key, value) = token.split_once('=')?;
+                let is_name = !key.is_empty()
+                    && !key.starts_with(|c: char| c.is_ascii_digit())
+                    && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                is_name.then(|| (key.to_string(), value.to_string()))
+            })
+            .collect();
+        let env_set = |key: &str| env.get(key).is_some_and(|value| !value.is_empty());
+
+        Self {
+            no_upgrade: args.contains(&"--no-upgrade") || env_set("HOMEBREW_BUNDLE_NO_UPGRADE"),
+            cleanup: CleanupMode::from_bundle_invocation(command),
+            force: args.contains(&"--force"),
+            greedy: args.contains(&"--greedy") || env_set("HOMEBREW_UPGRADE_GREEDY"),
+            skip: SkipLists::from_env(&env),
+            env,
+        }
+    }
+}
+
+/// Split a command line into words like sh, honoring single and double quotes
+fn shell_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+/// What activation does with installed packages that aren't in the Brewfile
+/// Mirrors nix-darwin's `homebrew.onActivation.cleanup` option
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CleanupMode {
+    /// Nothing is uninstalled (nix-darwin's default)
+    #[default]
+    None,
+    /// `--cleanup`: undeclared packages are uninstalled
+    Uninstall,
+    /// `--cleanup --zap`: undeclared casks are zapped, including their app data
+    Zap,
+}
+
+impl CleanupMode {
+    /// Detect the cleanup mode from a `brew bundle` invocation
+    pub fn from_bundle_invocation(invocation: &str) -> Self {
+        let flags: Vec<&str> = invocation.split_whitespace().collect();
+        if flags.contains(&"--zap") {
+            CleanupMode::Zap
+        } else if flags.contains(&"--cleanup") {
+            CleanupMode::Uninstall
+        } else {
+            CleanupMode::None
+        }
+    }
+
+    /// Check if activation will uninstall packages that aren't declared
+    pub fn removes_packages(&self) -> bool {
+        !matches!(self, CleanupMode::None)
+    }
+}
+
+/// Options controlling how intent is extracted from a profile
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    /// Follow Brewfile paths outside the Nix store and the profile, e.g. for tests
+    pub allow_untrusted_brewfile: bool,
+}
+
+/// Where built Brewfiles live, anything else in an activation script is suspicious
+const NIX_STORE: &str = "/nix/store";
+
+/// Profile scripts that have run `brew bundle` across nix-darwin versions, newest first
+/// Before system-wide activation, Homebrew was set up as the user from `activate-user`
+const ACTIVATION_SCRIPTS: [&str; 2] = ["activate", "activate-user"];
+
 impl HomebrewIntent {
     /// Extract Homebrew intent from a nix-darwin profile
+    /// A Brewfile passed instead of a profile is parsed directly, without activation flags
     pub fn extract(profile: &Path) -> Result<Self> {
-        Self::extract_from_activation_script(profile)
+        Self::extract_with_options(profile, &ExtractOptions::default())
+    }
+
+    /// Extract Homebrew intent from a nix-darwin profile using the given options
+    pub fn extract_with_options(profile: &Path, options: &ExtractOptions) -> Result<Self> {
+        if Self::is_brewfile(profile) {
+            return Self::parse_brewfile(profile);
+        }
+        Self::extract_from_activation_script(profile, options)
+    }
+
+    /// Whether the path is a Brewfile rather than a profile, e.g. `/nix/store/xxx-Brewfile`
+    fn is_brewfile(path: &Path) -> bool {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        path.is_file()
+            && (name == "Brewfile" || name.ends_with("-Brewfile") || name.ends_with(".Brewfile"))
     }
 
     /// Check if there are any packages configured
@@ -24,30 +245,174 @@ impl HomebrewIntent {
         !self.brews.is_empty() || !self.casks.is_empty() || !self.mas_apps.is_empty()
     }
 
-    fn extract_from_activation_script(profile: &Path) -> Result<Self> {
-        let activate_path = profile.join("activate");
-        if !activate_path.exists() {
-            return Err(Error::NoActivationScript(
-                activate_path.to_string_lossy().to_string(),
-            ));
+    /// Resolve App Store apps declared with a placeholder name (e.g. just their id)
+    /// to their canonical names via the iTunes lookup API
+    /// This requires network access, curl runs through `runner`, returns the number of resolved apps
+    pub fn resolve_mas_names(&mut self, runner: &dyn CommandRunner) -> Result<usize> {
+        let placeholders: Vec<(String, String)> = self
+            .mas_apps
+            .iter()
+            .filter_map(|entry| {
+                let (name, id) = entry.strip_suffix(')')?.rsplit_once(" (")?;
+                let is_placeholder = name.trim().is_empty()
+                    || name == id
+                    || name.chars().all(|c| c.is_ascii_digit());
+                is_placeholder.then(|| (entry.clone(), id.to_string()))
+            })
+            .collect();
+
+        if placeholders.is_empty() {
+            return Ok(0);
         }
 
-        let content = fs::read_to_string(&activate_path)?;
+        let mut ids: Vec<&str> = placeholders.iter().map(|(_, id)| id.as_str()).collect();
+        ids.sort_unstable();
+        let url = format!("https://itunes.apple.com/lookup?id={}", ids.join(","));
+        let output = runner
+            .output("curl", &["--silent", "--fail", "--max-time", "10", &url])
+            .map_err(|e| Error::CommandFailed(format!("iTunes lookup failed: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(Error::CommandFailed(format!(
+                "iTunes lookup failed with {}",
+                output.status
+            )));
+        }
 
-        // Look for the brew bundle command
+        let names = Self::parse_itunes_lookup(&output.stdout)?;
+        let mut resolved = 0;
+        for (entry, id) in placeholders {
+            if let Some(name) = names.get(&id) {
+                self.mas_apps.remove(&entry);
+                self.mas_apps.insert(format!("{} ({})", name, id));
+                resolved += 1;
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    /// Parse an iTunes lookup response into id -> app name
+    fn parse_itunes_lookup(json: &[u8]) -> Result<BTreeMap<String, String>> {
+        let response: serde_json::Value = serde_json::from_slice(json)
+            .map_err(|e| Error::ParseError(format!("Invalid iTunes lookup response: {}", e)))?;
+
+        let results = response
+            .get("results")
+            .and_then(|results| results.as_array())
+            .map(|results| results.as_slice())
+            .unwrap_or_default();
+
+        Ok(results
+            .iter()
+            .filter_map(|result| {
+                let id = result.get("trackId")?.as_u64()?;
+                let name = result.get("trackName")?.as_str()?;
+                Some((id.to_string(), name.to_string()))
+            })
+            .collect())
+    }
+
+    fn extract_from_activation_script(profile: &Path, options: &ExtractOptions) -> Result<Self> {
+        let _span = span!("extract_intent", profile = %profile.display());
+        let (brewfile_path, invocation) = Self::find_bundle_invocation(profile)?;
+        let brewfile_path = Self::validate_brewfile_path(profile, &brewfile_path, options)?;
+        let mut intent = Self::parse_brewfile(&brewfile_path)?;
+        intent.flags = IntentFlags::from_bundle_invocation(&invocation);
+        Ok(intent)
+    }
+
+    /// Locate the Brewfile referenced by the profile's activation script
+    pub(crate) fn find_brewfile_path(profile: &Path) -> Result<PathBuf> {
+        if Self::is_brewfile(profile) {
+            return Ok(profile.to_path_buf());
+        }
+        let (path, _) = Self::find_bundle_invocation(profile)?;
+        Self::validate_brewfile_path(profile, &path, &ExtractOptions::default())
+    }
+
+    /// Canonicalize the Brewfile path and make sure it's in the Nix store or the profile
+    /// A tampered activation script could otherwise point us at arbitrary files
+    fn validate_brewfile_path(
+        profile: &Path,
+        path: &Path,
+        options: &ExtractOptions,
+    ) -> Result<PathBuf> {
+        let canonical = match fs::canonicalize(path) {
+            Ok(canonical) => canonical,
+            Err(_) if options.allow_untrusted_brewfile => return Ok(path.to_path_buf()),
+            Err(_) => {
+                return Err(Error::ParseError(format!(
+                    "Brewfile not found at: {}",
+                    path.display()
+                )))
+            }
+        };
+        if options.allow_untrusted_brewfile || canonical.starts_with(NIX_STORE) {
+            return Ok(canonical);
+        }
+
+        let in_profile = fs::canonicalize(profile)
+            .map(|profile| canonical.starts_with(profile))
+            .unwrap_or(false);
+        if in_profile {
+            Ok(canonical)
+        } else {
+            Err(Error::UntrustedBrewfile(path.display().to_string()))
+        }
+    }
+
+    /// Find the `brew bundle` invocation in the profile's activation scripts
+    /// Returns the Brewfile path and the full invocation
+    fn find_bundle_invocation(profile: &Path) -> Result<(PathBuf, String)> {
+        let mut found_script = false;
+        for name in ACTIVATION_SCRIPTS {
+            let Ok(content) = fs::read_to_string(profile.join(name)) else {
+                continue;
+            };
+            found_script = true;
+            match Self::bundle_invocation_from_script(&content) {
+                Err(Error::BrewfileNotFound) => continue,
+                result => return result,
+            }
+        }
+
+        if found_script {
+            Err(Error::BrewfileNotFound)
+        } else {
+            Err(Error::NoActivationScript(
+                profile.join("activate").to_string_lossy().to_string(),
+            ))
+        }
+    }
+
+    /// Find the `brew bundle` invocation in the contents of an activation script
+    /// Tolerates env prefixes, `sudo` wrappers continued over several lines, an absolute
+    /// path to brew and any quoting of `--file`, see tests/fixtures/activation
+    pub(crate) fn bundle_invocation_from_script(content: &str) -> Result<(PathBuf, String)> {
         // Example: brew bundle --file='/nix/store/xxx-Brewfile' --no-upgrade
-        // Also handle paths that aren't in /nix/store for testing
-        let brewfile_regex = Regex::new(r"brew bundle --file='([^']+Brewfile)'.*")?;
+        let bundle_regex = Regex::new(r"(?:^|[\s/])brew\s+bundle\s")?;
+        let file_regex = Regex::new(r#"\s--file(?:=|\s+)(?:'([^']+)'|"([^"]+)"|([^\s'";&|]+))"#)?;
 
-        if let Some(captures) = brewfile_regex.captures(&content) {
-            let brewfile_path = captures.get(1).unwrap().as_str();
-            return Self::parse_brewfile(Path::new(brewfile_path));
+        let script = content.replace("\\\n", " ");
+        for line in script.lines() {
+            if line.trim_start().starts_with('#') || !bundle_regex.is_match(line) {
+                continue;
+            }
+            if let Some(captures) = file_regex.captures(line) {
+                let brewfile_path = (1..=3)
+                    .find_map(|group| captures.get(group))
+                    .map(|path| path.as_str())
+                    .unwrap_or_default();
+                return Ok((PathBuf::from(brewfile_path), line.to_string()));
+            }
         }
 
         Err(Error::BrewfileNotFound)
     }
 
-    fn parse_brewfile(path: &Path) -> Result<Self> {
+    pub(crate) fn parse_brewfile(path: &Path) -> Result<Self> {
+        let _span = span!("parse_brewfile", path = %path.display());
         if !path.exists() {
             return Err(Error::ParseError(format!(
                 "Brewfile not found at: {}",
@@ -56,57 +421,265 @@ impl HomebrewIntent {
         }
 
         let content = fs::read_to_string(path)?;
+        // Only look up the machine when there are conditionals to evaluate
+        let context = if content.contains("if ") || content.contains("unless ") {
+            BrewfileContext::detect(&SystemRunner::default())
+        } else {
+            BrewfileContext::default()
+        };
+        let mut intent = Self::from_brewfile_for(&content, &context);
+        intent.locked = LockedVersions::load(path);
+        Ok(intent)
+    }
+
+    /// Parse a Brewfile as of a git revision, e.g. `HEAD~10` or `main@{1.week.ago}`
+    /// `file` is relative to the repository root, the working tree isn't touched
+    pub fn from_git(repo: &Path, rev: &str, file: &Path) -> Result<Self> {
+        Self::from_git_with(&SystemRunner::default(), repo, rev, file)
+    }
+
+    /// Like `from_git`, running git through the given runner
+    pub fn from_git_with(
+        runner: &dyn CommandRunner,
+        repo: &Path,
+        rev: &str,
+        file: &Path,
+    ) -> Result<Self> {
+        let _span = span!("brewfile_from_git", rev = rev, file = %file.display());
+        let repo_arg = repo.to_string_lossy();
+        let object = format!("{}:{}", rev, file.display());
+        let output = runner
+            .output("git", &["-C", &repo_arg, "show", &object])
+            .map_err(|e| Error::CommandFailed(format!("git show failed: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(Error::CommandFailed(format!(
+                "git show {} failed: {}",
+                object,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        Ok(Self::from_brewfile(&String::from_utf8(output.stdout)?))
+    }
+
+    /// Parse the contents of a Brewfile, without touching the filesystem
+    /// Activation flags are left at their defaults since they come from the activation script
+    /// Host and user conditionals can't be evaluated, so every entry counts
+    pub fn from_brewfile(content: &str) -> Self {
+        Self::from_brewfile_for(content, &BrewfileContext::default())
+    }
+
+    /// Parse the contents of a Brewfile, skipping entries whose host or user conditionals
+    /// don't hold for the given machine
+    pub fn from_brewfile_for(content: &str, context: &BrewfileContext) -> Self {
         let mut intent = Self::default();
+        let mut blocks = condition::Blocks::default();
 
         for line in content.lines() {
             let line = line.trim();
             if line.starts_with('#') || line.is_empty() {
                 continue;
             }
+            if blocks.control(line, context) || !blocks.active() {
+                continue;
+            }
+            let line = match condition::split_modifier(line) {
+                (entry, Some((condition, negate))) => {
+                    if condition::evaluate(condition, context) == Some(negate) {
+                        continue;
+                    }
+                    entry
+                }
+                (entry, None) => entry,
+            };
+            // Tolerate any whitespace between the directive and its arguments
+            let Some((directive, rest)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+            let rest = rest.trim_start();
+            if directive != "cask_args" && !rest.starts_with('"') {
+                continue;
+            }
 
-            if line.starts_with("brew \"") {
-                if let Some(formula) = Self::extract_quoted_value(line) {
-                    intent.brews.insert(formula);
+            match directive {
+                "brew" => {
+                    if let Some(formula) = Self::extract_quoted_value(rest) {
+                        let options = Self::parse_options(rest);
+                        if !options.is_empty() {
+                            let brew_options = BrewOptions {
+                                link: options.get("link").and_then(|v| v.parse().ok()),
+                                args: options
+                                    .get("args")
+                                    .map(|v| Self::parse_string_array(v))
+                                    .unwrap_or_default(),
+                            };
+                            intent.brew_options.insert(formula.clone(), brew_options);
+                        }
+                        intent.brews.insert(formula);
+                    }
                 }
-            } else if line.starts_with("cask \"") {
-                if let Some(cask) = Self::extract_quoted_value(line) {
-                    intent.casks.insert(cask);
+                "cask" => {
+                    if let Some(cask) = Self::extract_quoted_value(rest) {
+                        let options = Self::parse_options(rest);
+                        if !options.is_empty() {
+                            let cask_options = CaskOptions {
+                                args: options
+                                    .get("args")
+                                    .map(|v| Self::parse_option_list(Self::unwrap_hash(v)))
+                                    .unwrap_or_default(),
+                                greedy: options.get("greedy").is_some_and(|v| v == "true"),
+                            };
+                            intent.cask_options.insert(cask.clone(), cask_options);
+                        }
+                        intent.casks.insert(cask);
+                    }
                 }
-            } else if line.starts_with("tap \"") {
-                if let Some(tap) = Self::extract_quoted_value(line) {
-                    intent.taps.insert(tap);
+                "cask_args" => intent.cask_args.extend(Self::parse_option_list(rest)),
+                "tap" => {
+                    if let Some(tap) = Self::extract_quoted_value(rest) {
+                        // tap "user/repo", "https://example.com/repo.git"
+                        let after_name = &rest[tap.len() + 2..];
+                        if let Some(remote) = after_name
+                            .find('"')
+                            .and_then(|start| Self::extract_quoted_value(&after_name[start..]))
+                        {
+                            intent.tap_remotes.insert(tap.clone(), remote);
+                        }
+                        intent.taps.insert(tap);
+                    }
                 }
-            } else if line.starts_with("mas \"") {
-                // Parse mas "App Name", id: 1234567890
-                if let Some((name, id)) = Self::parse_mas_line(line) {
-                    // Store as "App Name (1234567890)" for display
-                    intent.mas_apps.insert(format!("{} ({})", name, id));
+                "mas" => {
+                    // Parse mas "App Name", id: 1234567890
+                    if let Some((name, id)) = Self::parse_mas_line(rest) {
+                        // Store as "App Name (1234567890)" for display
+                        intent.mas_apps.insert(format!("{} ({})", name, id));
+                    }
                 }
+                _ => {}
             }
         }
 
-        Ok(intent)
+        event!(
+            brews = intent.brews.len(),
+            casks = intent.casks.len(),
+            taps = intent.taps.len(),
+            mas_apps = intent.mas_apps.len(),
+            "parsed Brewfile"
+        );
+        intent
     }
 
-    fn extract_quoted_value(line: &str) -> Option<String> {
+    pub(crate) fn extract_quoted_value(line: &str) -> Option<String> {
         let start = line.find('"')?;
         let end = line[start + 1..].find('"')?;
-        Some(line[start + 1..start + 1 + end].to_string())
+        // An empty name can't refer to anything, e.g. `cask ""`
+        (end > 0).then(|| line[start + 1..start + 1 + end].to_string())
+    }
+
+    /// Parse the `key: value` options following the quoted name of a Brewfile line
+    /// Values are kept raw, e.g. `false` or `["with-x", "with-y"]`
+    pub(crate) fn parse_options(line: &str) -> BTreeMap<String, String> {
+        let Some(start) = line.find('"') else {
+            return BTreeMap::new();
+        };
+        let Some(end) = line[start + 1..].find('"') else {
+            return BTreeMap::new();
+        };
+        Self::parse_option_list(&line[start + 1 + end + 1..])
     }
 
-    fn parse_mas_line(line: &str) -> Option<(String, String)> {
-        // Parse: mas "App Name", id: 1234567890
+    /// Parse a comma separated `key: value` list, e.g. the inside of `args: { ... }`
+    fn parse_option_list(rest: &str) -> BTreeMap<String, String> {
+        let mut options = BTreeMap::new();
+
+        // Split on commas that aren't nested in brackets, braces or quotes
+        let mut parts = Vec::new();
+        let (mut depth, mut in_quotes, mut part_start) = (0i32, false, 0);
+        for (idx, c) in rest.char_indices() {
+            match c {
+                '"' => in_quotes = !in_quotes,
+                '[' | '{' if !in_quotes => depth += 1,
+                ']' | '}' if !in_quotes => depth -= 1,
+                ',' if !in_quotes && depth == 0 => {
+                    parts.push(&rest[part_start..idx]);
+                    part_start = idx + 1;
+                }
+                _ => {}
+            }
+        }
+        parts.push(&rest[part_start..]);
+
+        for part in parts {
+            if let Some((key, value)) = part.split_once(':') {
+                let key = key.trim();
+                if !key.is_empty() && !key.contains(char::is_whitespace) {
+                    options.insert(key.to_string(), value.trim().to_string());
+                }
+            }
+        }
+
+        options
+    }
+
+    /// Strip the braces of a raw hash option value and unquote its values
+    fn unwrap_hash(value: &str) -> &str {
+        value.trim().trim_start_matches('{').trim_end_matches('}')
+    }
+
+    /// The directory a cask's app is intended to be installed to
+    pub fn intended_appdir(&self, cask: &str) -> Option<&str> {
+        self.cask_options
+            .get(cask)
+            .and_then(|options| options.args.get("appdir"))
+            .or_else(|| self.cask_args.get("appdir"))
+            .map(|appdir| appdir.trim_matches('"'))
+    }
+
+    /// Whether activation upgrades the cask even if it updates itself
+    pub fn is_greedy(&self, cask: &str) -> bool {
+        self.flags.greedy
+            || self
+                .cask_options
+                .get(cask)
+                .is_some_and(|options| options.greedy)
+            || self.cask_args.get("greedy").is_some_and(|v| v == "true")
+    }
+
+    /// Parse a raw array option value like `["a", "b"]` into its quoted strings
+    fn parse_string_array(value: &str) -> Vec<String> {
+        value
+            .trim()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .split(',')
+            .map(|item| item.trim().trim_matches('"').to_string())
+            .filter(|item| !item.is_empty())
+            .collect()
+    }
+
+    pub(crate) fn parse_mas_line(line: &str) -> Option<(String, String)> {
+        // Parse: "App Name", id: 1234567890
+        // Only look for the id after the name, which may contain "id:" itself
         let name = Self::extract_quoted_value(line)?;
-        let id_part = line.split("id:").nth(1)?;
-        let id = id_part.trim().to_string();
-        Some((name, id))
+        let after_name = &line[line.find('"')? + name.len() + 2..];
+        let id: String = after_name
+            .split_once("id:")?
+            .1
+            .trim_start()
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect();
+        (!id.is_empty()).then_some((name, id))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixtures::FixtureRunner;
     use std::fs;
+    use std::process::Command;
     use tempfile::TempDir;
 
     #[test]
@@ -147,6 +720,40 @@ cask "visual-studio-code"
         assert!(intent.taps.contains("homebrew/core"));
     }
 
+    #[test]
+    fn test_from_brewfile_tolerates_malformed_lines() {
+        let intent = HomebrewIntent::from_brewfile(
+            "brew\t\"wget\"\ncask   \"firefox\"\nbrew \"unterminated\nbrew\ncask \"\"\nmas \"Fluid: id: Notes\", id: 123\nmas \"Broken\", id: \n",
+        );
+
+        assert_eq!(intent.brews.len(), 1);
+        assert!(intent.brews.contains("wget"));
+        assert_eq!(intent.casks.len(), 1);
+        assert!(intent.casks.contains("firefox"));
+        assert_eq!(intent.mas_apps.len(), 1);
+        assert!(intent.mas_apps.contains("Fluid: id: Notes (123)"));
+    }
+
+    #[test]
+    fn test_parse_brewfile_tap_remotes() {
+        let temp_dir = TempDir::new().unwrap();
+        let brewfile_path = temp_dir.path().join("Brewfile");
+        fs::write(
+            &brewfile_path,
+            "tap \"homebrew/core\"\ntap \"acme/tools\", \"https://git.acme.dev/tools.git\"\n",
+        )
+        .unwrap();
+
+        let intent = HomebrewIntent::parse_brewfile(&brewfile_path).unwrap();
+
+        assert_eq!(intent.taps.len(), 2);
+        assert_eq!(intent.tap_remotes.len(), 1);
+        assert_eq!(
+            intent.tap_remotes["acme/tools"],
+            "https://git.acme.dev/tools.git"
+        );
+    }
+
     #[test]
     fn test_extract_quoted_value() {
         assert_eq!(
@@ -184,5 +791,379 @@ echo "Done"
 
         let intent = HomebrewIntent::extract(temp_dir.path()).unwrap();
         assert!(intent.brews.contains("git"));
+        assert!(intent.flags.no_upgrade);
+        assert_eq!(intent.flags.cleanup, CleanupMode::None);
+    }
+
+    #[test]
+    fn test_bundle_invocation_historical_layouts() {
+        let layouts = [
+            (
+                include_str!("../tests/fixtures/activation/activate-user-2023.sh"),
+                "/nix/store/4q9ph0l1m5i0mi6wcwlqgbmd2p0kyfrg-Brewfile",
+                CleanupMode::None,
+            ),
+            (
+                include_str!("../tests/fixtures/activation/activate-2024.sh"),
+                "/nix/store/b1w0l0c7d8pfh3lc5mnkyfmd4nxs4l7x-Brewfile",
+                CleanupMode::Uninstall,
+            ),
+            (
+                include_str!("../tests/fixtures/activation/activate-2025.sh"),
+                "/nix/store/z3f8jpl4n3qsw2d6z2x1pf0dyj6ragsb-Brewfile",
+                CleanupMode::Zap,
+            ),
+        ];
+
+        for (script, brewfile, cleanup) in layouts {
+            let (path, invocation) = HomebrewIntent::bundle_invocation_from_script(script).unwrap();
+            assert_eq!(path, PathBuf::from(brewfile));
+
+            let flags = IntentFlags::from_bundle_invocation(&invocation);
+            assert!(flags.no_upgrade);
+            assert_eq!(flags.cleanup, cleanup);
+            assert_eq!(flags.env["HOMEBREW_NO_AUTO_UPDATE"], "1");
+        }
+
+        assert!(matches!(
+            HomebrewIntent::bundle_invocation_from_script(
+                "# brew bundle --file='/b/Brewfile'\nbrew bundle check\n"
+            ),
+            Err(Error::BrewfileNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_extract_from_user_activation_script() {
+        let profile = TempDir::new().unwrap();
+        let brewfile_path = profile.path().join("Brewfile");
+        fs::write(&brewfile_path, "cask \"firefox\"\n").unwrap();
+        fs::write(profile.path().join("activate"), "echo setting up\n").unwrap();
+        fs::write(
+            profile.path().join("activate-user"),
+            format!(
+                "PATH=\"/opt/homebrew/bin:$PATH\" brew bundle --file=\"{}\" --no-lock\n",
+                brewfile_path.display()
+            ),
+        )
+        .unwrap();
+
+        let intent = HomebrewIntent::extract(profile.path()).unwrap();
+        assert!(intent.casks.contains("firefox"));
+
+        let empty = TempDir::new().unwrap();
+        assert!(matches!(
+            HomebrewIntent::extract(empty.path()),
+            Err(Error::NoActivationScript(_))
+        ));
+    }
+
+    #[test]
+    fn test_extract_brewfile_directly() {
+        let temp_dir = TempDir::new().unwrap();
+        for name in ["Brewfile", "work.Brewfile", "4q9ph0l1m5i0mi6w-Brewfile"] {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, "brew \"git\"\n").unwrap();
+
+            let intent = HomebrewIntent::extract(&path).unwrap();
+            assert!(intent.brews.contains("git"));
+            assert_eq!(intent.flags, IntentFlags::default());
+            assert_eq!(HomebrewIntent::find_brewfile_path(&path).unwrap(), path);
+        }
+
+        let other = temp_dir.path().join("packages.txt");
+        fs::write(&other, "brew \"git\"\n").unwrap();
+        assert!(matches!(
+            HomebrewIntent::extract(&other),
+            Err(Error::NoActivationScript(_))
+        ));
+    }
+
+    #[test]
+    fn test_extract_rejects_untrusted_brewfile() {
+        let profile = TempDir::new().unwrap();
+        let elsewhere = TempDir::new().unwrap();
+        let brewfile_path = elsewhere.path().join("Brewfile");
+        fs::write(&brewfile_path, "brew \"git\"\n").unwrap();
+        fs::write(
+            profile.path().join("activate"),
+            format!("brew bundle --file='{}'\n", brewfile_path.display()),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            HomebrewIntent::extract(profile.path()),
+            Err(Error::UntrustedBrewfile(_))
+        ));
+
+        let options = ExtractOptions {
+            allow_untrusted_brewfile: true,
+        };
+        let intent = HomebrewIntent::extract_with_options(profile.path(), &options).unwrap();
+        assert!(intent.brews.contains("git"));
+    }
+
+    #[test]
+    fn test_cleanup_mode_from_bundle_invocation() {
+        assert_eq!(
+            CleanupMode::from_bundle_invocation("brew bundle --file='/b/Brewfile' --no-upgrade"),
+            CleanupMode::None
+        );
+        assert_eq!(
+            CleanupMode::from_bundle_invocation("brew bundle --file='/b/Brewfile' --cleanup"),
+            CleanupMode::Uninstall
+        );
+        assert_eq!(
+            CleanupMode::from_bundle_invocation(
+                "brew bundle --file='/b/Brewfile' --no-upgrade --cleanup --zap"
+            ),
+            CleanupMode::Zap
+        );
+    }
+
+    #[test]
+    fn test_intent_flags_from_bundle_invocation() {
+        let flags = IntentFlags::from_bundle_invocation(
+            "  sudo --user=kilian --set-home env HOMEBREW_NO_AUTO_UPDATE=1 PATH='/opt/homebrew/bin' \
+             brew bundle --file='/nix/store/xxx-Brewfile' --no-upgrade --cleanup --force",
+        );
+
+        assert!(flags.no_upgrade);
+        assert!(flags.force);
+        assert_eq!(flags.cleanup, CleanupMode::Uninstall);
+        assert_eq!(flags.env.len(), 2);
+        assert_eq!(flags.env["HOMEBREW_NO_AUTO_UPDATE"], "1");
+        assert_eq!(flags.env["PATH"], "/opt/homebrew/bin");
+
+        let defaults = IntentFlags::from_bundle_invocation("brew bundle --file='/b/Brewfile'");
+        assert_eq!(defaults, IntentFlags::default());
+    }
+
+    #[test]
+    fn test_intent_flags_bundle_env_overrides() {
+        let flags = IntentFlags::from_bundle_invocation(
+            "env HOMEBREW_BUNDLE_NO_UPGRADE=1 HOMEBREW_BUNDLE_BREW_SKIP='wget node' \
+             HOMEBREW_BUNDLE_MAS_SKIP=\"497799835\" brew bundle --file='/nix/store/xxx-Brewfile'",
+        );
+
+        assert!(flags.no_upgrade);
+        assert_eq!(
+            flags.skip.brews,
+            ["wget".to_string(), "node".to_string()].into()
+        );
+        assert!(flags.skip.casks.is_empty());
+        assert!(flags.skip.skips_mas_app("Xcode (497799835)"));
+        assert!(!flags.skip.skips_mas_app("Keynote (409183694)"));
+    }
+
+    #[test]
+    fn test_from_brewfile_host_conditionals() {
+        let brewfile = r#"
+brew "git"
+if `hostname`.chomp == "work-mac"
+  cask "slack"
+elsif Socket.gethostname == "studio"
+  cask "logic-pro"
+else
+  cask "steam"
+end
+unless ENV["USER"] == "kilian"
+  brew "guest-tool"
+end
+cask "zoom" if ENV["USER"] == "kilian"
+brew "htop", link: false unless `hostname`.chomp == "work-mac"
+if File.exist?("/opt/custom")
+  brew "custom"
+end
+"#;
+        let context = BrewfileContext {
+            hostnames: vec!["work-mac".to_string()],
+            user: Some("kilian".to_string()),
+        };
+
+        let intent = HomebrewIntent::from_brewfile_for(brewfile, &context);
+        assert_eq!(
+            intent.brews,
+            ["git".to_string(), "custom".to_string()].into()
+        );
+        assert_eq!(
+            intent.casks,
+            ["slack".to_string(), "zoom".to_string()].into()
+        );
+        assert!(intent.brew_options.is_empty());
+
+        // Without knowing the machine, every entry counts
+        let intent = HomebrewIntent::from_brewfile(brewfile);
+        assert_eq!(intent.brews.len(), 4);
+        assert_eq!(intent.casks.len(), 4);
+        assert_eq!(intent.brew_options["htop"].link, Some(false));
+    }
+
+    #[test]
+    fn test_shell_words() {
+        assert_eq!(
+            shell_words(r#"  env A='x y' B="z"w C= "#),
+            vec!["env", "A=x y", "B=zw", "C="]
+        );
+    }
+
+    #[test]
+    fn test_parse_itunes_lookup() {
+        let response = br#"{
+            "resultCount": 1,
+            "results": [{"kind": "mac-software", "trackId": 497799835, "trackName": "Xcode"}]
+        }"#;
+        let names = HomebrewIntent::parse_itunes_lookup(response).unwrap();
+        assert_eq!(names.get("497799835"), Some(&"Xcode".to_string()));
+
+        let empty = HomebrewIntent::parse_itunes_lookup(br#"{"resultCount": 0, "results": []}"#);
+        assert!(empty.unwrap().is_empty());
+        assert!(HomebrewIntent::parse_itunes_lookup(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_resolve_mas_names_without_placeholders() {
+        let mut intent = HomebrewIntent::default();
+        intent.mas_apps.insert("Xcode (497799835)".to_string());
+
+        // Nothing to resolve, so no lookup is made
+        let runner = FixtureRunner::default();
+        assert_eq!(intent.resolve_mas_names(&runner).unwrap(), 0);
+        assert!(intent.mas_apps.contains("Xcode (497799835)"));
+        assert!(runner.calls().is_empty());
+    }
+
+    #[test]
+    fn test_resolve_mas_names() {
+        let mut intent = HomebrewIntent::default();
+        intent.mas_apps.insert("497799835 (497799835)".to_string());
+        intent.mas_apps.insert(" (409183694)".to_string());
+        intent.mas_apps.insert("Things 3 (904280696)".to_string());
+        let runner = FixtureRunner::default().with(
+            "curl --silent --fail --max-time 10 https://itunes.apple.com/lookup?id=409183694,497799835",
+            r#"{"resultCount": 1, "results": [{"trackId": 497799835, "trackName": "Xcode"}]}"#,
+        );
+
+        assert_eq!(intent.resolve_mas_names(&runner).unwrap(), 1);
+        assert_eq!(
+            intent.mas_apps,
+            HashSet::from([
+                "Xcode (497799835)".to_string(),
+                " (409183694)".to_string(),
+                "Things 3 (904280696)".to_string(),
+            ])
+        );
+
+        // A failed lookup is an error rather than leaving placeholders silently
+        let mut intent = HomebrewIntent::default();
+        intent.mas_apps.insert(" (409183694)".to_string());
+        assert!(intent.resolve_mas_names(&FixtureRunner::default()).is_err());
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options = HomebrewIntent::parse_options(
+            r#"brew "ffmpeg", link: false, args: ["with-fdk-aac", "HEAD"], restart_service: :changed"#,
+        );
+        assert_eq!(options.len(), 3);
+        assert_eq!(options["link"], "false");
+        assert_eq!(options["args"], r#"["with-fdk-aac", "HEAD"]"#);
+        assert_eq!(options["restart_service"], ":changed");
+        assert_eq!(
+            HomebrewIntent::parse_string_array(&options["args"]),
+            vec!["with-fdk-aac", "HEAD"]
+        );
+
+        assert!(HomebrewIntent::parse_options(r#"brew "wget""#).is_empty());
+    }
+
+    #[test]
+    fn test_parse_brewfile_link_option() {
+        let temp_dir = TempDir::new().unwrap();
+        let brewfile_path = temp_dir.path().join("Brewfile");
+        fs::write(
+            &brewfile_path,
+            "brew \"openssl@3\", link: true\nbrew \"python@3.11\", link: false\nbrew \"wget\"\n",
+        )
+        .unwrap();
+
+        let intent = HomebrewIntent::parse_brewfile(&brewfile_path).unwrap();
+
+        assert_eq!(intent.brews.len(), 3);
+        assert_eq!(intent.brew_options["openssl@3"].link, Some(true));
+        assert_eq!(intent.brew_options["python@3.11"].link, Some(false));
+        assert!(!intent.brew_options.contains_key("wget"));
+    }
+
+    #[test]
+    fn test_parse_brewfile_cask_appdir() {
+        let temp_dir = TempDir::new().unwrap();
+        let brewfile_path = temp_dir.path().join("Brewfile");
+        fs::write(
+            &brewfile_path,
+            r#"cask_args appdir: "~/Applications", require_sha: true
+cask "firefox"
+cask "docker", args: { appdir: "/Applications" }
+"#,
+        )
+        .unwrap();
+
+        let intent = HomebrewIntent::parse_brewfile(&brewfile_path).unwrap();
+
+        assert_eq!(intent.casks.len(), 2);
+        assert_eq!(intent.cask_args["require_sha"], "true");
+        assert_eq!(intent.intended_appdir("firefox"), Some("~/Applications"));
+        assert_eq!(intent.intended_appdir("docker"), Some("/Applications"));
+    }
+
+    #[test]
+    fn test_parse_brewfile_greedy_casks() {
+        let temp_dir = TempDir::new().unwrap();
+        let brewfile_path = temp_dir.path().join("Brewfile");
+        fs::write(
+            &brewfile_path,
+            "cask \"google-chrome\", greedy: true\ncask \"firefox\"\n",
+        )
+        .unwrap();
+
+        let mut intent = HomebrewIntent::parse_brewfile(&brewfile_path).unwrap();
+        assert!(intent.is_greedy("google-chrome"));
+        assert!(!intent.is_greedy("firefox"));
+
+        intent.flags = IntentFlags::from_bundle_invocation(
+            "HOMEBREW_UPGRADE_GREEDY=1 brew bundle --file='/nix/store/abc-Brewfile'",
+        );
+        assert!(intent.is_greedy("firefox"));
+    }
+
+    #[test]
+    fn test_from_git_revision() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(repo)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        fs::create_dir(repo.join("hosts")).unwrap();
+        git(&["init", "--quiet"]);
+        fs::write(repo.join("hosts/Brewfile"), "brew \"wget\"\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "--quiet", "-m", "wget"]);
+        fs::write(repo.join("hosts/Brewfile"), "brew \"curl\"\n").unwrap();
+        git(&["commit", "--quiet", "-am", "curl"]);
+
+        let file = Path::new("hosts/Brewfile");
+        let before = HomebrewIntent::from_git(repo, "HEAD~1", file).unwrap();
+        assert_eq!(before.brews, ["wget".to_string()].into());
+        let head = HomebrewIntent::from_git(repo, "HEAD", file).unwrap();
+        assert_eq!(head.brews, ["curl".to_string()].into());
+
+        let error = HomebrewIntent::from_git(repo, "HEAD", Path::new("missing")).unwrap_err();
+        assert!(matches!(error, Error::CommandFailed(_)));
     }
 }
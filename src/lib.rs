@@ -1,60 +1,481 @@
+pub mod allowlist;
+pub mod annotations;
+pub mod audit;
+pub mod brew_config;
+pub mod bundle;
+pub mod cache;
+pub mod cask_match;
+pub mod compat;
+pub mod condition;
+pub mod config;
+pub mod date;
+pub mod delta;
 pub mod diff;
 pub mod display;
+pub mod dix;
+pub mod doctor;
+pub mod drift;
+pub mod duplicates;
 pub mod error;
+pub mod events;
+pub mod explain;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod filter;
+#[cfg(test)]
+mod fixtures;
+pub mod flake;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+pub mod generations;
+pub mod hook;
 pub mod intent;
+pub mod lint;
+#[cfg(unix)]
+pub mod lock;
+pub mod lockfile;
+pub mod metadata;
+pub mod nh;
+pub mod nixpkgs;
+pub mod outcome;
+pub mod overlap;
+pub mod prompt;
+pub mod redact;
+pub mod runner;
+pub mod safety;
+pub mod sbom;
+pub mod schedule;
+pub mod security;
+pub mod session;
+pub mod severity;
+mod shell;
 pub mod state;
+pub mod stream;
+pub mod timings;
+mod trace;
+pub mod undo;
+pub mod version;
+#[cfg(feature = "watch")]
+pub mod watch;
 
 use std::fmt::Write;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::thread::{self, JoinHandle};
 
-pub use diff::{HomebrewDiffData, PackageDiff};
+pub use allowlist::{AcknowledgedDrift, Acknowledgement, Allowlist, DriftDirection};
+pub use annotations::Annotations;
+pub use audit::{AuditLog, AuditRecord, AuditingRunner};
+pub use brew_config::BrewConfig;
+pub use cache::StateCache;
+pub use cask_match::CaskMatch;
+pub use compat::{BrewCompat, BrewFeature};
+pub use condition::BrewfileContext;
+pub use diff::{Diff, HomebrewDiffData, ItemKey, PackageDiff, SetDiff, SharedDiff};
+pub use display::{DiffReport, DisplayOptions, Flush, FontDisplay, SectionKind, SectionReport};
+pub use drift::{CategoryDrift, DriftScore};
+pub use duplicates::Duplicate;
 pub use error::{Error, Result};
-pub use intent::HomebrewIntent;
-pub use state::HomebrewState;
+pub use events::{Category, CategorySet, DiffEvent};
+pub use explain::Explanation;
+pub use filter::NameFilter;
+pub use hook::{HookKind, HookOptions};
+pub use intent::{ExtractOptions, HomebrewIntent};
+pub use lint::LintFinding;
+#[cfg(unix)]
+pub use lock::ReconcileLock;
+pub use lockfile::LockedVersions;
+pub use metadata::MetadataOptions;
+pub use outcome::{outcome_for, Diagnostics, DiffOutcome};
+pub use overlap::NixOverlap;
+pub use prompt::{PromptCache, PromptSummary};
+pub use runner::{CommandEnvironment, CommandRunner, SystemRunner};
+pub use safety::{SafetyPolicy, SafetyViolation, DEFAULT_MAX_REMOVALS};
+pub use sbom::{SbomComponent, SbomFormat, SbomScope};
+pub use schedule::ScheduleOptions;
+pub use security::{AdvisoryQuery, AdvisorySource, AffectedFormula, OsvSource};
+pub use session::DiffSession;
+pub use severity::{Concern, Severity};
+pub use state::{DetectOptions, HomebrewState, StateMeta};
+pub use timings::Timings;
+#[cfg(feature = "watch")]
+pub use watch::StateWatcher;
 
 /// Primary API - compare current Homebrew state with new nix-darwin config
 /// Mirrors dix's spawn pattern for async processing
+/// Uses the process-wide `StateCache`, see `invalidate_homebrew_state`
 pub fn spawn_homebrew_diff(new_profile: PathBuf) -> JoinHandle<Result<HomebrewDiffData>> {
     thread::spawn(move || {
-        let current_state = HomebrewState::detect()?;
         let nix_intent = HomebrewIntent::extract(&new_profile)?;
-        Ok(HomebrewDiffData::compute(&current_state, &nix_intent))
+        let current_state = StateCache::global().get_for(&nix_intent, &SystemRunner::default())?;
+        let mut diff_data = HomebrewDiffData::compute(&current_state, &nix_intent);
+        diff_data.load_versions(&SystemRunner::default())?;
+        Ok(diff_data)
+    })
+}
+
+/// Like `spawn_homebrew_diff`, but diffs against an already detected state
+/// Lets callers detect once (or load a cached state) and skip running brew again
+/// Locked versions are compared against the state as is, see `HomebrewState::load_locked_versions`
+pub fn spawn_diff_with_state(
+    current_state: HomebrewState,
+    new_profile: PathBuf,
+) -> JoinHandle<Result<HomebrewDiffData>> {
+    thread::spawn(move || {
+        let nix_intent = HomebrewIntent::extract(&new_profile)?;
+        Ok(HomebrewDiffData::compute_owned(current_state, nix_intent))
     })
 }
 
-/// Write homebrew diff output, returns number of lines written
+/// Like `spawn_homebrew_diff`, but also hands back the detected state and extracted intent
+pub fn spawn_homebrew_diff_shared(new_profile: PathBuf) -> JoinHandle<Result<SharedDiff>> {
+    thread::spawn(move || {
+        let nix_intent = Arc::new(HomebrewIntent::extract(&new_profile)?);
+        let current_state = StateCache::global().get_for(&nix_intent, &SystemRunner::default())?;
+        let mut shared = SharedDiff::compute(current_state, nix_intent);
+        shared.diff.load_versions(&SystemRunner::default())?;
+        Ok(shared)
+    })
+}
+
+/// Like `spawn_homebrew_diff`, but also reports how long each phase took
+pub fn spawn_homebrew_diff_with_timings(
+    new_profile: PathBuf,
+) -> JoinHandle<Result<(HomebrewDiffData, Timings)>> {
+    thread::spawn(move || {
+        let mut timings = Timings::default();
+        let runner = SystemRunner::default();
+        let mut current_state = HomebrewState::detect_timed(&runner, &mut timings)?;
+        let nix_intent = timings::time(&mut timings.intent, || {
+            HomebrewIntent::extract(&new_profile)
+        })?;
+        timings::time(&mut timings.versions, || {
+            current_state.load_locked_versions(&runner, &nix_intent.locked)
+        })?;
+        let mut diff_data = timings::time(&mut timings.diff, || {
+            HomebrewDiffData::compute_owned(current_state, nix_intent)
+        });
+        timings::time(&mut timings.versions, || diff_data.load_versions(&runner))?;
+        Ok((diff_data, timings))
+    })
+}
+
+/// Diff a Brewfile against `brew bundle dump` output of the current machine
+/// Pure parsing and diffing without subprocesses or filesystem access, so it also works on wasm32
+pub fn diff_brewfile_against_dump(brewfile: &str, bundle_dump: &str) -> HomebrewDiffData {
+    HomebrewDiffData::compute_owned(
+        HomebrewState::from_bundle_dump(bundle_dump),
+        HomebrewIntent::from_brewfile(brewfile),
+    )
+}
+
+/// Render a diff to a string, e.g. for display in a web page
+pub fn render_homebrew_diff(
+    diff_data: &HomebrewDiffData,
+    options: &DisplayOptions,
+) -> Result<String> {
+    let mut rendered = String::new();
+    display::write_diff_with_options(&mut rendered, diff_data, options)?;
+    Ok(rendered)
+}
+
+/// Write homebrew diff output, returns what was written
 /// This version includes the header with profile paths (matches dix exactly)
 pub fn write_homebrew_diffln<W: Write>(
     writer: &mut W,
     old_profile: &Path,
     new_profile: &Path,
-) -> Result<usize> {
-    let current_state = HomebrewState::detect()?;
+) -> Result<DiffReport> {
     let nix_intent = HomebrewIntent::extract(new_profile)?;
-    let diff_data = HomebrewDiffData::compute(&current_state, &nix_intent);
+    let current_state = StateCache::global().get_for(&nix_intent, &SystemRunner::default())?;
+    let mut diff_data = HomebrewDiffData::compute(&current_state, &nix_intent);
+    diff_data.load_versions(&SystemRunner::default())?;
 
     display::write_diff_with_header(writer, old_profile, new_profile, &diff_data)
 }
 
 /// Write homebrew diff output without header
 /// Use this when you want just the diff content
-pub fn write_homebrew_diff_content<W: Write>(writer: &mut W, new_profile: &Path) -> Result<usize> {
-    let current_state = HomebrewState::detect()?;
+pub fn write_homebrew_diff_content<W: Write>(
+    writer: &mut W,
+    new_profile: &Path,
+) -> Result<DiffReport> {
     let nix_intent = HomebrewIntent::extract(new_profile)?;
-    let diff_data = HomebrewDiffData::compute(&current_state, &nix_intent);
+    let current_state = StateCache::global().get_for(&nix_intent, &SystemRunner::default())?;
+    let mut diff_data = HomebrewDiffData::compute(&current_state, &nix_intent);
+    diff_data.load_versions(&SystemRunner::default())?;
 
     display::write_diff(writer, &diff_data)
 }
 
+/// Write homebrew diff output without header, detecting only the categories the options select
+/// e.g. for `--only casks` or `--skip mas,taps`, bypasses the `StateCache` unless all are selected
+pub fn write_homebrew_diff_with_options<W: Write>(
+    writer: &mut W,
+    new_profile: &Path,
+    detect_options: &DetectOptions,
+    display_options: &DisplayOptions,
+) -> Result<DiffReport> {
+    let runner = SystemRunner::default();
+    let nix_intent = HomebrewIntent::extract(new_profile)?;
+    let current_state = if detect_options.categories.is_all() {
+        StateCache::global().get_for(&nix_intent, &runner)?
+    } else {
+        let mut state =
+            HomebrewState::detect_with_options(&runner, detect_options, &mut Timings::default())?;
+        state.load_locked_versions(&runner, &nix_intent.locked)?;
+        Arc::new(state)
+    };
+    // Undetected categories would list every declared entry as added
+    let mut diff_data = HomebrewDiffData::compute(&current_state, &nix_intent)
+        .restricted(detect_options.categories);
+    diff_data.load_versions(&runner)?;
+
+    display::write_diff_with_options(writer, &diff_data, display_options)
+}
+
+/// Write homebrew diff output for a flake's darwin configuration without building it
+/// e.g. `write_homebrew_diff_from_flake(&mut out, ".", Some("work-mac"))`, see `HomebrewIntent::from_flake`
+pub fn write_homebrew_diff_from_flake<W: Write>(
+    writer: &mut W,
+    flake: &str,
+    host: Option<&str>,
+) -> Result<DiffReport> {
+    let nix_intent = HomebrewIntent::from_flake(flake, host)?;
+    let current_state = StateCache::global().get_for(&nix_intent, &SystemRunner::default())?;
+    let mut diff_data = HomebrewDiffData::compute(&current_state, &nix_intent);
+    diff_data.load_versions(&SystemRunner::default())?;
+
+    display::write_diff(writer, &diff_data)
+}
+
+/// Write homebrew diff output section by section as detection progresses
+/// Bypasses the `StateCache` since the point is to not wait for a full detection pass
+pub fn write_homebrew_diff_streaming<W: Write>(
+    writer: &mut W,
+    new_profile: &Path,
+) -> Result<DiffReport> {
+    stream::write_diff_streaming(writer, new_profile, &DisplayOptions::default())
+}
+
+/// Write a Brewfile with only the additions the new profile would make, returns the number of entries
+/// Lets users install new packages with `brew bundle --file=<delta>` without a full activation
+pub fn write_homebrew_delta_brewfile<W: Write>(
+    writer: &mut W,
+    new_profile: &Path,
+) -> Result<usize> {
+    let nix_intent = HomebrewIntent::extract(new_profile)?;
+    let current_state = StateCache::global().get_for(&nix_intent, &SystemRunner::default())?;
+    let diff_data = HomebrewDiffData::compute(&current_state, &nix_intent);
+
+    delta::write_additions_brewfile(writer, &diff_data, &nix_intent)
+}
+
+/// Write a shell script reverting what activating the new profile changes, returns the number of commands
+/// Run this before activation, afterwards the removed packages and their versions are gone
+pub fn write_homebrew_undo_script<W: Write>(writer: &mut W, new_profile: &Path) -> Result<usize> {
+    let nix_intent = HomebrewIntent::extract(new_profile)?;
+    let current_state = StateCache::global().get_for(&nix_intent, &SystemRunner::default())?;
+    let mut diff_data = HomebrewDiffData::compute(&current_state, &nix_intent);
+    diff_data.load_versions(&SystemRunner::default())?;
+
+    undo::write_undo_script(writer, &diff_data)
+}
+
+/// Write a CycloneDX or SPDX JSON SBOM of the installed packages, or with a profile, of the packages
+/// installed once it's activated, returns the number of packages
+pub fn write_homebrew_sbom<W: Write>(
+    writer: &mut W,
+    new_profile: Option<&Path>,
+    format: SbomFormat,
+) -> Result<usize> {
+    let mut current_state = HomebrewState::clone(&*StateCache::global().get()?);
+    current_state.load_versions(&SystemRunner::default())?;
+    let sbom = match new_profile {
+        Some(new_profile) => {
+            let nix_intent = HomebrewIntent::extract(new_profile)?;
+            let mut diff_data = HomebrewDiffData::compute(&current_state, &nix_intent);
+            diff_data.load_versions(&SystemRunner::default())?;
+            sbom::document(&current_state, SbomScope::Intended(&diff_data), format)
+        }
+        None => sbom::document(&current_state, SbomScope::Installed, format),
+    };
+
+    let packages = match format {
+        SbomFormat::CycloneDx => &sbom["components"],
+        SbomFormat::Spdx => &sbom["packages"],
+    };
+    let count = packages.as_array().map_or(0, Vec::len);
+    let json = serde_json::to_string_pretty(&sbom).map_err(std::io::Error::from)?;
+    writeln!(writer, "{}", json)?;
+    Ok(count)
+}
+
+/// Check what activating the new profile would remove against the policy
+/// Fails with `Error::Unsafe` unless `force` is set, then violations are returned as warnings
+pub fn check_homebrew_safety(
+    new_profile: &Path,
+    policy: &SafetyPolicy,
+    force: bool,
+) -> Result<Vec<SafetyViolation>> {
+    let nix_intent = HomebrewIntent::extract(new_profile)?;
+    let current_state = StateCache::global().get_for(&nix_intent, &SystemRunner::default())?;
+    let diff_data = HomebrewDiffData::compute(&current_state, &nix_intent);
+
+    policy.check(&diff_data, &current_state, &SystemRunner::default(), force)
+}
+
+/// Write why `name` shows up in the diff of the new profile, e.g. behind an `explain <package>` subcommand
+/// Returns true if the name matched any declared, installed or changed entry
+pub fn write_homebrew_explanation<W: Write>(
+    writer: &mut W,
+    new_profile: &Path,
+    name: &str,
+) -> Result<bool> {
+    // The intent comes from the profile like for the diff, with its bundle flags, evaluated
+    // conditionals and lock file, the Brewfile's text is only needed for line numbers
+    let nix_intent = HomebrewIntent::extract(new_profile)?;
+    let current_state = StateCache::global().get_for(&nix_intent, &SystemRunner::default())?;
+    let brewfile = HomebrewIntent::find_brewfile_path(new_profile)?;
+    let content = std::fs::read_to_string(&brewfile)?;
+    let mut diff_data = HomebrewDiffData::compute(&current_state, &nix_intent);
+    diff_data.load_versions(&SystemRunner::default())?;
+
+    let mut explanation = explain::explain(name, &content, &current_state, &diff_data);
+    explanation.brewfile = Some(brewfile);
+    explain::write_explanation(writer, &explanation)?;
+    Ok(!explanation.is_empty())
+}
+
+/// Install a git hook into a nix-darwin config repository, e.g. behind `brewdiff hook install`
+/// The hook evaluates the config's Brewfile without building and runs `options.command` on it
+/// Returns the hook's path
+pub fn install_git_hook(repo: &Path, options: &HookOptions) -> Result<PathBuf> {
+    hook::install(repo, options, &SystemRunner::default())
+}
+
+/// What an installed git hook runs, e.g. as `brewdiff hook run <brewfile>`
+/// Writes how this machine drifted from the evaluated Brewfile, nothing if it didn't
+pub fn run_git_hook<W: Write>(writer: &mut W, brewfile: &Path) -> Result<DiffReport> {
+    let nix_intent = HomebrewIntent::parse_brewfile(brewfile)?;
+    let current_state = StateCache::global().get_for(&nix_intent, &SystemRunner::default())?;
+    let mut diff_data = HomebrewDiffData::compute(&current_state, &nix_intent);
+    diff_data.load_versions(&SystemRunner::default())?;
+
+    hook::write_drift(writer, &diff_data)
+}
+
+/// Install a launchd agent running `options.command` every `options.interval`, e.g. behind
+/// `brewdiff schedule install --interval 6h`, returns the agent's plist path
+pub fn install_drift_schedule(options: &ScheduleOptions) -> Result<PathBuf> {
+    let agents_dir = schedule::default_agents_dir()
+        .ok_or_else(|| Error::InvalidConfig("no home directory for launch agents".to_string()))?;
+    schedule::install(&agents_dir, options, &SystemRunner::default())
+}
+
+/// Unload and remove the launchd agent, returns whether it was installed
+pub fn uninstall_drift_schedule() -> Result<bool> {
+    let Some(agents_dir) = schedule::default_agents_dir() else {
+        return Ok(false);
+    };
+    schedule::uninstall(&agents_dir, &SystemRunner::default())
+}
+
+/// Write how the declared packages changed across system generations in `profiles_dir`,
+/// usually `generations::PROFILES_DIR`, returns number of lines written
+pub fn write_homebrew_generation_timeline<W: Write>(
+    writer: &mut W,
+    profiles_dir: &Path,
+) -> Result<usize> {
+    let intents = generations::intents(profiles_dir)?;
+    generations::write_timeline(writer, &generations::timeline(&intents))
+}
+
+/// Write a prompt segment like `🍺 +3/-1` from the prompt cache, without running brew
+/// Writes nothing if activation wouldn't change anything or the cache is stale,
+/// returns whether the cache was fresh so callers can run `refresh_homebrew_prompt_cache` in the background
+pub fn write_homebrew_prompt_segment<W: Write>(writer: &mut W, new_profile: &Path) -> Result<bool> {
+    let Some(cache) = PromptCache::default_path().map(PromptCache::new) else {
+        return Ok(false);
+    };
+    let prefix = Path::new(HomebrewState::get_brew_prefix());
+    match cache.load(new_profile, prefix) {
+        Some(summary) => {
+            if !summary.is_clean() {
+                write!(writer, "{}", summary)?;
+            }
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Compute the full diff and store its summary for `write_homebrew_prompt_segment`
+/// e.g. after activation or detached from the prompt when the cache was stale
+pub fn refresh_homebrew_prompt_cache(new_profile: &Path) -> Result<PromptSummary> {
+    let nix_intent = HomebrewIntent::extract(new_profile)?;
+    let current_state = StateCache::global().get_for(&nix_intent, &SystemRunner::default())?;
+    let mut diff_data = HomebrewDiffData::compute(&current_state, &nix_intent);
+    diff_data.load_versions(&SystemRunner::default())?;
+
+    let summary = PromptSummary::from_diff(&diff_data);
+    if let Some(path) = PromptCache::default_path() {
+        let prefix = Path::new(HomebrewState::get_brew_prefix());
+        PromptCache::new(path).store(new_profile, prefix, summary)?;
+    }
+    Ok(summary)
+}
+
 /// Write homebrew diff statistics
 pub fn write_homebrew_stats<W: Write>(writer: &mut W, diff_data: &HomebrewDiffData) -> Result<()> {
     display::write_stats(writer, diff_data)
 }
 
-/// Get current Homebrew state
+/// Write homebrew diff statistics for the entries the options render, e.g. with a name filter
+pub fn write_homebrew_stats_with_options<W: Write>(
+    writer: &mut W,
+    diff_data: &HomebrewDiffData,
+    options: &DisplayOptions,
+) -> Result<()> {
+    display::write_stats_with_options(writer, diff_data, options)
+}
+
+/// Write per-phase durations, e.g. behind a `--timings` flag
+pub fn write_homebrew_timings<W: Write>(writer: &mut W, timings: &Timings) -> Result<()> {
+    display::write_timings(writer, timings)
+}
+
+/// Write environment diagnostics for the given profile
+/// Returns true if any check failed
+pub fn write_homebrew_doctor<W: Write>(writer: &mut W, profile: &Path) -> Result<bool> {
+    let findings = doctor::run_diagnostics(profile);
+    doctor::write_diagnostics(writer, &findings)?;
+    Ok(doctor::has_failures(&findings))
+}
+
+/// Validate a Brewfile and write line-numbered findings, e.g. behind `brewdiff lint <Brewfile>`
+/// Returns true if any finding would make brew bundle fail
+pub fn write_brewfile_lint<W: Write>(writer: &mut W, brewfile: &Path) -> Result<bool> {
+    let findings = lint::validate(&std::fs::read_to_string(brewfile)?);
+    lint::write_findings(writer, &brewfile.display().to_string(), &findings)?;
+    Ok(lint::has_errors(&findings))
+}
+
+/// Write a redacted debug bundle for bug reports to `output` as a tar archive
+/// Captures detection command output, the Brewfile, the parsed intent and the computed diff
+pub fn write_homebrew_debug_bundle(profile: &Path, output: &Path) -> Result<()> {
+    bundle::write_debug_bundle(profile, output)
+}
+
+/// Get current Homebrew state, detected once per process until invalidated
 pub fn get_current_homebrew_state() -> Result<HomebrewState> {
-    HomebrewState::detect()
+    Ok(HomebrewState::clone(&*StateCache::global().get()?))
+}
+
+/// Forget the state shared by the convenience functions so the next call detects again
+pub fn invalidate_homebrew_state() {
+    StateCache::global().invalidate()
 }
 
 /// Extract nix-darwin intent from a built profile
@@ -70,9 +491,75 @@ mod tests {
     fn test_public_api_exists() {
         // Just verify the public API compiles
         let _ = get_current_homebrew_state;
+        let _ = invalidate_homebrew_state;
         let _ = extract_nix_darwin_intent;
         let _ = spawn_homebrew_diff;
+        let _ = spawn_homebrew_diff_shared;
+        let _ = spawn_diff_with_state;
+        let _ = spawn_homebrew_diff_with_timings;
         let _ = write_homebrew_diffln::<String>;
         let _ = write_homebrew_stats::<String>;
+        let _ = install_git_hook;
+        let _ = run_git_hook::<String>;
+        let _ = install_drift_schedule;
+        let _ = uninstall_drift_schedule;
+        let _ = write_homebrew_diff_with_options::<String>;
+        let _ = write_homebrew_stats_with_options::<String>;
+        let _ = write_homebrew_explanation::<String>;
+        let _ = write_homebrew_diff_streaming::<String>;
+        let _ = write_homebrew_timings::<String>;
+        let _ = write_homebrew_doctor::<String>;
+        let _ = write_brewfile_lint::<String>;
+        let _ = write_homebrew_debug_bundle;
+        let _ = write_homebrew_delta_brewfile::<String>;
+        let _ = write_homebrew_undo_script::<String>;
+        let _ = check_homebrew_safety;
+        let _ = diff_brewfile_against_dump;
+        let _ = render_homebrew_diff;
+        let _ = write_homebrew_prompt_segment::<String>;
+        let _ = refresh_homebrew_prompt_cache;
+    }
+
+    #[test]
+    fn test_diff_brewfile_against_dump() {
+        let brewfile = "tap \"hashicorp/tap\"\nbrew \"git\"\nbrew \"hashicorp/tap/terraform\"\n";
+        let dump = "brew \"git\"\nbrew \"wget\"\ncask \"firefox\"\nmas \"Xcode\", id: 497799835\n";
+
+        let diff = diff_brewfile_against_dump(brewfile, dump);
+
+        assert_eq!(diff.taps.added, vec!["hashicorp/tap"]);
+        assert_eq!(diff.brews.added, vec!["hashicorp/tap/terraform"]);
+        assert_eq!(diff.brews.removed[0].name, "wget");
+        assert_eq!(diff.brews.removed[0].version, None);
+        assert_eq!(diff.casks.removed[0].name, "firefox");
+        assert_eq!(diff.mas_apps.removed[0].name, "Xcode (497799835)");
+
+        let rendered = render_homebrew_diff(&diff, &DisplayOptions::default()).unwrap();
+        assert!(rendered.contains("hashicorp/tap/terraform"));
+    }
+
+    #[test]
+    fn test_spawn_diff_with_state() {
+        let profile = tempfile::TempDir::new().unwrap();
+        let brewfile_path = profile.path().join("Brewfile");
+        std::fs::write(&brewfile_path, "brew \"git\"\n").unwrap();
+        std::fs::write(
+            profile.path().join("activate"),
+            format!("brew bundle --file='{}'\n", brewfile_path.display()),
+        )
+        .unwrap();
+
+        let mut state = HomebrewState::default();
+        state
+            .installed_brews
+            .insert("wget".to_string(), "1.0".to_string());
+
+        let diff = spawn_diff_with_state(state, profile.path().to_path_buf())
+            .join()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(diff.brews.added, vec!["git"]);
+        assert_eq!(diff.brews.removed[0].name, "wget");
     }
 }
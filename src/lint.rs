@@ -0,0 +1,377 @@
+//! Brewfile validation with line-numbered findings, e.g. behind `brewdiff lint <Brewfile>`
+//! Only needs the file, so config repositories can check it in CI before building anything
+
+use crate::condition::{self, BrewfileContext};
+use crate::error::Result;
+use crate::intent::HomebrewIntent;
+use crate::severity::Severity;
+use owo_colors::OwoColorize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+/// Directives `brew bundle` understands, anything else is Ruby it may not
+const DIRECTIVES: [&str; 11] = [
+    "tap",
+    "brew",
+    "cask",
+    "mas",
+    "cask_args",
+    "whalebrew",
+    "vscode",
+    "go",
+    "cargo",
+    "uv",
+    "flatpak",
+];
+
+/// A problem on a Brewfile line
+/// Critical findings make `brew bundle` fail, warnings are likely mistakes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintFinding {
+    /// 1-based
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl LintFinding {
+    fn new(line: usize, severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            severity,
+            message: message.into(),
+        }
+    }
+}
+
+/// An unconditional entry seen before, for duplicate and conflict checks
+struct Declared {
+    line: usize,
+    options: BTreeMap<String, String>,
+}
+
+/// Check a Brewfile for unknown directives, malformed entries, invalid option values and
+/// duplicate or conflicting declarations
+/// Entries under conditionals may legitimately repeat, so only unconditional ones are compared
+pub fn validate(content: &str) -> Vec<LintFinding> {
+    let mut findings = Vec::new();
+    let mut blocks = condition::Blocks::default();
+    let context = BrewfileContext::default();
+    let mut declared: HashMap<(&str, String), Declared> = HashMap::new();
+    let mut mas_ids: HashMap<String, (usize, String)> = HashMap::new();
+
+    for (idx, line) in content.lines().enumerate() {
+        let number = idx + 1;
+        let line = line.trim();
+        if line.starts_with('#') || line.is_empty() || blocks.control(line, &context) {
+            continue;
+        }
+        let (entry, modifier) = condition::split_modifier(line);
+        let conditional = modifier.is_some() || blocks.depth() > 0;
+        let (directive, rest) = entry
+            .split_once(char::is_whitespace)
+            .map_or((entry, ""), |(directive, rest)| {
+                (directive, rest.trim_start())
+            });
+
+        if !DIRECTIVES.contains(&directive) {
+            findings.push(LintFinding::new(
+                number,
+                Severity::Warning,
+                format!(
+                    "unknown directive `{}`, brew bundle fails unless it's valid Ruby",
+                    directive
+                ),
+            ));
+            continue;
+        }
+        if directive == "cask_args" {
+            continue;
+        }
+        // Valid Ruby, but brewdiff only reads the double-quoted names nix-darwin writes
+        if let Some(name) = rest
+            .strip_prefix('\'')
+            .and_then(|rest| rest.split_once('\''))
+            .map(|(name, _)| name)
+            .filter(|name| !name.is_empty())
+        {
+            findings.push(LintFinding::new(
+                number,
+                Severity::Warning,
+                format!(
+                    "{} '{}' is single-quoted, brewdiff only reads {} \"{}\"",
+                    directive, name, directive, name
+                ),
+            ));
+            continue;
+        }
+        let Some(name) =
+            HomebrewIntent::extract_quoted_value(rest).filter(|_| rest.starts_with('"'))
+        else {
+            findings.push(LintFinding::new(
+                number,
+                Severity::Critical,
+                format!(
+                    "{} needs a quoted name, e.g. {} \"name\"",
+                    directive, directive
+                ),
+            ));
+            continue;
+        };
+        let parsed = HomebrewIntent::parse_options(rest);
+
+        match directive {
+            "brew" => {
+                check_bool(&mut findings, number, &parsed, "link");
+                if parsed.get("args").is_some_and(|v| !v.starts_with('[')) {
+                    findings.push(LintFinding::new(
+                        number,
+                        Severity::Critical,
+                        format!(
+                            "args of brew \"{}\" must be an array, e.g. [\"with-x\"]",
+                            name
+                        ),
+                    ));
+                }
+            }
+            "cask" => {
+                check_bool(&mut findings, number, &parsed, "greedy");
+                if parsed.get("args").is_some_and(|v| !v.starts_with('{')) {
+                    findings.push(LintFinding::new(
+                        number,
+                        Severity::Critical,
+                        format!(
+                            "args of cask \"{}\" must be a hash, e.g. {{ appdir: \"~/Applications\" }}",
+                            name
+                        ),
+                    ));
+                }
+            }
+            "mas" => {
+                let Some((_, id)) = HomebrewIntent::parse_mas_line(rest) else {
+                    findings.push(LintFinding::new(
+                        number,
+                        Severity::Critical,
+                        format!(
+                            "mas \"{}\" needs a numeric id, e.g. mas \"{}\", id: 497799835",
+                            name, name
+                        ),
+                    ));
+                    continue;
+                };
+                if !conditional {
+                    match mas_ids.get(&id) {
+                        Some((first, other)) if *other != name => {
+                            findings.push(LintFinding::new(
+                                number,
+                                Severity::Warning,
+                                format!(
+                                    "mas \"{}\" has the same id {} as \"{}\" on line {}",
+                                    name, id, other, first
+                                ),
+                            ));
+                        }
+                        Some(_) => {}
+                        None => {
+                            mas_ids.insert(id, (number, name.clone()));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if conditional {
+            continue;
+        }
+        // Names are case-insensitive to brew, e.g. `tap "Homebrew/cask-fonts"`
+        let key = (directive, name.to_lowercase());
+        match declared.get(&key) {
+            Some(first) if first.options == normalized(&parsed) => {
+                findings.push(LintFinding::new(
+                    number,
+                    Severity::Warning,
+                    format!(
+                        "duplicate {} \"{}\", first declared on line {}",
+                        directive, name, first.line
+                    ),
+                ));
+            }
+            Some(first) => {
+                findings.push(LintFinding::new(
+                    number,
+                    Severity::Warning,
+                    format!(
+                        "{} \"{}\" is declared with different options on line {}, only one applies",
+                        directive, name, first.line
+                    ),
+                ));
+            }
+            None => {
+                declared.insert(
+                    key,
+                    Declared {
+                        line: number,
+                        options: normalized(&parsed),
+                    },
+                );
+            }
+        }
+    }
+
+    findings
+}
+
+/// Option values without whitespace outside of quotes, so `["a","b"]` equals `["a", "b"]`
+fn normalized(options: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    options
+        .iter()
+        .map(|(key, value)| {
+            let mut in_quotes = false;
+            let value = value
+                .chars()
+                .filter(|c| {
+                    if *c == '"' {
+                        in_quotes = !in_quotes;
+                    }
+                    in_quotes || !c.is_whitespace()
+                })
+                .collect();
+            (key.clone(), value)
+        })
+        .collect()
+}
+
+/// Flag an option that brew bundle expects to be `true` or `false`
+/// A bare word like `no` is an undefined name to Ruby, so brew bundle fails on it
+fn check_bool(
+    findings: &mut Vec<LintFinding>,
+    line: usize,
+    options: &BTreeMap<String, String>,
+    key: &str,
+) {
+    let Some(value) = options
+        .get(key)
+        .filter(|v| !matches!(v.as_str(), "true" | "false"))
+    else {
+        return;
+    };
+    let undefined = value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && value.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && value != "nil";
+    if undefined {
+        findings.push(LintFinding::new(
+            line,
+            Severity::Critical,
+            format!(
+                "{}: {} is an undefined name to Ruby, use true or false",
+                key, value
+            ),
+        ));
+    } else {
+        findings.push(LintFinding::new(
+            line,
+            Severity::Warning,
+            format!("{}: {} isn't true or false", key, value),
+        ));
+    }
+}
+
+/// Check if any finding makes brew bundle fail
+pub fn has_errors(findings: &[LintFinding]) -> bool {
+    findings
+        .iter()
+        .any(|finding| finding.severity == Severity::Critical)
+}
+
+/// Write findings as `Brewfile:3: warning: ...`, returns number of lines written
+pub fn write_findings<W: Write>(
+    writer: &mut W,
+    path: &str,
+    findings: &[LintFinding],
+) -> Result<usize> {
+    for finding in findings {
+        let severity = match finding.severity {
+            Severity::Critical => "error".red().bold().to_string(),
+            Severity::Warning => "warning".yellow().bold().to_string(),
+            Severity::Info => "info".blue().bold().to_string(),
+        };
+        writeln!(
+            writer,
+            "{}:{}: {}: {}",
+            path, finding.line, severity, finding.message
+        )?;
+    }
+    Ok(findings.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let brewfile = r#"# Taps
+tap "homebrew/bundle"
+brew "wget"
+brew "wget"
+brew "ffmpeg", args: ["with-fdk-aac"]
+brew "ffmpeg"
+brew "jq", link: no
+cask "firefox", greedy: true
+cask "firefox",greedy:true
+brew 'htop'
+brew "curl", link: "no"
+cask "docker", args: "~/Applications"
+mas "Xcode", id: 497799835
+mas "Xcode beta", id: 497799835
+mas "Keynote"
+brew htop
+flatpak "org.gimp.GIMP"
+nix "ripgrep"
+if `hostname`.chomp == "work-mac"
+  cask "slack"
+else
+  cask "slack"
+end
+cask "zoom" if ENV["USER"] == "kilian"
+cask "zoom"
+"#;
+
+        let findings: Vec<String> = validate(brewfile)
+            .iter()
+            .map(|finding| format!("{} {}: {}", finding.line, finding.severity, finding.message))
+            .collect();
+
+        assert_eq!(
+            findings,
+            vec![
+                "4 warning: duplicate brew \"wget\", first declared on line 3",
+                "6 warning: brew \"ffmpeg\" is declared with different options on line 5, only one applies",
+                "7 critical: link: no is an undefined name to Ruby, use true or false",
+                "9 warning: duplicate cask \"firefox\", first declared on line 8",
+                "10 warning: brew 'htop' is single-quoted, brewdiff only reads brew \"htop\"",
+                "11 warning: link: \"no\" isn't true or false",
+                "12 critical: args of cask \"docker\" must be a hash, e.g. { appdir: \"~/Applications\" }",
+                "14 warning: mas \"Xcode beta\" has the same id 497799835 as \"Xcode\" on line 13",
+                "15 critical: mas \"Keynote\" needs a numeric id, e.g. mas \"Keynote\", id: 497799835",
+                "16 critical: brew needs a quoted name, e.g. brew \"name\"",
+                "18 warning: unknown directive `nix`, brew bundle fails unless it's valid Ruby",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_findings() {
+        let findings = validate("brew \"git\"\nmas \"Xcode\"\n");
+        assert!(has_errors(&findings));
+
+        let mut output = String::new();
+        let lines = write_findings(&mut output, "Brewfile", &findings).unwrap();
+
+        assert_eq!(lines, 1);
+        assert!(output.starts_with("Brewfile:2: "));
+        assert!(output.contains("error"));
+        assert!(output.contains(": mas \"Xcode\" needs a numeric id"));
+    }
+}
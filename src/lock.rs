@@ -0,0 +1,109 @@
+//! Advisory lock so only one reconciliation changes the Homebrew installation at a time
+
+use crate::error::{Error, Result};
+use crate::state::HomebrewState;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Held while reconciling, released when dropped or when the process exits
+#[derive(Debug)]
+pub struct ReconcileLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl ReconcileLock {
+    /// Next to brew's own locks, so every user of the prefix shares it
+    pub fn default_path() -> PathBuf {
+        Path::new(HomebrewState::get_brew_prefix()).join("var/homebrew/locks/brewdiff.lock")
+    }
+
+    /// Take the lock without waiting, fails with `Error::ReconcileInProgress` if it's held
+    pub fn acquire(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&path)?;
+
+        if !try_lock(&file)? {
+            // The holder writes its pid, it's only informational
+            let holder = fs::read_to_string(&path).unwrap_or_default();
+            let holder = match holder.trim() {
+                "" => String::new(),
+                pid => format!(" by pid {}", pid),
+            };
+            return Err(Error::ReconcileInProgress(format!(
+                "{}{}",
+                path.display(),
+                holder
+            )));
+        }
+
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+        Ok(Self { file, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ReconcileLock {
+    fn drop(&mut self) {
+        // Leave the file in place, removing it would race with the next holder
+        let _ = self.file.set_len(0);
+        // SAFETY: the descriptor stays open for the duration of the call
+        unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+/// Take an exclusive flock without waiting, false if someone else holds it
+/// Called through libc since `File::try_lock` needs Rust 1.89
+fn try_lock(file: &File) -> io::Result<bool> {
+    // SAFETY: the descriptor stays open for the duration of the call
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let error = io::Error::last_os_error();
+    if error.kind() == io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("locks/brewdiff.lock");
+
+        let lock = ReconcileLock::acquire(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(lock.path()).unwrap(),
+            std::process::id().to_string()
+        );
+
+        let error = ReconcileLock::acquire(&path).unwrap_err();
+        assert!(matches!(error, Error::ReconcileInProgress(_)));
+        assert!(error
+            .to_string()
+            .contains(&format!("pid {}", std::process::id())));
+
+        drop(lock);
+        assert!(ReconcileLock::acquire(&path).is_ok());
+    }
+}
@@ -0,0 +1,134 @@
+//! `Brewfile.lock.json`, written by `brew bundle` next to the Brewfile with the versions it resolved
+
+use crate::error::{Error, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Shape of the lock file, only the parts with versions
+#[derive(Debug, Default, Deserialize)]
+struct LockFile {
+    #[serde(default)]
+    entries: LockEntries,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct LockEntries {
+    #[serde(default)]
+    brew: HashMap<String, LockEntry>,
+    #[serde(default)]
+    cask: HashMap<String, LockEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockEntry {
+    version: Option<String>,
+}
+
+/// Versions a lock file resolved declared formulae and casks to, by name
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LockedVersions {
+    pub brews: HashMap<String, String>,
+    pub casks: HashMap<String, String>,
+}
+
+impl LockedVersions {
+    /// The lock file belonging to a Brewfile, e.g. `Brewfile.lock.json` for `Brewfile`
+    pub fn path_for(brewfile: &Path) -> PathBuf {
+        let mut name = brewfile.file_name().unwrap_or_default().to_os_string();
+        name.push(".lock.json");
+        brewfile.with_file_name(name)
+    }
+
+    /// Read the lock file next to a Brewfile
+    /// Without one, or with one that can't be read, intent stays version-less
+    pub fn load(brewfile: &Path) -> Self {
+        let path = Self::path_for(brewfile);
+        let Ok(json) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        let locked = Self::parse(&json);
+        #[cfg(feature = "tracing")]
+        if let Err(e) = &locked {
+            crate::trace::event!(error = %e, path = %path.display(), "ignoring unreadable lock file");
+        }
+        locked.unwrap_or_default()
+    }
+
+    /// Parse the contents of a lock file
+    pub fn parse(json: &str) -> Result<Self> {
+        let lock: LockFile = serde_json::from_str(json)
+            .map_err(|e| Error::ParseError(format!("invalid Brewfile.lock.json: {}", e)))?;
+        let versions = |entries: HashMap<String, LockEntry>| {
+            entries
+                .into_iter()
+                .filter_map(|(name, entry)| Some((name, entry.version?)))
+                .collect()
+        };
+
+        Ok(Self {
+            brews: versions(lock.entries.brew),
+            casks: versions(lock.entries.cask),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.brews.is_empty() && self.casks.is_empty()
+    }
+
+    /// Whether the lock file resolved the formula, or with `cask`, the cask
+    pub fn pins(&self, name: &str, cask: bool) -> bool {
+        if cask {
+            self.casks.contains_key(name)
+        } else {
+            self.brews.contains_key(name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const LOCK: &str = r#"{
+        "entries": {
+            "tap": {"homebrew/bundle": {"revision": "a1b2c3"}},
+            "brew": {
+                "wget": {"version": "1.24.5", "bottle": {"rebuild": 0}},
+                "jq": {"version": "1.7.1"}
+            },
+            "cask": {"firefox": {"version": "125.0.3", "options": {"full_name": "firefox"}}},
+            "mas": {"Xcode": {"id": 497799835, "version": "15.4"}}
+        },
+        "system": {"macos": {}}
+    }"#;
+
+    #[test]
+    fn test_parse_lock_file() {
+        let locked = LockedVersions::parse(LOCK).unwrap();
+
+        assert_eq!(locked.brews.len(), 2);
+        assert_eq!(locked.brews["wget"], "1.24.5");
+        assert_eq!(locked.casks["firefox"], "125.0.3");
+        assert!(LockedVersions::parse("not json").is_err());
+    }
+
+    #[test]
+    fn test_load_adjacent_lock_file() {
+        let dir = TempDir::new().unwrap();
+        let brewfile = dir.path().join("Brewfile");
+        assert_eq!(
+            LockedVersions::path_for(&brewfile),
+            dir.path().join("Brewfile.lock.json")
+        );
+        assert!(LockedVersions::load(&brewfile).is_empty());
+
+        fs::write(dir.path().join("Brewfile.lock.json"), LOCK).unwrap();
+        assert_eq!(LockedVersions::load(&brewfile).brews["jq"], "1.7.1");
+
+        fs::write(dir.path().join("Brewfile.lock.json"), "{").unwrap();
+        assert!(LockedVersions::load(&brewfile).is_empty());
+    }
+}
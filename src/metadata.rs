@@ -0,0 +1,313 @@
+use crate::error::{Error, Result};
+use crate::runner::CommandRunner;
+use crate::state::{tap_repository, HomebrewState};
+use crate::trace::{event, span};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Formula properties that aren't part of the installed state
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct FormulaMetadata {
+    pub name: String,
+    /// Not linked into the prefix, usually needs extra PATH or build flags
+    #[serde(default)]
+    pub keg_only: bool,
+    /// One-line summary, e.g. `Internet file retriever`
+    #[serde(default)]
+    pub desc: Option<String>,
+    /// Tap the formula comes from, e.g. `homebrew/core`
+    #[serde(default)]
+    pub tap: Option<String>,
+    /// Upstream project page, often its repository
+    #[serde(default)]
+    pub homepage: Option<String>,
+    #[serde(default)]
+    pub versions: FormulaVersions,
+}
+
+/// Versions a formula is available in
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct FormulaVersions {
+    /// The version `brew install` installs, e.g. `1.24.5`
+    #[serde(default)]
+    pub stable: Option<String>,
+}
+
+/// An installed tap as reported by `brew tap-info`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct TapInfo {
+    pub name: String,
+    /// Clone URL of the tap's repository, None for taps installed from the API
+    #[serde(default)]
+    pub remote: Option<String>,
+}
+
+/// Shape of Homebrew's cached API files, the payload is a JSON string
+#[derive(Debug, Deserialize)]
+struct ApiCache {
+    payload: String,
+}
+
+/// Shape of `brew info --json=v2`
+#[derive(Debug, Deserialize)]
+struct InfoReport {
+    #[serde(default)]
+    formulae: Vec<FormulaMetadata>,
+}
+
+/// Where metadata may be looked up beyond what Homebrew has locally
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetadataOptions {
+    /// Fetch formulae of taps that aren't tapped yet from their GitHub repository
+    /// Off by default, it needs the network and may take seconds per formula
+    pub fetch_untapped: bool,
+}
+
+/// Look up metadata for the given formulae
+/// Reads Homebrew's API cache and falls back to `brew info` for anything missing
+/// Formulae from taps that aren't tapped yet are only looked up with `fetch_untapped`
+pub fn formula_metadata(
+    runner: &dyn CommandRunner,
+    names: &[&str],
+    options: &MetadataOptions,
+) -> Result<HashMap<String, FormulaMetadata>> {
+    let _span = span!("formula_metadata", count = names.len());
+    let wanted: HashSet<&str> = names.iter().copied().collect();
+    let mut result = api_cache_path("formula.jws.json")
+        .and_then(|path| fs::read(path).ok())
+        .and_then(|content| parse_api_cache(&content, &wanted).ok())
+        .unwrap_or_default();
+
+    // brew knows nothing about formulae from taps that aren't tapped yet
+    let (untapped, missing): (Vec<&str>, Vec<&str>) = names
+        .iter()
+        .copied()
+        .filter(|name| !result.contains_key(*name))
+        .partition(|name| {
+            tap_of(name).is_some_and(|tap| {
+                !tap_repository(Path::new(HomebrewState::get_brew_prefix()), tap).is_dir()
+            })
+        });
+    event!(
+        untapped = untapped.len(),
+        missing = missing.len(),
+        "API cache lookup done"
+    );
+    if options.fetch_untapped {
+        for name in untapped {
+            if let Some(metadata) = fetch_tap_formula(runner, name) {
+                result.insert(name.to_string(), metadata);
+            }
+        }
+    }
+    if missing.is_empty() || !HomebrewState::homebrew_installed() {
+        return Ok(result);
+    }
+
+    let mut args = vec!["info", "--json=v2", "--formula"];
+    args.extend(missing);
+    let output = runner
+        .output(HomebrewState::get_brew_command(), &args)
+        .map_err(|e| Error::CommandFailed(format!("brew info failed: {}", e)))?;
+
+    // brew info fails entirely if any name is unknown, which is fine for annotations
+    if output.status.success() {
+        result.extend(parse_info_output(&output.stdout)?);
+    }
+
+    Ok(result)
+}
+
+/// Look up remotes of all installed taps via `brew tap-info`
+pub fn tap_info(runner: &dyn CommandRunner) -> Result<HashMap<String, TapInfo>> {
+    let _span = span!("tap_info");
+    if !HomebrewState::homebrew_installed() {
+        return Ok(HashMap::new());
+    }
+
+    let output = runner
+        .output(
+            HomebrewState::get_brew_command(),
+            &["tap-info", "--json", "--installed"],
+        )
+        .map_err(|e| Error::CommandFailed(format!("brew tap-info failed: {}", e)))?;
+
+    if !output.status.success() {
+        return Ok(HashMap::new());
+    }
+
+    parse_tap_info_output(&output.stdout)
+}
+
+fn parse_tap_info_output(output: &[u8]) -> Result<HashMap<String, TapInfo>> {
+    let taps: Vec<TapInfo> = serde_json::from_slice(output)
+        .map_err(|e| Error::CommandFailed(format!("brew tap-info returned invalid JSON: {}", e)))?;
+
+    Ok(taps
+        .into_iter()
+        .map(|tap| (tap.name.clone(), tap))
+        .collect())
+}
+
+/// The tap part of a fully qualified name like `hashicorp/tap/terraform`
+pub(crate) fn tap_of(name: &str) -> Option<&str> {
+    let (tap, _) = name.rsplit_once('/')?;
+    (tap.matches('/').count() == 1).then_some(tap)
+}
+
+/// Best-effort lookup of a formula from a tap's GitHub repository, without tapping it
+fn fetch_tap_formula(runner: &dyn CommandRunner, name: &str) -> Option<FormulaMetadata> {
+    let tap = tap_of(name)?;
+    let (user, repo) = tap.split_once('/')?;
+    let formula = &name[tap.len() + 1..];
+
+    ["Formula/", "HomebrewFormula/", ""].iter().find_map(|dir| {
+        let url = format!(
+            "https://raw.githubusercontent.com/{}/homebrew-{}/HEAD/{}{}.rb",
+            user, repo, dir, formula
+        );
+        let output = runner
+            .output("curl", &["--silent", "--fail", "--max-time", "5", &url])
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| parse_formula_source(name, &String::from_utf8_lossy(&output.stdout)))
+    })
+}
+
+/// Pull the bits we care about out of a formula's Ruby source
+fn parse_formula_source(name: &str, source: &str) -> FormulaMetadata {
+    let desc = source.lines().find_map(|line| {
+        let rest = line.trim().strip_prefix("desc ")?;
+        Some(rest.trim().trim_matches('"').to_string())
+    });
+    let keg_only = source
+        .lines()
+        .any(|line| line.trim_start().starts_with("keg_only"));
+
+    FormulaMetadata {
+        name: name.to_string(),
+        keg_only,
+        desc,
+        tap: tap_of(name).map(String::from),
+        ..Default::default()
+    }
+}
+
+fn api_cache_path(file: &str) -> Option<PathBuf> {
+    let cache = match std::env::var_os("HOMEBREW_CACHE") {
+        Some(cache) => PathBuf::from(cache),
+        None => PathBuf::from(std::env::var_os("HOME")?).join("Library/Caches/Homebrew"),
+    };
+    Some(cache.join("api").join(file))
+}
+
+fn parse_api_cache(
+    content: &[u8],
+    wanted: &HashSet<&str>,
+) -> Result<HashMap<String, FormulaMetadata>> {
+    let cache: ApiCache = serde_json::from_slice(content)
+        .map_err(|e| Error::ParseError(format!("Invalid Homebrew API cache: {}", e)))?;
+    let formulae: Vec<FormulaMetadata> = serde_json::from_str(&cache.payload)
+        .map_err(|e| Error::ParseError(format!("Invalid Homebrew API cache payload: {}", e)))?;
+
+    Ok(formulae
+        .into_iter()
+        .filter(|formula| wanted.contains(formula.name.as_str()))
+        .map(|formula| (formula.name.clone(), formula))
+        .collect())
+}
+
+fn parse_info_output(output: &[u8]) -> Result<HashMap<String, FormulaMetadata>> {
+    let report: InfoReport = serde_json::from_slice(output)
+        .map_err(|e| Error::CommandFailed(format!("brew info returned invalid JSON: {}", e)))?;
+
+    Ok(report
+        .formulae
+        .into_iter()
+        .map(|formula| (formula.name.clone(), formula))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_api_cache() {
+        let payload = r#"[{"name": "openssl@3", "keg_only": true}, {"name": "wget", "keg_only": false}, {"name": "curl", "keg_only": true}]"#;
+        let content = serde_json::json!({ "payload": payload, "signatures": [] }).to_string();
+        let wanted: HashSet<&str> = ["openssl@3", "wget"].into_iter().collect();
+
+        let result = parse_api_cache(content.as_bytes(), &wanted).unwrap();
+
+        assert_eq!(result.len(), 2);
+        assert!(result["openssl@3"].keg_only);
+        assert!(!result["wget"].keg_only);
+    }
+
+    #[test]
+    fn test_parse_info_output() {
+        let output = br#"{"formulae": [{"name": "sqlite", "keg_only": true, "desc": "SQL engine", "homepage": "https://sqlite.org/index.html", "versions": {"stable": "3.46.0", "head": null, "bottle": true}}], "casks": []}"#;
+        let result = parse_info_output(output).unwrap();
+
+        assert_eq!(
+            result.get("sqlite"),
+            Some(&FormulaMetadata {
+                name: "sqlite".to_string(),
+                keg_only: true,
+                desc: Some("SQL engine".to_string()),
+                tap: None,
+                homepage: Some("https://sqlite.org/index.html".to_string()),
+                versions: FormulaVersions {
+                    stable: Some("3.46.0".to_string()),
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_tap_of() {
+        assert_eq!(tap_of("hashicorp/tap/terraform"), Some("hashicorp/tap"));
+        assert_eq!(tap_of("terraform"), None);
+        assert_eq!(tap_of("python@3.12"), None);
+    }
+
+    #[test]
+    fn test_parse_formula_source() {
+        let source = r#"class Terraform < Formula
+  desc "Tool to build, change, and version infrastructure"
+  homepage "https://www.terraform.io/"
+  version "1.9.5"
+end
+"#;
+        let metadata = parse_formula_source("hashicorp/tap/terraform", source);
+
+        assert_eq!(metadata.name, "hashicorp/tap/terraform");
+        assert_eq!(metadata.tap.as_deref(), Some("hashicorp/tap"));
+        assert!(!metadata.keg_only);
+        assert_eq!(
+            metadata.desc.as_deref(),
+            Some("Tool to build, change, and version infrastructure")
+        );
+    }
+
+    #[test]
+    fn test_parse_tap_info_output() {
+        let output = br#"[
+            {"name": "homebrew/cask", "installed": true, "remote": null},
+            {"name": "acme/tools", "installed": true, "remote": "https://github.com/acme/homebrew-tools", "custom_remote": false}
+        ]"#;
+        let result = parse_tap_info_output(output).unwrap();
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(result["homebrew/cask"].remote, None);
+        assert_eq!(
+            result["acme/tools"].remote.as_deref(),
+            Some("https://github.com/acme/homebrew-tools")
+        );
+    }
+}
@@ -0,0 +1,59 @@
+//! Single-call entry point for nh's diff hook
+
+use crate::cache::StateCache;
+use crate::diff::HomebrewDiffData;
+use crate::display;
+use crate::error::{Error, Result};
+use crate::intent::HomebrewIntent;
+use crate::runner::SystemRunner;
+use crate::state::HomebrewState;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Write the Homebrew diff for a built profile, returns whether anything was printed
+/// Prints nothing when the profile doesn't configure Homebrew or Homebrew isn't installed
+pub fn write_diff_hook<W: Write>(writer: &mut W, profile: &Path) -> Result<bool> {
+    let nix_intent = match HomebrewIntent::extract(profile) {
+        Ok(intent) => intent,
+        Err(Error::BrewfileNotFound) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if !HomebrewState::homebrew_installed() {
+        return Ok(false);
+    }
+
+    let runner = SystemRunner::default();
+    let current_state = StateCache::global().get_for(&nix_intent, &runner)?;
+    let mut diff_data = HomebrewDiffData::compute(&current_state, &nix_intent);
+    diff_data.load_versions(&runner)?;
+    Ok(display::write_diff(writer, &diff_data)?.had_changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_silent_without_homebrew_config() {
+        let profile = TempDir::new().unwrap();
+        fs::write(profile.path().join("activate"), "echo setting up\n").unwrap();
+
+        let mut output = String::new();
+        let printed = write_diff_hook(&mut output, profile.path()).unwrap();
+
+        assert!(!printed);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_missing_activation_script_is_an_error() {
+        let profile = TempDir::new().unwrap();
+
+        let mut output = String::new();
+        let result = write_diff_hook(&mut output, profile.path());
+
+        assert!(matches!(result, Err(Error::NoActivationScript(_))));
+    }
+}
@@ -0,0 +1,105 @@
+//! Hints for formulae that nixpkgs packages under the same name, for managing CLI tools with nix
+
+use crate::diff::HomebrewDiffData;
+use crate::error::{Error, Result};
+use crate::runner::CommandRunner;
+use crate::trace::{event, span};
+
+/// Keeps the names nixpkgs has a package for, without evaluating the packages themselves
+const FILTER: &str =
+    "names: let pkgs = (builtins.getFlake \"nixpkgs\").legacyPackages.${builtins.currentSystem}; \
+                      in builtins.filter (name: builtins.hasAttr name pkgs) names";
+
+/// The names nixpkgs has a package for, via the `nixpkgs` flake registry entry
+pub fn available(runner: &dyn CommandRunner, names: &[&str]) -> Result<Vec<String>> {
+    let _span = span!("nixpkgs_lookup", count = names.len());
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let names = serde_json::to_string(names).map_err(std::io::Error::from)?;
+    let expr = format!("({}) (builtins.fromJSON ''{}'')", FILTER, names);
+    let output = runner
+        .output(
+            "nix",
+            &[
+                "--extra-experimental-features",
+                "nix-command flakes",
+                "eval",
+                "--impure",
+                "--json",
+                "--expr",
+                &expr,
+            ],
+        )
+        .map_err(|e| Error::CommandFailed(format!("nix eval failed: {}", e)))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::CommandFailed(format!(
+            "nixpkgs lookup failed: {}",
+            stderr.trim().lines().last().unwrap_or_default()
+        )));
+    }
+
+    let available: Vec<String> = serde_json::from_slice(&output.stdout)
+        .map_err(|e| Error::CommandFailed(format!("nix eval returned invalid JSON: {}", e)))?;
+    event!(available = available.len(), "nixpkgs lookup done");
+    Ok(available)
+}
+
+impl HomebrewDiffData {
+    /// Look up which added formulae nixpkgs has a package of the same name for
+    /// Tapped formulae are looked up by their name without the tap
+    pub fn load_nixpkgs_equivalents(&mut self, runner: &dyn CommandRunner) -> Result<()> {
+        let short = |name: &str| name.rsplit('/').next().unwrap_or(name).to_string();
+        let names: Vec<String> = self.brews.added.iter().map(|name| short(name)).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let available = available(runner, &names)?;
+
+        self.nixpkgs_equivalents = self
+            .brews
+            .added
+            .iter()
+            .filter(|name| available.contains(&short(name)))
+            .map(|name| (name.clone(), short(name)))
+            .collect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::FixtureRunner;
+
+    #[test]
+    fn test_load_nixpkgs_equivalents() {
+        let mut diff = HomebrewDiffData::default();
+        diff.brews.added = vec![
+            "ripgrep".to_string(),
+            "hashicorp/tap/terraform".to_string(),
+            "acme-internal-cli".to_string(),
+        ];
+        // Tapped formulae are looked up without their tap
+        let expr = format!(
+            r#"({}) (builtins.fromJSON ''["ripgrep","terraform","acme-internal-cli"]'')"#,
+            FILTER
+        );
+        let runner = FixtureRunner::default().with(
+            format!(
+                "nix --extra-experimental-features nix-command flakes eval --impure --json --expr {}",
+                expr
+            ),
+            r#"["ripgrep","terraform"]"#,
+        );
+
+        diff.load_nixpkgs_equivalents(&runner).unwrap();
+
+        assert_eq!(diff.nixpkgs_equivalents.len(), 2);
+        assert_eq!(
+            diff.nixpkgs_equivalents["hashicorp/tap/terraform"],
+            "terraform"
+        );
+        assert!(!diff.nixpkgs_equivalents.contains_key("acme-internal-cli"));
+    }
+}
@@ -0,0 +1,176 @@
+//! What a diff run amounted to, with stable exit codes so the CLI, nh and scripts agree
+//!
+//! | Outcome            | Exit code |
+//! |--------------------|-----------|
+//! | `Clean`            | 0         |
+//! | `Drift`            | 1         |
+//! | `Error`            | 2         |
+//! | `PartialDetection` | 3         |
+//!
+//! Like diff(1), 0 means no differences, 1 differences and 2 trouble
+
+use crate::compat::BrewFeature;
+use crate::diff::HomebrewDiffData;
+use crate::doctor::{CheckStatus, Finding};
+use crate::events::{Category, CategorySet};
+use crate::state::{DetectionSource, HomebrewState};
+use std::fmt;
+use std::process::ExitCode;
+
+/// Ordered from best to worst, so combining runs can take the maximum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DiffOutcome {
+    /// Activation wouldn't change anything
+    Clean,
+    /// Activation would add, remove or upgrade something
+    Drift,
+    /// Some categories weren't detected or a check failed, so the diff may miss changes
+    PartialDetection,
+    /// No diff could be computed
+    Error,
+}
+
+impl DiffOutcome {
+    pub fn code(self) -> u8 {
+        match self {
+            DiffOutcome::Clean => 0,
+            DiffOutcome::Drift => 1,
+            DiffOutcome::Error => 2,
+            DiffOutcome::PartialDetection => 3,
+        }
+    }
+
+    /// The outcome a process exited with, e.g. when running the CLI from a script
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(DiffOutcome::Clean),
+            1 => Some(DiffOutcome::Drift),
+            2 => Some(DiffOutcome::Error),
+            3 => Some(DiffOutcome::PartialDetection),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DiffOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DiffOutcome::Clean => "clean",
+            DiffOutcome::Drift => "drift",
+            DiffOutcome::PartialDetection => "partial detection",
+            DiffOutcome::Error => "error",
+        })
+    }
+}
+
+impl From<DiffOutcome> for ExitCode {
+    fn from(outcome: DiffOutcome) -> Self {
+        ExitCode::from(outcome.code())
+    }
+}
+
+/// How complete the inputs of a diff were
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics {
+    /// Categories that were asked for but not detected, e.g. without Homebrew installed
+    pub undetected: Vec<Category>,
+    /// Features the installed brew lacks, so parts of detection were skipped
+    pub unsupported: Vec<BrewFeature>,
+    /// Results of `doctor::run_diagnostics`, failed checks make the diff partial
+    pub findings: Vec<Finding>,
+}
+
+impl Diagnostics {
+    /// The selected categories the state has no detection source for,
+    /// and the brew features detection had to do without
+    pub fn from_state(state: &HomebrewState, selected: CategorySet) -> Self {
+        let sources = &state.sources;
+        let undetected = selected
+            .iter()
+            .filter(|category| {
+                let source = match category {
+                    Category::Taps => sources.taps,
+                    Category::Formulae => sources.formulae,
+                    Category::Casks => sources.casks,
+                    Category::AppStore => sources.mas_apps,
+                };
+                source == DetectionSource::None
+            })
+            .collect();
+
+        Self {
+            undetected,
+            unsupported: state.brew_compat().unsupported(),
+            findings: Vec::new(),
+        }
+    }
+
+    pub fn is_partial(&self) -> bool {
+        !self.undetected.is_empty()
+            || !self.unsupported.is_empty()
+            || self
+                .findings
+                .iter()
+                .any(|finding| finding.status == CheckStatus::Failed)
+    }
+}
+
+/// The outcome of a computed diff, an incomplete detection outweighs drift
+pub fn outcome_for(diff_data: &HomebrewDiffData, diagnostics: &Diagnostics) -> DiffOutcome {
+    if diagnostics.is_partial() {
+        DiffOutcome::PartialDetection
+    } else if diff_data.has_changes() {
+        DiffOutcome::Drift
+    } else {
+        DiffOutcome::Clean
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outcome_for() {
+        let mut state = HomebrewState::from_bundle_dump("brew \"git\"\n");
+        let diagnostics = Diagnostics::from_state(&state, CategorySet::all());
+        let mut diff = HomebrewDiffData::default();
+
+        assert_eq!(outcome_for(&diff, &diagnostics), DiffOutcome::Clean);
+        diff.brews.added = vec!["wget".to_string()];
+        assert_eq!(outcome_for(&diff, &diagnostics), DiffOutcome::Drift);
+
+        state.sources.mas_apps = DetectionSource::None;
+        let diagnostics = Diagnostics::from_state(&state, CategorySet::all());
+        assert_eq!(diagnostics.undetected, vec![Category::AppStore]);
+        assert_eq!(
+            outcome_for(&diff, &diagnostics),
+            DiffOutcome::PartialDetection
+        );
+        let skipped = CategorySet::all().without(CategorySet::only([Category::AppStore]));
+        assert!(!Diagnostics::from_state(&state, skipped).is_partial());
+
+        state.brew_config.version = Some("3.0.0".to_string());
+        let diagnostics = Diagnostics::from_state(&state, skipped);
+        assert_eq!(
+            diagnostics.unsupported,
+            vec![BrewFeature::GreedyAutoUpdates]
+        );
+        assert!(diagnostics.is_partial());
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        for outcome in [
+            DiffOutcome::Clean,
+            DiffOutcome::Drift,
+            DiffOutcome::PartialDetection,
+            DiffOutcome::Error,
+        ] {
+            assert_eq!(DiffOutcome::from_code(outcome.code()), Some(outcome));
+        }
+        assert_eq!(DiffOutcome::Error.code(), 2);
+        assert_eq!(DiffOutcome::from_code(42), None);
+        assert!(DiffOutcome::Error > DiffOutcome::Drift);
+    }
+}
@@ -0,0 +1,232 @@
+//! Formulae that the nix profile already provides, e.g. `brew "ripgrep"` next to ripgrep from nixpkgs
+
+use crate::diff::HomebrewDiffData;
+use crate::intent::HomebrewIntent;
+use crate::state::HomebrewState;
+use crate::trace::event;
+use crate::version;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// A formula installed or declared alongside the same software from nix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NixOverlap {
+    pub formula: String,
+    /// The nix package name, e.g. `ripgrep` from `/nix/store/…-ripgrep-14.1.0`
+    pub nix_package: String,
+    /// Executables both put on the PATH, e.g. `rg`, empty if only the names match
+    pub executables: Vec<String>,
+}
+
+/// Packages whose executables the profile links, by name, with those executables
+/// Reads `sw/bin` of system profiles and `bin` of user profiles
+pub fn profile_packages(profile: &Path) -> BTreeMap<String, BTreeSet<String>> {
+    let mut packages: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for bin in [profile.join("sw/bin"), profile.join("bin")] {
+        let Ok(entries) = fs::read_dir(&bin) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Some(package) = fs::read_link(entry.path())
+                .ok()
+                .and_then(|target| store_package(&target))
+            else {
+                continue;
+            };
+            packages
+                .entry(package)
+                .or_default()
+                .insert(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    packages
+}
+
+/// The package name of a store path, e.g. `ripgrep` for `/nix/store/<hash>-ripgrep-14.1.0/bin/rg`
+fn store_package(path: &Path) -> Option<String> {
+    let mut components = path
+        .components()
+        .skip_while(|c| *c != Component::Normal("store".as_ref()));
+    components.next()?;
+    let name = components.next()?.as_os_str().to_str()?;
+    let (hash, name) = name.split_once('-')?;
+    if hash.len() != 32 {
+        return None;
+    }
+    Some(package_name(name).to_string())
+}
+
+/// Strip the version off a store name, it starts at the first dash followed by a digit
+fn package_name(name: &str) -> &str {
+    name.match_indices('-')
+        .find(|(i, _)| name[i + 1..].starts_with(|c: char| c.is_ascii_digit()))
+        .map_or(name, |(i, _)| &name[..i])
+}
+
+/// The name a formula's software goes by, e.g. `python` for `python@3.12`
+fn software_name(formula: &str) -> &str {
+    let name = formula.rsplit('/').next().unwrap_or(formula);
+    name.split('@').next().unwrap_or(name)
+}
+
+/// The keg of an installed formula, the linked one in `opt/` or else the newest in the Cellar
+/// Read from the prefix, since the state's versions may not be looked up yet
+fn keg(prefix: &Path, name: &str) -> Option<PathBuf> {
+    let opt = prefix.join("opt").join(name);
+    if opt.is_dir() {
+        return Some(opt);
+    }
+    let cellar = prefix.join("Cellar").join(name);
+    let newest = fs::read_dir(&cellar)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|version| !version.starts_with('.'))
+        .max_by(|a, b| version::compare(a, b))?;
+    Some(cellar.join(newest))
+}
+
+/// Executables an installed formula's keg provides
+fn keg_executables(prefix: &Path, name: &str) -> BTreeSet<String> {
+    keg(prefix, name)
+        .and_then(|keg| fs::read_dir(keg.join("bin")).ok())
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Installed and declared formulae matching a nix package by name, or installed ones
+/// sharing executables with it
+pub fn find_overlaps(
+    nix_packages: &BTreeMap<String, BTreeSet<String>>,
+    current_state: &HomebrewState,
+    nix_intent: &HomebrewIntent,
+) -> Vec<NixOverlap> {
+    let formulae: BTreeSet<&str> = current_state
+        .installed_brews
+        .keys()
+        .chain(&nix_intent.brews)
+        .map(String::as_str)
+        .collect();
+    // Bundle dumps and recorded states have no Cellar to look into
+    let prefix = current_state.brew_config.prefix.as_deref();
+
+    formulae
+        .into_iter()
+        .filter_map(|formula| {
+            let executables = match prefix {
+                Some(prefix) if current_state.installed_brews.contains_key(formula) => {
+                    keg_executables(prefix, formula)
+                }
+                _ => BTreeSet::new(),
+            };
+            let software = software_name(formula);
+            nix_packages.iter().find_map(|(package, nix_executables)| {
+                let shared: Vec<String> =
+                    executables.intersection(nix_executables).cloned().collect();
+                (package.eq_ignore_ascii_case(software) || !shared.is_empty()).then(|| NixOverlap {
+                    formula: formula.to_string(),
+                    nix_package: package.clone(),
+                    executables: shared,
+                })
+            })
+        })
+        .collect()
+}
+
+impl HomebrewDiffData {
+    /// Find formulae that the profile's nix packages already provide, for consolidating on one
+    pub fn load_nix_overlaps(
+        &mut self,
+        profile: &Path,
+        current_state: &HomebrewState,
+        nix_intent: &HomebrewIntent,
+    ) {
+        let packages = profile_packages(profile);
+        self.nix_overlaps = find_overlaps(&packages, current_state, nix_intent);
+        event!(
+            packages = packages.len(),
+            overlaps = self.nix_overlaps.len(),
+            "compared with nix profile"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    const HASH: &str = "0c8f3ifn3cd3x4hbmxh3wvxvsgqfbq7r";
+
+    #[test]
+    fn test_package_name() {
+        assert_eq!(package_name("ripgrep-14.1.0"), "ripgrep");
+        assert_eq!(package_name("python3-3.12.4"), "python3");
+        assert_eq!(package_name("git-lfs-3.5.1"), "git-lfs");
+        assert_eq!(package_name("hello"), "hello");
+        assert_eq!(software_name("python@3.12"), "python");
+        assert_eq!(
+            store_package(Path::new(&format!(
+                "/nix/store/{}-ripgrep-14.1.0/bin/rg",
+                HASH
+            ))),
+            Some("ripgrep".to_string())
+        );
+        assert_eq!(store_package(Path::new("/usr/bin/rg")), None);
+    }
+
+    #[test]
+    fn test_find_overlaps() {
+        let dir = TempDir::new().unwrap();
+        let bin = dir.path().join("profile/sw/bin");
+        fs::create_dir_all(&bin).unwrap();
+        for (exe, package) in [
+            ("rg", "ripgrep-14.1.0"),
+            ("fd", "fd-10.1.0"),
+            ("jq", "jq-1.7.1"),
+        ] {
+            symlink(
+                format!("/nix/store/{}-{}/bin/{}", HASH, package, exe),
+                bin.join(exe),
+            )
+            .unwrap();
+        }
+        // Two kegs, only the newer one provides fd
+        let prefix = dir.path().join("homebrew");
+        fs::create_dir_all(prefix.join("Cellar/fd-find/9.0.0/bin")).unwrap();
+        fs::create_dir_all(prefix.join("Cellar/fd-find/10.1.0/bin")).unwrap();
+        fs::write(prefix.join("Cellar/fd-find/10.1.0/bin/fd"), "").unwrap();
+
+        // Versions weren't looked up, the keg is found in the prefix
+        let mut state = HomebrewState::from_bundle_dump("brew \"fd-find\"\nbrew \"wget\"\n");
+        state.brew_config.prefix = Some(prefix);
+        let intent = HomebrewIntent::from_brewfile("brew \"ripgrep\"\nbrew \"wget\"\n");
+
+        let packages = profile_packages(&dir.path().join("profile"));
+        assert_eq!(packages.len(), 3);
+        let overlaps = find_overlaps(&packages, &state, &intent);
+
+        assert_eq!(
+            overlaps,
+            vec![
+                NixOverlap {
+                    formula: "fd-find".to_string(),
+                    nix_package: "fd".to_string(),
+                    executables: vec!["fd".to_string()],
+                },
+                NixOverlap {
+                    formula: "ripgrep".to_string(),
+                    nix_package: "ripgrep".to_string(),
+                    executables: Vec::new(),
+                },
+            ]
+        );
+    }
+}
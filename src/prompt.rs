@@ -0,0 +1,206 @@
+//! Compact output for shell prompt segments, e.g. starship or powerlevel10k custom segments
+//! Prompts render on every command, so the segment is read from a cache and never runs brew
+
+use crate::diff::HomebrewDiffData;
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Cached summaries older than this are stale, e.g. after `brew update` found new versions
+pub const MAX_AGE_SECS: u64 = 60 * 60;
+
+/// Counts of the changes activation would make
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PromptSummary {
+    pub added: usize,
+    /// Only entries activation uninstalls, unmanaged ones stay installed
+    pub removed: usize,
+    pub upgraded: usize,
+}
+
+impl PromptSummary {
+    pub fn from_diff(diff_data: &HomebrewDiffData) -> Self {
+        let removed = [
+            &diff_data.taps.removed,
+            &diff_data.brews.removed,
+            &diff_data.casks.removed,
+            &diff_data.mas_apps.removed,
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|removal| removal.effect.is_destructive())
+        .count();
+
+        Self {
+            added: diff_data.taps.added.len()
+                + diff_data.brews.added.len()
+                + diff_data.casks.added.len()
+                + diff_data.mas_apps.added.len(),
+            removed,
+            upgraded: diff_data.brews.upgrades().count() + diff_data.casks.upgrades().count(),
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for PromptSummary {
+    /// e.g. `🍺 +3/-1`, or `🍺 +3/-1 ↑2` with upgrades
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "🍺 +{}/-{}", self.added, self.removed)?;
+        if self.upgraded > 0 {
+            write!(f, " ↑{}", self.upgraded)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a cached summary was computed from, any difference makes it stale
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Fingerprint {
+    /// The profile with symlinks resolved, so a new generation behind the same link counts
+    profile: PathBuf,
+    /// Milliseconds since the Unix epoch, the directories change on installs and removals
+    cellar: Option<u64>,
+    caskroom: Option<u64>,
+}
+
+impl Fingerprint {
+    /// Only stats a few paths, cheap enough for every prompt
+    fn current(profile: &Path, prefix: &Path) -> Self {
+        Self {
+            profile: fs::canonicalize(profile).unwrap_or_else(|_| profile.to_path_buf()),
+            cellar: modified_millis(&prefix.join("Cellar")),
+            caskroom: modified_millis(&prefix.join("Caskroom")),
+        }
+    }
+}
+
+fn modified_millis(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_millis() as u64)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheRecord {
+    fingerprint: Fingerprint,
+    /// Seconds since the Unix epoch
+    written_at: u64,
+    summary: PromptSummary,
+}
+
+/// A JSON file holding the summary of the last full diff
+#[derive(Debug, Clone)]
+pub struct PromptCache {
+    path: PathBuf,
+}
+
+impl PromptCache {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// `~/Library/Caches/brewdiff/prompt.json`, None without a home directory
+    pub fn default_path() -> Option<PathBuf> {
+        let home = std::env::var_os("HOME")?;
+        Some(Path::new(&home).join("Library/Caches/brewdiff/prompt.json"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The cached summary, None if there is none or it no longer matches the profile or prefix
+    pub fn load(&self, profile: &Path, prefix: &Path) -> Option<PromptSummary> {
+        let content = fs::read(&self.path).ok()?;
+        let record: CacheRecord = serde_json::from_slice(&content).ok()?;
+        let fresh = now_secs().saturating_sub(record.written_at) <= MAX_AGE_SECS
+            && record.fingerprint == Fingerprint::current(profile, prefix);
+        fresh.then_some(record.summary)
+    }
+
+    /// Store a summary computed for the profile against the installation at the prefix
+    pub fn store(&self, profile: &Path, prefix: &Path, summary: PromptSummary) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let record = CacheRecord {
+            fingerprint: Fingerprint::current(profile, prefix),
+            written_at: now_secs(),
+            summary,
+        };
+        // Write and rename, so a prompt never reads a half-written file
+        let partial = self.path.with_extension("json.tmp");
+        fs::write(
+            &partial,
+            serde_json::to_vec(&record).map_err(std::io::Error::from)?,
+        )?;
+        fs::rename(&partial, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::{ChangeEffect, Removal};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_prompt_summary() {
+        let mut diff = HomebrewDiffData::default();
+        diff.brews.added = vec!["curl".to_string(), "jq".to_string()];
+        diff.casks.added = vec!["firefox".to_string()];
+        diff.brews.removed = vec![
+            Removal::new("wget", ChangeEffect::WillBeRemoved),
+            Removal::new("htop", ChangeEffect::LeftInstalled),
+        ];
+
+        let summary = PromptSummary::from_diff(&diff);
+
+        assert_eq!(summary.to_string(), "🍺 +3/-1");
+        assert!(PromptSummary::from_diff(&HomebrewDiffData::default()).is_clean());
+        let upgrades = PromptSummary {
+            upgraded: 2,
+            ..summary
+        };
+        assert_eq!(upgrades.to_string(), "🍺 +3/-1 ↑2");
+    }
+
+    #[test]
+    fn test_prompt_cache() {
+        let dir = TempDir::new().unwrap();
+        let prefix = dir.path().join("homebrew");
+        fs::create_dir_all(prefix.join("Cellar")).unwrap();
+        let profile = dir.path().join("system-42-link");
+        fs::create_dir(&profile).unwrap();
+        let cache = PromptCache::new(dir.path().join("cache/prompt.json"));
+        let summary = PromptSummary {
+            added: 3,
+            removed: 1,
+            upgraded: 0,
+        };
+
+        assert_eq!(cache.load(&profile, &prefix), None);
+        cache.store(&profile, &prefix, summary).unwrap();
+        assert_eq!(cache.load(&profile, &prefix), Some(summary));
+
+        let other = dir.path().join("system-43-link");
+        fs::create_dir(&other).unwrap();
+        assert_eq!(cache.load(&other, &prefix), None);
+
+        fs::remove_dir(prefix.join("Cellar")).unwrap();
+        assert_eq!(cache.load(&profile, &prefix), None);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::trace::span;
+use std::ffi::OsString;
+use std::io;
+use std::process::{Command, Output};
+
+/// Runs the external commands detection relies on (brew, mas, mdls, ...)
+pub trait CommandRunner: Send + Sync {
+    fn output(&self, program: &str, args: &[&str]) -> io::Result<Output>;
+}
+
+/// The environment detection commands run with
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum CommandEnvironment {
+    /// Inherit the caller's environment as is
+    #[default]
+    Inherit,
+    /// Use an explicit PATH and drop `HOMEBREW_*` variables that aren't allowed
+    /// Keeps results reproducible across shells and launchd contexts
+    Sanitized {
+        path: String,
+        allowed_vars: Vec<String>,
+    },
+}
+
+impl CommandEnvironment {
+    /// Sanitized environment with the system and Homebrew paths and no `HOMEBREW_*` variables
+    pub fn sanitized() -> Self {
+        CommandEnvironment::Sanitized {
+            path: "/opt/homebrew/bin:/usr/local/bin:/usr/bin:/bin:/usr/sbin:/sbin".to_string(),
+            allowed_vars: Vec::new(),
+        }
+    }
+
+    fn apply(&self, command: &mut Command) {
+        self.apply_over(command, std::env::vars_os().map(|(key, _)| key));
+    }
+
+    /// Apply the environment over the variables a command would otherwise inherit
+    fn apply_over(&self, command: &mut Command, inherited: impl IntoIterator<Item = OsString>) {
+        let CommandEnvironment::Sanitized { path, allowed_vars } = self else {
+            return;
+        };
+
+        command.env("PATH", path);
+        for key in inherited {
+            let key = key.to_string_lossy();
+            if key.starts_with("HOMEBREW_") && !allowed_vars.iter().any(|var| *var == key) {
+                command.env_remove(key.as_ref());
+            }
+        }
+    }
+}
+
+/// Runs commands on the host system
+#[derive(Debug, Clone, Default)]
+pub struct SystemRunner {
+    pub environment: CommandEnvironment,
+}
+
+impl SystemRunner {
+    pub fn new(environment: CommandEnvironment) -> Self {
+        Self { environment }
+    }
+}
+
+impl CommandRunner for SystemRunner {
+    fn output(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+        let _span = span!("command", program, ?args);
+        let mut command = Command::new(program);
+        command.args(args);
+        self.environment.apply(&mut command);
+        let output = command.output();
+        #[cfg(feature = "tracing")]
+        match &output {
+            Ok(output) => crate::trace::event!(status = ?output.status.code(), "command finished"),
+            Err(e) => crate::trace::event!(error = %e, "command failed to start"),
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsStr;
+
+    #[test]
+    fn test_sanitized_environment_scrubs_homebrew_vars() {
+        let environment = CommandEnvironment::Sanitized {
+            path: "/usr/bin:/bin".to_string(),
+            allowed_vars: vec!["HOMEBREW_NO_ANALYTICS".to_string()],
+        };
+        let inherited = ["HOME", "HOMEBREW_GITHUB_API_TOKEN", "HOMEBREW_NO_ANALYTICS"];
+        let mut command = Command::new("env");
+
+        environment.apply_over(&mut command, inherited.map(OsString::from));
+
+        let changed: Vec<(&OsStr, Option<&OsStr>)> = command.get_envs().collect();
+        assert_eq!(
+            changed,
+            vec![
+                (OsStr::new("HOMEBREW_GITHUB_API_TOKEN"), None),
+                (OsStr::new("PATH"), Some(OsStr::new("/usr/bin:/bin"))),
+            ]
+        );
+
+        let mut command = Command::new("env");
+        CommandEnvironment::Inherit.apply_over(&mut command, inherited.map(OsString::from));
+        assert_eq!(command.get_envs().count(), 0);
+    }
+
+    #[test]
+    fn test_system_runner_applies_environment() {
+        let runner = SystemRunner::new(CommandEnvironment::sanitized());
+        let output = runner.output("env", &[]).unwrap();
+        let env = String::from_utf8_lossy(&output.stdout);
+
+        assert!(env
+            .lines()
+            .any(|line| line.starts_with("PATH=/opt/homebrew/bin")));
+    }
+}
@@ -0,0 +1,297 @@
+//! Guardrails evaluated before activation applies a diff
+
+use crate::diff::{destructive, HomebrewDiffData};
+use crate::error::{Error, Result};
+use crate::metadata;
+use crate::runner::CommandRunner;
+use crate::state::HomebrewState;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Default for `SafetyPolicy::max_removals` and `DisplayOptions::removal_warning_threshold`
+pub const DEFAULT_MAX_REMOVALS: usize = 20;
+
+/// Which destructive changes need `--force`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SafetyPolicy {
+    /// Most formulae, casks and App Store apps removed at once, None for no limit
+    pub max_removals: Option<usize>,
+    /// Refuse to uninstall casks whose apps are running
+    pub check_running_apps: bool,
+    /// Refuse to untap taps that installed packages still come from
+    pub check_tap_usage: bool,
+}
+
+impl Default for SafetyPolicy {
+    fn default() -> Self {
+        Self {
+            max_removals: Some(DEFAULT_MAX_REMOVALS),
+            check_running_apps: true,
+            check_tap_usage: true,
+        }
+    }
+}
+
+/// A destructive change the policy doesn't allow without `--force`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SafetyViolation {
+    /// The cask's app is running and would be uninstalled from under it
+    RunningApp { cask: String, app: String },
+    /// More removals than `max_removals`
+    TooManyRemovals { count: usize, max: usize },
+    /// The tap is untapped while packages from it stay installed
+    TapInUse { tap: String, packages: Vec<String> },
+}
+
+impl fmt::Display for SafetyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SafetyViolation::RunningApp { cask, app } => {
+                write!(f, "{} would be uninstalled while {} is running", cask, app)
+            }
+            SafetyViolation::TooManyRemovals { count, max } => {
+                write!(f, "{} packages would be removed, more than {}", count, max)
+            }
+            SafetyViolation::TapInUse { tap, packages } => write!(
+                f,
+                "{} would be untapped while {} stay installed",
+                tap,
+                packages.join(", ")
+            ),
+        }
+    }
+}
+
+impl SafetyViolation {
+    /// Join violations for a single line error message
+    pub(crate) fn join(violations: &[SafetyViolation]) -> String {
+        violations
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+impl SafetyPolicy {
+    /// Check the diff before activation, running apps are detected through the runner
+    /// Fails with `Error::Unsafe` unless `force` is set, in which case violations are returned as warnings
+    pub fn check(
+        &self,
+        diff_data: &HomebrewDiffData,
+        current_state: &HomebrewState,
+        runner: &dyn CommandRunner,
+        force: bool,
+    ) -> Result<Vec<SafetyViolation>> {
+        let running = if self.check_running_apps {
+            running_apps(runner)?
+        } else {
+            HashSet::new()
+        };
+
+        let violations = self.evaluate(diff_data, current_state, &running);
+        if !violations.is_empty() && !force {
+            return Err(Error::Unsafe(violations));
+        }
+        Ok(violations)
+    }
+
+    /// Evaluate the policy against a diff, given the names of running app bundles
+    pub fn evaluate(
+        &self,
+        diff_data: &HomebrewDiffData,
+        current_state: &HomebrewState,
+        running_apps: &HashSet<String>,
+    ) -> Vec<SafetyViolation> {
+        let mut violations = Vec::new();
+
+        if self.check_running_apps {
+            for removal in destructive(&diff_data.casks.removed) {
+                let Some(receipt) = current_state.cask_receipts.get(&removal.name) else {
+                    continue;
+                };
+                for app in receipt
+                    .apps
+                    .iter()
+                    .filter(|app| running_apps.contains(*app))
+                {
+                    violations.push(SafetyViolation::RunningApp {
+                        cask: removal.name.clone(),
+                        app: app.clone(),
+                    });
+                }
+            }
+        }
+
+        if let Some(max) = self.max_removals {
+            let count = diff_data.destructive_removals();
+            if count > max {
+                violations.push(SafetyViolation::TooManyRemovals { count, max });
+            }
+        }
+
+        if self.check_tap_usage {
+            let removed: HashSet<&str> = destructive(&diff_data.brews.removed)
+                .chain(destructive(&diff_data.casks.removed))
+                .map(|removal| removal.name.as_str())
+                .collect();
+            for tap in destructive(&diff_data.taps.removed) {
+                let from_tap = |found: Option<&str>| found == Some(tap.name.as_str());
+                // Kegs of dependencies count too, they aren't among the installed brews
+                let mut packages: Vec<String> = current_state
+                    .installed_brews
+                    .keys()
+                    .chain(current_state.keg_taps.keys())
+                    .chain(current_state.installed_casks.keys())
+                    .filter(|name| !removed.contains(name.as_str()))
+                    .filter(|name| {
+                        from_tap(metadata::tap_of(name))
+                            || from_tap(current_state.keg_taps.get(*name).map(String::as_str))
+                            || from_tap(
+                                current_state
+                                    .cask_receipts
+                                    .get(*name)
+                                    .and_then(|receipt| receipt.tap.as_deref()),
+                            )
+                    })
+                    .cloned()
+                    .collect();
+                if !packages.is_empty() {
+                    packages.sort();
+                    packages.dedup();
+                    violations.push(SafetyViolation::TapInUse {
+                        tap: tap.name.clone(),
+                        packages,
+                    });
+                }
+            }
+        }
+
+        violations
+    }
+}
+
+/// Names of the app bundles with a running process, e.g. `Firefox.app`
+fn running_apps(runner: &dyn CommandRunner) -> Result<HashSet<String>> {
+    let output = runner
+        .output("ps", &["-axo", "comm="])
+        .map_err(|e| Error::CommandFailed(format!("ps failed: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(Error::CommandFailed(
+            "ps failed, can't tell which apps are running".to_string(),
+        ));
+    }
+
+    Ok(parse_running_apps(&String::from_utf8(output.stdout)?))
+}
+
+/// Parse executable paths like `/Applications/Firefox.app/Contents/MacOS/firefox`
+fn parse_running_apps(ps_output: &str) -> HashSet<String> {
+    ps_output
+        .lines()
+        .filter_map(|line| {
+            let bundle = &line[..line.find(".app/Contents/")? + ".app".len()];
+            Some(bundle.rsplit('/').next()?.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::{ChangeEffect, Removal};
+    use crate::state::CaskReceipt;
+
+    fn state() -> HomebrewState {
+        let mut state = HomebrewState::default();
+        state.cask_receipts.insert(
+            "docker".to_string(),
+            CaskReceipt {
+                apps: vec!["Docker.app".to_string()],
+                ..Default::default()
+            },
+        );
+        state.cask_receipts.insert(
+            "boundary".to_string(),
+            CaskReceipt {
+                tap: Some("hashicorp/tap".to_string()),
+                ..Default::default()
+            },
+        );
+        state
+            .installed_casks
+            .insert("boundary".to_string(), String::new());
+        for name in ["terraform", "hashicorp/tap/vault", "wget"] {
+            state
+                .installed_brews
+                .insert(name.to_string(), String::new());
+        }
+        // Installed as a dependency of terraform, so only its keg knows the tap
+        for name in ["terraform", "libhcl"] {
+            state
+                .keg_taps
+                .insert(name.to_string(), "hashicorp/tap".to_string());
+        }
+        state
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let mut diff = HomebrewDiffData::default();
+        diff.casks.removed = vec![Removal::new("docker", ChangeEffect::WillBeZapped)];
+        diff.brews.removed = vec![Removal::new("wget", ChangeEffect::WillBeRemoved)];
+        diff.taps.removed = vec![Removal::new("hashicorp/tap", ChangeEffect::WillBeRemoved)];
+        let running = HashSet::from(["Docker.app".to_string()]);
+        let policy = SafetyPolicy {
+            max_removals: Some(1),
+            ..Default::default()
+        };
+
+        let violations = policy.evaluate(&diff, &state(), &running);
+
+        assert_eq!(
+            violations,
+            vec![
+                SafetyViolation::RunningApp {
+                    cask: "docker".to_string(),
+                    app: "Docker.app".to_string(),
+                },
+                SafetyViolation::TooManyRemovals { count: 2, max: 1 },
+                SafetyViolation::TapInUse {
+                    tap: "hashicorp/tap".to_string(),
+                    packages: vec![
+                        "boundary".to_string(),
+                        "hashicorp/tap/vault".to_string(),
+                        "libhcl".to_string(),
+                        "terraform".to_string(),
+                    ],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unmanaged_removals_are_safe() {
+        let mut diff = HomebrewDiffData::default();
+        diff.casks.removed = vec![Removal::new("docker", ChangeEffect::LeftInstalled)];
+        diff.taps.removed = vec![Removal::new("hashicorp/tap", ChangeEffect::LeftInstalled)];
+        let running = HashSet::from(["Docker.app".to_string()]);
+
+        let policy = SafetyPolicy {
+            max_removals: Some(0),
+            ..Default::default()
+        };
+        assert!(policy.evaluate(&diff, &state(), &running).is_empty());
+    }
+
+    #[test]
+    fn test_parse_running_apps() {
+        let ps = "/sbin/launchd\n/Applications/Docker.app/Contents/MacOS/com.docker.backend\n/Users/me/Applications/Firefox.app/Contents/MacOS/firefox\n";
+
+        assert_eq!(
+            parse_running_apps(ps),
+            HashSet::from(["Docker.app".to_string(), "Firefox.app".to_string()])
+        );
+    }
+}
@@ -0,0 +1,528 @@
+//! CycloneDX and SPDX software bills of materials, for organizations that inventory software
+//! on managed Macs
+//! Taps aren't components themselves, they're recorded as each package's origin
+
+use crate::date::date_from_days;
+use crate::diff::{natural_cmp, HomebrewDiffData};
+use crate::events::Category;
+use crate::metadata;
+use crate::state::HomebrewState;
+use crate::version;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The CycloneDX specification version the JSON follows
+pub const CYCLONEDX_VERSION: &str = "1.5";
+/// The SPDX specification version the JSON follows
+pub const SPDX_VERSION: &str = "SPDX-2.3";
+
+/// The SBOM standard to write, compliance pipelines differ in which one they take
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SbomFormat {
+    #[default]
+    CycloneDx,
+    Spdx,
+}
+
+/// Which packages the SBOM lists
+#[derive(Debug, Clone, Copy)]
+pub enum SbomScope<'a> {
+    /// What's installed right now
+    /// Formulae are those installed on request, dependencies like `openssl@3` aren't listed
+    Installed,
+    /// What's installed once activation applied the diff, upgrades at their new version
+    Intended(&'a HomebrewDiffData),
+}
+
+/// A package listed in the SBOM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SbomComponent {
+    /// Without its tap, e.g. `terraform` for `hashicorp/tap/terraform`
+    pub name: String,
+    pub category: Category,
+    /// None for packages that aren't installed yet, casks versioned `latest` and App Store apps
+    /// The newest if several versions are installed
+    pub version: Option<String>,
+    /// e.g. `homebrew/core`, None for App Store apps
+    pub tap: Option<String>,
+    /// App Store id
+    pub mas_id: Option<String>,
+}
+
+impl SbomComponent {
+    fn formula(name: &str, version: Option<&str>, tap: &str) -> Self {
+        Self::package(name, Category::Formulae, version, tap)
+    }
+
+    fn cask(name: &str, version: Option<&str>, tap: &str) -> Self {
+        Self::package(name, Category::Casks, version, tap)
+    }
+
+    fn package(name: &str, category: Category, version: Option<&str>, tap: &str) -> Self {
+        Self {
+            name: name.rsplit('/').next().unwrap_or(name).to_string(),
+            category,
+            version: version
+                .and_then(version::newest)
+                .filter(|version| !version::is_unversioned(version))
+                .map(str::to_string),
+            tap: Some(tap.to_string()),
+            mas_id: None,
+        }
+    }
+
+    /// From an entry stored as "App Name (id)"
+    fn mas_app(app: &str) -> Self {
+        let (name, id) = app
+            .strip_suffix(')')
+            .and_then(|app| app.rsplit_once(" ("))
+            .map_or((app, None), |(name, id)| (name, Some(id.to_string())));
+        Self {
+            name: name.to_string(),
+            category: Category::AppStore,
+            version: None,
+            tap: None,
+            mas_id: id,
+        }
+    }
+
+    /// Unique within the SBOM, e.g. `formula:homebrew/core/wget` or `mas:497799835`
+    fn bom_ref(&self) -> String {
+        match (self.category, &self.mas_id, &self.tap) {
+            (Category::AppStore, Some(id), _) => format!("mas:{}", id),
+            (Category::Casks, _, Some(tap)) => format!("cask:{}/{}", tap, self.name),
+            (_, _, Some(tap)) => format!("formula:{}/{}", tap, self.name),
+            _ => format!("{}:{}", category_name(self.category), self.name),
+        }
+    }
+
+    /// e.g. `pkg:generic/wget@1.24.5?tap=homebrew%2Fcore`, None for App Store apps
+    /// purl has no registered Homebrew type, so packages are generic ones qualified by their tap
+    fn purl(&self) -> Option<String> {
+        let tap = self.tap.as_deref()?;
+        let version = self
+            .version
+            .as_deref()
+            .map(|version| format!("@{}", percent_encode(version)))
+            .unwrap_or_default();
+        Some(format!(
+            "pkg:generic/{}{}?tap={}",
+            percent_encode(&self.name),
+            version,
+            percent_encode(tap)
+        ))
+    }
+
+    fn to_json(&self) -> Value {
+        let mut properties = vec![json!({
+            "name": "brewdiff:category",
+            "value": category_name(self.category),
+        })];
+        if let Some(tap) = &self.tap {
+            properties.push(json!({ "name": "brewdiff:tap", "value": tap }));
+        }
+        if let Some(id) = &self.mas_id {
+            properties.push(json!({ "name": "brewdiff:mas-id", "value": id }));
+        }
+
+        let mut component = json!({
+            "type": "application",
+            "bom-ref": self.bom_ref(),
+            "name": self.name,
+            "properties": properties,
+        });
+        if let Some(version) = &self.version {
+            component["version"] = json!(version);
+        }
+        if let Some(purl) = self.purl() {
+            component["purl"] = json!(purl);
+        }
+        component
+    }
+
+    /// The bom-ref with characters SPDX ids don't allow replaced,
+    /// e.g. `SPDXRef-formula-homebrew-core-wget`
+    fn spdx_id(&self) -> String {
+        let id: String = self
+            .bom_ref()
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        format!("SPDXRef-{}", id)
+    }
+
+    fn to_spdx(&self) -> Value {
+        let origin = match (&self.tap, &self.mas_id) {
+            (Some(tap), _) => format!("{} from {}", category_name(self.category), tap),
+            (None, Some(id)) => format!("App Store app {}", id),
+            (None, None) => category_name(self.category).to_string(),
+        };
+        let mut package = json!({
+            "SPDXID": self.spdx_id(),
+            "name": self.name,
+            "downloadLocation": "NOASSERTION",
+            "filesAnalyzed": false,
+            "comment": origin,
+        });
+        if let Some(version) = &self.version {
+            package["versionInfo"] = json!(version);
+        }
+        if let Some(purl) = self.purl() {
+            package["externalRefs"] = json!([{
+                "referenceCategory": "PACKAGE-MANAGER",
+                "referenceType": "purl",
+                "referenceLocator": purl,
+            }]);
+        }
+        package
+    }
+}
+
+fn category_name(category: Category) -> &'static str {
+    match category {
+        Category::Taps => "tap",
+        Category::Formulae => "formula",
+        Category::Casks => "cask",
+        Category::AppStore => "mas",
+    }
+}
+
+/// Percent-encode everything but unreserved characters, as purl components require
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// The packages in scope, formulae first, then casks and App Store apps, each sorted by name
+/// Installed packages are listed at the versions in the state, without one if it wasn't looked up,
+/// see `HomebrewState::load_versions`
+pub fn components(state: &HomebrewState, scope: SbomScope) -> Vec<SbomComponent> {
+    let formula_tap = |name: &str| {
+        metadata::tap_of(name)
+            .or_else(|| {
+                state
+                    .formula_receipts
+                    .get(name)?
+                    .source
+                    .as_ref()?
+                    .tap
+                    .as_deref()
+            })
+            .unwrap_or("homebrew/core")
+            .to_string()
+    };
+    let cask_tap = |name: &str| {
+        metadata::tap_of(name)
+            .or_else(|| state.cask_receipts.get(name)?.tap.as_deref())
+            .unwrap_or("homebrew/cask")
+            .to_string()
+    };
+
+    // Detection through brew leaves versions empty until they're looked up
+    let known = |version: &String| Some(version.clone()).filter(|version| !version.is_empty());
+    // name -> (version, tap), so the intended scope can apply the diff by name
+    let mut brews: BTreeMap<String, (Option<String>, String)> = state
+        .installed_brews
+        .iter()
+        .map(|(name, version)| (name.clone(), (known(version), formula_tap(name))))
+        .collect();
+    let mut casks: BTreeMap<String, (Option<String>, String)> = state
+        .installed_casks
+        .iter()
+        .map(|(name, version)| (name.clone(), (known(version), cask_tap(name))))
+        .collect();
+    let mut mas_apps: Vec<String> = state.installed_mas_apps.iter().cloned().collect();
+
+    if let SbomScope::Intended(diff) = scope {
+        for (packages, diff_packages) in [(&mut brews, &diff.brews), (&mut casks, &diff.casks)] {
+            for removal in &diff_packages.removed {
+                if removal.effect.is_destructive() {
+                    packages.remove(&removal.name);
+                }
+            }
+            for change in diff_packages.upgrades() {
+                if let Some((version, _)) = packages.get_mut(&change.name) {
+                    *version = Some(change.available.clone());
+                }
+            }
+        }
+        for name in &diff.brews.added {
+            brews.insert(name.clone(), (None, diff.formula_tap(name).to_string()));
+        }
+        for name in &diff.casks.added {
+            casks.insert(name.clone(), (None, diff.cask_tap(name).to_string()));
+        }
+        // nix-darwin never uninstalls App Store apps
+        mas_apps.extend(diff.mas_apps.added.iter().cloned());
+    }
+
+    let mut formulae: Vec<SbomComponent> = brews
+        .iter()
+        .map(|(name, (version, tap))| SbomComponent::formula(name, version.as_deref(), tap))
+        .collect();
+    let mut cask_components: Vec<SbomComponent> = casks
+        .iter()
+        .map(|(name, (version, tap))| SbomComponent::cask(name, version.as_deref(), tap))
+        .collect();
+    let mut apps: Vec<SbomComponent> = mas_apps
+        .iter()
+        .map(|app| SbomComponent::mas_app(app))
+        .collect();
+    for components in [&mut formulae, &mut cask_components, &mut apps] {
+        components.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+    }
+
+    formulae
+        .into_iter()
+        .chain(cask_components)
+        .chain(apps)
+        .collect()
+}
+
+impl SbomScope<'_> {
+    fn name(self) -> &'static str {
+        match self {
+            SbomScope::Installed => "installed",
+            SbomScope::Intended(_) => "intended",
+        }
+    }
+}
+
+/// A JSON document of the packages in scope in the given format
+pub fn document(state: &HomebrewState, scope: SbomScope, format: SbomFormat) -> Value {
+    match format {
+        SbomFormat::CycloneDx => cyclonedx(state, scope),
+        SbomFormat::Spdx => spdx(state, scope),
+    }
+}
+
+/// A CycloneDX JSON document of the packages in scope, with the installation in its metadata
+pub fn cyclonedx(state: &HomebrewState, scope: SbomScope) -> Value {
+    let mut properties = vec![json!({ "name": "brewdiff:scope", "value": scope.name() })];
+    let config = &state.brew_config;
+    if let Some(version) = &config.version {
+        properties.push(json!({ "name": "brewdiff:homebrew-version", "value": version }));
+    }
+    if let Some(prefix) = &config.prefix {
+        properties
+            .push(json!({ "name": "brewdiff:prefix", "value": prefix.display().to_string() }));
+    }
+    if let Some(arch) = &config.arch {
+        properties.push(json!({ "name": "brewdiff:arch", "value": arch }));
+    }
+    if let Some(macos) = &config.macos {
+        properties.push(json!({ "name": "brewdiff:macos", "value": macos }));
+    }
+
+    let components: Vec<Value> = components(state, scope)
+        .iter()
+        .map(SbomComponent::to_json)
+        .collect();
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": CYCLONEDX_VERSION,
+        "version": 1,
+        "metadata": {
+            "timestamp": timestamp(),
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": "brewdiff",
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            },
+            "properties": properties,
+        },
+        "components": components,
+    })
+}
+
+/// An SPDX JSON document of the packages in scope, each described by the document
+pub fn spdx(state: &HomebrewState, scope: SbomScope) -> Value {
+    let components = components(state, scope);
+    let packages: Vec<Value> = components.iter().map(SbomComponent::to_spdx).collect();
+    let relationships: Vec<Value> = components
+        .iter()
+        .map(|component| {
+            json!({
+                "spdxElementId": "SPDXRef-DOCUMENT",
+                "relationshipType": "DESCRIBES",
+                "relatedSpdxElement": component.spdx_id(),
+            })
+        })
+        .collect();
+
+    let mut comment = format!("Homebrew packages {}", scope.name());
+    if state.brew_config != Default::default() {
+        comment = format!("{}, {}", comment, state.brew_config);
+    }
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    json!({
+        "spdxVersion": SPDX_VERSION,
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": format!("brewdiff-{}", scope.name()),
+        // Has to be unique per document
+        "documentNamespace": format!(
+            "https://github.com/kiliankoe/brewdiff/spdx/{}-{}.{:09}",
+            scope.name(),
+            since_epoch.as_secs(),
+            since_epoch.subsec_nanos()
+        ),
+        "creationInfo": {
+            "created": timestamp(),
+            "creators": [format!("Tool: brewdiff-{}", env!("CARGO_PKG_VERSION"))],
+            "comment": comment,
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+/// The current time in UTC, e.g. `2026-10-16T09:30:00Z`
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        date_from_days((secs / 86_400) as i64),
+        secs % 86_400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::{ChangeEffect, Removal, VersionChange};
+    use crate::fixtures::FixtureRunner;
+
+    fn state() -> HomebrewState {
+        HomebrewState::from_bundle_dump(
+            "tap \"hashicorp/tap\"\n\
+             brew \"wget\"\n\
+             brew \"hashicorp/tap/terraform\"\n\
+             cask \"firefox\"\n\
+             mas \"Xcode\", id: 497799835\n",
+        )
+    }
+
+    #[test]
+    fn test_installed_components() {
+        let mut state = state();
+        state
+            .installed_brews
+            .insert("wget".to_string(), "1.24.5".to_string());
+
+        let components = components(&state, SbomScope::Installed);
+        let names: Vec<&str> = components.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["terraform", "wget", "firefox", "Xcode"]);
+
+        let terraform = &components[0];
+        assert_eq!(terraform.tap.as_deref(), Some("hashicorp/tap"));
+        let wget = components[1].to_json();
+        assert_eq!(wget["purl"], "pkg:generic/wget@1.24.5?tap=homebrew%2Fcore");
+        assert_eq!(wget["bom-ref"], "formula:homebrew/core/wget");
+        let xcode = components[3].to_json();
+        assert_eq!(xcode["bom-ref"], "mas:497799835");
+        assert!(xcode.get("purl").is_none());
+    }
+
+    #[test]
+    fn test_intended_cyclonedx() {
+        let mut state = state();
+        state
+            .installed_casks
+            .insert("firefox".to_string(), "126.0".to_string());
+        let mut diff = HomebrewDiffData::default();
+        diff.brews.added = vec!["jq".to_string()];
+        diff.brews.removed = vec![Removal::new("wget", ChangeEffect::WillBeRemoved)];
+        diff.casks.upgraded = vec![VersionChange {
+            name: "firefox".to_string(),
+            installed: "126.0".to_string(),
+            available: "127.0".to_string(),
+            applied: true,
+            auto_updates: false,
+        }];
+
+        let sbom = cyclonedx(&state, SbomScope::Intended(&diff));
+
+        assert_eq!(sbom["bomFormat"], "CycloneDX");
+        assert_eq!(sbom["metadata"]["properties"][0]["value"], "intended");
+        let components = sbom["components"].as_array().unwrap();
+        let names: Vec<&str> = components
+            .iter()
+            .map(|c| c["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["jq", "terraform", "firefox", "Xcode"]);
+        assert!(components[0].get("version").is_none());
+        assert_eq!(components[2]["version"], "127.0");
+    }
+
+    #[test]
+    fn test_spdx() {
+        let mut state = state();
+        state
+            .installed_brews
+            .insert("wget".to_string(), "1.24.5".to_string());
+
+        let sbom = document(&state, SbomScope::Installed, SbomFormat::Spdx);
+
+        assert_eq!(sbom["spdxVersion"], "SPDX-2.3");
+        let packages = sbom["packages"].as_array().unwrap();
+        assert_eq!(packages.len(), 4);
+        let wget = &packages[1];
+        assert_eq!(wget["SPDXID"], "SPDXRef-formula-homebrew-core-wget");
+        assert_eq!(wget["versionInfo"], "1.24.5");
+        assert_eq!(wget["comment"], "formula from homebrew/core");
+        assert_eq!(
+            wget["externalRefs"][0]["referenceLocator"],
+            "pkg:generic/wget@1.24.5?tap=homebrew%2Fcore"
+        );
+        assert_eq!(packages[3]["SPDXID"], "SPDXRef-mas-497799835");
+        assert_eq!(
+            sbom["relationships"][3]["relatedSpdxElement"],
+            "SPDXRef-mas-497799835"
+        );
+    }
+
+    #[test]
+    fn test_installed_versions_loaded() {
+        let runner = FixtureRunner::load("sonoma-arm64");
+        let mut state = runner.detect().unwrap();
+        let unloaded = components(&state, SbomScope::Installed);
+        let node = unloaded.iter().find(|c| c.name == "node").unwrap();
+        assert_eq!(node.version, None);
+        state.load_versions(&runner).unwrap();
+
+        let components = components(&state, SbomScope::Installed);
+        let node = components.iter().find(|c| c.name == "node").unwrap();
+        assert_eq!(node.version.as_deref(), Some("21.7.3"));
+        assert_eq!(
+            node.purl().as_deref(),
+            Some("pkg:generic/node@21.7.3?tap=homebrew%2Fcore")
+        );
+        let slack = components.iter().find(|c| c.name == "slack").unwrap();
+        assert_eq!(slack.version.as_deref(), Some("4.38.125"));
+    }
+}
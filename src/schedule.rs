@@ -0,0 +1,255 @@
+//! Per-user launchd agent that runs drift checks periodically, e.g. behind `brewdiff schedule install`
+
+use crate::error::{Error, Result};
+use crate::runner::CommandRunner;
+use crate::shell::quote;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The agent's launchd label, also the name of its plist
+pub const LABEL: &str = "io.github.kiliankoe.brewdiff.check";
+
+/// How often the agent runs what
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleOptions {
+    /// Time between checks, launchd counts it in whole seconds
+    pub interval: Duration,
+    /// Run by the agent, should exit 1 on drift like `DiffOutcome::Drift`
+    pub command: String,
+    /// Post a notification when the command reports drift
+    pub notify: bool,
+    /// Where each check's output is appended, `~/Library/Logs/brewdiff/check.log` if None
+    pub log: Option<PathBuf>,
+}
+
+impl Default for ScheduleOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(6 * 60 * 60),
+            command: "brewdiff check".to_string(),
+            notify: false,
+            log: None,
+        }
+    }
+}
+
+/// Parse an interval like `6h`, `30m`, `1d` or `90s`, bare numbers are seconds
+pub fn parse_interval(interval: &str) -> Result<Duration> {
+    let interval = interval.trim();
+    let split = interval
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(interval.len());
+    let (count, unit) = interval.split_at(split);
+    let seconds = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => 0,
+    };
+    let total = count
+        .parse::<u64>()
+        .ok()
+        .and_then(|count| count.checked_mul(seconds))
+        .filter(|total| *total > 0);
+    match total {
+        Some(total) => Ok(Duration::from_secs(total)),
+        None => Err(Error::InvalidConfig(format!(
+            "invalid interval {:?}, expected e.g. 6h, 30m or 1d",
+            interval
+        ))),
+    }
+}
+
+/// `~/Library/LaunchAgents`, None without a home directory
+pub fn default_agents_dir() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(Path::new(&home).join("Library/LaunchAgents"))
+}
+
+/// `~/Library/Logs/brewdiff/check.log`, None without a home directory
+pub fn default_log_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(Path::new(&home).join("Library/Logs/brewdiff/check.log"))
+}
+
+/// What the agent runs, a timestamped check that notifies on drift if asked to
+fn script(options: &ScheduleOptions) -> String {
+    let mut script = format!(
+        "echo \"--- $(date -u +%Y-%m-%dT%H:%M:%SZ)\"\n{}\nstatus=$?\n",
+        options.command
+    );
+    if options.notify {
+        let notification = "display notification \"Homebrew drifted from the nix-darwin config\" with title \"brewdiff\"";
+        script += &format!(
+            "[ \"$status\" -eq 1 ] && osascript -e {}\n",
+            quote(notification)
+        );
+    }
+    script += "exit \"$status\"";
+    script
+}
+
+/// Escape text for an XML plist
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The agent's plist, runs the check at load and then every interval
+pub fn plist(options: &ScheduleOptions, log: &Path) -> String {
+    let log = escape(&log.to_string_lossy());
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{LABEL}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>/bin/sh</string>
+        <string>-lc</string>
+        <string>{script}</string>
+    </array>
+    <key>StartInterval</key>
+    <integer>{interval}</integer>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>ProcessType</key>
+    <string>Background</string>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#,
+        script = escape(&script(options)),
+        interval = options.interval.as_secs().max(1),
+    )
+}
+
+/// The launchd domain of the current user's GUI session, e.g. `gui/501`
+fn gui_domain(runner: &dyn CommandRunner) -> Result<String> {
+    let output = runner
+        .output("id", &["-u"])
+        .map_err(|e| Error::CommandFailed(format!("id failed: {}", e)))?;
+    if !output.status.success() {
+        return Err(Error::CommandFailed("id -u failed".to_string()));
+    }
+    Ok(format!("gui/{}", String::from_utf8(output.stdout)?.trim()))
+}
+
+/// Write the agent's plist into `agents_dir` and (re)load it, returns the plist's path
+pub fn install(
+    agents_dir: &Path,
+    options: &ScheduleOptions,
+    runner: &dyn CommandRunner,
+) -> Result<PathBuf> {
+    let log = match &options.log {
+        Some(log) => log.clone(),
+        None => default_log_path()
+            .ok_or_else(|| Error::InvalidConfig("no home directory for the log".to_string()))?,
+    };
+    if let Some(dir) = log.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::create_dir_all(agents_dir)?;
+    let path = agents_dir.join(format!("{}.plist", LABEL));
+    fs::write(&path, plist(options, &log))?;
+
+    // Unload a previous version first, bootstrap fails for loaded labels
+    let domain = gui_domain(runner)?;
+    let service = format!("{}/{}", domain, LABEL);
+    let _ = runner.output("launchctl", &["bootout", &service]);
+    let output = runner
+        .output(
+            "launchctl",
+            &["bootstrap", &domain, &path.to_string_lossy()],
+        )
+        .map_err(|e| Error::CommandFailed(format!("launchctl failed: {}", e)))?;
+    if !output.status.success() {
+        return Err(Error::CommandFailed(format!(
+            "launchctl bootstrap failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(path)
+}
+
+/// Unload the agent and remove its plist, returns whether it was installed
+pub fn uninstall(agents_dir: &Path, runner: &dyn CommandRunner) -> Result<bool> {
+    let path = agents_dir.join(format!("{}.plist", LABEL));
+    if !path.exists() {
+        return Ok(false);
+    }
+    let service = format!("{}/{}", gui_domain(runner)?, LABEL);
+    let _ = runner.output("launchctl", &["bootout", &service]);
+    fs::remove_file(&path)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::FixtureRunner;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("6h").unwrap(), Duration::from_secs(21600));
+        assert_eq!(parse_interval("30m").unwrap(), Duration::from_secs(1800));
+        assert_eq!(parse_interval("1d").unwrap(), Duration::from_secs(86400));
+        assert_eq!(parse_interval("90").unwrap(), Duration::from_secs(90));
+        assert!(parse_interval("0h").is_err());
+        assert!(parse_interval("6 weeks").is_err());
+        assert!(parse_interval("h").is_err());
+        assert!(parse_interval("999999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_install_schedule() {
+        let dir = TempDir::new().unwrap();
+        let agents = dir.path().join("LaunchAgents");
+        let options = ScheduleOptions {
+            interval: parse_interval("6h").unwrap(),
+            notify: true,
+            log: Some(dir.path().join("Logs/check.log")),
+            ..Default::default()
+        };
+        let path = agents.join(format!("{}.plist", LABEL));
+        // Unloading a previous version fails when there is none, like here
+        let runner = FixtureRunner::parse("$ id -u\n501\n").with(
+            format!("launchctl bootstrap gui/501 {}", path.display()),
+            "",
+        );
+
+        assert_eq!(install(&agents, &options, &runner).unwrap(), path);
+        let plist = fs::read_to_string(&path).unwrap();
+        assert!(plist.contains("<integer>21600</integer>"));
+        assert!(plist.contains("brewdiff check\nstatus=$?\n"));
+        assert!(
+            plist.contains("[ \"$status\" -eq 1 ] &amp;&amp; osascript -e 'display notification")
+        );
+        assert!(plist.contains(&format!(
+            "<string>{}</string>",
+            dir.path().join("Logs/check.log").display()
+        )));
+        assert!(dir.path().join("Logs").is_dir());
+        assert_eq!(
+            runner.calls(),
+            vec![
+                "id -u".to_string(),
+                format!("launchctl bootout gui/501/{}", LABEL),
+                format!("launchctl bootstrap gui/501 {}", path.display()),
+            ]
+        );
+
+        assert!(uninstall(&agents, &runner).unwrap());
+        assert!(!path.exists());
+        assert!(!uninstall(&agents, &runner).unwrap());
+    }
+}
@@ -0,0 +1,286 @@
+//! Known vulnerabilities of formulae the diff installs or leaves outdated, looked up online
+//! OSV has no Homebrew ecosystem, so formulae are looked up by their upstream git repository
+
+use crate::diff::HomebrewDiffData;
+use crate::error::{Error, Result};
+use crate::runner::CommandRunner;
+use crate::trace::{event, span};
+use crate::version;
+use serde::Deserialize;
+use serde_json::json;
+
+/// A formula version to look up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdvisoryQuery {
+    pub name: String,
+    pub version: String,
+    /// Upstream repository, e.g. `https://github.com/curl/curl`
+    pub repository: String,
+}
+
+/// Where advisories come from, e.g. `OsvSource`
+pub trait AdvisorySource {
+    /// Advisory ids affecting each query, in the order of the queries
+    fn advisories(&self, queries: &[AdvisoryQuery]) -> Result<Vec<Vec<String>>>;
+}
+
+/// The OSV database at osv.dev, queried with curl
+pub struct OsvSource<'a> {
+    runner: &'a dyn CommandRunner,
+}
+
+impl<'a> OsvSource<'a> {
+    pub const URL: &'static str = "https://api.osv.dev/v1/querybatch";
+
+    pub fn new(runner: &'a dyn CommandRunner) -> Self {
+        Self { runner }
+    }
+}
+
+/// Shape of OSV's batch query response, vulnerabilities only carry their ids
+#[derive(Debug, Deserialize)]
+struct OsvBatch {
+    #[serde(default)]
+    results: Vec<OsvResult>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct OsvResult {
+    #[serde(default)]
+    vulns: Vec<OsvVuln>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvVuln {
+    id: String,
+}
+
+impl AdvisorySource for OsvSource<'_> {
+    fn advisories(&self, queries: &[AdvisoryQuery]) -> Result<Vec<Vec<String>>> {
+        let _span = span!("osv_query", count = queries.len());
+        if queries.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let body = json!({
+            "queries": queries
+                .iter()
+                .map(|query| json!({
+                    "package": { "name": query.repository, "ecosystem": "GIT" },
+                    "version": query.version,
+                }))
+                .collect::<Vec<_>>(),
+        })
+        .to_string();
+        let output = self
+            .runner
+            .output(
+                "curl",
+                &[
+                    "--silent",
+                    "--fail",
+                    "--max-time",
+                    "10",
+                    "--header",
+                    "Content-Type: application/json",
+                    "--data",
+                    &body,
+                    Self::URL,
+                ],
+            )
+            .map_err(|e| Error::CommandFailed(format!("OSV query failed: {}", e)))?;
+        if !output.status.success() {
+            return Err(Error::CommandFailed(format!(
+                "OSV query failed with {}",
+                output.status
+            )));
+        }
+
+        let mut batch: OsvBatch = serde_json::from_slice(&output.stdout)
+            .map_err(|e| Error::CommandFailed(format!("OSV returned invalid JSON: {}", e)))?;
+        batch.results.resize_with(queries.len(), OsvResult::default);
+        event!(
+            affected = batch.results.iter().filter(|r| !r.vulns.is_empty()).count(),
+            "OSV query done"
+        );
+        Ok(batch
+            .results
+            .into_iter()
+            .map(|result| result.vulns.into_iter().map(|vuln| vuln.id).collect())
+            .collect())
+    }
+}
+
+/// A formula with advisories at the version it's installed or about to be installed at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AffectedFormula {
+    pub name: String,
+    pub version: String,
+    /// e.g. `GHSA-…` or `CVE-…`
+    pub advisories: Vec<String>,
+    /// Activation installs it, otherwise it's installed with a newer version available
+    pub added: bool,
+}
+
+/// The repository of a GitHub or GitLab homepage, e.g. `https://github.com/curl/curl`
+pub fn repository_of(homepage: &str) -> Option<String> {
+    let rest = homepage
+        .strip_prefix("https://")
+        .or_else(|| homepage.strip_prefix("http://"))?;
+    let mut parts = rest.split('/');
+    let host = parts.next()?;
+    let owner = parts.next().filter(|owner| !owner.is_empty())?;
+    let repo = parts.next().filter(|repo| !repo.is_empty())?;
+    matches!(host, "github.com" | "gitlab.com").then(|| {
+        format!(
+            "https://{}/{}/{}",
+            host,
+            owner,
+            repo.trim_end_matches(".git")
+        )
+    })
+}
+
+/// The upstream release of a Homebrew version, e.g. `8.5.0` of `8.5.0_1`
+/// Of several installed versions the newest, None if unknown, since OSV answers a query
+/// without a version with every advisory the package ever had
+fn upstream_version(versions: &str) -> Option<&str> {
+    let (release, _) = version::split_revision(version::newest(versions)?);
+    Some(release)
+}
+
+impl HomebrewDiffData {
+    /// Added formulae at the version brew installs and outdated ones at their installed version,
+    /// along with whether they're added, needs `load_metadata` for repositories and versions
+    fn advisory_queries(&self) -> Vec<(AdvisoryQuery, bool)> {
+        let query = |name: &str, version: Option<&str>| {
+            let metadata = self.formula_metadata.get(name)?;
+            let version = version.or(metadata.versions.stable.as_deref())?;
+            Some(AdvisoryQuery {
+                name: name.to_string(),
+                version: upstream_version(version)?.to_string(),
+                repository: repository_of(metadata.homepage.as_deref()?)?,
+            })
+        };
+
+        let added = self
+            .brews
+            .added
+            .iter()
+            .filter_map(|name| Some((query(name, None)?, true)));
+        let outdated = self
+            .brews
+            .upgraded
+            .iter()
+            .filter_map(|change| Some((query(&change.name, Some(&change.installed))?, false)));
+        added.chain(outdated).collect()
+    }
+
+    /// Look up advisories for added and outdated formulae, e.g. with `OsvSource`
+    /// Loads installed versions and formula metadata first if they weren't loaded yet,
+    /// formulae without a GitHub or GitLab homepage aren't checked
+    pub fn load_advisories(
+        &mut self,
+        runner: &dyn CommandRunner,
+        source: &dyn AdvisorySource,
+    ) -> Result<()> {
+        self.load_versions(runner)?;
+        if self.formula_metadata.is_empty() {
+            self.load_metadata(runner)?;
+        }
+
+        let queries = self.advisory_queries();
+        let (queries, added): (Vec<AdvisoryQuery>, Vec<bool>) = queries.into_iter().unzip();
+        let advisories = source.advisories(&queries)?;
+        self.security = queries
+            .into_iter()
+            .zip(added)
+            .zip(advisories)
+            .filter(|(_, advisories)| !advisories.is_empty())
+            .map(|((query, added), advisories)| AffectedFormula {
+                name: query.name,
+                version: query.version,
+                advisories,
+                added,
+            })
+            .collect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::VersionChange;
+    use crate::fixtures::FixtureRunner;
+    use crate::metadata::{FormulaMetadata, FormulaVersions};
+
+    #[test]
+    fn test_repository_of() {
+        assert_eq!(
+            repository_of("https://github.com/curl/curl"),
+            Some("https://github.com/curl/curl".to_string())
+        );
+        assert_eq!(
+            repository_of("https://gitlab.com/gnutls/gnutls.git/"),
+            Some("https://gitlab.com/gnutls/gnutls".to_string())
+        );
+        assert_eq!(repository_of("https://curl.se/"), None);
+        assert_eq!(repository_of("https://github.com/curl"), None);
+    }
+
+    #[test]
+    fn test_upstream_version() {
+        assert_eq!(upstream_version("8.5.0_1"), Some("8.5.0"));
+        assert_eq!(upstream_version("8.4.0 8.5.0_1"), Some("8.5.0"));
+        assert_eq!(upstream_version(""), None);
+    }
+
+    #[test]
+    fn test_load_advisories() {
+        let mut diff = HomebrewDiffData::default();
+        diff.brews.added = vec!["jq".to_string(), "sqlite".to_string()];
+        let upgrade = |name: &str, installed: &str| VersionChange {
+            name: name.to_string(),
+            installed: installed.to_string(),
+            available: "8.8.0".to_string(),
+            applied: true,
+            auto_updates: false,
+        };
+        // git's installed version isn't loaded yet, it's looked up rather than left out
+        diff.brews.upgraded = vec![upgrade("curl", "8.4.0 8.5.0_1"), upgrade("git", "")];
+        for (name, homepage, stable) in [
+            ("jq", "https://github.com/jqlang/jq", "1.7.1"),
+            ("sqlite", "https://sqlite.org/index.html", "3.46.0"),
+            ("curl", "https://github.com/curl/curl", "8.8.0"),
+            ("git", "https://github.com/git/git", "2.45.2"),
+        ] {
+            diff.formula_metadata.insert(
+                name.to_string(),
+                FormulaMetadata {
+                    name: name.to_string(),
+                    homepage: Some(homepage.to_string()),
+                    versions: FormulaVersions {
+                        stable: Some(stable.to_string()),
+                    },
+                    ..Default::default()
+                },
+            );
+        }
+        // Only answers the exact request body, with one result per query
+        let runner = FixtureRunner::load("osv-querybatch");
+
+        diff.load_advisories(&runner, &OsvSource::new(&runner))
+            .unwrap();
+
+        assert_eq!(
+            diff.security,
+            vec![AffectedFormula {
+                name: "curl".to_string(),
+                version: "8.5.0".to_string(),
+                advisories: vec!["CVE-2024-2398".to_string()],
+                added: false,
+            }]
+        );
+    }
+}
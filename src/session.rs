@@ -0,0 +1,78 @@
+use crate::diff::HomebrewDiffData;
+use crate::error::Result;
+use crate::intent::{ExtractOptions, HomebrewIntent};
+use crate::state::HomebrewState;
+use std::path::Path;
+
+/// Holds a detected state to diff successive intents against
+/// Meant for previews that rebuild the profile repeatedly, so brew only runs once
+#[derive(Debug, Clone)]
+pub struct DiffSession {
+    state: HomebrewState,
+}
+
+impl DiffSession {
+    /// Start a session from an already detected state
+    pub fn new(state: HomebrewState) -> Self {
+        Self { state }
+    }
+
+    /// Start a session by detecting the current state
+    pub fn detect() -> Result<Self> {
+        Ok(Self::new(HomebrewState::detect()?))
+    }
+
+    /// The state all diffs are computed against
+    pub fn state(&self) -> &HomebrewState {
+        &self.state
+    }
+
+    /// Diff the held state against a new intent
+    pub fn rediff(&self, intent: &HomebrewIntent) -> HomebrewDiffData {
+        HomebrewDiffData::compute(&self.state, intent)
+    }
+
+    /// Extract the intent from a freshly built profile and diff against it
+    pub fn rediff_profile(&self, profile: &Path) -> Result<HomebrewDiffData> {
+        self.rediff_profile_with_options(profile, &ExtractOptions::default())
+    }
+
+    /// Like `rediff_profile`, using the given extraction options
+    pub fn rediff_profile_with_options(
+        &self,
+        profile: &Path,
+        options: &ExtractOptions,
+    ) -> Result<HomebrewDiffData> {
+        let intent = HomebrewIntent::extract_with_options(profile, options)?;
+        Ok(self.rediff(&intent))
+    }
+
+    /// Replace the held state, e.g. after activation changed what's installed
+    pub fn refresh(&mut self) -> Result<()> {
+        self.state = HomebrewState::detect()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rediff_reuses_state() {
+        let mut state = HomebrewState::default();
+        state
+            .installed_brews
+            .insert("git".to_string(), "2.0".to_string());
+        let session = DiffSession::new(state);
+
+        let mut intent = HomebrewIntent::default();
+        intent.brews.insert("git".to_string());
+        assert!(!session.rediff(&intent).has_changes());
+
+        intent.brews.insert("wget".to_string());
+        let diff = session.rediff(&intent);
+        assert_eq!(diff.brews.added, vec!["wget"]);
+        assert!(session.state().installed_brews.contains_key("git"));
+    }
+}
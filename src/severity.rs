@@ -0,0 +1,192 @@
+//! How much attention a diff entry or diagnostic needs before activating
+
+use crate::diff::HomebrewDiffData;
+use crate::doctor::{CheckStatus, Finding};
+use crate::safety::SafetyViolation;
+use std::fmt;
+
+/// Ordered from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Worth knowing, nothing to act on
+    Info,
+    /// Likely unintended or hard to undo, check before activating
+    Warning,
+    /// Activation would break something, e.g. zap a running app
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        })
+    }
+}
+
+/// Something about the diff that deserves attention, rendered in the WARNINGS block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Concern {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Concern {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<&SafetyViolation> for Concern {
+    fn from(violation: &SafetyViolation) -> Self {
+        Self::new(violation.severity(), violation.to_string())
+    }
+}
+
+impl SafetyViolation {
+    /// Uninstalling a running app breaks it, the other violations are only risky
+    pub fn severity(&self) -> Severity {
+        match self {
+            SafetyViolation::RunningApp { .. } => Severity::Critical,
+            SafetyViolation::TooManyRemovals { .. } | SafetyViolation::TapInUse { .. } => {
+                Severity::Warning
+            }
+        }
+    }
+}
+
+impl Finding {
+    /// Failed checks keep the diff from being accurate, so they're critical
+    pub fn severity(&self) -> Severity {
+        match self.status {
+            CheckStatus::Ok => Severity::Info,
+            CheckStatus::Warning => Severity::Warning,
+            CheckStatus::Failed => Severity::Critical,
+        }
+    }
+}
+
+impl HomebrewDiffData {
+    /// Concerns derived from the diff alone, without running apps or other state
+    /// More destructive removals than `removal_threshold` usually mean detection failed
+    /// or cleanup is misunderstood
+    pub fn concerns(&self, removal_threshold: Option<usize>) -> Vec<Concern> {
+        let mut concerns = Vec::new();
+
+        let removals = self.destructive_removals();
+        if let Some(threshold) = removal_threshold.filter(|threshold| removals > *threshold) {
+            concerns.push(Concern::new(
+                Severity::Warning,
+                format!(
+                    "{} packages will be removed, more than {}, check that detection worked",
+                    removals, threshold
+                ),
+            ));
+        }
+
+        let mut pinned: Vec<&str> = self
+            .brews
+            .removed
+            .iter()
+            .filter(|r| r.effect.is_destructive() && self.pinned_brews.contains(&r.name))
+            .map(|r| r.name.as_str())
+            .collect();
+        pinned.sort_unstable();
+        for formula in pinned {
+            concerns.push(Concern::new(
+                Severity::Warning,
+                format!("{} is pinned, cleanup uninstalls it anyway", formula),
+            ));
+        }
+
+        // brew bundle installs entries in order, so without the tap the install fails
+        for tap in &self.missing_taps {
+            concerns.push(Concern::new(
+                Severity::Critical,
+                format!(
+                    "{} isn't declared or tapped, installing {} would fail, add tap \"{}\"",
+                    tap.name,
+                    tap.packages.join(", "),
+                    tap.name
+                ),
+            ));
+        }
+
+        for drift in &self.expired_acknowledgements {
+            concerns.push(Concern::new(
+                Severity::Warning,
+                format!(
+                    "{} was acknowledged until {}, it counts as drift again",
+                    drift.name,
+                    drift.expires.as_deref().unwrap_or_default()
+                ),
+            ));
+        }
+
+        concerns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::{ChangeEffect, MissingTap, Removal};
+
+    #[test]
+    fn test_diff_concerns() {
+        let mut diff = HomebrewDiffData::default();
+        diff.casks.removed = vec![
+            Removal::new("slack", ChangeEffect::WillBeZapped),
+            Removal::new("zoom", ChangeEffect::WillBeZapped),
+        ];
+        diff.taps.removed = vec![Removal::new("acme/tools", ChangeEffect::WillBeRemoved)];
+        diff.brews.removed = vec![Removal::new("node", ChangeEffect::WillBeRemoved)];
+        diff.pinned_brews.insert("node".to_string());
+
+        assert_eq!(
+            diff.concerns(Some(2)),
+            vec![
+                Concern::new(
+                    Severity::Warning,
+                    "3 packages will be removed, more than 2, check that detection worked"
+                ),
+                Concern::new(
+                    Severity::Warning,
+                    "node is pinned, cleanup uninstalls it anyway"
+                ),
+            ]
+        );
+        assert_eq!(diff.concerns(None).len(), 1);
+
+        let diff = HomebrewDiffData {
+            missing_taps: vec![MissingTap {
+                name: "hashicorp/tap".to_string(),
+                packages: vec!["hashicorp/tap/terraform".to_string()],
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            diff.concerns(None),
+            vec![Concern::new(
+                Severity::Critical,
+                "hashicorp/tap isn't declared or tapped, installing hashicorp/tap/terraform would fail, add tap \"hashicorp/tap\""
+            )]
+        );
+    }
+
+    #[test]
+    fn test_severity_order() {
+        let running = SafetyViolation::RunningApp {
+            cask: "docker".to_string(),
+            app: "Docker.app".to_string(),
+        };
+        assert_eq!(Concern::from(&running).severity, Severity::Critical);
+        assert!(Severity::Critical > Severity::Warning);
+        assert!(Severity::Warning > Severity::Info);
+    }
+}
@@ -0,0 +1,17 @@
+//! Quoting for the shell scripts and commands brewdiff writes
+
+/// Quote a value for POSIX sh
+pub(crate) fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("wget"), "'wget'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+}
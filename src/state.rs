@@ -1,101 +1,887 @@
+use crate::brew_config::BrewConfig;
+use crate::compat::{BrewCompat, BrewFeature};
 use crate::error::{Error, Result};
+use crate::events::{Category, CategorySet};
+use crate::intent::HomebrewIntent;
+use crate::lockfile::LockedVersions;
+use crate::runner::{CommandRunner, SystemRunner};
+use crate::timings::{self, Timings};
+use crate::trace::{event, span};
+use crate::version;
+use serde::Deserialize;
 use std::collections::{HashMap, HashSet};
-use std::process::Command;
+use std::fmt;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Formulae per `brew list --versions` call, well below ARG_MAX even with long names
+const LIST_VERSIONS_CHUNK: usize = 200;
+
+/// Where brew lives: Apple Silicon, Intel macs and Linux
+const BREW_LOCATIONS: &[&str] = &[
+    "/opt/homebrew/bin/brew",
+    "/usr/local/bin/brew",
+    "/home/linuxbrew/.linuxbrew/bin/brew",
+];
 
 /// What's actually installed via Homebrew right now
 #[derive(Debug, Clone, Default)]
 pub struct HomebrewState {
-    pub installed_brews: HashMap<String, String>, // name -> version
-    pub installed_casks: HashMap<String, String>, // name -> version
+    pub installed_brews: HashMap<String, String>, // name -> version, empty until `load_versions`
+    pub installed_casks: HashMap<String, String>, // name -> version, empty until `load_versions`
     pub installed_taps: HashSet<String>,
     pub installed_mas_apps: HashSet<String>, // Store as "name (id)" for display
+    pub outdated_brews: HashMap<String, String>, // name -> newest available version
+    pub outdated_casks: HashMap<String, String>, // name -> newest available version
+    pub formula_receipts: HashMap<String, FormulaReceipt>, // name -> receipt of the active keg
+    pub keg_taps: HashMap<String, String>,   // name -> tap of every keg, dependencies included
+    pub linked_brews: Option<HashSet<String>>, // None when link data isn't available
+    pub cask_receipts: HashMap<String, CaskReceipt>, // token -> install metadata
+    pub pinned_brews: HashSet<String>,       // `brew pin`ned, brew upgrade leaves them alone
+    pub sources: DetectionSources,           // where each category was detected
+    pub brew_config: BrewConfig,             // the installation detection ran against
+    pub detected_at: Option<u64>, // seconds since the Unix epoch, None unless detected live
+}
+
+/// What to detect, e.g. from `--only casks` or `--skip mas,taps`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DetectOptions {
+    /// Undetected categories stay empty, so diff and display them with the same selection
+    pub categories: CategorySet,
+}
+
+/// Where detection found the installed entries of a category
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DetectionSource {
+    /// Not detected, e.g. because Homebrew isn't installed
+    #[default]
+    None,
+    /// `Cellar/<name>/<version>` and install receipts
+    Cellar,
+    /// `brew leaves`
+    BrewLeaves,
+    /// `Caskroom/<token>/<version>`
+    Caskroom,
+    /// `brew list --cask`
+    BrewListCask,
+    /// `brew tap`
+    BrewTap,
+    /// `_MASReceipt` in app bundles
+    MasReceipts,
+    /// `mas list`
+    MasList,
+    /// Spotlight's App Store receipt metadata
+    Spotlight,
+    /// `brew bundle dump` output
+    BundleDump,
+}
+
+impl fmt::Display for DetectionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DetectionSource::None => "not detected",
+            DetectionSource::Cellar => "Cellar scan",
+            DetectionSource::BrewLeaves => "brew leaves",
+            DetectionSource::Caskroom => "Caskroom scan",
+            DetectionSource::BrewListCask => "brew list --cask",
+            DetectionSource::BrewTap => "brew tap",
+            DetectionSource::MasReceipts => "App Store receipts",
+            DetectionSource::MasList => "mas list",
+            DetectionSource::Spotlight => "Spotlight",
+            DetectionSource::BundleDump => "brew bundle dump",
+        })
+    }
+}
+
+/// Detection source of each category
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DetectionSources {
+    pub formulae: DetectionSource,
+    pub casks: DetectionSource,
+    pub taps: DetectionSource,
+    pub mas_apps: DetectionSource,
+}
+
+/// Where and how a state was obtained, so cached, snapshotted or merged states stay distinguishable
+/// Derived from the state's `brew_config`, `sources` and `detected_at`, see `HomebrewState::meta`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateMeta<'a> {
+    /// The prefix, release and architecture
+    pub brew_config: &'a BrewConfig,
+    pub sources: &'a DetectionSources,
+    /// Seconds since the Unix epoch, None if the state wasn't detected at a known time
+    pub detected_at: Option<u64>,
+}
+
+impl StateMeta<'_> {
+    /// e.g. `live detection` or `brew bundle dump`
+    pub fn backend(&self) -> &'static str {
+        let detected: Vec<DetectionSource> = [
+            self.sources.formulae,
+            self.sources.casks,
+            self.sources.taps,
+            self.sources.mas_apps,
+        ]
+        .into_iter()
+        .filter(|source| *source != DetectionSource::None)
+        .collect();
+        if detected.is_empty() {
+            "unknown source"
+        } else if detected
+            .iter()
+            .all(|source| *source == DetectionSource::BundleDump)
+        {
+            "brew bundle dump"
+        } else if self.detected_at.is_some() {
+            "live detection"
+        } else {
+            "recorded output"
+        }
+    }
+}
+
+impl fmt::Display for StateMeta<'_> {
+    /// e.g. `live detection of Homebrew 4.3.5 at /opt/homebrew (arm64)`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.backend())?;
+        if let Some(version) = &self.brew_config.version {
+            write!(f, " of Homebrew {}", version)?;
+        }
+        if let Some(prefix) = &self.brew_config.prefix {
+            write!(f, " at {}", prefix.display())?;
+        }
+        if let Some(arch) = &self.brew_config.arch {
+            write!(f, " ({})", arch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Seconds since the Unix epoch, for `HomebrewState::detected_at`
+fn now() -> Option<u64> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|since| since.as_secs())
+}
+
+/// How a cask was installed, read from `Caskroom/<token>/.metadata`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CaskReceipt {
+    /// Directory the app was installed to, e.g. /Applications or ~/Applications
+    pub appdir: Option<String>,
+    /// The app updates itself, so the Caskroom version goes stale (`auto_updates true`)
+    pub auto_updates: bool,
+    /// App bundles the cask installs, e.g. `Firefox.app`
+    pub apps: Vec<String>,
+    /// The tap the cask was installed from, e.g. `homebrew/cask`, None for older installs
+    pub tap: Option<String>,
+}
+
+impl CaskReceipt {
+    /// Read the install config brew records in `.metadata/config.json`
+    /// Explicit `--appdir` settings take precedence over the environment and defaults
+    fn from_config(config: &[u8]) -> Self {
+        let config: serde_json::Value = serde_json::from_slice(config).unwrap_or_default();
+        let appdir = ["explicit", "env", "default"]
+            .iter()
+            .find_map(|source| config.get(source)?.get("appdir")?.as_str())
+            .map(String::from);
+
+        Self {
+            appdir,
+            ..Default::default()
+        }
+    }
+
+    /// Read `auto_updates true` and the installed apps from the cask definition brew stored at
+    /// install time, which lives at `.metadata/<version>/<timestamp>/Casks/<token>.{json,rb}`
+    /// Only JSON definitions name their tap, for `.rb` ones it's the tap in `taps_dir` defining
+    /// the token, since casks from third-party taps are installed from the tapped `.rb`
+    fn read_definition(&mut self, cask_dir: &Path, taps_dir: &Path, token: &str, version: &str) {
+        let Ok(entries) = fs::read_dir(cask_dir.join(".metadata").join(version)) else {
+            return;
+        };
+        let Some(latest) = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .max()
+        else {
+            return;
+        };
+
+        let casks = latest.join("Casks");
+        if let Ok(json) = fs::read(casks.join(format!("{}.json", token))) {
+            let definition: serde_json::Value = serde_json::from_slice(&json).unwrap_or_default();
+            self.auto_updates =
+                definition.get("auto_updates").and_then(|v| v.as_bool()) == Some(true);
+            self.tap = definition
+                .get("tap")
+                .and_then(|v| v.as_str())
+                .map(String::from);
+            // e.g. "artifacts": [{"app": ["Docker.app"]}, {"binary": [...]}]
+            self.apps = definition
+                .get("artifacts")
+                .and_then(|v| v.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|artifact| artifact.get("app")?.as_array())
+                .filter_map(|app| app.first()?.as_str())
+                .map(String::from)
+                .collect();
+        } else if let Ok(rb) = fs::read_to_string(casks.join(format!("{}.rb", token))) {
+            self.auto_updates = rb.lines().any(|line| line.trim() == "auto_updates true");
+            // e.g. app "Docker.app"
+            self.apps = rb
+                .lines()
+                .filter_map(|line| line.trim().strip_prefix("app "))
+                .filter_map(|rest| rest.split('"').nth(1))
+                .map(String::from)
+                .collect();
+            self.tap = tap_defining_cask(taps_dir, token);
+        }
+    }
+}
+
+/// Where a tap is cloned, `Library/Taps/<user>/homebrew-<repo>` under the prefix
+pub(crate) fn tap_repository(prefix: &Path, tap: &str) -> PathBuf {
+    let (user, repo) = tap.split_once('/').unwrap_or((tap, ""));
+    prefix
+        .join("Library/Taps")
+        .join(user)
+        .join(format!("homebrew-{}", repo))
+}
+
+/// The tap whose repository defines a cask, e.g. `acme/tools` for
+/// `Library/Taps/acme/homebrew-tools/Casks/widget.rb`, also looked up in `Casks/w/` shards
+fn tap_defining_cask(taps_dir: &Path, token: &str) -> Option<String> {
+    let file = format!("{}.rb", token);
+    let shard = token.get(..1).unwrap_or_default();
+    let mut repos: Vec<PathBuf> = fs::read_dir(taps_dir)
+        .ok()?
+        .filter_map(|user| fs::read_dir(user.ok()?.path()).ok())
+        .flatten()
+        .filter_map(|repo| Some(repo.ok()?.path()))
+        .collect();
+    repos.sort();
+
+    let repo = repos.into_iter().find(|repo| {
+        let casks = repo.join("Casks");
+        casks.join(&file).is_file() || casks.join(shard).join(&file).is_file()
+    })?;
+    let name = repo.file_name()?.to_string_lossy();
+    let user = repo.parent()?.file_name()?.to_string_lossy();
+    Some(format!(
+        "{}/{}",
+        user,
+        name.strip_prefix("homebrew-").unwrap_or(&name)
+    ))
+}
+
+/// Split a DER element into its tag, contents and the bytes after it
+/// BER allows indefinite lengths for constructed elements, those take the rest of the input
+fn der_element(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = data.split_first()?;
+    let (&length, rest) = rest.split_first()?;
+    let (length, rest) = match length {
+        0x80 => return Some((tag, rest, &[])),
+        0..=0x7f => (usize::from(length), rest),
+        _ => {
+            let bytes = usize::from(length & 0x7f);
+            if bytes > 4 || rest.len() < bytes {
+                return None;
+            }
+            let (bytes, rest) = rest.split_at(bytes);
+            let length = bytes
+                .iter()
+                .fold(0usize, |length, byte| length << 8 | usize::from(*byte));
+            (length, rest)
+        }
+    };
+    (rest.len() >= length).then(|| (tag, &rest[..length], &rest[length..]))
+}
+
+/// The App Store id in an app's `_MASReceipt/receipt`, a PKCS #7 container whose payload is
+/// a set of `(type, version, value)` attributes, type 1 being the app's item id
+fn adam_id_from_receipt(receipt: &[u8]) -> Option<u64> {
+    // The payload follows the OID of PKCS #7 data, 1.2.840.113549.1.7.1
+    const DATA_OID: [u8; 11] = [
+        0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01,
+    ];
+    let at = receipt
+        .windows(DATA_OID.len())
+        .position(|window| window == DATA_OID)?;
+    let (_, content, _) =
+        der_element(&receipt[at + DATA_OID.len()..]).filter(|(tag, ..)| *tag == 0xa0)?;
+    let (_, payload, _) = der_element(content).filter(|(tag, ..)| *tag == 0x04)?;
+    let (_, mut attributes, _) = der_element(payload).filter(|(tag, ..)| *tag == 0x31)?;
+
+    while let Some((tag, attribute, rest)) = der_element(attributes) {
+        attributes = rest;
+        if tag != 0x30 {
+            continue;
+        }
+        let (_, kind, rest) = der_element(attribute).filter(|(tag, ..)| *tag == 0x02)?;
+        let (_, _version, rest) = der_element(rest)?;
+        let (_, value, _) = der_element(rest).filter(|(tag, ..)| *tag == 0x04)?;
+        if kind == [1] {
+            let (_, id, _) = der_element(value).filter(|(tag, ..)| *tag == 0x02)?;
+            return (!id.is_empty() && id.len() <= 8)
+                .then(|| id.iter().fold(0u64, |id, byte| id << 8 | u64::from(*byte)));
+        }
+    }
+    None
+}
+
+/// Shape of `brew outdated --json=v2`
+#[derive(Debug, Default, Deserialize)]
+struct OutdatedReport {
+    #[serde(default)]
+    formulae: Vec<OutdatedEntry>,
+    #[serde(default)]
+    casks: Vec<OutdatedEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OutdatedEntry {
+    name: String,
+    current_version: String,
+}
+
+/// How a formula was installed, read from its keg's INSTALL_RECEIPT.json
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct FormulaReceipt {
+    #[serde(default)]
+    pub installed_on_request: bool,
+    /// False when the formula was built from source rather than installed from a bottle
+    #[serde(default)]
+    pub poured_from_bottle: bool,
+    /// Options the keg was built with, e.g. `--with-fdk-aac`
+    #[serde(default)]
+    pub used_options: Vec<String>,
+    #[serde(default)]
+    pub source: Option<ReceiptSource>,
+}
+
+/// Where a formula was installed from, the `source` of its receipt
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ReceiptSource {
+    /// e.g. `homebrew/core`, None for formulae installed from a file or URL
+    #[serde(default)]
+    pub tap: Option<String>,
+}
+
+impl FormulaReceipt {
+    /// The tap the formula was installed from
+    pub fn tap(&self) -> Option<&str> {
+        self.source.as_ref()?.tap.as_deref()
+    }
+
+    /// Source builds often mean the formula is deliberately managed outside nix
+    pub fn built_from_source(&self) -> bool {
+        !self.poured_from_bottle
+    }
 }
 
 impl HomebrewState {
     /// Detect current Homebrew state by querying brew commands
     pub fn detect() -> Result<Self> {
+        Self::detect_with(&SystemRunner::default())
+    }
+
+    /// Detect current Homebrew state, running commands through the given runner
+    /// e.g. `SystemRunner::new(CommandEnvironment::sanitized())` for reproducible results
+    pub fn detect_with(runner: &dyn CommandRunner) -> Result<Self> {
+        Self::detect_timed(runner, &mut Timings::default())
+    }
+
+    /// Detect current Homebrew state, recording how long each detection phase took
+    pub fn detect_timed(runner: &dyn CommandRunner, timings: &mut Timings) -> Result<Self> {
+        Self::detect_with_options(runner, &DetectOptions::default(), timings)
+    }
+
+    /// Detect only the categories the options select, the others stay empty and undetected
+    /// Skipping App Store apps avoids the slowest queries when they aren't of interest
+    pub fn detect_with_options(
+        runner: &dyn CommandRunner,
+        options: &DetectOptions,
+        timings: &mut Timings,
+    ) -> Result<Self> {
+        let _span = span!("detect_state");
         if !Self::homebrew_installed() {
-            return Ok(Self::default());
+            event!("Homebrew not installed");
+            return Ok(Self {
+                detected_at: now(),
+                ..Default::default()
+            });
         }
 
-        Ok(Self {
-            installed_brews: Self::get_installed_formulae()?,
-            installed_casks: Self::get_installed_casks()?,
-            installed_taps: Self::get_taps()?,
-            installed_mas_apps: Self::get_mas_apps()?,
-        })
+        // The brew binary's location implies the prefix, so scans don't wait for brew config
+        let prefix = PathBuf::from(Self::get_brew_prefix());
+        let mut brew_config = BrewConfig::from_prefix(&prefix);
+        let categories = options.categories;
+        let prefix = prefix.as_path();
+        // Only brew outdated depends on the release, mas-only or taps-only runs don't ask for it
+        let needs_release =
+            categories.contains(Category::Formulae) || categories.contains(Category::Casks);
+        if needs_release {
+            brew_config.detect_version(runner, Self::get_brew_command());
+        }
+        let mut state = Self {
+            brew_config,
+            detected_at: now(),
+            ..Default::default()
+        };
+
+        if needs_release {
+            let (outdated_brews, outdated_casks) = timings::time(&mut timings.outdated, || {
+                Self::get_outdated(runner, &state.brew_compat())
+            })?;
+            if categories.contains(Category::Formulae) {
+                state.outdated_brews = outdated_brews;
+            }
+            if categories.contains(Category::Casks) {
+                state.outdated_casks = outdated_casks;
+            }
+        }
+        if categories.contains(Category::Formulae) {
+            (
+                (
+                    state.installed_brews,
+                    state.formula_receipts,
+                    state.keg_taps,
+                    state.sources.formulae,
+                ),
+                state.linked_brews,
+            ) = timings::time(&mut timings.formulae, || {
+                let formulae = Self::get_installed_formulae(runner, prefix)?;
+                Ok::<_, Error>((formulae, Self::get_linked_formulae(prefix)))
+            })?;
+            state.pinned_brews = Self::get_pinned_formulae(prefix);
+        }
+        if categories.contains(Category::Casks) {
+            (
+                state.installed_casks,
+                state.cask_receipts,
+                state.sources.casks,
+            ) = timings::time(&mut timings.casks, || {
+                Self::get_installed_casks(runner, prefix)
+            })?;
+        }
+        if categories.contains(Category::Taps) {
+            state.installed_taps = timings::time(&mut timings.taps, || Self::get_taps(runner))?;
+            state.sources.taps = DetectionSource::BrewTap;
+        }
+        if categories.contains(Category::AppStore) && Self::on_macos() {
+            (state.installed_mas_apps, state.sources.mas_apps) =
+                timings::time(&mut timings.mas, || Self::get_mas_apps(runner))?;
+        }
+
+        Ok(state)
+    }
+
+    /// Build a state from `brew bundle dump` output, without running any commands
+    /// The dump has no versions, so upgrades and installed versions are unknown
+    pub fn from_bundle_dump(dump: &str) -> Self {
+        let dumped = HomebrewIntent::from_brewfile(dump);
+        let unversioned = |names: HashSet<String>| -> HashMap<String, String> {
+            names
+                .into_iter()
+                .map(|name| (name, String::new()))
+                .collect()
+        };
+
+        Self {
+            installed_brews: unversioned(dumped.brews),
+            installed_casks: unversioned(dumped.casks),
+            installed_taps: dumped.taps,
+            installed_mas_apps: dumped.mas_apps,
+            sources: DetectionSources {
+                formulae: DetectionSource::BundleDump,
+                casks: DetectionSource::BundleDump,
+                taps: DetectionSource::BundleDump,
+                mas_apps: DetectionSource::BundleDump,
+            },
+            ..Default::default()
+        }
     }
 
-    fn homebrew_installed() -> bool {
+    /// Where and how the state was obtained
+    pub fn meta(&self) -> StateMeta<'_> {
+        StateMeta {
+            brew_config: &self.brew_config,
+            sources: &self.sources,
+            detected_at: self.detected_at,
+        }
+    }
+
+    /// Features the installed brew supports, from the release in `brew_config`
+    pub fn brew_compat(&self) -> BrewCompat {
+        self.brew_config.compat()
+    }
+
+    /// App Store apps and app directories only exist on macOS, on Linux they stay undetected
+    pub(crate) fn on_macos() -> bool {
+        cfg!(target_os = "macos")
+    }
+
+    pub(crate) fn homebrew_installed() -> bool {
         // Check for Homebrew at common locations
-        std::path::Path::new("/opt/homebrew/bin/brew").exists()
-            || std::path::Path::new("/usr/local/bin/brew").exists()
+        BREW_LOCATIONS.iter().any(|brew| Path::new(brew).exists())
     }
 
-    fn get_brew_command() -> &'static str {
-        if std::path::Path::new("/opt/homebrew/bin/brew").exists() {
-            "/opt/homebrew/bin/brew"
-        } else {
-            "/usr/local/bin/brew"
+    pub(crate) fn get_brew_command() -> &'static str {
+        BREW_LOCATIONS
+            .iter()
+            .find(|brew| Path::new(brew).exists())
+            .unwrap_or(&"/usr/local/bin/brew")
+    }
+
+    /// Homebrew's prefix, e.g. /opt/homebrew on Apple Silicon
+    pub(crate) fn get_brew_prefix() -> &'static str {
+        Self::get_brew_command().trim_end_matches("/bin/brew")
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn get_installed_formulae(
+        runner: &dyn CommandRunner,
+        prefix: &Path,
+    ) -> Result<(
+        HashMap<String, String>,
+        HashMap<String, FormulaReceipt>,
+        HashMap<String, String>,
+        DetectionSource,
+    )> {
+        // Scanning the Cellar avoids spawning brew, which takes seconds
+        let cellar = prefix.join("Cellar");
+        if let Some((formulae, receipts, keg_taps)) = Self::scan_cellar(&cellar) {
+            event!(formulae = formulae.len(), "scanned Cellar");
+            return Ok((formulae, receipts, keg_taps, DetectionSource::Cellar));
         }
+        event!("Cellar unreadable, falling back to brew");
+
+        // Receipts live in the Cellar, so without it there's nothing to record
+        Ok((
+            Self::get_installed_formulae_from_brew(runner)?,
+            HashMap::new(),
+            HashMap::new(),
+            DetectionSource::BrewLeaves,
+        ))
     }
 
-    fn get_installed_formulae() -> Result<HashMap<String, String>> {
+    /// Build the formulae map from `Cellar/<name>/<version>` directories
+    /// Only formulae installed on request are included, so unlike `brew leaves` a requested
+    /// formula that another formula depends on is kept, e.g. a declared `openssl@3`
+    /// The taps of all kegs are kept, so taps that only provide dependencies count as used
+    /// Returns None if the Cellar or any receipt can't be read, so callers can fall back to brew
+    #[allow(clippy::type_complexity)]
+    fn scan_cellar(
+        cellar: &Path,
+    ) -> Option<(
+        HashMap<String, String>,
+        HashMap<String, FormulaReceipt>,
+        HashMap<String, String>,
+    )> {
+        let opt = cellar.parent()?.join("opt");
+        let mut result = HashMap::new();
+        let mut receipts = HashMap::new();
+        let mut keg_taps = HashMap::new();
+
+        for entry in fs::read_dir(cellar).ok()? {
+            let formula_dir = entry.ok()?.path();
+            let name = formula_dir.file_name()?.to_string_lossy().to_string();
+
+            let mut versions: Vec<String> = fs::read_dir(&formula_dir)
+                .ok()?
+                .filter_map(|keg| keg.ok())
+                .filter(|keg| keg.path().is_dir())
+                .map(|keg| keg.file_name().to_string_lossy().to_string())
+                .collect();
+            if versions.is_empty() {
+                continue;
+            }
+            versions.sort_by(|a, b| version::compare(a, b));
+
+            // opt/<name> points at the active keg, otherwise use the newest one
+            let active = fs::read_link(opt.join(&name))
+                .ok()
+                .and_then(|target| Some(target.file_name()?.to_string_lossy().to_string()))
+                .filter(|version| versions.contains(version))
+                .unwrap_or_else(|| versions[versions.len() - 1].clone());
+
+            let receipt = fs::read(formula_dir.join(&active).join("INSTALL_RECEIPT.json")).ok()?;
+            let receipt: FormulaReceipt = serde_json::from_slice(&receipt).ok()?;
+            if let Some(tap) = receipt.tap() {
+                keg_taps.insert(name.clone(), tap.to_string());
+            }
+            if receipt.installed_on_request {
+                result.insert(name.clone(), versions.join(" "));
+                receipts.insert(name, receipt);
+            }
+        }
+
+        Some((result, receipts, keg_taps))
+    }
+
+    /// Read linked kegs from `var/homebrew/linked`, which brew keeps a symlink per linked formula in
+    pub(crate) fn get_linked_formulae(prefix: &Path) -> Option<HashSet<String>> {
+        let linked = fs::read_dir(prefix.join("var/homebrew/linked")).ok()?;
+        Some(
+            linked
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect(),
+        )
+    }
+
+    /// Read pinned formulae from the `var/homebrew/pinned/<name>` links `brew pin` creates
+    pub(crate) fn get_pinned_formulae(prefix: &Path) -> HashSet<String> {
+        let Ok(entries) = fs::read_dir(prefix.join("var/homebrew/pinned")) else {
+            return HashSet::new();
+        };
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect()
+    }
+
+    pub(crate) fn get_installed_formulae_from_brew(
+        runner: &dyn CommandRunner,
+    ) -> Result<HashMap<String, String>> {
         // Use 'brew leaves' to get only user-installed formulae (not dependencies)
         // This avoids showing confusing removals for dependencies like pcre2 that
         // are only installed because they're required by other formulae.
         // Users typically only care about the top-level packages they explicitly installed.
-        let leaves_output = Command::new(Self::get_brew_command())
-            .args(["leaves"])
-            .output()
+        let leaves_output = runner
+            .output(Self::get_brew_command(), &["leaves"])
             .map_err(|e| Error::CommandFailed(format!("brew leaves failed: {}", e)))?;
 
         if !leaves_output.status.success() {
             return Ok(HashMap::new());
         }
 
+        // Versions are looked up later, only for formulae that end up in the diff
         let leaves_str = String::from_utf8(leaves_output.stdout)?;
-        let leaves: Vec<String> = leaves_str.lines().map(|s| s.to_string()).collect();
+        Ok(Self::unversioned(leaves_str.lines()))
+    }
 
-        if leaves.is_empty() {
-            return Ok(HashMap::new());
+    /// Map names to an empty version, which stands for "not looked up"
+    fn unversioned<'a>(names: impl IntoIterator<Item = &'a str>) -> HashMap<String, String> {
+        names
+            .into_iter()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| (name.to_string(), String::new()))
+            .collect()
+    }
+
+    /// Run `brew list --versions` for the given formulae or casks, in batches of `LIST_VERSIONS_CHUNK`
+    /// Keeps large installations clear of ARG_MAX, batches run concurrently
+    pub(crate) fn get_list_versions(
+        runner: &dyn CommandRunner,
+        names: &[String],
+        cask: bool,
+    ) -> Result<HashMap<String, String>> {
+        let chunk_results: Vec<Result<Option<HashMap<String, String>>>> = thread::scope(|scope| {
+            let handles: Vec<_> = names
+                .chunks(LIST_VERSIONS_CHUNK)
+                .map(|chunk| scope.spawn(move || Self::list_versions_chunk(runner, chunk, cask)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|p| panic::resume_unwind(p)))
+                .collect()
+        });
+
+        let mut result = HashMap::new();
+        for chunk in chunk_results {
+            // Like a single call, a failing batch means versions can't be trusted
+            let Some(versions) = chunk? else {
+                return Ok(HashMap::new());
+            };
+            result.extend(versions);
         }
+        Ok(result)
+    }
+
+    /// Look up the versions detection left empty, like the `brew leaves` and `brew list --cask`
+    /// fallbacks do, for consumers that need every installed package's version
+    /// Several installed versions stay space-separated, e.g. `20.12.2 21.7.3`
+    pub fn load_versions(&mut self, runner: &dyn CommandRunner) -> Result<()> {
+        self.load_versions_of(runner, |_, _| true)
+    }
+
+    /// Like `load_versions`, only for the installed packages a lock file pins, which the diff
+    /// compares the locked versions against
+    pub fn load_locked_versions(
+        &mut self,
+        runner: &dyn CommandRunner,
+        locked: &LockedVersions,
+    ) -> Result<()> {
+        self.load_versions_of(runner, |name, cask| locked.pins(name, cask))
+    }
+
+    /// Whether an installed package the lock file pins has no version yet
+    pub fn lacks_locked_versions(&self, locked: &LockedVersions) -> bool {
+        [
+            (&self.installed_brews, false),
+            (&self.installed_casks, true),
+        ]
+        .iter()
+        .any(|(packages, cask)| {
+            packages
+                .iter()
+                .any(|(name, version)| version.is_empty() && locked.pins(name, *cask))
+        })
+    }
+
+    fn load_versions_of(
+        &mut self,
+        runner: &dyn CommandRunner,
+        wanted: impl Fn(&str, bool) -> bool,
+    ) -> Result<()> {
+        for (packages, cask) in [
+            (&mut self.installed_brews, false),
+            (&mut self.installed_casks, true),
+        ] {
+            let mut names: Vec<String> = packages
+                .iter()
+                .filter(|(name, version)| version.is_empty() && wanted(name, cask))
+                .map(|(name, _)| name.clone())
+                .collect();
+            if names.is_empty() {
+                continue;
+            }
+            names.sort();
 
-        // Get versions for the leaves
-        let mut args = vec!["list", "--versions"];
-        for leaf in &leaves {
-            args.push(leaf);
+            for (name, version) in Self::get_list_versions(runner, &names, cask)? {
+                if let Some(installed) = packages.get_mut(&name).filter(|v| v.is_empty()) {
+                    *installed = version;
+                }
+            }
         }
+        Ok(())
+    }
+
+    /// Returns None if brew exited unsuccessfully
+    fn list_versions_chunk(
+        runner: &dyn CommandRunner,
+        names: &[String],
+        cask: bool,
+    ) -> Result<Option<HashMap<String, String>>> {
+        let mut args = if cask {
+            vec!["list", "--cask", "--versions"]
+        } else {
+            vec!["list", "--versions"]
+        };
+        args.extend(names.iter().map(String::as_str));
 
-        let versions_output = Command::new(Self::get_brew_command())
-            .args(&args)
-            .output()
+        let versions_output = runner
+            .output(Self::get_brew_command(), &args)
             .map_err(|e| Error::CommandFailed(format!("brew list --versions failed: {}", e)))?;
 
         if !versions_output.status.success() {
-            return Ok(HashMap::new());
+            return Ok(None);
+        }
+
+        Self::parse_list_versions_output(&versions_output.stdout).map(Some)
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn get_installed_casks(
+        runner: &dyn CommandRunner,
+        prefix: &Path,
+    ) -> Result<(
+        HashMap<String, String>,
+        HashMap<String, CaskReceipt>,
+        DetectionSource,
+    )> {
+        // brew list --cask is one of the slowest calls, so scan first
+        let caskroom = prefix.join("Caskroom");
+        if let Some((casks, mut receipts)) = Self::scan_caskroom(&caskroom) {
+            event!(casks = casks.len(), "scanned Caskroom");
+            // Casks on Linux don't install apps, whatever appdir brew recorded is unused
+            if !Self::on_macos() {
+                for receipt in receipts.values_mut() {
+                    receipt.appdir = None;
+                }
+            }
+            return Ok((casks, receipts, DetectionSource::Caskroom));
+        }
+        event!("Caskroom unreadable, falling back to brew");
+
+        Ok((
+            Self::get_installed_casks_from_brew(runner)?,
+            HashMap::new(),
+            DetectionSource::BrewListCask,
+        ))
+    }
+
+    /// Build the cask map from `Caskroom/<token>/<version>` directories
+    /// Casks without a `.metadata` directory are half-installed and skipped, like brew does
+    /// Returns None if the Caskroom can't be read, so callers can fall back to brew
+    #[allow(clippy::type_complexity)]
+    fn scan_caskroom(
+        caskroom: &Path,
+    ) -> Option<(HashMap<String, String>, HashMap<String, CaskReceipt>)> {
+        let mut result = HashMap::new();
+        let mut receipts = HashMap::new();
+        // The Caskroom and the taps are both in the prefix
+        let taps_dir = caskroom.parent().unwrap_or(caskroom).join("Library/Taps");
+
+        for entry in fs::read_dir(caskroom).ok()? {
+            let cask_dir = entry.ok()?.path();
+            let token = cask_dir.file_name()?.to_string_lossy().to_string();
+            if token.starts_with('.') || !cask_dir.join(".metadata").is_dir() {
+                continue;
+            }
+
+            let mut versions: Vec<String> = fs::read_dir(&cask_dir)
+                .ok()?
+                .filter_map(|version| version.ok())
+                .map(|version| version.file_name().to_string_lossy().to_string())
+                .filter(|version| !version.starts_with('.'))
+                .collect();
+            if versions.is_empty() {
+                continue;
+            }
+            versions.sort_by(|a, b| version::compare(a, b));
+
+            let mut receipt = fs::read(cask_dir.join(".metadata/config.json"))
+                .map(|config| CaskReceipt::from_config(&config))
+                .unwrap_or_default();
+            receipt.read_definition(&cask_dir, &taps_dir, &token, &versions[versions.len() - 1]);
+            if receipt != CaskReceipt::default() {
+                receipts.insert(token.clone(), receipt);
+            }
+            result.insert(token, versions.join(" "));
         }
 
-        Self::parse_list_versions_output(&versions_output.stdout)
+        Some((result, receipts))
     }
 
-    fn get_installed_casks() -> Result<HashMap<String, String>> {
-        let output = Command::new(Self::get_brew_command())
-            .args(["list", "--cask", "--versions"])
-            .output()
+    pub(crate) fn get_installed_casks_from_brew(
+        runner: &dyn CommandRunner,
+    ) -> Result<HashMap<String, String>> {
+        // Like formulae, versions are looked up later for casks in the diff
+        let output = runner
+            .output(Self::get_brew_command(), &["list", "--cask"])
             .map_err(|e| Error::CommandFailed(format!("brew list --cask failed: {}", e)))?;
 
         if !output.status.success() {
             return Ok(HashMap::new());
         }
 
-        Self::parse_list_versions_output(&output.stdout)
+        Ok(Self::unversioned(String::from_utf8(output.stdout)?.lines()))
     }
 
-    fn get_taps() -> Result<HashSet<String>> {
-        let output = Command::new(Self::get_brew_command())
-            .args(["tap"])
-            .output()
+    pub(crate) fn get_taps(runner: &dyn CommandRunner) -> Result<HashSet<String>> {
+        let output = runner
+            .output(Self::get_brew_command(), &["tap"])
             .map_err(|e| Error::CommandFailed(format!("brew tap failed: {}", e)))?;
 
         if !output.status.success() {
@@ -106,53 +892,246 @@ impl HomebrewState {
         Ok(content.lines().map(|s| s.to_string()).collect())
     }
 
-    fn get_mas_apps() -> Result<HashSet<String>> {
+    /// Outdated formulae and casks, nothing if this brew can't report them as JSON
+    /// or its report doesn't parse, upgrades aren't worth failing detection over
+    pub(crate) fn get_outdated(
+        runner: &dyn CommandRunner,
+        compat: &BrewCompat,
+    ) -> Result<(HashMap<String, String>, HashMap<String, String>)> {
+        let Some(output) = Self::run_outdated(runner, compat)? else {
+            return Ok((HashMap::new(), HashMap::new()));
+        };
+        let outdated = Self::parse_outdated_output(&output);
+        #[cfg(feature = "tracing")]
+        if let Err(e) = &outdated {
+            event!(error = %e, "skipping upgrades");
+        }
+        Ok(outdated.unwrap_or_default())
+    }
+
+    /// stdout of `brew outdated --json=v2`, None if this brew lacks it or the command failed
+    pub(crate) fn run_outdated(
+        runner: &dyn CommandRunner,
+        compat: &BrewCompat,
+    ) -> Result<Option<Vec<u8>>> {
+        // The v1 schema has a different shape, misparsing it would invent upgrades
+        if !compat.supports(BrewFeature::OutdatedJsonV2) {
+            event!("brew too old for outdated --json=v2, skipping upgrades");
+            return Ok(None);
+        }
+
+        // Include self-updating casks, the diff decides whether activation upgrades them
+        let mut args = vec!["outdated", "--json=v2"];
+        if compat.supports(BrewFeature::GreedyAutoUpdates) {
+            args.push("--greedy-auto-updates");
+        }
+        let output = runner
+            .output(Self::get_brew_command(), &args)
+            .map_err(|e| Error::CommandFailed(format!("brew outdated failed: {}", e)))?;
+
+        Ok(Some(output.stdout).filter(|_| output.status.success()))
+    }
+
+    pub(crate) fn parse_outdated_output(
+        output: &[u8],
+    ) -> Result<(HashMap<String, String>, HashMap<String, String>)> {
+        let report: OutdatedReport = serde_json::from_slice(output).map_err(|e| {
+            Error::CommandFailed(format!("brew outdated returned invalid JSON: {}", e))
+        })?;
+
+        let collect = |entries: Vec<OutdatedEntry>| {
+            entries
+                .into_iter()
+                .map(|entry| (entry.name, entry.current_version))
+                .collect()
+        };
+
+        Ok((collect(report.formulae), collect(report.casks)))
+    }
+
+    pub(crate) fn get_mas_apps(
+        runner: &dyn CommandRunner,
+    ) -> Result<(HashSet<String>, DetectionSource)> {
+        // Prefer App Store receipts in app bundles over parsing mas list's text output
+        let from_receipts = Self::get_mas_apps_from_receipts(runner)?;
+        if !from_receipts.is_empty() {
+            return Ok((from_receipts, DetectionSource::MasReceipts));
+        }
+
         // Check if mas is installed
-        let mas_check = Command::new("which")
-            .arg("mas")
-            .output()
+        let mas_check = runner
+            .output("which", &["mas"])
             .map_err(|e| Error::CommandFailed(format!("which mas failed: {}", e)))?;
 
         if !mas_check.status.success() {
-            // mas not installed, no MAS apps
-            return Ok(HashSet::new());
+            // mas not installed, fall back to Spotlight's App Store receipts
+            let from_spotlight = Self::get_mas_apps_from_spotlight(runner)?;
+            return Ok((from_spotlight, DetectionSource::Spotlight));
         }
 
-        let output = Command::new("mas")
-            .arg("list")
-            .output()
+        let output = runner
+            .output("mas", &["list"])
             .map_err(|e| Error::CommandFailed(format!("mas list failed: {}", e)))?;
 
         if !output.status.success() {
-            return Ok(HashSet::new());
+            return Ok((HashSet::new(), DetectionSource::MasList));
         }
 
         let content = String::from_utf8(output.stdout)?;
+        Ok((
+            Self::parse_mas_list_output(&content),
+            DetectionSource::MasList,
+        ))
+    }
+
+    /// Parse `mas list` output, e.g. "1234567890  App Name     (1.2.3)"
+    pub(crate) fn parse_mas_list_output(content: &str) -> HashSet<String> {
         let mut apps = HashSet::new();
 
-        // Parse output format: "1234567890  App Name     (1.2.3)"
         for line in content.lines() {
-            // Split on whitespace and filter out empty strings
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                let id = parts[0];
-                // Find where the version starts (last item in parentheses)
-                let version_start = parts.iter().rposition(|p| p.starts_with('('));
-                let name_parts = if let Some(idx) = version_start {
-                    &parts[1..idx]
-                } else {
-                    &parts[1..]
-                };
-                let name = name_parts.join(" ");
-                // Store as "App Name (id)" to match intent format
-                apps.insert(format!("{} ({})", name, id));
+            let mut parts = line.split_whitespace();
+            // Skip messages like "No installed apps found"
+            let Some(id) = parts
+                .next()
+                .filter(|id| id.chars().all(|c| c.is_ascii_digit()))
+            else {
+                continue;
+            };
+            let parts: Vec<&str> = parts.collect();
+            // Drop the version, the last item in parentheses
+            let name_parts = match parts.iter().rposition(|p| p.starts_with('(')) {
+                Some(idx) => &parts[..idx],
+                None => &parts[..],
+            };
+            if name_parts.is_empty() {
+                continue;
+            }
+            // Store as "App Name (id)" to match intent format
+            apps.insert(format!("{} ({})", name_parts.join(" "), id));
+        }
+
+        apps
+    }
+
+    fn get_mas_apps_from_receipts(runner: &dyn CommandRunner) -> Result<HashSet<String>> {
+        let mut app_dirs = vec![PathBuf::from("/Applications")];
+        if let Some(home) = std::env::var_os("HOME") {
+            app_dirs.push(Path::new(&home).join("Applications"));
+        }
+
+        let mut apps = HashSet::new();
+        for app_path in app_dirs
+            .iter()
+            .flat_map(|dir| Self::find_mas_receipt_apps(dir))
+        {
+            let info_plist = app_path.join("Contents/Info.plist");
+            let plist_output = runner.output(
+                "plutil",
+                &["-convert", "json", "-o", "-", &info_plist.to_string_lossy()],
+            );
+            let name = match plist_output {
+                Ok(output) if output.status.success() => {
+                    Self::app_name_from_info_plist(&output.stdout)
+                }
+                _ => None,
+            };
+
+            // The App Store id isn't part of Info.plist, but of the receipt the App Store signed
+            let Some(id) = fs::read(app_path.join("Contents/_MASReceipt/receipt"))
+                .ok()
+                .and_then(|receipt| adam_id_from_receipt(&receipt))
+            else {
+                continue;
+            };
+            let id = id.to_string();
+
+            let name = name.or_else(|| Some(app_path.file_stem()?.to_string_lossy().to_string()));
+            if let Some(app) = name.and_then(|name| Self::mas_entry(&name, &id)) {
+                apps.insert(app);
+            }
+        }
+
+        Ok(apps)
+    }
+
+    /// Find app bundles in a directory that carry an App Store receipt
+    fn find_mas_receipt_apps(dir: &Path) -> Vec<PathBuf> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+
+        let mut apps: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "app"))
+            .filter(|path| path.join("Contents/_MASReceipt/receipt").is_file())
+            .collect();
+        apps.sort();
+        apps
+    }
+
+    /// Read the display name from an Info.plist converted to JSON
+    fn app_name_from_info_plist(json: &[u8]) -> Option<String> {
+        let info: serde_json::Value = serde_json::from_slice(json).ok()?;
+        ["CFBundleDisplayName", "CFBundleName"]
+            .iter()
+            .filter_map(|key| info.get(key)?.as_str())
+            .map(|name| name.trim().to_string())
+            .find(|name| !name.is_empty())
+    }
+
+    fn get_mas_apps_from_spotlight(runner: &dyn CommandRunner) -> Result<HashSet<String>> {
+        let output = match runner.output("mdfind", &["kMDItemAppStoreHasReceipt=1"]) {
+            Ok(output) if output.status.success() => output,
+            // Spotlight unavailable or disabled, no MAS apps
+            _ => return Ok(HashSet::new()),
+        };
+
+        let content = String::from_utf8(output.stdout)?;
+        let mut apps = HashSet::new();
+
+        for app_path in content.lines().filter(|line| line.ends_with(".app")) {
+            let id_output = runner
+                .output(
+                    "mdls",
+                    &["-raw", "-name", "kMDItemAppStoreAdamID", app_path],
+                )
+                .map_err(|e| Error::CommandFailed(format!("mdls failed: {}", e)))?;
+
+            if !id_output.status.success() {
+                continue;
+            }
+
+            let id = String::from_utf8(id_output.stdout)?;
+            if let Some(app) = Self::mas_entry_from_spotlight(app_path, &id) {
+                apps.insert(app);
             }
         }
 
         Ok(apps)
     }
 
-    fn parse_list_versions_output(output: &[u8]) -> Result<HashMap<String, String>> {
+    /// Build an "App Name (id)" entry from an app bundle path and its App Store id
+    fn mas_entry_from_spotlight(app_path: &str, adam_id: &str) -> Option<String> {
+        let name = Path::new(app_path)
+            .file_stem()?
+            .to_string_lossy()
+            .to_string();
+        Self::mas_entry(&name, adam_id)
+    }
+
+    /// Build an "App Name (id)" entry, skipping apps without a valid App Store id
+    fn mas_entry(name: &str, adam_id: &str) -> Option<String> {
+        let id = adam_id.trim();
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+            // mdls prints "(null)" for apps without an App Store id
+            return None;
+        }
+
+        Some(format!("{} ({})", name, id))
+    }
+
+    pub(crate) fn parse_list_versions_output(output: &[u8]) -> Result<HashMap<String, String>> {
         let content = String::from_utf8(output.to_vec())?;
         let mut result = HashMap::new();
 
@@ -180,6 +1159,44 @@ impl HomebrewState {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixtures::FixtureRunner;
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn test_detect_outside_macos() {
+        let state = HomebrewState::detect().unwrap();
+        assert!(state.installed_mas_apps.is_empty());
+        assert_eq!(state.sources.mas_apps, DetectionSource::None);
+    }
+
+    #[test]
+    fn test_state_meta() {
+        let state = HomebrewState::from_bundle_dump("brew \"git\"\n");
+        assert_eq!(state.meta().to_string(), "brew bundle dump");
+        assert_eq!(HomebrewState::default().meta().backend(), "unknown source");
+
+        let state = HomebrewState {
+            brew_config: BrewConfig::from_prefix(Path::new("/opt/homebrew")),
+            sources: DetectionSources {
+                formulae: DetectionSource::Cellar,
+                ..Default::default()
+            },
+            detected_at: now(),
+            ..Default::default()
+        };
+        assert_eq!(
+            state.meta().to_string(),
+            "live detection at /opt/homebrew (arm64)"
+        );
+
+        let apple_silicon = BrewConfig::from_prefix(Path::new("/opt/homebrew"));
+        let intel = BrewConfig {
+            arch: Some("x86_64".to_string()),
+            ..BrewConfig::from_prefix(Path::new("/usr/local"))
+        };
+        assert!(!apple_silicon.same_installation(&intel));
+        assert!(apple_silicon.same_installation(&BrewConfig::default()));
+    }
 
     #[test]
     fn test_parse_list_versions_output() {
@@ -199,15 +1216,347 @@ mod tests {
         assert!(result.is_empty());
     }
 
+    #[test]
+    fn test_parse_outdated_output() {
+        let input = br#"{
+            "formulae": [
+                {"name": "wget", "installed_versions": ["1.21.3"], "current_version": "1.24.5", "pinned": false, "pinned_version": null}
+            ],
+            "casks": [
+                {"name": "firefox", "installed_versions": ["120.0"], "current_version": "121.0"}
+            ]
+        }"#;
+        let (brews, casks) = HomebrewState::parse_outdated_output(input).unwrap();
+
+        assert_eq!(brews.get("wget"), Some(&"1.24.5".to_string()));
+        assert_eq!(casks.get("firefox"), Some(&"121.0".to_string()));
+    }
+
+    #[test]
+    fn test_get_outdated_ignores_invalid_json() {
+        let runner = FixtureRunner::parse(
+            "$ brew outdated --json=v2 --greedy-auto-updates\nError: No available formula\n",
+        );
+        let compat = BrewCompat {
+            version: Some("4.3.5".to_string()),
+        };
+
+        let (brews, casks) = HomebrewState::get_outdated(&runner, &compat).unwrap();
+
+        assert!(brews.is_empty());
+        assert!(casks.is_empty());
+    }
+
+    #[test]
+    fn test_parse_mas_list_output() {
+        let apps = HomebrewState::parse_mas_list_output(
+            "497799835  Xcode (15.4)\nNo installed apps found\n(1.0)\n123\n409183694   Keynote   (14.0)\n",
+        );
+
+        assert_eq!(apps.len(), 2);
+        assert!(apps.contains("Xcode (497799835)"));
+        assert!(apps.contains("Keynote (409183694)"));
+    }
+
+    #[test]
+    fn test_list_versions_is_chunked() {
+        let mut names: Vec<String> = (0..450).map(|i| format!("formula{}", i)).collect();
+        let runner =
+            names
+                .chunks(LIST_VERSIONS_CHUNK)
+                .fold(FixtureRunner::default(), |runner, chunk| {
+                    let versions: String =
+                        chunk.iter().map(|name| format!("{} 1.0\n", name)).collect();
+                    runner.with(
+                        format!("brew list --versions {}", chunk.join(" ")),
+                        versions,
+                    )
+                });
+
+        let versions = HomebrewState::get_list_versions(&runner, &names, false).unwrap();
+        assert_eq!(versions.len(), 450);
+        assert_eq!(versions["formula449"], "1.0");
+        let mut calls: Vec<usize> = runner
+            .calls()
+            .iter()
+            .map(|call| call.split(' ').count() - 3)
+            .collect();
+        calls.sort();
+        assert_eq!(calls, vec![50, 200, 200]);
+
+        // The last batch now has a name brew doesn't know, it isn't recorded and fails
+        names.push("broken".to_string());
+        let versions = HomebrewState::get_list_versions(&runner, &names, false).unwrap();
+        assert!(versions.is_empty());
+    }
+
+    #[test]
+    fn test_mas_entry_from_spotlight() {
+        assert_eq!(
+            HomebrewState::mas_entry_from_spotlight("/Applications/Xcode.app", "497799835\n"),
+            Some("Xcode (497799835)".to_string())
+        );
+        assert_eq!(
+            HomebrewState::mas_entry_from_spotlight("/Applications/Safari.app", "(null)"),
+            None
+        );
+    }
+
+    /// A receipt carrying only the payload, wrapped like the App Store wraps it
+    fn mas_receipt(adam_id: &[u8]) -> Vec<u8> {
+        fn element(tag: u8, contents: &[u8]) -> Vec<u8> {
+            let mut element = vec![tag, contents.len() as u8];
+            element.extend_from_slice(contents);
+            element
+        }
+        let attribute = |kind: u8, value: &[u8]| {
+            let mut sequence = element(0x02, &[kind]);
+            sequence.extend(element(0x02, &[1]));
+            sequence.extend(element(0x04, value));
+            element(0x30, &sequence)
+        };
+
+        let mut attributes = attribute(2, &element(0x0c, b"com.apple.dt.Xcode"));
+        attributes.extend(attribute(1, &element(0x02, adam_id)));
+        let mut content_info = vec![
+            0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01,
+        ];
+        content_info.extend(element(0xa0, &element(0x04, &element(0x31, &attributes))));
+        element(0x30, &content_info)
+    }
+
+    #[test]
+    fn test_adam_id_from_receipt() {
+        // 497799835 is 0x1dabd29b
+        assert_eq!(
+            adam_id_from_receipt(&mas_receipt(&[0x1d, 0xab, 0xd2, 0x9b])),
+            Some(497799835)
+        );
+        assert_eq!(adam_id_from_receipt(b""), None);
+        assert_eq!(adam_id_from_receipt(&mas_receipt(&[])), None);
+    }
+
+    #[test]
+    fn test_find_mas_receipt_apps() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let receipt_dir = temp_dir.path().join("Xcode.app/Contents/_MASReceipt");
+        fs::create_dir_all(&receipt_dir).unwrap();
+        fs::write(receipt_dir.join("receipt"), b"").unwrap();
+        fs::create_dir_all(temp_dir.path().join("Firefox.app/Contents")).unwrap();
+
+        let apps = HomebrewState::find_mas_receipt_apps(temp_dir.path());
+        assert_eq!(apps, vec![temp_dir.path().join("Xcode.app")]);
+    }
+
+    #[test]
+    fn test_app_name_from_info_plist() {
+        let plist = br#"{"CFBundleIdentifier": "com.apple.dt.Xcode", "CFBundleName": "Xcode"}"#;
+        assert_eq!(
+            HomebrewState::app_name_from_info_plist(plist),
+            Some("Xcode".to_string())
+        );
+
+        let plist = br#"{"CFBundleDisplayName": "Keynote", "CFBundleName": "Keynote-App"}"#;
+        assert_eq!(
+            HomebrewState::app_name_from_info_plist(plist),
+            Some("Keynote".to_string())
+        );
+        assert_eq!(HomebrewState::app_name_from_info_plist(b"{}"), None);
+    }
+
+    fn write_keg(cellar: &Path, name: &str, version: &str, receipt: &str) {
+        let keg = cellar.join(name).join(version);
+        fs::create_dir_all(&keg).unwrap();
+        fs::write(keg.join("INSTALL_RECEIPT.json"), receipt).unwrap();
+    }
+
+    #[test]
+    fn test_scan_cellar() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cellar = temp_dir.path().join("Cellar");
+        write_keg(
+            &cellar,
+            "wget",
+            "1.21.3",
+            r#"{"installed_on_request": true, "poured_from_bottle": false, "source": {"tap": "homebrew/core", "spec": "stable"}}"#,
+        );
+        write_keg(
+            &cellar,
+            "git",
+            "2.41.0",
+            r#"{"installed_on_request": true}"#,
+        );
+        write_keg(
+            &cellar,
+            "git",
+            "2.42.0",
+            r#"{"installed_on_request": true, "poured_from_bottle": true}"#,
+        );
+        write_keg(
+            &cellar,
+            "pcre2",
+            "10.42",
+            r#"{"installed_on_request": false, "source": {"tap": "acme/libs"}}"#,
+        );
+
+        let (result, receipts, keg_taps) = HomebrewState::scan_cellar(&cellar).unwrap();
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(receipts.len(), 2);
+        assert!(receipts["wget"].built_from_source());
+        assert!(!receipts["git"].built_from_source());
+        assert_eq!(receipts["wget"].tap(), Some("homebrew/core"));
+        assert_eq!(receipts["git"].tap(), None);
+        assert_eq!(result.get("wget"), Some(&"1.21.3".to_string()));
+        assert_eq!(result.get("git"), Some(&"2.41.0 2.42.0".to_string()));
+        // Dependencies installed for other formulae aren't reported, only their tap is
+        assert!(!result.contains_key("pcre2"));
+        assert_eq!(keg_taps["pcre2"], "acme/libs");
+        assert_eq!(keg_taps["wget"], "homebrew/core");
+        assert!(!keg_taps.contains_key("git"));
+    }
+
+    #[test]
+    fn test_scan_cellar_without_opt_link_uses_newest_keg() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cellar = temp_dir.path().join("Cellar");
+        write_keg(&cellar, "jq", "1.10", r#"{"installed_on_request": true}"#);
+        write_keg(&cellar, "jq", "1.9", r#"{"installed_on_request": false}"#);
+
+        let (result, _, _) = HomebrewState::scan_cellar(&cellar).unwrap();
+
+        // 1.10 is newer than 1.9 even though it sorts before it as a string
+        assert_eq!(result.get("jq"), Some(&"1.9 1.10".to_string()));
+    }
+
+    #[test]
+    fn test_scan_cellar_falls_back() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cellar = temp_dir.path().join("Cellar");
+        assert!(HomebrewState::scan_cellar(&cellar).is_none());
+
+        // An unreadable receipt means brew has to be asked instead
+        write_keg(&cellar, "wget", "1.21.3", "not json");
+        assert!(HomebrewState::scan_cellar(&cellar).is_none());
+    }
+
+    #[test]
+    fn test_get_linked_formulae() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        assert!(HomebrewState::get_linked_formulae(temp_dir.path()).is_none());
+
+        let linked = temp_dir.path().join("var/homebrew/linked");
+        fs::create_dir_all(linked.join("wget")).unwrap();
+        let result = HomebrewState::get_linked_formulae(temp_dir.path()).unwrap();
+        assert_eq!(result, HashSet::from(["wget".to_string()]));
+    }
+
+    #[test]
+    fn test_get_pinned_formulae() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        assert!(HomebrewState::get_pinned_formulae(temp_dir.path()).is_empty());
+
+        let pinned = temp_dir.path().join("var/homebrew/pinned");
+        fs::create_dir_all(&pinned).unwrap();
+        std::os::unix::fs::symlink("../../../Cellar/wget/1.21.3", pinned.join("wget")).unwrap();
+        let result = HomebrewState::get_pinned_formulae(temp_dir.path());
+        assert_eq!(result, HashSet::from(["wget".to_string()]));
+    }
+
+    #[test]
+    fn test_tap_repository() {
+        assert_eq!(
+            tap_repository(Path::new("/opt/homebrew"), "hashicorp/tap"),
+            Path::new("/opt/homebrew/Library/Taps/hashicorp/homebrew-tap")
+        );
+    }
+
+    #[test]
+    fn test_scan_caskroom() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let caskroom = temp_dir.path().join("Caskroom");
+        for (token, version) in [("firefox", "121.0"), ("docker", "latest")] {
+            fs::create_dir_all(caskroom.join(token).join(version)).unwrap();
+            fs::create_dir_all(caskroom.join(token).join(".metadata").join(version)).unwrap();
+        }
+        // Interrupted install without metadata
+        fs::create_dir_all(caskroom.join("broken").join("1.0")).unwrap();
+        fs::write(
+            caskroom.join("firefox/.metadata/config.json"),
+            r#"{"default": {"appdir": "/Applications"}, "env": {}, "explicit": {"appdir": "~/Applications"}}"#,
+        )
+        .unwrap();
+        let definition = caskroom.join("docker/.metadata/latest/20240101120000.000/Casks");
+        fs::create_dir_all(&definition).unwrap();
+        fs::write(
+            definition.join("docker.json"),
+            r#"{"token": "docker", "tap": "homebrew/cask", "auto_updates": true, "artifacts": [{"app": ["Docker.app"]}, {"binary": ["docker"]}]}"#,
+        )
+        .unwrap();
+        // Installed from a third-party tap, which only stores the `.rb`
+        let definition = caskroom.join("widget/.metadata/2.1/20240101120000.000/Casks");
+        fs::create_dir_all(caskroom.join("widget/2.1")).unwrap();
+        fs::create_dir_all(&definition).unwrap();
+        fs::write(
+            definition.join("widget.rb"),
+            "cask \"widget\" do\n  version \"2.1\"\n  app \"Widget.app\"\nend\n",
+        )
+        .unwrap();
+        let tap = temp_dir
+            .path()
+            .join("Library/Taps/acme/homebrew-tools/Casks");
+        fs::create_dir_all(&tap).unwrap();
+        fs::write(tap.join("widget.rb"), "cask \"widget\" do\nend\n").unwrap();
+
+        let (result, receipts) = HomebrewState::scan_caskroom(&caskroom).unwrap();
+        assert_eq!(receipts.len(), 3);
+        assert!(receipts["docker"].auto_updates);
+        assert_eq!(receipts["docker"].apps, vec!["Docker.app".to_string()]);
+        assert_eq!(receipts["docker"].tap.as_deref(), Some("homebrew/cask"));
+        assert_eq!(receipts["widget"].apps, vec!["Widget.app".to_string()]);
+        assert_eq!(receipts["widget"].tap.as_deref(), Some("acme/tools"));
+        assert!(!receipts["firefox"].auto_updates);
+        assert_eq!(
+            receipts["firefox"].appdir.as_deref(),
+            Some("~/Applications")
+        );
+
+        assert_eq!(result.len(), 3);
+        assert_eq!(result.get("firefox"), Some(&"121.0".to_string()));
+        assert_eq!(result.get("docker"), Some(&"latest".to_string()));
+        assert!(HomebrewState::scan_caskroom(&temp_dir.path().join("missing")).is_none());
+    }
+
+    #[test]
+    fn test_scan_caskroom_reads_newest_version() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let caskroom = temp_dir.path().join("Caskroom");
+        for (version, auto_updates) in [("1.9", false), ("1.10", true)] {
+            let definition = caskroom
+                .join("widget/.metadata")
+                .join(version)
+                .join("20240101120000.000/Casks");
+            fs::create_dir_all(caskroom.join("widget").join(version)).unwrap();
+            fs::create_dir_all(&definition).unwrap();
+            fs::write(
+                definition.join("widget.json"),
+                format!(r#"{{"token": "widget", "auto_updates": {}}}"#, auto_updates),
+            )
+            .unwrap();
+        }
+
+        let (result, receipts) = HomebrewState::scan_caskroom(&caskroom).unwrap();
+
+        assert_eq!(result.get("widget"), Some(&"1.9 1.10".to_string()));
+        assert!(receipts["widget"].auto_updates);
+    }
+
     #[test]
     fn test_homebrew_detection() {
         // This test will pass/fail based on whether Homebrew is installed
         let is_installed = HomebrewState::homebrew_installed();
         if is_installed {
-            assert!(
-                std::path::Path::new("/opt/homebrew/bin/brew").exists()
-                    || std::path::Path::new("/usr/local/bin/brew").exists()
-            );
+            assert!(Path::new(HomebrewState::get_brew_command()).exists());
         }
     }
 }
@@ -0,0 +1,289 @@
+use crate::brew_config::BrewConfig;
+use crate::diff::HomebrewDiffData;
+use crate::display::{self, DiffReport, DisplayOptions};
+use crate::duplicates;
+use crate::error::Result;
+use crate::intent::HomebrewIntent;
+use crate::runner::{CommandRunner, SystemRunner};
+use crate::state::{DetectionSource, HomebrewState};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Write the diff one category at a time, each as soon as its detection finishes
+/// Taps come first since they're quickest to detect, App Store apps last
+/// Returns what was written
+pub fn write_diff_streaming<W: Write>(
+    writer: &mut W,
+    new_profile: &Path,
+    options: &DisplayOptions,
+) -> Result<DiffReport> {
+    write_diff_streaming_with(writer, &SystemRunner::default(), new_profile, options)
+}
+
+/// Like `write_diff_streaming`, running detection commands through the given runner
+pub fn write_diff_streaming_with<W: Write>(
+    writer: &mut W,
+    runner: &dyn CommandRunner,
+    new_profile: &Path,
+    options: &DisplayOptions,
+) -> Result<DiffReport> {
+    let intent = HomebrewIntent::extract(new_profile)?;
+    let installed = HomebrewState::homebrew_installed();
+    let prefix = PathBuf::from(HomebrewState::get_brew_prefix());
+    let prefix = prefix.as_path();
+    let mut stream = SectionStream::new(writer, runner, options);
+
+    let mut taps = HomebrewState::default();
+    if installed {
+        taps.installed_taps = HomebrewState::get_taps(runner)?;
+        taps.sources.taps = DetectionSource::BrewTap;
+    }
+    let mut diff_data = HomebrewDiffData::compute(
+        &taps,
+        &HomebrewIntent {
+            taps: intent.taps.clone(),
+            tap_remotes: intent.tap_remotes.clone(),
+            flags: intent.flags.clone(),
+            ..Default::default()
+        },
+    );
+    // Declared formulae and casks are in later sections, only the full intent knows their taps
+    diff_data.missing_taps = HomebrewDiffData::compute_missing_taps(&taps, &intent);
+    stream.write_diff(Some("Taps"), diff_data)?;
+
+    // brew outdated covers casks as well, keep them for the casks section
+    let mut formulae = HomebrewState::default();
+    let mut outdated_casks = Default::default();
+    if installed {
+        // Like detection, the release is only asked for because brew outdated depends on it
+        let mut brew_config = BrewConfig::from_prefix(prefix);
+        brew_config.detect_version(runner, HomebrewState::get_brew_command());
+        formulae.brew_config = brew_config;
+        (formulae.outdated_brews, outdated_casks) =
+            HomebrewState::get_outdated(runner, &formulae.brew_compat())?;
+        (
+            formulae.installed_brews,
+            formulae.formula_receipts,
+            formulae.keg_taps,
+            formulae.sources.formulae,
+        ) = HomebrewState::get_installed_formulae(runner, prefix)?;
+        formulae.linked_brews = HomebrewState::get_linked_formulae(prefix);
+        formulae.pinned_brews = HomebrewState::get_pinned_formulae(prefix);
+        formulae.load_locked_versions(runner, &intent.locked)?;
+    }
+    stream.write(
+        "Formulae",
+        &formulae,
+        &HomebrewIntent {
+            brews: intent.brews.clone(),
+            brew_options: intent.brew_options.clone(),
+            locked: intent.locked.clone(),
+            flags: intent.flags.clone(),
+            ..Default::default()
+        },
+    )?;
+
+    let mut casks = HomebrewState {
+        outdated_casks,
+        ..Default::default()
+    };
+    if installed {
+        (
+            casks.installed_casks,
+            casks.cask_receipts,
+            casks.sources.casks,
+        ) = HomebrewState::get_installed_casks(runner, prefix)?;
+        casks.load_locked_versions(runner, &intent.locked)?;
+    }
+    stream.write(
+        "Casks",
+        &casks,
+        &HomebrewIntent {
+            casks: intent.casks.clone(),
+            cask_args: intent.cask_args.clone(),
+            cask_options: intent.cask_options.clone(),
+            locked: intent.locked.clone(),
+            flags: intent.flags.clone(),
+            ..Default::default()
+        },
+    )?;
+
+    let mut mas_apps = HomebrewState::default();
+    if installed && HomebrewState::on_macos() {
+        (mas_apps.installed_mas_apps, mas_apps.sources.mas_apps) =
+            HomebrewState::get_mas_apps(runner)?;
+    }
+    stream.write(
+        "App Store",
+        &mas_apps,
+        &HomebrewIntent {
+            mas_apps: intent.mas_apps.clone(),
+            flags: intent.flags.clone(),
+            ..Default::default()
+        },
+    )?;
+
+    // Duplicates span categories, so they're only found once everything is detected
+    let state = HomebrewState {
+        installed_brews: formulae.installed_brews,
+        installed_casks: casks.installed_casks,
+        cask_receipts: casks.cask_receipts,
+        installed_mas_apps: mas_apps.installed_mas_apps,
+        ..Default::default()
+    };
+    let diff_data = HomebrewDiffData {
+        duplicates: duplicates::find_duplicates(&state, &intent),
+        ..Default::default()
+    };
+    stream.write_diff(None, diff_data)?;
+
+    Ok(stream.report)
+}
+
+/// Writes partial diffs one after another, separated by blank lines
+/// Each category is written once under its own heading, so section headings aren't repeated
+/// at the same level across chunks
+struct SectionStream<'a, W: Write> {
+    writer: &'a mut W,
+    runner: &'a dyn CommandRunner,
+    options: &'a DisplayOptions,
+    report: DiffReport,
+}
+
+impl<'a, W: Write> SectionStream<'a, W> {
+    fn new(writer: &'a mut W, runner: &'a dyn CommandRunner, options: &'a DisplayOptions) -> Self {
+        Self {
+            writer,
+            runner,
+            options,
+            report: DiffReport::default(),
+        }
+    }
+
+    /// Diff a partial state against the matching part of the intent and write it under the
+    /// category's heading
+    /// Missing taps and duplicates need the whole intent, so they're left to the caller
+    fn write(
+        &mut self,
+        category: &str,
+        state: &HomebrewState,
+        intent: &HomebrewIntent,
+    ) -> Result<()> {
+        let mut diff_data = HomebrewDiffData::compute(state, intent);
+        diff_data.missing_taps.clear();
+        diff_data.duplicates.clear();
+        self.write_diff(Some(category), diff_data)
+    }
+
+    /// Write a partial diff after looking up the versions of its entries, under the category's
+    /// heading if it holds a single one
+    fn write_diff(
+        &mut self,
+        category: Option<&str>,
+        mut diff_data: HomebrewDiffData,
+    ) -> Result<()> {
+        diff_data.load_versions(self.runner)?;
+        let mut section = String::new();
+        let report = match category {
+            Some(category) => display::write_category_with_options(
+                &mut section,
+                category,
+                &diff_data,
+                self.options,
+            )?,
+            None => display::write_diff_with_options(&mut section, &diff_data, self.options)?,
+        };
+        if report.lines == 0 {
+            return Ok(());
+        }
+
+        if self.report.lines > 0 {
+            writeln!(self.writer)?;
+            self.report.lines += 1;
+        }
+        self.writer.write_str(&section)?;
+        self.report.lines += report.lines;
+        // A section spanning several categories is reported once, with all of its lines
+        for section in report.sections {
+            match self
+                .report
+                .sections
+                .iter_mut()
+                .find(|written| written.kind == section.kind)
+            {
+                Some(written) => written.lines += section.lines,
+                None => self.report.sections.push(section),
+            }
+        }
+        self.report.had_changes |= report.had_changes;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::{strip_ansi_codes, SectionKind};
+
+    #[test]
+    fn test_sections_are_separated() {
+        let options = DisplayOptions::default();
+        let mut output = String::new();
+        let runner = SystemRunner::default();
+        let mut stream = SectionStream::new(&mut output, &runner, &options);
+
+        let taps = HomebrewIntent {
+            taps: ["hashicorp/tap".to_string()].into(),
+            ..Default::default()
+        };
+        stream
+            .write("Taps", &HomebrewState::default(), &taps)
+            .unwrap();
+        stream
+            .write(
+                "Casks",
+                &HomebrewState::default(),
+                &HomebrewIntent::default(),
+            )
+            .unwrap();
+        let brews = HomebrewIntent {
+            brews: ["wget".to_string()].into(),
+            ..Default::default()
+        };
+        stream
+            .write("Formulae", &HomebrewState::default(), &brews)
+            .unwrap();
+        let report = stream.report;
+
+        // Taps + ADDED + tap, blank line, Formulae + ADDED + formula
+        assert_eq!(report.lines, 7);
+        assert_eq!(report.section_lines(SectionKind::Added), Some(4));
+        assert!(report.had_changes);
+        let clean = strip_ansi_codes(&output);
+        assert_eq!(
+            clean,
+            "Taps\nADDED (1)\n[A] hashicorp/tap\n\nFormulae\nADDED (1)\n[A] wget\n"
+        );
+        assert!(!clean.contains("Casks"));
+    }
+
+    #[test]
+    fn test_partial_sections_leave_missing_taps_alone() {
+        let options = DisplayOptions::default();
+        let mut output = String::new();
+        let runner = SystemRunner::default();
+        let mut stream = SectionStream::new(&mut output, &runner, &options);
+
+        // The formulae section's intent has no taps, its tap is declared in the taps section
+        let brews = HomebrewIntent {
+            brews: ["hashicorp/tap/terraform".to_string()].into(),
+            ..Default::default()
+        };
+        stream
+            .write("Formulae", &HomebrewState::default(), &brews)
+            .unwrap();
+
+        assert!(output.contains("hashicorp/tap/terraform"));
+        assert!(!output.contains("WARNINGS"));
+    }
+}
@@ -0,0 +1,78 @@
+use std::time::{Duration, Instant};
+
+/// How long each phase of a diff run took
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// `brew outdated` for available upgrades
+    pub outdated: Duration,
+    /// Installed formulae, receipts and links
+    pub formulae: Duration,
+    /// Installed casks and receipts
+    pub casks: Duration,
+    /// Installed taps
+    pub taps: Duration,
+    /// Mac App Store apps
+    pub mas: Duration,
+    /// Reading the activation script and parsing the Brewfile
+    pub intent: Duration,
+    /// Comparing state with intent
+    pub diff: Duration,
+    /// Installed versions of locked packages and packages in the diff, when detection didn't find them
+    pub versions: Duration,
+}
+
+impl Timings {
+    /// Sum of all phases
+    pub fn total(&self) -> Duration {
+        self.phases().iter().map(|(_, duration)| *duration).sum()
+    }
+
+    /// Phases in the order they run, labelled for display
+    pub fn phases(&self) -> [(&'static str, Duration); 8] {
+        [
+            ("Outdated", self.outdated),
+            ("Formulae", self.formulae),
+            ("Casks", self.casks),
+            ("Taps", self.taps),
+            ("App Store", self.mas),
+            ("Intent", self.intent),
+            ("Diff", self.diff),
+            ("Versions", self.versions),
+        ]
+    }
+}
+
+/// Run `f` and add its duration to `slot`
+pub(crate) fn time<T>(slot: &mut Duration, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    *slot += start.elapsed();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_total_sums_phases() {
+        let timings = Timings {
+            formulae: Duration::from_millis(120),
+            casks: Duration::from_millis(30),
+            diff: Duration::from_millis(1),
+            ..Default::default()
+        };
+
+        assert_eq!(timings.total(), Duration::from_millis(151));
+    }
+
+    #[test]
+    fn test_time_accumulates() {
+        let mut slot = Duration::from_secs(1);
+
+        let value = time(&mut slot, || 42);
+
+        assert_eq!(value, 42);
+        assert!(slot >= Duration::from_secs(1));
+    }
+}
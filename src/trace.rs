@@ -0,0 +1,36 @@
+//! Tracing helpers that compile to nothing without the `tracing` feature
+
+/// Enter a debug span for the rest of the scope, e.g. `let _span = span!("detect");`
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($($args:tt)*) => {
+        tracing::debug_span!($($args)*).entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($($args:tt)*) => {
+        $crate::trace::NoopSpan
+    };
+}
+
+/// Emit a debug event
+#[cfg(feature = "tracing")]
+macro_rules! event {
+    ($($args:tt)*) => {
+        tracing::debug!($($args)*)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! event {
+    ($($args:tt)*) => {{}};
+}
+
+pub(crate) use event;
+pub(crate) use span;
+
+/// Stand-in for an entered span when tracing is disabled
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoopSpan;
@@ -0,0 +1,156 @@
+//! Shell script reverting what activation changes, generated before activating
+
+use crate::diff::{self, destructive, ChangeEffect, HomebrewDiffData};
+use crate::error::Result;
+use crate::shell::quote;
+use std::fmt::Write;
+
+/// Write a shell script that undoes the changes activation makes according to the diff
+/// Installed packages are uninstalled and removed ones reinstalled, upgrades can't be
+/// reverted by brew so their previous versions are only listed
+/// Returns the number of commands written
+pub fn write_undo_script<W: Write>(writer: &mut W, diff_data: &HomebrewDiffData) -> Result<usize> {
+    writeln!(writer, "#!/bin/sh")?;
+    writeln!(
+        writer,
+        "# Reverts the Homebrew changes of an activation, commands keep going if one fails"
+    )?;
+    writeln!(writer, "set -u")?;
+    let mut commands = 0;
+
+    // Taps come first, removed packages may be installed from them
+    let restored_taps: Vec<_> = destructive(&diff_data.taps.removed).collect();
+    let restored_brews: Vec<_> = destructive(&diff_data.brews.removed).collect();
+    let restored_casks: Vec<_> = destructive(&diff_data.casks.removed).collect();
+    let restored_apps: Vec<_> = destructive(&diff_data.mas_apps.removed).collect();
+    if !restored_taps.is_empty()
+        || !restored_brews.is_empty()
+        || !restored_casks.is_empty()
+        || !restored_apps.is_empty()
+    {
+        writeln!(writer, "\n# Reinstall what activation removes")?;
+    }
+    for removal in restored_taps {
+        writeln!(writer, "brew tap {}", quote(&removal.name))?;
+        commands += 1;
+    }
+    let restored_packages = restored_brews
+        .into_iter()
+        .map(|removal| ("--formula", removal))
+        .chain(
+            restored_casks
+                .into_iter()
+                .map(|removal| ("--cask", removal)),
+        );
+    for (kind, removal) in restored_packages {
+        write!(writer, "brew install {} {}", kind, quote(&removal.name))?;
+        // brew only installs the current version, keep the recorded one for reference
+        if let Some(version) = &removal.version {
+            write!(writer, "  # was {}", version)?;
+        }
+        if removal.effect == ChangeEffect::WillBeZapped {
+            write!(writer, "  # zapped app data can't be restored")?;
+        }
+        writeln!(writer)?;
+        commands += 1;
+    }
+    for removal in restored_apps {
+        if let Some(id) = diff::mas_id(&removal.name) {
+            writeln!(writer, "mas install {}  # {}", id, removal.name)?;
+            commands += 1;
+        }
+    }
+
+    if !diff_data.brews.added.is_empty()
+        || !diff_data.casks.added.is_empty()
+        || !diff_data.taps.added.is_empty()
+        || !diff_data.mas_apps.added.is_empty()
+    {
+        writeln!(writer, "\n# Uninstall what activation installs")?;
+    }
+    for app in &diff_data.mas_apps.added {
+        if let Some(id) = diff::mas_id(app) {
+            writeln!(writer, "sudo mas uninstall {}  # {}", id, app)?;
+            commands += 1;
+        }
+    }
+    for cask in &diff_data.casks.added {
+        writeln!(writer, "brew uninstall --cask {}", quote(cask))?;
+        commands += 1;
+    }
+    for brew in &diff_data.brews.added {
+        writeln!(writer, "brew uninstall --formula {}", quote(brew))?;
+        commands += 1;
+    }
+    // Untap last, brew refuses while packages from the tap are installed
+    for tap in &diff_data.taps.added {
+        writeln!(writer, "brew untap {}", quote(tap))?;
+        commands += 1;
+    }
+
+    let upgrades: Vec<_> = diff_data
+        .brews
+        .upgrades()
+        .chain(diff_data.casks.upgrades())
+        .collect();
+    if !upgrades.is_empty() {
+        writeln!(
+            writer,
+            "\n# Upgrades can't be reverted with brew, versions before activation:"
+        )?;
+        for change in upgrades {
+            writeln!(
+                writer,
+                "#   {} {} -> {}",
+                change.name, change.installed, change.available
+            )?;
+        }
+    }
+
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::{Removal, VersionChange};
+
+    #[test]
+    fn test_undo_script() {
+        let mut diff = HomebrewDiffData::default();
+        diff.taps.added = vec!["hashicorp/tap".to_string()];
+        diff.brews.added = vec!["hashicorp/tap/terraform".to_string()];
+        diff.brews.removed =
+            vec![Removal::new("git", ChangeEffect::WillBeRemoved).with_version("2.44.0")];
+        diff.casks.removed = vec![
+            Removal::new("slack", ChangeEffect::WillBeZapped),
+            Removal::new("zoom", ChangeEffect::LeftInstalled),
+        ];
+        diff.mas_apps.added = vec!["Xcode (497799835)".to_string()];
+        diff.brews.upgraded = vec![VersionChange {
+            name: "node".to_string(),
+            installed: "21.7.3".to_string(),
+            available: "22.2.0".to_string(),
+            applied: true,
+            auto_updates: false,
+        }];
+
+        let mut output = String::new();
+        let commands = write_undo_script(&mut output, &diff).unwrap();
+
+        assert_eq!(commands, 5);
+        assert!(output.starts_with("#!/bin/sh\n"));
+        assert!(output.contains("brew install --formula 'git'  # was 2.44.0\n"));
+        assert!(
+            output.contains("brew install --cask 'slack'  # zapped app data can't be restored\n")
+        );
+        assert!(!output.contains("zoom"));
+        assert!(output.contains("sudo mas uninstall 497799835  # Xcode (497799835)\n"));
+        assert!(output.contains("#   node 21.7.3 -> 22.2.0\n"));
+        let uninstall = output
+            .find("brew uninstall --formula 'hashicorp/tap/terraform'")
+            .unwrap();
+        let untap = output.find("brew untap 'hashicorp/tap'").unwrap();
+        assert!(uninstall < untap);
+    }
+}
@@ -0,0 +1,287 @@
+use std::cmp::Ordering;
+
+/// A single component of a version string
+/// Variants are ordered by how they compare against each other when kinds differ
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token<'a> {
+    /// Pre-release markers: alpha < beta < pre < rc
+    PreRelease(u8, &'a str),
+    /// Arbitrary words, e.g. the `stable` in `1.0-stable`
+    Word(&'a str),
+    Numeric(&'a str),
+    /// Post-release markers like `p1` or `post2`
+    PostRelease(&'a str),
+}
+
+impl Token<'_> {
+    fn rank(&self) -> u8 {
+        match self {
+            Token::PreRelease(..) => 0,
+            Token::Word(_) => 1,
+            Token::Numeric(_) => 2,
+            Token::PostRelease(_) => 3,
+        }
+    }
+
+    /// Compare against a missing token, e.g. the 4th token when comparing `1.0` to `1.0.1`
+    fn cmp_missing(&self) -> Ordering {
+        match self {
+            Token::PreRelease(..) => Ordering::Less,
+            Token::Numeric(n) if n.trim_start_matches('0').is_empty() => Ordering::Equal,
+            Token::Word(_) | Token::Numeric(_) | Token::PostRelease(_) => Ordering::Greater,
+        }
+    }
+}
+
+impl Ord for Token<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Token::PreRelease(a_kind, a), Token::PreRelease(b_kind, b)) => {
+                a_kind.cmp(b_kind).then_with(|| cmp_numeric(a, b))
+            }
+            (Token::Word(a), Token::Word(b)) => a.to_lowercase().cmp(&b.to_lowercase()),
+            (Token::Numeric(a), Token::Numeric(b))
+            | (Token::PostRelease(a), Token::PostRelease(b)) => cmp_numeric(a, b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Token<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// How big a jump between two versions is, judged by the first differing numeric segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Bump {
+    /// Same version rebuilt, e.g. `1.2.3` to `1.2.3_1`
+    Revision,
+    Patch,
+    Minor,
+    Major,
+}
+
+/// Whether a version says nothing about what's installed, e.g. casks with `version :latest`
+/// Their Caskroom directory is always `latest`, so they never compare as changed
+pub fn is_unversioned(version: &str) -> bool {
+    version.is_empty() || version.eq_ignore_ascii_case("latest")
+}
+
+/// Classify the change between two versions, None if they're equivalent
+/// Anything past the second numeric segment (including pre-release changes) is a patch
+/// Unversioned casks are never a change
+pub fn classify(from: &str, to: &str) -> Option<Bump> {
+    if is_unversioned(from) || is_unversioned(to) || compare(from, to) == Ordering::Equal {
+        return None;
+    }
+    let (from, _) = split_revision(from);
+    let (to, _) = split_revision(to);
+    if compare(from, to) == Ordering::Equal {
+        return Some(Bump::Revision);
+    }
+
+    let numeric = |version: &'_ str| -> Vec<String> {
+        let first_part = version.split(',').next().unwrap_or_default();
+        tokenize(first_part)
+            .into_iter()
+            .map_while(|token| match token {
+                Token::Numeric(n) => Some(n.trim_start_matches('0').to_string()),
+                _ => None,
+            })
+            .collect()
+    };
+    let from = numeric(from);
+    let to = numeric(to);
+    let segment = |segments: &[String], i: usize| segments.get(i).cloned().unwrap_or_default();
+
+    if segment(&from, 0) != segment(&to, 0) {
+        Some(Bump::Major)
+    } else if segment(&from, 1) != segment(&to, 1) {
+        Some(Bump::Minor)
+    } else {
+        Some(Bump::Patch)
+    }
+}
+
+/// Compare two Homebrew version strings
+/// Follows Homebrew's ordering: numeric segments compare numerically, pre-releases
+/// (alpha, beta, pre, rc) sort before the release and cask versions like
+/// `1.2.3,4567` compare each comma separated part in turn
+/// Bottle revisions (`1.2.3_1`) only break ties between equal versions
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let (a, a_revision) = split_revision(a);
+    let (b, b_revision) = split_revision(b);
+    compare_versions(a, b).then(a_revision.cmp(&b_revision))
+}
+
+/// The newest of an installed package's versions, which are space separated when several
+/// kegs are installed, e.g. `125.0 126.0`, None if its versions weren't looked up
+pub fn newest(versions: &str) -> Option<&str> {
+    versions.split_whitespace().max_by(|a, b| compare(a, b))
+}
+
+/// Split off the bottle revision Homebrew appends to rebuilt formulae, e.g. `1.2.3_1`
+/// Versions without a revision are revision 0
+pub fn split_revision(version: &str) -> (&str, u32) {
+    version
+        .rsplit_once('_')
+        .and_then(|(base, revision)| Some((base, revision.parse().ok()?)))
+        .filter(|(base, _)| !base.is_empty())
+        .unwrap_or((version, 0))
+}
+
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut a_parts = a.split(',');
+    let mut b_parts = b.split(',');
+
+    loop {
+        match (a_parts.next(), b_parts.next()) {
+            (Some(a), Some(b)) => match compare_part(a, b) {
+                Ordering::Equal => continue,
+                ordering => return ordering,
+            },
+            (Some(_), None) => return Ordering::Greater,
+            (None, Some(_)) => return Ordering::Less,
+            (None, None) => return Ordering::Equal,
+        }
+    }
+}
+
+fn compare_part(a: &str, b: &str) -> Ordering {
+    let a = tokenize(a);
+    let b = tokenize(b);
+
+    for i in 0..a.len().max(b.len()) {
+        let ordering = match (a.get(i), b.get(i)) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (Some(a), None) => a.cmp_missing(),
+            (None, Some(b)) => b.cmp_missing().reverse(),
+            (None, None) => Ordering::Equal,
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    Ordering::Equal
+}
+
+/// Split a version into tokens, separators like `.`, `-` and `_` are dropped
+fn tokenize(version: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = version;
+
+    while let Some(start) = rest.find(|c: char| c.is_ascii_alphanumeric()) {
+        rest = &rest[start..];
+        let is_digit = rest.starts_with(|c: char| c.is_ascii_digit());
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != is_digit || !c.is_ascii_alphanumeric())
+            .unwrap_or(rest.len());
+        let (run, after) = rest.split_at(end);
+        rest = after;
+
+        if is_digit {
+            tokens.push(Token::Numeric(run));
+            continue;
+        }
+
+        // Markers take the number that directly follows them, e.g. `rc2`
+        let digits_end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (number, after_number) = rest.split_at(digits_end);
+        let marker = match run.to_ascii_lowercase().as_str() {
+            "alpha" => Some(Token::PreRelease(0, number)),
+            "a" if !number.is_empty() => Some(Token::PreRelease(0, number)),
+            "beta" => Some(Token::PreRelease(1, number)),
+            "b" if !number.is_empty() => Some(Token::PreRelease(1, number)),
+            "pre" => Some(Token::PreRelease(2, number)),
+            "rc" => Some(Token::PreRelease(3, number)),
+            "post" | "patch" => Some(Token::PostRelease(number)),
+            "p" if !number.is_empty() => Some(Token::PostRelease(number)),
+            _ => None,
+        };
+        match marker {
+            Some(token) => {
+                tokens.push(token);
+                rest = after_number;
+            }
+            None => tokens.push(Token::Word(run)),
+        }
+    }
+
+    tokens
+}
+
+/// Compare digit strings without overflowing on long date-like versions
+fn cmp_numeric(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_numeric_segments() {
+        assert_eq!(compare("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare("1.0", "1.0.0"), Ordering::Equal);
+        assert_eq!(compare("1.0", "1.0.1"), Ordering::Less);
+        assert_eq!(compare("20240101", "20231231"), Ordering::Greater);
+        assert_eq!(compare("1.02", "1.2"), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_compare_pre_releases() {
+        assert_eq!(compare("1.0-alpha", "1.0"), Ordering::Less);
+        assert_eq!(compare("1.0alpha2", "1.0beta1"), Ordering::Less);
+        assert_eq!(compare("1.0b1", "1.0rc1"), Ordering::Less);
+        assert_eq!(compare("1.0rc2", "1.0rc10"), Ordering::Less);
+        assert_eq!(compare("1.0rc1", "1.0.1"), Ordering::Less);
+        assert_eq!(compare("1.0p1", "1.0"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_revisions() {
+        assert_eq!(split_revision("1.2.3_1"), ("1.2.3", 1));
+        assert_eq!(split_revision("1.2.3"), ("1.2.3", 0));
+        assert_eq!(split_revision("2024_beta"), ("2024_beta", 0));
+        assert_eq!(compare("1.2_1", "1.2.1"), Ordering::Less);
+        assert_eq!(compare("1.2.3_2", "1.2.3_1"), Ordering::Greater);
+        assert_eq!(compare("1.2.3_1", "1.2.4"), Ordering::Less);
+        assert_eq!(classify("1.2.3", "1.2.3_1"), Some(Bump::Revision));
+        assert_eq!(classify("1.2.3_1", "1.2.4"), Some(Bump::Patch));
+    }
+
+    #[test]
+    fn test_newest() {
+        assert_eq!(newest("125.0 126.0"), Some("126.0"));
+        assert_eq!(newest("1.10 1.9"), Some("1.10"));
+        assert_eq!(newest("1.7.1"), Some("1.7.1"));
+        assert_eq!(newest(""), None);
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify("latest", "latest"), None);
+        assert_eq!(classify("latest", "1.0"), None);
+        assert_eq!(classify("1.21.3", "2.0"), Some(Bump::Major));
+        assert_eq!(classify("1.21.3", "1.24.5"), Some(Bump::Minor));
+        assert_eq!(classify("1.21.3", "1.21.4"), Some(Bump::Patch));
+        assert_eq!(classify("1.0", "1.0.0.1"), Some(Bump::Patch));
+        assert_eq!(classify("2.0rc1", "2.0"), Some(Bump::Patch));
+        assert_eq!(classify("1.0", "1.0.0"), None);
+    }
+
+    #[test]
+    fn test_compare_cask_versions() {
+        assert_eq!(compare("4.28.0,139574", "4.28.0,139573"), Ordering::Greater);
+        assert_eq!(compare("4.29.0,1", "4.28.0,139573"), Ordering::Greater);
+        assert_eq!(compare("1.2.3", "1.2.3,45"), Ordering::Less);
+        assert_eq!(compare("latest", "latest"), Ordering::Equal);
+    }
+}
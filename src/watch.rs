@@ -0,0 +1,84 @@
+use crate::cache::StateCache;
+use crate::error::Result;
+use crate::state::HomebrewState;
+use crate::trace::event;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Watches the Cellar, Caskroom and taps so long-running processes notice installs and removals
+/// Invalidates the given cache on every change instead of polling brew
+pub struct StateWatcher {
+    _watcher: RecommendedWatcher,
+    dirty: Arc<AtomicBool>,
+}
+
+impl StateWatcher {
+    /// Start watching Homebrew's directories, invalidating `cache` whenever they change
+    pub fn new(cache: &'static StateCache) -> Result<Self> {
+        Self::watch_dirs(&Self::homebrew_dirs(), move || cache.invalidate())
+    }
+
+    /// Directories whose contents make up the detected state
+    fn homebrew_dirs() -> Vec<PathBuf> {
+        let prefix = Path::new(HomebrewState::get_brew_prefix());
+        vec![
+            prefix.join("Cellar"),
+            prefix.join("Caskroom"),
+            prefix.join("Library/Taps"),
+        ]
+    }
+
+    /// Watch the existing ones of `dirs`, calling `on_change` for every change
+    fn watch_dirs(dirs: &[PathBuf], on_change: impl Fn() + Send + 'static) -> Result<Self> {
+        let dirty = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&dirty);
+        // Errors may mean missed events, so they count as changes too
+        let mut watcher = notify::recommended_watcher(move |_: notify::Result<notify::Event>| {
+            event!("Homebrew directories changed");
+            flag.store(true, Ordering::SeqCst);
+            on_change();
+        })?;
+
+        for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+            watcher.watch(dir, RecursiveMode::Recursive)?;
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            dirty,
+        })
+    }
+
+    /// Check if anything changed since the last call, resetting the flag
+    pub fn take_dirty(&self) -> bool {
+        self.dirty.swap(false, Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_change_marks_dirty() {
+        let cellar = TempDir::new().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let watcher = StateWatcher::watch_dirs(&[cellar.path().to_path_buf()], move || {
+            let _ = sender.send(());
+        })
+        .unwrap();
+        assert!(!watcher.take_dirty());
+
+        fs::create_dir(cellar.path().join("wget")).unwrap();
+
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(watcher.take_dirty());
+        assert!(!watcher.take_dirty());
+    }
+}
@@ -0,0 +1,20 @@
+#!/nix/store/8ii0dz6gmmrdvqw2bz5jj0jjq2pxcdgf-bash-5.2p32/bin/bash
+set -e
+set -o pipefail
+export PATH=/nix/store/1fm59m7fp3q2c2kpqqz26g7n4d8nmdj2-coreutils-9.5/bin:/run/current-system/sw/bin:/usr/bin:/bin:/usr/sbin:/sbin
+
+systemConfig=/nix/store/hx6rwv2k3gb2c0f9n4sd1bygsbl2gfp3-darwin-system-24.11
+_status=0
+
+# Homebrew Bundle
+echo >&2 "Homebrew bundle..."
+if [ -f /opt/homebrew/bin/brew ]; then
+  sudo --user=kilian --set-home \
+    env PATH="/opt/homebrew/bin:$PATH" HOMEBREW_NO_AUTO_UPDATE=1 \
+    brew bundle --file='/nix/store/b1w0l0c7d8pfh3lc5mnkyfmd4nxs4l7x-Brewfile' --no-lock --no-upgrade --cleanup
+else
+  echo -e "\e[1;31merror: Homebrew is not installed, skipping...\e[0m" >&2
+fi
+
+ln -sfn "$(readlink -f "$systemConfig")" /run/current-system
+exit $_status
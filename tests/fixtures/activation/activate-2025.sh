@@ -0,0 +1,22 @@
+#!/nix/store/gn0wc4fxfzz3h1d2qd6vn6rlzc2lbhsq-bash-5.2p37/bin/bash
+set -e
+set -o pipefail
+export PATH=/nix/store/0wsxqm3z2c3s7yk4ifcy8q9pqv0wdyl2-coreutils-9.6/bin:/run/current-system/sw/bin:/usr/bin:/bin:/usr/sbin:/sbin
+
+systemConfig=/nix/store/d3l1p0wfdbyw3k5c1h5l0ahlsjp2c0p8-darwin-system-25.05
+
+# Homebrew Bundle
+echo >&2 "Homebrew bundle..."
+if [ -f /opt/homebrew/bin/brew ]; then
+  PATH="/opt/homebrew/bin:/usr/local/bin:$PATH" \
+  sudo \
+    --preserve-env=PATH \
+    --user=kilian \
+    --set-home \
+    env \
+    HOMEBREW_NO_AUTO_UPDATE=1 /opt/homebrew/bin/brew bundle --file "/nix/store/z3f8jpl4n3qsw2d6z2x1pf0dyj6ragsb-Brewfile" --no-upgrade --cleanup --zap
+else
+  echo -e "\e[1;31merror: Homebrew is not installed, skipping...\e[0m" >&2
+fi
+
+ln -sfn "$(readlink -f "$systemConfig")" /run/current-system
@@ -0,0 +1,16 @@
+#!/nix/store/2a4ifrkbhsc0z5agnzq2lmzmwdbj4yrl-bash-5.2-p15/bin/bash
+set -e
+set -o pipefail
+export PATH=/nix/store/pr49xw6qv30q1rgcfdnfv0ykmhdqv3ak-coreutils-9.3/bin:@out@/sw/bin:/usr/bin:/bin:/usr/sbin:/sbin
+
+echo "setting up user launchd services..."
+
+# Homebrew Bundle
+echo >&2 "Homebrew bundle..."
+if [ -f /opt/homebrew/bin/brew ]; then
+  PATH="/opt/homebrew/bin:$PATH" HOMEBREW_NO_AUTO_UPDATE=1 brew bundle --file='/nix/store/4q9ph0l1m5i0mi6wcwlqgbmd2p0kyfrg-Brewfile' --no-lock --no-upgrade
+else
+  echo -e "\e[1;31merror: Homebrew is not installed, skipping...\e[0m" >&2
+fi
+
+echo "setting up ~/Applications..." >&2